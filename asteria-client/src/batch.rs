@@ -0,0 +1,195 @@
+use asteria_core::protocol::{InputEventType, Message, Packet};
+use std::time::{Duration, Instant};
+
+/// Collects typed events for a short window so bursts travel as one packet
+pub struct EventBatcher {
+    window: Duration,
+    max_events: usize,
+    events: Vec<InputEventType>,
+    /// When the oldest pending event was added
+    started_at: Option<Instant>,
+    /// Capture timestamp of the oldest pending event, carried by the batch packet
+    timestamp: Option<u64>,
+}
+
+impl EventBatcher {
+    pub fn new(window: Duration, max_events: usize) -> Self {
+        Self {
+            window,
+            max_events: max_events.max(1),
+            events: Vec::new(),
+            started_at: None,
+            timestamp: None,
+        }
+    }
+
+    /// Batcher whose batches are only ended by [`flush`](Self::flush) or by filling up,
+    /// for callers that flush at their own boundaries
+    pub fn unwindowed(max_events: usize) -> Self {
+        Self::new(Duration::MAX, max_events)
+    }
+
+    /// Add an event captured at `timestamp`, returning a packet if the batch is
+    /// full or batching is disabled
+    pub fn push(&mut self, event: InputEventType, timestamp: u64, now: Instant) -> Option<Packet> {
+        self.started_at.get_or_insert(now);
+        self.timestamp.get_or_insert(timestamp);
+        self.events.push(event);
+
+        if self.window.is_zero() || self.events.len() >= self.max_events {
+            return self.flush();
+        }
+        None
+    }
+
+    /// Flush the pending events once the oldest has waited out the window
+    pub fn flush_if_due(&mut self, now: Instant) -> Option<Packet> {
+        let started_at = self.started_at?;
+        if now.duration_since(started_at) < self.window {
+            return None;
+        }
+        self.flush()
+    }
+
+    /// Take every pending event as a single packet, in the order they were added
+    pub fn flush(&mut self) -> Option<Packet> {
+        self.started_at = None;
+        let timestamp = self.timestamp.take();
+
+        let message = match self.events.len() {
+            0 => return None,
+            1 => Message::InputEventTyped(self.events.pop().expect("one pending event")),
+            _ => Message::Batch(std::mem::take(&mut self.events)),
+        };
+
+        let mut packet = Packet::new(message);
+        if let Some(timestamp) = timestamp {
+            packet.timestamp = timestamp;
+        }
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_press(key_code: u16) -> InputEventType {
+        InputEventType::KeyPress { key_code }
+    }
+
+    /// Events a packet carries, formatted for comparison
+    fn events(packet: &Packet) -> Vec<String> {
+        match &packet.message {
+            Message::InputEventTyped(event) => vec![format!("{:?}", event)],
+            Message::Batch(events) => events.iter().map(|event| format!("{:?}", event)).collect(),
+            message => panic!("unexpected {:?}", message),
+        }
+    }
+
+    #[test]
+    fn batch_is_held_until_the_window_passes() {
+        let mut batcher = EventBatcher::new(Duration::from_millis(2), 16);
+        let start = Instant::now();
+
+        assert!(batcher.push(key_press(30), 100, start).is_none());
+        assert!(
+            batcher
+                .push(key_press(48), 101, start + Duration::from_millis(1))
+                .is_none()
+        );
+        assert!(
+            batcher
+                .flush_if_due(start + Duration::from_millis(1))
+                .is_none()
+        );
+
+        let packet = batcher
+            .flush_if_due(start + Duration::from_millis(2))
+            .unwrap();
+        assert_eq!(events(&packet).len(), 2);
+        // The batch carries when its oldest event was captured
+        assert_eq!(packet.timestamp, 100);
+        assert!(
+            batcher
+                .flush_if_due(start + Duration::from_secs(1))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn full_batch_is_sent_at_once() {
+        let mut batcher = EventBatcher::new(Duration::from_secs(1), 3);
+        let now = Instant::now();
+
+        assert!(batcher.push(key_press(30), 1, now).is_none());
+        assert!(batcher.push(key_press(48), 2, now).is_none());
+        let packet = batcher.push(key_press(46), 3, now).unwrap();
+        assert_eq!(
+            events(&packet),
+            [
+                "KeyPress { key_code: 30 }",
+                "KeyPress { key_code: 48 }",
+                "KeyPress { key_code: 46 }"
+            ]
+        );
+
+        // The next event starts a new batch
+        assert!(batcher.push(key_press(32), 4, now).is_none());
+        assert_eq!(batcher.flush().unwrap().timestamp, 4);
+    }
+
+    #[test]
+    fn zero_window_sends_every_event_alone() {
+        let mut batcher = EventBatcher::new(Duration::ZERO, 16);
+        let packet = batcher.push(key_press(30), 1, Instant::now()).unwrap();
+        assert!(matches!(packet.message, Message::InputEventTyped(_)));
+    }
+
+    #[test]
+    fn unwindowed_batch_waits_for_the_dispatch_boundary() {
+        let mut batcher = EventBatcher::unwindowed(16);
+        let start = Instant::now();
+
+        // A button press and the motion of the same frame, one dispatch cycle
+        batcher.push(
+            InputEventType::MouseButton {
+                button: 1,
+                pressed: true,
+            },
+            1,
+            start,
+        );
+        batcher.push(InputEventType::MouseMove { x: 4, y: 2 }, 1, start);
+        assert!(
+            batcher
+                .flush_if_due(start + Duration::from_secs(60))
+                .is_none()
+        );
+
+        let packet = batcher.flush().unwrap();
+        assert_eq!(
+            events(&packet),
+            [
+                "MouseButton { button: 1, pressed: true }",
+                "MouseMove { x: 4, y: 2 }"
+            ]
+        );
+        // Nothing is left for the next dispatch cycle
+        assert!(batcher.flush().is_none());
+    }
+
+    #[test]
+    fn unwindowed_batch_still_splits_at_max_events() {
+        let mut batcher = EventBatcher::unwindowed(2);
+        let now = Instant::now();
+
+        assert!(batcher.push(key_press(30), 1, now).is_none());
+        assert_eq!(
+            events(&batcher.push(key_press(48), 1, now).unwrap()).len(),
+            2
+        );
+        assert!(batcher.push(key_press(46), 1, now).is_none());
+        assert_eq!(events(&batcher.flush().unwrap()).len(), 1);
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::{Context, Result, bail};
+use asteria_core::{
+    clipboard::{ClipboardDebouncer, ClipboardPayload, chunk_payload},
+    config::ClipboardConfig,
+    protocol::{ClipboardFormat, Message, Packet},
+};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// The local clipboard's text content
+pub trait ClipboardSource: Send {
+    /// Current clipboard text, or `None` if the clipboard holds no text
+    fn read_text(&mut self) -> Result<Option<String>>;
+    /// Replace the clipboard content with `text`
+    fn write_text(&mut self, text: &str) -> Result<()>;
+}
+
+/// Uses `wl-paste` and `wl-copy` on Wayland, `xclip` on X11
+pub struct CommandClipboardSource {
+    reader: (&'static str, &'static [&'static str]),
+    writer: (&'static str, &'static [&'static str]),
+}
+
+impl CommandClipboardSource {
+    pub fn new() -> Self {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Self {
+                reader: ("wl-paste", &["--no-newline", "--type", "text/plain"]),
+                writer: ("wl-copy", &["--type", "text/plain"]),
+            }
+        } else {
+            Self {
+                reader: ("xclip", &["-selection", "clipboard", "-out"]),
+                writer: ("xclip", &["-selection", "clipboard", "-in"]),
+            }
+        }
+    }
+}
+
+impl Default for CommandClipboardSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClipboardSource for CommandClipboardSource {
+    fn read_text(&mut self) -> Result<Option<String>> {
+        let (program, args) = self.reader;
+        let output = Command::new(program).args(args).output()?;
+
+        // Both tools exit non-zero when the clipboard is empty or holds no text
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        match String::from_utf8(output.stdout) {
+            Ok(text) => Ok(Some(text)),
+            Err(_) => bail!("{} returned non-UTF-8 clipboard content", program),
+        }
+    }
+
+    fn write_text(&mut self, text: &str) -> Result<()> {
+        let (program, args) = self.writer;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", program))?;
+
+        // Closing stdin tells the tool the content is complete, it then keeps
+        // serving the selection from a background process of its own
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        stdin.write_all(text.as_bytes())?;
+        drop(stdin);
+
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("{} exited with {}", program, status);
+        }
+        Ok(())
+    }
+}
+
+/// Polls a clipboard source, forwarding settled changes to the server and
+/// applying the server's changes in turn
+pub struct ClipboardMonitor {
+    source: Box<dyn ClipboardSource>,
+    poll_interval: Duration,
+    debouncer: ClipboardDebouncer,
+    formats: Vec<ClipboardFormat>,
+    max_size: usize,
+    /// Id of the next transfer, telling its chunks apart from the previous one's
+    next_transfer_id: u32,
+}
+
+impl ClipboardMonitor {
+    pub fn new(source: Box<dyn ClipboardSource>, config: &ClipboardConfig) -> Self {
+        Self {
+            source,
+            poll_interval: Duration::from_millis(config.sync_poll_interval_ms),
+            debouncer: ClipboardDebouncer::new(Duration::from_millis(config.sync_debounce_ms)),
+            formats: config.formats.clone(),
+            max_size: config.max_payload_size,
+            next_transfer_id: 0,
+        }
+    }
+
+    /// Watch the clipboard until the packet channel closes, putting content
+    /// from `received` on the local clipboard
+    pub async fn run(
+        mut self,
+        packet_sender: mpsc::Sender<Packet>,
+        mut received: mpsc::Receiver<ClipboardPayload>,
+    ) {
+        if !self.formats.contains(&ClipboardFormat::Text) {
+            warn!("Clipboard sync needs the text format in clipboard.formats, not syncing");
+            return;
+        }
+        info!("Clipboard sync enabled");
+
+        let mut interval = tokio::time::interval(self.poll_interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                Some(payload) = received.recv() => {
+                    self.apply(payload);
+                    continue;
+                }
+            }
+
+            let content = match self.source.read_text() {
+                Ok(Some(content)) => content,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("Failed to read clipboard: {}", e);
+                    continue;
+                }
+            };
+
+            if let Some(content) = self.debouncer.observe(content, Instant::now()) {
+                let chunks = match chunk_payload(
+                    self.next_transfer_id,
+                    &ClipboardPayload::text(content),
+                    self.max_size,
+                ) {
+                    Ok(chunks) => chunks,
+                    Err(e) => {
+                        warn!("Not syncing clipboard content: {}", e);
+                        continue;
+                    }
+                };
+                self.next_transfer_id = self.next_transfer_id.wrapping_add(1);
+
+                debug!("Sending clipboard update in {} chunks", chunks.len());
+                for chunk in chunks {
+                    let packet = Packet::new(Message::ClipboardChunk(chunk));
+                    if packet_sender.send(packet).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Put clipboard content received from the server on the local clipboard
+    fn apply(&mut self, payload: ClipboardPayload) {
+        let Some(text) = payload.as_text() else {
+            warn!(
+                "Ignoring {:?} clipboard content from the server, only text can be set",
+                payload.format
+            );
+            return;
+        };
+
+        match self.source.write_text(text) {
+            // Seen already, so the next poll doesn't send it straight back
+            Ok(()) => self.debouncer.mark_seen(text.to_string()),
+            Err(e) => warn!("Failed to set clipboard: {}", e),
+        }
+    }
+}
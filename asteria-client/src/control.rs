@@ -0,0 +1,228 @@
+use anyhow::{Result, bail};
+use asteria_core::protocol::{InputEventType, MAX_TYPE_TEXT_LEN, Message, Packet};
+use serde_json::{Value, json};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::UnixListener;
+use tokio::sync::{RwLock, mpsc};
+use tracing::{debug, info, warn};
+
+use crate::input::RelayState;
+
+/// Serve relay status and toggle requests on a Unix socket.
+///
+/// Each request is one line: `status` answers with the current relay state as
+/// JSON, `toggle` asks the capture loop to toggle relay and `type TEXT` types
+/// the rest of the line on the server while relay is on.
+pub async fn serve_control_socket(
+    path: &Path,
+    relay_state: Arc<RwLock<RelayState>>,
+    toggle_requested: Arc<AtomicBool>,
+    packet_sender: mpsc::Sender<Packet>,
+) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        prepare_socket_dir(parent)?;
+    }
+    // A socket left behind by a previous run would make the bind fail
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => bail!("{} exists and is not a socket", path.display()),
+        Err(_) => {}
+    }
+
+    // Created without group or other access, so nobody else can connect
+    // between the bind and a later chmod
+    let previous_umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(previous_umask) };
+    let listener = listener?;
+    info!("Control socket listening on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let relay_state = Arc::clone(&relay_state);
+        let toggle_requested = Arc::clone(&toggle_requested);
+        let packet_sender = packet_sender.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) =
+                handle_control_client(stream, relay_state, toggle_requested, packet_sender).await
+            {
+                debug!("Control client error: {}", e);
+            }
+        });
+    }
+}
+
+/// Create the socket's directory private to this user, refusing one another
+/// user could swap the socket in
+fn prepare_socket_dir(dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+
+    let metadata = std::fs::metadata(dir)?;
+    if metadata.uid() != unsafe { libc::geteuid() } {
+        bail!(
+            "Control socket directory {} is owned by another user",
+            dir.display()
+        );
+    }
+    if metadata.permissions().mode() & 0o022 != 0 {
+        bail!(
+            "Control socket directory {} is writable by other users",
+            dir.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Answer requests from one control client until it disconnects
+async fn handle_control_client<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    relay_state: Arc<RwLock<RelayState>>,
+    toggle_requested: Arc<AtomicBool>,
+    packet_sender: mpsc::Sender<Packet>,
+) -> Result<()> {
+    let mut lines = BufReader::new(stream).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        // The text is taken verbatim, so it is split off before trimming
+        if let Some(text) = line.strip_prefix("type ") {
+            let response = if relay_state.read().await.relay_enabled {
+                type_text(text, &packet_sender).await
+            } else {
+                json!({ "error": "relay is off" })
+            };
+            write_response(lines.get_mut().get_mut(), response).await?;
+            continue;
+        }
+
+        let response = match line.trim() {
+            "status" => serde_json::to_value(&*relay_state.read().await)?,
+            "toggle" => {
+                toggle_requested.store(true, Ordering::SeqCst);
+                json!({ "ok": true })
+            }
+            "" => continue,
+            command => {
+                warn!("Unknown control command: {}", command);
+                json!({ "error": format!("unknown command: {}", command) })
+            }
+        };
+
+        write_response(lines.get_mut().get_mut(), response).await?;
+    }
+
+    Ok(())
+}
+
+/// Send text to be typed on the server
+async fn type_text(text: &str, packet_sender: &mpsc::Sender<Packet>) -> Value {
+    if text.len() > MAX_TYPE_TEXT_LEN {
+        return json!({
+            "error": format!("text is {} bytes, limit is {} bytes", text.len(), MAX_TYPE_TEXT_LEN)
+        });
+    }
+
+    let event = InputEventType::TypeText {
+        text: text.to_string(),
+    };
+    match packet_sender
+        .send(Packet::new(Message::InputEventTyped(event)))
+        .await
+    {
+        Ok(()) => json!({ "ok": true }),
+        Err(_) => json!({ "error": "relay is not running" }),
+    }
+}
+
+async fn write_response<W: AsyncWrite + Unpin>(stream: &mut W, response: Value) -> Result<()> {
+    stream
+        .write_all(format!("{}\n", response).as_bytes())
+        .await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("asteria-control-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn socket_dir_is_created_private() {
+        let dir = scratch_dir("private").join("asteria");
+        prepare_socket_dir(&dir).unwrap();
+
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[test]
+    fn socket_dir_writable_by_others_is_refused() {
+        let dir = scratch_dir("shared");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+
+        assert!(prepare_socket_dir(&dir).is_err());
+    }
+
+    /// Send `request` to a control client and return the response line and
+    /// whatever it queued for the server
+    async fn request(relay_enabled: bool, request: &str) -> (String, Option<Packet>) {
+        let relay_state = Arc::new(RwLock::new(RelayState {
+            relay_enabled,
+            ..RelayState::default()
+        }));
+        let (packet_sender, mut packets) = mpsc::channel(1);
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_control_client(
+            server,
+            relay_state,
+            Arc::new(AtomicBool::new(false)),
+            packet_sender,
+        ));
+
+        let mut lines = BufReader::new(client).lines();
+        let stream = lines.get_mut().get_mut();
+        stream
+            .write_all(format!("{}\n", request).as_bytes())
+            .await
+            .unwrap();
+        let response = lines.next_line().await.unwrap().unwrap();
+        (response, packets.try_recv().ok())
+    }
+
+    #[tokio::test]
+    async fn type_is_refused_while_relay_is_off() {
+        let (response, packet) = request(false, "type hello").await;
+        assert!(response.contains("relay is off"));
+        assert!(packet.is_none());
+    }
+
+    #[tokio::test]
+    async fn type_sends_text_while_relaying() {
+        let (response, packet) = request(true, "type hello ").await;
+        assert_eq!(response, r#"{"ok":true}"#);
+        match packet.map(|packet| packet.message) {
+            Some(Message::InputEventTyped(InputEventType::TypeText { text })) => {
+                assert_eq!(text, "hello ")
+            }
+            other => panic!("unexpected packet {:?}", other),
+        }
+    }
+}
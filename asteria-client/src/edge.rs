@@ -0,0 +1,221 @@
+use asteria_core::config::{EdgeSwitchConfig, ScreenEdge, ScreenSize};
+use asteria_core::protocol::ABSOLUTE_AXIS_MAX;
+
+/// The pointer crossing between the local screen and the server's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeCrossing {
+    /// Left the local screen; `entry` is where it enters the server's screen,
+    /// normalized to `0..=ABSOLUTE_AXIS_MAX` on both axes
+    Leave { entry: (i32, i32) },
+    /// Came back from the server's screen
+    Return,
+}
+
+/// Follows the pointer from relative motion, on the local screen while relay
+/// is off and on the server's screen while it is on, to tell when it crosses
+/// the configured edge.
+///
+/// The position is only an estimate, since neither side reports the real one.
+/// Pushing against a screen edge keeps the estimate there, so it catches up
+/// with the real pointer whenever that stops at an edge.
+pub struct EdgeSwitch {
+    edge: ScreenEdge,
+    local: (f64, f64),
+    remote: (f64, f64),
+    /// Estimated pointer position on the screen it is on
+    position: (f64, f64),
+    on_remote: bool,
+}
+
+impl EdgeSwitch {
+    pub fn new(config: &EdgeSwitchConfig) -> Self {
+        let local = screen_extent(config.local_screen);
+        Self {
+            edge: config.edge,
+            local,
+            remote: screen_extent(config.remote_screen),
+            position: center(local),
+            on_remote: false,
+        }
+    }
+
+    /// Move the pointer by `dx`, `dy`, reporting when that takes it across the
+    /// edge. `relaying` is whether relay is on at the moment.
+    pub fn motion(&mut self, dx: f64, dy: f64, relaying: bool) -> Option<EdgeCrossing> {
+        if relaying != self.on_remote {
+            // Relay was switched some other way, e.g. with the toggle key, so
+            // there's no telling where the pointer is
+            self.on_remote = relaying;
+            self.position = center(self.screen());
+        }
+
+        let screen = self.screen();
+        let (x, y) = (self.position.0 + dx, self.position.1 + dy);
+
+        // Locally the pointer leaves through the configured edge and on the
+        // server's screen it comes back through the opposite one
+        let exit = if self.on_remote {
+            opposite(self.edge)
+        } else {
+            self.edge
+        };
+        let overshoot = match exit {
+            ScreenEdge::Left => -x,
+            ScreenEdge::Right => x - screen.0,
+            ScreenEdge::Top => -y,
+            ScreenEdge::Bottom => y - screen.1,
+        };
+
+        if overshoot <= 0.0 {
+            self.position = (x.clamp(0.0, screen.0), y.clamp(0.0, screen.1));
+            return None;
+        }
+
+        // Keep the pointer at the same relative spot along the edge, and carry
+        // the part of the motion past the edge onto the other screen
+        self.on_remote = !self.on_remote;
+        let next = self.screen();
+        let along_x = x.clamp(0.0, screen.0) / screen.0.max(1.0) * next.0;
+        let along_y = y.clamp(0.0, screen.1) / screen.1.max(1.0) * next.1;
+        self.position = match opposite(exit) {
+            ScreenEdge::Left => (overshoot.min(next.0), along_y),
+            ScreenEdge::Right => ((next.0 - overshoot).max(0.0), along_y),
+            ScreenEdge::Top => (along_x, overshoot.min(next.1)),
+            ScreenEdge::Bottom => (along_x, (next.1 - overshoot).max(0.0)),
+        };
+
+        Some(if self.on_remote {
+            EdgeCrossing::Leave {
+                entry: (
+                    normalize(self.position.0, next.0),
+                    normalize(self.position.1, next.1),
+                ),
+            }
+        } else {
+            EdgeCrossing::Return
+        })
+    }
+
+    /// Follow the pointer over a server screen of `size` from now on, e.g.
+    /// the one the server reported instead of the configured one
+    pub fn set_remote_screen(&mut self, size: ScreenSize) {
+        self.remote = screen_extent(size);
+        if self.on_remote {
+            self.position = (
+                self.position.0.min(self.remote.0),
+                self.position.1.min(self.remote.1),
+            );
+        }
+    }
+
+    /// Extent of the screen the pointer is on
+    fn screen(&self) -> (f64, f64) {
+        if self.on_remote {
+            self.remote
+        } else {
+            self.local
+        }
+    }
+}
+
+/// Largest coordinate on each axis of a screen
+fn screen_extent(size: ScreenSize) -> (f64, f64) {
+    (
+        f64::from(size.width.max(1) - 1),
+        f64::from(size.height.max(1) - 1),
+    )
+}
+
+fn center(extent: (f64, f64)) -> (f64, f64) {
+    (extent.0 / 2.0, extent.1 / 2.0)
+}
+
+fn opposite(edge: ScreenEdge) -> ScreenEdge {
+    match edge {
+        ScreenEdge::Left => ScreenEdge::Right,
+        ScreenEdge::Right => ScreenEdge::Left,
+        ScreenEdge::Top => ScreenEdge::Bottom,
+        ScreenEdge::Bottom => ScreenEdge::Top,
+    }
+}
+
+/// Scale a coordinate within `0..=extent` to the protocol's absolute range
+fn normalize(value: f64, extent: f64) -> i32 {
+    if extent <= 0.0 {
+        return 0;
+    }
+    (value / extent * f64::from(ABSOLUTE_AXIS_MAX)).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Server screen to the right, twice the size of the local one
+    fn edge_switch() -> EdgeSwitch {
+        EdgeSwitch::new(&EdgeSwitchConfig {
+            enabled: true,
+            edge: ScreenEdge::Right,
+            local_screen: ScreenSize {
+                width: 1001,
+                height: 501,
+            },
+            remote_screen: ScreenSize {
+                width: 2001,
+                height: 1001,
+            },
+        })
+    }
+
+    #[test]
+    fn crossing_the_edge_enters_the_server_screen_at_the_same_height() {
+        let mut edge = edge_switch();
+
+        // From the center to 100 pixels short of the right edge
+        assert_eq!(edge.motion(400.0, 0.0, false), None);
+        assert_eq!(
+            edge.motion(150.0, 0.0, false),
+            Some(EdgeCrossing::Leave {
+                entry: (normalize(50.0, 2000.0), normalize(500.0, 1000.0)),
+            })
+        );
+    }
+
+    #[test]
+    fn pointer_returns_through_the_opposite_edge() {
+        let mut edge = edge_switch();
+        edge.motion(550.0, 0.0, false);
+
+        // 50 pixels in from the server screen's left edge
+        assert_eq!(edge.motion(-40.0, 0.0, true), None);
+        assert_eq!(edge.motion(-30.0, 0.0, true), Some(EdgeCrossing::Return));
+        // Back on the local screen 20 pixels short of the edge
+        assert_eq!(edge.motion(19.0, 0.0, false), None);
+        assert!(matches!(
+            edge.motion(2.0, 0.0, false),
+            Some(EdgeCrossing::Leave { .. })
+        ));
+    }
+
+    #[test]
+    fn other_edges_and_pushing_against_them_do_not_cross() {
+        let mut edge = edge_switch();
+
+        assert_eq!(edge.motion(-5000.0, 0.0, false), None);
+        assert_eq!(edge.motion(0.0, 5000.0, false), None);
+        // Pushing left kept the pointer at x = 0
+        assert_eq!(edge.motion(1000.0, 0.0, false), None);
+        assert!(edge.motion(1.0, 0.0, false).is_some());
+    }
+
+    #[test]
+    fn toggling_relay_another_way_recenters_the_pointer() {
+        let mut edge = edge_switch();
+        edge.motion(450.0, 0.0, false);
+
+        // Relay went on with the toggle key, so the server screen's center
+        // is assumed and 1000 pixels to its left is the edge
+        assert_eq!(edge.motion(-999.0, 0.0, true), None);
+        assert_eq!(edge.motion(-2.0, 0.0, true), Some(EdgeCrossing::Return));
+    }
+}
@@ -0,0 +1,128 @@
+use asteria_core::config::DebugNetworkConfig;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Makes a working link behave like a lossy, laggy one, to reproduce what
+/// reconnects and the server's jitter buffer have to cope with.
+///
+/// Drops and delays come from a seeded generator, so a run with the same seed
+/// and the same packets misbehaves the same way.
+pub struct FlakyLink {
+    drop_rate: f64,
+    extra_latency: Duration,
+    jitter_ms: u64,
+    rng: StdRng,
+}
+
+impl FlakyLink {
+    /// Simulation described by `config`, `None` when it changes nothing
+    pub fn new(config: &DebugNetworkConfig) -> Option<Self> {
+        let drop_rate = f64::from(config.drop_rate).clamp(0.0, 1.0);
+        if drop_rate == 0.0 && config.extra_latency_ms == 0 && config.jitter_ms == 0 {
+            return None;
+        }
+
+        let seed = config.seed.unwrap_or_else(rand::random);
+        warn!(
+            "Simulating a flaky network: dropping {:.0}% of packets and delaying them by {}ms plus up to {}ms (seed {})",
+            drop_rate * 100.0,
+            config.extra_latency_ms,
+            config.jitter_ms,
+            seed
+        );
+        Some(Self {
+            drop_rate,
+            extra_latency: Duration::from_millis(config.extra_latency_ms),
+            jitter_ms: config.jitter_ms,
+            rng: StdRng::seed_from_u64(seed),
+        })
+    }
+
+    /// Whether the next packet is lost
+    pub fn drops_next(&mut self) -> bool {
+        self.drop_rate > 0.0 && self.rng.random_bool(self.drop_rate)
+    }
+
+    /// How much longer a packet captured at `timestamp`, in milliseconds since
+    /// the Unix epoch, is held back before it is sent.
+    ///
+    /// The delay counts from capture rather than from now, so packets that
+    /// queued up behind a delayed one aren't delayed again on top of it.
+    pub fn remaining_delay(&mut self, timestamp: u64) -> Duration {
+        let jitter = if self.jitter_ms > 0 {
+            self.rng.random_range(0..=self.jitter_ms)
+        } else {
+            0
+        };
+        let send_at =
+            Duration::from_millis(timestamp) + self.extra_latency + Duration::from_millis(jitter);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        send_at.saturating_sub(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(drop_rate: f32, seed: u64) -> FlakyLink {
+        FlakyLink::new(&DebugNetworkConfig {
+            drop_rate,
+            extra_latency_ms: 0,
+            jitter_ms: 0,
+            seed: Some(seed),
+        })
+        .unwrap()
+    }
+
+    fn drops(link: &mut FlakyLink, packets: usize) -> Vec<bool> {
+        (0..packets).map(|_| link.drops_next()).collect()
+    }
+
+    #[test]
+    fn same_seed_drops_the_same_packets() {
+        let first = drops(&mut link(0.3, 42), 100);
+        let second = drops(&mut link(0.3, 42), 100);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn drop_rate_is_roughly_kept() {
+        let dropped = drops(&mut link(0.3, 7), 10_000)
+            .into_iter()
+            .filter(|&dropped| dropped)
+            .count();
+        assert!((2_700..=3_300).contains(&dropped), "dropped {}", dropped);
+    }
+
+    #[test]
+    fn link_without_loss_or_delay_is_not_simulated() {
+        assert!(FlakyLink::new(&DebugNetworkConfig::default()).is_none());
+        assert!(
+            drops(&mut link(1.0, 1), 10)
+                .into_iter()
+                .all(|dropped| dropped)
+        );
+    }
+
+    #[test]
+    fn delay_counts_from_capture() {
+        let mut link = FlakyLink::new(&DebugNetworkConfig {
+            extra_latency_ms: 50,
+            ..DebugNetworkConfig::default()
+        })
+        .unwrap();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        assert!(link.remaining_delay(now) <= Duration::from_millis(50));
+        // Captured long enough ago to be due already
+        assert_eq!(link.remaining_delay(now - 1_000), Duration::ZERO);
+    }
+}
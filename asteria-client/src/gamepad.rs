@@ -0,0 +1,304 @@
+use anyhow::{Context, Result};
+use asteria_core::protocol::{
+    GAMEPAD_AXIS_MAX, InputEventType, Message, Packet, gamepad_axes, gamepad_buttons,
+};
+use libc::O_NONBLOCK;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::os::unix::{fs::OpenOptionsExt, io::AsRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::{RwLock, mpsc};
+use tracing::{debug, info, warn};
+
+use crate::input::{RelayState, decode_device_name};
+
+// Linux input event ioctl constants
+const EVIOCGBIT_KEY: u64 = 0x80604521;
+const EVIOCGNAME: u64 = 0x80ff4506;
+/// `EVIOCGABS(0)`, the axis code is added for other axes
+const EVIOCGABS: u64 = 0x80184540;
+
+const EV_KEY: u16 = 0x01;
+const EV_ABS: u16 = 0x03;
+
+/// Key code whose presence marks a device as a gamepad
+const BTN_GAMEPAD: u16 = gamepad_buttons::SOUTH;
+
+/// evdev absolute axes of a gamepad and the protocol axes they map to
+const GAMEPAD_AXES: [(u16, u8); 8] = [
+    (0x00, gamepad_axes::LEFT_X),        // ABS_X
+    (0x01, gamepad_axes::LEFT_Y),        // ABS_Y
+    (0x03, gamepad_axes::RIGHT_X),       // ABS_RX
+    (0x04, gamepad_axes::RIGHT_Y),       // ABS_RY
+    (0x02, gamepad_axes::LEFT_TRIGGER),  // ABS_Z
+    (0x05, gamepad_axes::RIGHT_TRIGGER), // ABS_RZ
+    (0x10, gamepad_axes::DPAD_X),        // ABS_HAT0X
+    (0x11, gamepad_axes::DPAD_Y),        // ABS_HAT0Y
+];
+
+const EVENT_SIZE: usize = std::mem::size_of::<libc::input_event>();
+
+/// Game controller read straight from evdev, since libinput doesn't handle them
+pub struct Gamepad {
+    path: PathBuf,
+    name: String,
+    device: AsyncFd<File>,
+    /// Reported range of each axis, keyed by evdev axis code
+    ranges: HashMap<u16, (i32, i32)>,
+    /// Buttons relayed as pressed and not released yet
+    pressed: HashSet<u16>,
+}
+
+impl Gamepad {
+    /// Open the device at `path` if it is a gamepad
+    fn open(path: &Path) -> Result<Option<Self>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(O_NONBLOCK)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let fd = file.as_raw_fd();
+
+        let mut key_bits = [0u8; 96];
+        let key_result = unsafe { libc::ioctl(fd, EVIOCGBIT_KEY, key_bits.as_mut_ptr()) };
+        let index = usize::from(BTN_GAMEPAD);
+        if key_result < 0 || key_bits[index / 8] & (1 << (index % 8)) == 0 {
+            return Ok(None);
+        }
+
+        let mut name_buf = [0u8; 256];
+        let name_result = unsafe { libc::ioctl(fd, EVIOCGNAME, name_buf.as_mut_ptr()) };
+        let name = if name_result > 0 {
+            decode_device_name(&name_buf[..name_result as usize])
+        } else {
+            path.display().to_string()
+        };
+
+        let mut ranges = HashMap::new();
+        for (code, _) in GAMEPAD_AXES {
+            let mut info: libc::input_absinfo = unsafe { std::mem::zeroed() };
+            let result = unsafe { libc::ioctl(fd, EVIOCGABS + u64::from(code), &mut info) };
+            if result >= 0 && info.maximum > info.minimum {
+                ranges.insert(code, (info.minimum, info.maximum));
+            }
+        }
+
+        Ok(Some(Self {
+            path: path.to_path_buf(),
+            name,
+            device: AsyncFd::new(file)?,
+            ranges,
+            pressed: HashSet::new(),
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Relay the controller's input while relay is enabled, until it is unplugged
+    /// or the packet channel closes
+    pub async fn relay(
+        mut self,
+        relay_state: Arc<RwLock<RelayState>>,
+        packet_sender: mpsc::Sender<Packet>,
+    ) {
+        let mut buffer = [0u8; EVENT_SIZE * 64];
+
+        loop {
+            let read = match self.device.readable().await {
+                Ok(mut guard) => match guard.try_io(|device| device.get_ref().read(&mut buffer)) {
+                    Ok(read) => read,
+                    Err(_would_block) => continue,
+                },
+                Err(e) => Err(e),
+            };
+
+            let length = match read {
+                Ok(0) => break,
+                Ok(length) => length,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    debug!("Reading {} failed: {}", self.path.display(), e);
+                    break;
+                }
+            };
+
+            let relay_enabled = relay_state.read().await.relay_enabled;
+            let events: Vec<InputEventType> = if relay_enabled {
+                buffer[..length]
+                    .chunks_exact(EVENT_SIZE)
+                    .filter_map(|chunk| {
+                        // The kernel hands out whole, but not necessarily aligned, events
+                        let event: libc::input_event =
+                            unsafe { std::ptr::read_unaligned(chunk.as_ptr().cast()) };
+                        self.convert_event(&event)
+                    })
+                    .collect()
+            } else {
+                // Don't leave buttons held on the server once relay is turned off
+                self.release_pressed()
+            };
+
+            for event in events {
+                let packet = Packet::new(Message::InputEventTyped(event));
+                if packet_sender.send(packet).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        info!("Gamepad {} disconnected", self.name);
+    }
+
+    fn convert_event(&mut self, event: &libc::input_event) -> Option<InputEventType> {
+        match event.type_ {
+            EV_KEY if is_gamepad_button(event.code) => {
+                // Controllers don't autorepeat, but ignore repeats all the same
+                let pressed = match event.value {
+                    0 => false,
+                    1 => true,
+                    _ => return None,
+                };
+
+                if pressed {
+                    self.pressed.insert(event.code);
+                } else {
+                    self.pressed.remove(&event.code);
+                }
+                Some(InputEventType::GamepadButton {
+                    button: event.code,
+                    pressed,
+                })
+            }
+            EV_ABS => {
+                let (_, axis) = GAMEPAD_AXES.iter().find(|(code, _)| *code == event.code)?;
+                let range = self.ranges.get(&event.code).copied()?;
+                Some(InputEventType::GamepadAxis {
+                    axis: *axis,
+                    value: normalize_axis(*axis, event.value, range),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn release_pressed(&mut self) -> Vec<InputEventType> {
+        self.pressed
+            .drain()
+            .map(|button| InputEventType::GamepadButton {
+                button,
+                pressed: false,
+            })
+            .collect()
+    }
+}
+
+/// Find the gamepads currently plugged in
+pub fn find_gamepads() -> Vec<Gamepad> {
+    let entries = match std::fs::read_dir("/dev/input/") {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Failed to list input devices: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("event"))
+        })
+        .collect();
+    paths.sort();
+
+    paths
+        .iter()
+        .filter_map(|path| match Gamepad::open(path) {
+            Ok(gamepad) => gamepad,
+            Err(e) => {
+                debug!("Skipping {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn is_gamepad_button(code: u16) -> bool {
+    matches!(
+        code,
+        gamepad_buttons::SOUTH..=gamepad_buttons::RIGHT_THUMB
+            | gamepad_buttons::DPAD_UP..=gamepad_buttons::DPAD_RIGHT
+    )
+}
+
+/// Scale a raw axis value within `(min, max)` to the protocol's range for `axis`
+fn normalize_axis(axis: u8, value: i32, (min, max): (i32, i32)) -> i32 {
+    let ratio = (value.clamp(min, max) - min) as f64 / (max - min) as f64;
+    match axis {
+        gamepad_axes::DPAD_X | gamepad_axes::DPAD_Y => value.signum(),
+        gamepad_axes::LEFT_TRIGGER | gamepad_axes::RIGHT_TRIGGER => {
+            (ratio * GAMEPAD_AXIS_MAX as f64).round() as i32
+        }
+        _ => ((ratio * 2.0 - 1.0) * GAMEPAD_AXIS_MAX as f64).round() as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_face_shoulder_and_dpad_buttons_are_relayed() {
+        assert!(is_gamepad_button(gamepad_buttons::SOUTH));
+        assert!(is_gamepad_button(gamepad_buttons::RIGHT_THUMB));
+        assert!(is_gamepad_button(gamepad_buttons::DPAD_RIGHT));
+        // BTN_LEFT, a mouse button
+        assert!(!is_gamepad_button(0x110));
+        // KEY_A
+        assert!(!is_gamepad_button(0x1e));
+    }
+
+    #[test]
+    fn sticks_are_scaled_around_the_center() {
+        let range = (0, 255);
+        assert_eq!(
+            normalize_axis(gamepad_axes::LEFT_X, 0, range),
+            -GAMEPAD_AXIS_MAX
+        );
+        assert_eq!(
+            normalize_axis(gamepad_axes::LEFT_X, 255, range),
+            GAMEPAD_AXIS_MAX
+        );
+        assert_eq!(
+            normalize_axis(gamepad_axes::RIGHT_Y, 300, range),
+            GAMEPAD_AXIS_MAX
+        );
+
+        let signed = (-32768, 32767);
+        assert_eq!(normalize_axis(gamepad_axes::LEFT_Y, 0, signed), 0);
+    }
+
+    #[test]
+    fn triggers_start_at_zero() {
+        let range = (0, 1023);
+        assert_eq!(normalize_axis(gamepad_axes::LEFT_TRIGGER, 0, range), 0);
+        assert_eq!(
+            normalize_axis(gamepad_axes::RIGHT_TRIGGER, 1023, range),
+            GAMEPAD_AXIS_MAX
+        );
+    }
+
+    #[test]
+    fn dpad_axes_keep_only_the_direction() {
+        let range = (-1, 1);
+        assert_eq!(normalize_axis(gamepad_axes::DPAD_X, -1, range), -1);
+        assert_eq!(normalize_axis(gamepad_axes::DPAD_Y, 0, range), 0);
+        assert_eq!(normalize_axis(gamepad_axes::DPAD_Y, 1, range), 1);
+    }
+}
@@ -1,35 +1,76 @@
-use anyhow::Result;
-use asteria_core::protocol::{InputEventType, Message, Packet};
+use anyhow::{Result, anyhow, bail};
+use asteria_core::{
+    config::{CaptureConfig, EventCategory, GrabConfig, GrabOverride, ScreenSize},
+    protocol::{
+        ABSOLUTE_AXIS_MAX, GestureKind, GesturePhase, InputEventType, LockState, Message, Packet,
+        ScreenInfo, mouse_buttons, normalize_absolute,
+    },
+};
 use input::{
-    Libinput, LibinputInterface,
+    AsRaw, Device, Libinput, LibinputInterface,
     event::{
-        Event,
+        DeviceEvent, Event, EventTrait,
+        gesture::{
+            GestureEndEvent, GestureEvent, GestureEventCoordinates, GestureEventTrait,
+            GesturePinchEvent, GesturePinchEventTrait, GestureSwipeEvent,
+        },
         keyboard::{KeyState, KeyboardEvent, KeyboardEventTrait},
         pointer::{Axis, ButtonState, PointerEvent, PointerScrollEvent},
     },
 };
-use libc::{O_RDONLY, O_RDWR, O_WRONLY};
-use std::collections::HashMap;
+use libc::{O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY};
+use serde::Serialize;
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ffi::CStr;
 use std::fs::{File, OpenOptions};
-use std::os::unix::{fs::OpenOptionsExt, io::OwnedFd};
-use std::path::Path;
+use std::future::poll_fn;
+use std::os::unix::{
+    fs::OpenOptionsExt,
+    io::{AsRawFd, OwnedFd, RawFd},
+};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
 use std::sync::Arc;
-use tokio::sync::{RwLock, mpsc};
-use tracing::{debug, error, info, warn};
-
-use crate::network::NetworkClient;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{Notify, RwLock, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{Span, debug, error, field, info, instrument, warn};
+
+use crate::batch::EventBatcher;
+use crate::clipboard::ClipboardMonitor;
+use crate::control::serve_control_socket;
+use crate::edge::{EdgeCrossing, EdgeSwitch};
+use crate::gamepad::find_gamepads;
+use crate::monitor::log_packets;
+use crate::network::{LinkValidator, NetworkClient, NetworkCounters};
+use crate::persist::{SavedRelayState, load_relay_state, relay_state_path, save_relay_state};
+use crate::queue::PacketQueue;
+use crate::reload::ConfigReloader;
+use crate::scroll::ScrollAccumulator;
+use crate::stats::CaptureStats;
+use crate::toggle::ToggleCombo;
 
 // Linux input event ioctl constants
-const EVIOCGRAB: u64 = 0x40044590;
 const EVIOCGBIT_KEY: u64 = 0x80604521;
 const EVIOCGBIT_REL: u64 = 0x80604522;
 const EVIOCGBIT_ABS: u64 = 0x80604523;
 const EVIOCGNAME: u64 = 0x80ff4506;
+const EVIOCGBIT_EV: u64 = 0x80084520;
+const EVIOCGLED: u64 = 0x80084519;
+
+// LED bits reported by EVIOCGLED
+const LED_NUML: u8 = 0x00;
+const LED_CAPSL: u8 = 0x01;
+const LED_SCROLLL: u8 = 0x02;
 
 // Event type constants
-const EV_KEY: u8 = 0x01;
-const EV_REL: u8 = 0x02;
-const EV_ABS: u8 = 0x03;
+const EV_LED: u8 = 0x11;
 
 // Key/button bit masks
 const REL_X: u8 = 0x00;
@@ -37,6 +78,22 @@ const REL_Y: u8 = 0x01;
 const ABS_X: u8 = 0x00;
 const ABS_Y: u8 = 0x01;
 
+/// Wall clock time that passes while the monotonic clock stands still means the system slept
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Where systemd-logind lists the seats it knows
+const SEATS_DIR: &str = "/run/systemd/seats";
+
+/// Consecutive libinput dispatch failures after which the seat is considered lost
+const MAX_DISPATCH_FAILURES: u32 = 100;
+
+/// Packets buffered between the capture queue and the relay; the queue itself
+/// is sized by `CaptureConfig::queue_capacity`
+const RELAY_CHANNEL_CAPACITY: usize = 64;
+
+/// How long shutdown waits for the relay to tell the server it is leaving
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[allow(dead_code)]
 struct Interface;
 
@@ -44,8 +101,8 @@ impl LibinputInterface for Interface {
     fn open_restricted(&mut self, path: &Path, flags: i32) -> Result<OwnedFd, i32> {
         OpenOptions::new()
             .custom_flags(flags)
-            .read((flags & O_RDONLY != 0) | (flags & O_RDWR != 0))
-            .write((flags & O_WRONLY != 0) | (flags & O_RDWR != 0))
+            .read((flags & O_ACCMODE == O_RDONLY) | (flags & O_ACCMODE == O_RDWR))
+            .write((flags & O_ACCMODE == O_WRONLY) | (flags & O_ACCMODE == O_RDWR))
             .open(path)
             .map(|file| file.into())
             .map_err(|err| err.raw_os_error().unwrap())
@@ -59,53 +116,426 @@ impl LibinputInterface for Interface {
 /// Input capture system that monitors Linux input events
 pub struct InputCapture {
     libinput: Libinput,
-    toggle_key: u32,
+    toggle_combo: ToggleCombo,
+    /// Forces relay off and releases everything, checked before anything else
+    panic_combo: Option<ToggleCombo>,
+    config: CaptureConfig,
     relay_state: Arc<RwLock<RelayState>>,
     grabbed_devices: HashMap<String, OwnedFd>,
+    /// Names of the devices libinput reported, by device path
+    device_names: HashMap<String, String>,
+    /// Number of grabbed devices, shared with the status line
+    grabbed_count: Arc<AtomicUsize>,
+    key_repeat: Option<KeyRepeat>,
+    /// Flipped by SIGUSR1 to pause or resume capture
+    pause_requested: Arc<AtomicBool>,
+    /// Set by the control socket to toggle relay from outside
+    toggle_requested: Arc<AtomicBool>,
+    /// Set by SIGUSR2 to log capture and network statistics
+    stats_requested: Arc<AtomicBool>,
+    /// Set by SIGHUP to load the config file again
+    reload_requested: Arc<AtomicBool>,
+    /// Loads the config file again, if the client knows where it is
+    config_reloader: Option<ConfigReloader>,
+    /// Traffic of the relay task, once it is started
+    network_counters: Option<Arc<NetworkCounters>>,
+    /// Where to serve the control socket, if enabled
+    control_socket: Option<PathBuf>,
+    /// Device name patterns overriding which devices are grabbed
+    grab: GrabConfig,
+    /// Whether relay was enabled when capture was paused
+    resume_relay: bool,
+    /// Clipboard watcher started alongside the relay, if clipboard sync is on
+    clipboard_monitor: Option<ClipboardMonitor>,
+    /// Events captured versus relayed, per category
+    stats: CaptureStats,
+    /// When capture statistics were last logged
+    stats_logged_at: Instant,
+    /// When the keyboard lock state was last checked
+    lock_state_checked_at: Instant,
+    /// Lock state last sent to the server, cleared when relay is disabled
+    sent_lock_state: Option<LockState>,
+    /// When the server was last told to reset its input state
+    input_reset_at: Instant,
+    /// When an event was last relayed, or relay was enabled
+    relay_active_at: Instant,
+    /// Keys and mouse buttons relayed as pressed and not released yet
+    pressed: PressedInputs,
+    /// Relative motion accumulated since the last move was sent, including
+    /// the sub-pixel remainder the previous move couldn't carry
+    pending_motion: (f64, f64),
+    /// Latency offset of the device the pending motion came from, in milliseconds
+    motion_latency_offset: u64,
+    /// When accumulated motion was last sent
+    motion_flushed_at: Instant,
+    /// Scroll accumulated until it adds up to whole steps
+    scroll: ScrollAccumulator,
+    /// Follows the pointer to the configured screen edge, if edge switching is on
+    edge_switch: Option<EdgeSwitch>,
+    /// Screen the server reported, which edge switching follows instead of the configured one
+    server_screen: Option<watch::Receiver<Option<ScreenInfo>>>,
+    /// Where the relay state is remembered across restarts, if it is
+    relay_state_file: Option<PathBuf>,
+    /// Typed events waiting to be sent together
+    batcher: EventBatcher,
+    /// Notices when the system comes back from suspend
+    resume_detector: ResumeDetector,
+    /// Checks the server answers before relay is toggled on, if enabled
+    link_validator: Option<LinkValidator>,
+    /// Queue to the running relay task, kept to send the final disconnect
+    packet_queue: Option<PacketQueue>,
+    /// Relay task started by `start_and_relay`
+    network_task: Option<JoinHandle<Result<()>>>,
+    /// Log captured events instead of relaying them, without grabbing devices
+    monitor_only: bool,
+}
+
+/// Detects suspend by comparing wall clock and monotonic time between checks,
+/// since the monotonic clock doesn't advance while the system sleeps
+struct ResumeDetector {
+    wall: SystemTime,
+    monotonic: Instant,
+}
+
+impl ResumeDetector {
+    fn new() -> Self {
+        Self {
+            wall: SystemTime::now(),
+            monotonic: Instant::now(),
+        }
+    }
+
+    /// Check whether the system slept since the previous check, returning how long
+    fn check(&mut self) -> Option<Duration> {
+        self.check_at(SystemTime::now(), Instant::now())
+    }
+
+    /// [`check`](Self::check) with the current wall clock and monotonic times
+    fn check_at(&mut self, wall: SystemTime, monotonic: Instant) -> Option<Duration> {
+        let wall_elapsed = wall.duration_since(self.wall).unwrap_or_default();
+        let slept = wall_elapsed.saturating_sub(monotonic.duration_since(self.monotonic));
+
+        self.wall = wall;
+        self.monotonic = monotonic;
+        (slept > SUSPEND_THRESHOLD).then_some(slept)
+    }
+}
+
+/// Synthesized autorepeat for the most recently pressed key
+struct KeyRepeat {
+    key_code: u16,
+    next_at: Instant,
+}
+
+impl KeyRepeat {
+    /// Start repeating `key_code`, pressed at `now`, once `delay` passed
+    fn new(key_code: u16, delay: Duration, now: Instant) -> Self {
+        Self {
+            key_code,
+            next_at: now + delay,
+        }
+    }
+
+    /// Whether a repeat is due at `now`, scheduling the next one `interval` later
+    fn due(&mut self, now: Instant, interval: Duration) -> bool {
+        if now < self.next_at {
+            return false;
+        }
+        self.next_at = now + interval;
+        true
+    }
+}
+
+/// Device libinput reported added or removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HotplugChange {
+    Added,
+    Removed,
+}
+
+/// What a hotplug event leaves for the capture to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HotplugOutcome {
+    /// The added device should be grabbed if it is one to grab
+    Grab,
+    /// The removed device was grabbed and its grab dropped
+    Released,
+    Unchanged,
+}
+
+/// Keys and mouse buttons relayed as pressed and not released yet
+#[derive(Debug, Default)]
+struct PressedInputs {
+    keys: HashSet<u16>,
+    buttons: HashSet<u8>,
+}
+
+impl PressedInputs {
+    /// Keep track of which keys and buttons relayed events leave held down.
+    ///
+    /// Returns false for a press of something already held or a release of
+    /// something that isn't, which change nothing and are not relayed.
+    fn track(&mut self, event: &InputEventType) -> bool {
+        match *event {
+            InputEventType::KeyPress { key_code } => self.keys.insert(key_code),
+            InputEventType::KeyRelease { key_code } => self.keys.remove(&key_code),
+            InputEventType::MouseButton { button, pressed } => {
+                if pressed {
+                    self.buttons.insert(button)
+                } else {
+                    self.buttons.remove(&button)
+                }
+            }
+            _ => true,
+        }
+    }
+
+    /// A release for every key and button held down
+    fn releases(&self) -> Vec<InputEventType> {
+        let keys = self
+            .keys
+            .iter()
+            .map(|&key_code| InputEventType::KeyRelease { key_code });
+        let buttons = self
+            .buttons
+            .iter()
+            .map(|&button| InputEventType::MouseButton {
+                button,
+                pressed: false,
+            });
+        keys.chain(buttons).collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.buttons.is_empty()
+    }
+}
+
+/// Setting of the capture config that decides whether an event is relayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RelaySwitch {
+    Keyboard,
+    Mouse,
+    Scroll,
+    /// Relayed whatever the settings
+    Always,
+}
+
+/// What the capture loop does with an event while capture isn't paused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EventRoute {
+    /// Dropped before any other work
+    Skip,
+    /// Checked against the toggle key but not relayed
+    ToggleKeyOnly,
+    /// Checked against the toggle key and relayed
+    Relay,
+}
+
+/// Change of capture state a pause request calls for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseTransition {
+    Unchanged,
+    /// Pause, turning relay off for the pause if it was on
+    Pause {
+        disable_relay: bool,
+    },
+    /// Resume, turning relay back on if the pause turned it off
+    Resume {
+        enable_relay: bool,
+    },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct RelayState {
     pub relay_enabled: bool,
     pub suppress_local_input: bool,
+    pub paused: bool,
 }
 
-impl Default for RelayState {
-    fn default() -> Self {
-        Self {
-            relay_enabled: false,
-            suppress_local_input: false,
+/// Devices the relay tried to open for tracking and how many of them it got.
+/// Devices are opened but not exclusively grabbed, since that would also cut
+/// libinput off from the toggle key
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GrabSummary {
+    pub opened: usize,
+    /// Paths of the devices that could not be opened
+    pub failed: Vec<String>,
+}
+
+impl GrabSummary {
+    /// Note the outcome of opening `device_path`
+    pub fn record(&mut self, device_path: &str, opened: bool) {
+        if opened {
+            self.opened += 1;
+        } else {
+            self.failed.push(device_path.to_string());
         }
     }
+
+    /// Devices an open was attempted on
+    pub fn attempted(&self) -> usize {
+        self.opened + self.failed.len()
+    }
 }
 
 impl InputCapture {
     pub fn new() -> Result<Self> {
-        Self::new_with_toggle_key(0x1D) // Default to Left Ctrl (KEY_LEFTCTRL)
+        // Default to Left Ctrl (KEY_LEFTCTRL)
+        Self::new_with_toggle_key(ToggleCombo::new(vec![0x1D]), CaptureConfig::default())
     }
 
-    pub fn new_with_toggle_key(toggle_key: u32) -> Result<Self> {
-        let mut libinput = Libinput::new_with_udev(Interface);
-
-        if let Err(e) = libinput.udev_assign_seat("seat0") {
-            error!("Failed to assign seat: {:?}", e);
-            return Err(anyhow::anyhow!("Failed to assign seat: {:?}", e));
+    pub fn new_with_toggle_key(toggle_combo: ToggleCombo, config: CaptureConfig) -> Result<Self> {
+        let libinput = create_libinput(&config.seat)?;
+        info!("Toggle key set to: {}", toggle_combo);
+        let panic_combo = panic_combo(&config)?;
+        if let Some(panic_combo) = &panic_combo {
+            info!("Panic key set to: {}", panic_combo);
+        }
+        for remap in &config.key_remap {
+            if toggle_combo.contains(remap.from) {
+                warn!(
+                    "Key {} is remapped to {} but belongs to the toggle key, which keeps matching the physical key",
+                    remap.from, remap.to
+                );
+            }
         }
-
-        info!("Successfully initialized libinput and assigned seat");
-        info!("Toggle key set to: 0x{:02x}", toggle_key);
 
         Ok(Self {
             libinput,
-            toggle_key,
+            toggle_combo,
+            panic_combo,
             relay_state: Arc::new(RwLock::new(RelayState::default())),
             grabbed_devices: HashMap::new(),
+            device_names: HashMap::new(),
+            grabbed_count: Arc::new(AtomicUsize::new(0)),
+            key_repeat: None,
+            pause_requested: Arc::new(AtomicBool::new(false)),
+            toggle_requested: Arc::new(AtomicBool::new(false)),
+            stats_requested: Arc::new(AtomicBool::new(false)),
+            reload_requested: Arc::new(AtomicBool::new(false)),
+            config_reloader: None,
+            network_counters: None,
+            control_socket: None,
+            grab: GrabConfig::default(),
+            resume_relay: false,
+            clipboard_monitor: None,
+            stats: CaptureStats::default(),
+            stats_logged_at: Instant::now(),
+            lock_state_checked_at: Instant::now(),
+            input_reset_at: Instant::now(),
+            sent_lock_state: None,
+            relay_active_at: Instant::now(),
+            pressed: PressedInputs::default(),
+            pending_motion: (0.0, 0.0),
+            motion_latency_offset: 0,
+            motion_flushed_at: Instant::now(),
+            scroll: ScrollAccumulator::new(&config),
+            edge_switch: config
+                .edge_switch
+                .enabled
+                .then(|| EdgeSwitch::new(&config.edge_switch)),
+            server_screen: None,
+            relay_state_file: if config.remember_relay_state {
+                relay_state_path()
+                    .inspect_err(|e| warn!("Not remembering the relay state: {}", e))
+                    .ok()
+            } else {
+                None
+            },
+            batcher: event_batcher(&config),
+            config,
+            resume_detector: ResumeDetector::new(),
+            link_validator: None,
+            packet_queue: None,
+            network_task: None,
+            monitor_only: false,
         })
     }
 
-    /// Get the current relay state
-    pub async fn get_relay_state(&self) -> RelayState {
-        self.relay_state.read().await.clone()
+    /// Statistics of events captured and relayed so far
+    pub fn capture_stats(&self) -> &CaptureStats {
+        &self.stats
+    }
+
+    /// Sync the local clipboard to the server while relaying
+    pub fn set_clipboard_monitor(&mut self, monitor: ClipboardMonitor) {
+        self.clipboard_monitor = Some(monitor);
+    }
+
+    /// Serve relay status and toggle requests on a Unix socket at `path` while relaying
+    pub fn set_control_socket(&mut self, path: PathBuf) {
+        self.control_socket = Some(path);
+    }
+
+    /// Always or never grab the devices matching the configured name patterns
+    pub fn set_grab_config(&mut self, grab: GrabConfig) {
+        self.grab = grab;
+    }
+
+    /// Load the config file again through `reloader` on SIGHUP
+    pub fn set_config_reloader(&mut self, reloader: ConfigReloader) {
+        self.config_reloader = Some(reloader);
+    }
+
+    /// Load the config file again and apply the capture settings that changed
+    pub async fn reload_config(&mut self, packet_queue: &PacketQueue) -> Result<()> {
+        let Some(reloader) = &mut self.config_reloader else {
+            warn!("No config file to reload");
+            return Ok(());
+        };
+        if let Some(config) = reloader.reload()? {
+            self.apply_capture_config(config, packet_queue)?;
+        }
+        Ok(())
+    }
+
+    /// Switch to new capture settings, rebuilding whatever was derived from
+    /// the old ones
+    fn apply_capture_config(
+        &mut self,
+        config: CaptureConfig,
+        packet_queue: &PacketQueue,
+    ) -> Result<()> {
+        let panic_combo = panic_combo(&config)?;
+
+        // Events already batched leave with the old settings
+        if let Some(packet) = self.batcher.flush() {
+            packet_queue.push(packet)?;
+        }
+        self.batcher = event_batcher(&config);
+        self.panic_combo = panic_combo;
+        self.scroll = ScrollAccumulator::new(&config);
+        self.edge_switch = config
+            .edge_switch
+            .enabled
+            .then(|| EdgeSwitch::new(&config.edge_switch));
+        self.config = config;
+        self.apply_server_screen();
+        Ok(())
+    }
+
+    /// Size the edge switch to the screen the server reported, or the
+    /// configured one while there is none
+    fn apply_server_screen(&mut self) {
+        let reported = self
+            .server_screen
+            .as_mut()
+            .and_then(|screen| *screen.borrow_and_update());
+        let size = reported.map_or(self.config.edge_switch.remote_screen, ScreenSize::from);
+        if let Some(edge_switch) = &mut self.edge_switch {
+            edge_switch.set_remote_screen(size);
+        }
+    }
+
+    /// Shared relay state, updated as relay is toggled or paused
+    pub fn relay_state_handle(&self) -> Arc<RwLock<RelayState>> {
+        Arc::clone(&self.relay_state)
+    }
+
+    /// Shared count of grabbed devices, updated as devices are grabbed and released
+    pub fn grabbed_device_count(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.grabbed_count)
     }
 
     /// Toggle the relay state
@@ -116,34 +546,182 @@ impl InputCapture {
         };
 
         if current_state {
-            // Disable relay and restore local input
+            self.disable_relay().await;
+            info!("🔄 Relay disabled - Linux input restored");
+        } else {
+            // Don't suppress local input into a link nobody is listening on
+            if let Some(validator) = &self.link_validator
+                && !validator.validate().await
             {
-                let mut state = self.relay_state.write().await;
-                state.relay_enabled = false;
-                state.suppress_local_input = false;
+                bail!("Server did not answer, staying in local control");
             }
 
-            // Release all grabbed devices
-            if let Err(e) = self.release_input_devices().await {
-                error!("Failed to release input devices: {}", e);
+            self.enable_relay().await?;
+            info!("🔄 Relay enabled - Linux input suppressed, relaying to Windows");
+        }
+
+        self.save_relay_state(!current_state);
+        Ok(())
+    }
+
+    /// Remember whether relay is enabled, if configured to. Monitor mode
+    /// doesn't count, since nothing was relayed.
+    fn save_relay_state(&self, relay_enabled: bool) {
+        if !self.monitor_only
+            && let Some(path) = &self.relay_state_file
+            && let Err(e) = save_relay_state(path, SavedRelayState { relay_enabled })
+        {
+            warn!("Failed to save the relay state: {:#}", e);
+        }
+    }
+
+    /// Enable relay again if it was enabled when the client last exited
+    async fn restore_relay_state(&mut self) {
+        let Some(path) = &self.relay_state_file else {
+            return;
+        };
+        if !load_relay_state(path).relay_enabled {
+            return;
+        }
+
+        info!("Relay was enabled when the client last exited, enabling it again");
+        if let Err(e) = self.toggle_relay().await {
+            error!("Failed to restore relay: {}", e);
+        }
+    }
+
+    /// Switch relay as the pointer crosses the configured screen edge
+    async fn cross_edge(
+        &mut self,
+        packet_queue: &PacketQueue,
+        crossing: EdgeCrossing,
+    ) -> Result<()> {
+        match crossing {
+            EdgeCrossing::Leave { entry: (x, y) } => {
+                info!("Pointer left the screen, switching to the server");
+                self.toggle_relay().await?;
+
+                // Start the server's pointer where this one left off
+                let entry = InputEventType::MouseMoveAbsolute { x, y };
+                self.relay_packet(packet_queue, Packet::new(Message::InputEventTyped(entry)))
+                    .await?;
+            }
+            EdgeCrossing::Return => {
+                info!("Pointer came back from the server");
+                self.toggle_relay().await?;
             }
+        }
+        Ok(())
+    }
 
-            info!("🔄 Relay disabled - Linux input restored");
-        } else {
-            // Grab all input devices first
-            if let Err(e) = self.grab_input_devices().await {
+    /// Grab input devices and start relaying
+    async fn enable_relay(&mut self) -> Result<()> {
+        if self.monitor_only {
+            // Local input keeps working while events are only logged
+            self.relay_state.write().await.relay_enabled = true;
+            self.relay_active_at = Instant::now();
+            return Ok(());
+        }
+
+        // Grab all input devices first
+        let summary = match self.grab_input_devices().await {
+            Ok(summary) => summary,
+            Err(e) => {
                 error!("Failed to grab input devices: {}", e);
                 return Err(e);
             }
+        };
 
-            // Enable relay and suppress local input
-            {
-                let mut state = self.relay_state.write().await;
-                state.relay_enabled = true;
-                state.suppress_local_input = true;
+        // Relaying without any device to track leaves nothing to release later
+        if summary.opened == 0 {
+            if let Err(e) = self.release_input_devices().await {
+                error!("Failed to release input devices: {}", e);
             }
+            bail!(
+                "None of the {} input devices could be opened, staying in local control",
+                summary.attempted()
+            );
+        }
+        if !summary.failed.is_empty() {
+            warn!(
+                "⚠ Only {} of {} input devices could be opened, not tracking {}",
+                summary.opened,
+                summary.attempted(),
+                summary.failed.join(", ")
+            );
+        }
 
-            info!("🔄 Relay enabled - Linux input suppressed, relaying to Windows");
+        self.relay_active_at = Instant::now();
+
+        // Enable relay and suppress local input
+        let mut state = self.relay_state.write().await;
+        state.relay_enabled = true;
+        state.suppress_local_input = true;
+
+        Ok(())
+    }
+
+    /// Stop relaying and restore local input
+    async fn disable_relay(&mut self) {
+        // Keys held while relaying, including the rest of a toggle combination,
+        // would otherwise stay down on the server
+        if let Err(e) = self.release_pressed().await {
+            error!("Failed to release held input on the server: {}", e);
+        }
+
+        {
+            let mut state = self.relay_state.write().await;
+            state.relay_enabled = false;
+            state.suppress_local_input = false;
+        }
+        self.key_repeat = None;
+        self.pending_motion = (0.0, 0.0);
+        // The server's lock state may change while it is used locally
+        self.sent_lock_state = None;
+
+        // Release all grabbed devices
+        if let Err(e) = self.release_input_devices().await {
+            error!("Failed to release input devices: {}", e);
+        }
+    }
+
+    /// Force relay off and release every grabbed device and held input,
+    /// whatever state relay, pause or a half-typed toggle combination are in
+    async fn panic(&mut self) {
+        warn!("Panic key pressed, disabling relay and releasing all devices");
+
+        // Disabled even if it seems off already, which releases devices a
+        // failed toggle may have left grabbed
+        self.disable_relay().await;
+        self.toggle_combo.reset();
+        // Resuming a pause doesn't turn relay back on
+        self.resume_relay = false;
+        self.save_relay_state(false);
+        info!("🛑 Relay disabled by the panic key - Linux input restored");
+    }
+
+    /// Pause or resume capture when a SIGUSR1 flipped the pause request
+    async fn apply_pause_request(&mut self) -> Result<()> {
+        let pause_requested = self.pause_requested.load(Ordering::SeqCst);
+        let state = self.relay_state.read().await.clone();
+
+        match pause_transition(pause_requested, &state, self.resume_relay) {
+            PauseTransition::Unchanged => {}
+            PauseTransition::Pause { disable_relay } => {
+                self.resume_relay = disable_relay;
+                if disable_relay {
+                    self.disable_relay().await;
+                }
+                self.relay_state.write().await.paused = true;
+                info!("⏸ Capture paused - Linux input restored, send SIGUSR1 again to resume");
+            }
+            PauseTransition::Resume { enable_relay } => {
+                self.relay_state.write().await.paused = false;
+                if enable_relay {
+                    self.enable_relay().await?;
+                }
+                info!("▶ Capture resumed");
+            }
         }
 
         Ok(())
@@ -153,150 +731,751 @@ impl InputCapture {
     pub async fn start_and_relay(&mut self, mut network_client: NetworkClient) -> Result<()> {
         info!("Starting input capture and relay...");
 
-        // Create a channel for packet communication
-        let (packet_sender, packet_receiver) = mpsc::channel(1000);
+        // Create a channel for packet communication, captured input reaches it
+        // through a queue that never blocks capture
+        let (packet_sender, packet_receiver) = mpsc::channel(RELAY_CHANNEL_CAPACITY);
+        let (packet_queue, forward_task) =
+            PacketQueue::forward_to(packet_sender.clone(), self.config.queue_capacity);
+
+        let reconnect = network_client.reconnect_handle();
+        self.network_counters = Some(network_client.counters());
+        self.server_screen = Some(network_client.server_screen());
+        let clipboard_received = self
+            .clipboard_monitor
+            .as_ref()
+            .map(|_| network_client.sync_clipboard());
+        if self.config.validate_link_on_enable {
+            self.link_validator = Some(network_client.link_validator(Duration::from_millis(
+                self.config.link_validation_timeout_ms,
+            )));
+        }
+
+        // Start the network relay task. It's kept on `self` from the start, so
+        // a shutdown that drops this future still finds it to disconnect.
+        self.packet_queue = Some(packet_queue.clone());
+        let (relay_done, relay_stopped) = oneshot::channel::<()>();
+        self.network_task = Some(tokio::spawn(async move {
+            let result = network_client.start_relay(packet_receiver).await;
+            drop(relay_done);
+            result
+        }));
+
+        // Listen for pause/resume and statistics requests
+        let pause_task = tokio::spawn(listen_for_pause_signal(Arc::clone(&self.pause_requested)));
+        let stats_task = tokio::spawn(listen_for_stats_signal(Arc::clone(&self.stats_requested)));
+        let reload_task =
+            tokio::spawn(listen_for_reload_signal(Arc::clone(&self.reload_requested)));
+
+        // Answer status and toggle requests from local tools
+        let control_task = self.control_socket.clone().map(|path| {
+            let relay_state = Arc::clone(&self.relay_state);
+            let toggle_requested = Arc::clone(&self.toggle_requested);
+            let packet_sender = packet_sender.clone();
+            tokio::spawn(async move {
+                if let Err(e) =
+                    serve_control_socket(&path, relay_state, toggle_requested, packet_sender).await
+                {
+                    error!("Control socket failed: {}", e);
+                }
+            })
+        });
+
+        // Start watching the clipboard if sync is enabled
+        let clipboard_task = self
+            .clipboard_monitor
+            .take()
+            .zip(clipboard_received)
+            .map(|(monitor, received)| tokio::spawn(monitor.run(packet_sender.clone(), received)));
+
+        let gamepad_tasks = self.spawn_gamepad_tasks(&packet_sender);
+
+        self.restore_relay_state().await;
+
+        // Start the input capture in the current task to avoid Send issues.
+        // Capture panicking or the relay ending stops both, so devices are
+        // never left grabbed with nothing relaying their input.
+        let input_result = tokio::select! {
+            result = watch_for_panic(self.capture_input_events(packet_queue, reconnect)) => result,
+            // Resolves once the relay task ends, however it ends
+            _ = relay_stopped => {
+                let finished = match self.network_task.take() {
+                    Some(network_task) => network_task.await,
+                    None => Ok(Ok(())),
+                };
+                let e = match finished {
+                    Ok(Ok(())) => anyhow!("Relay stopped while capture was running"),
+                    Ok(Err(e)) => anyhow!("Relay failed: {}", e),
+                    Err(e) => anyhow!("Relay task failed: {}", e),
+                };
+                error!("💥 {}, releasing all devices", e);
+                Err(e)
+            }
+        };
+        if input_result.is_err() {
+            // Local input comes back now rather than whenever the caller shuts down
+            self.disable_relay().await;
+        }
+
+        // Cancel the signal and clipboard tasks and disconnect if input capture ends
+        pause_task.abort();
+        stats_task.abort();
+        reload_task.abort();
+        for gamepad_task in gamepad_tasks {
+            gamepad_task.abort();
+        }
+        if let Some(clipboard_task) = clipboard_task {
+            clipboard_task.abort();
+        }
+        if let Some(control_task) = control_task {
+            control_task.abort();
+        }
+        self.disconnect("input capture stopped").await;
+        forward_task.abort();
 
-        // Start the network relay task
-        let network_task =
-            tokio::spawn(async move { network_client.start_relay(packet_receiver).await });
+        input_result
+    }
 
-        // Start the input capture in the current task to avoid Send issues
-        let input_result = self.capture_input_events(packet_sender).await;
+    /// Capture input events and log what would be relayed, without a server
+    /// connection and without grabbing any device. The toggle key still
+    /// switches logging on and off.
+    pub async fn start_monitor(&mut self) -> Result<()> {
+        info!("Starting input capture in monitor mode...");
+        self.monitor_only = true;
+
+        let (packet_sender, packet_receiver) = mpsc::channel(RELAY_CHANNEL_CAPACITY);
+        let (packet_queue, forward_task) =
+            PacketQueue::forward_to(packet_sender.clone(), self.config.queue_capacity);
+        let monitor_task = tokio::spawn(log_packets(packet_receiver));
+        let gamepad_tasks = self.spawn_gamepad_tasks(&packet_sender);
+
+        self.enable_relay().await?;
+        let result = self
+            .capture_input_events(packet_queue, Arc::new(Notify::new()))
+            .await;
+
+        forward_task.abort();
+        monitor_task.abort();
+        for gamepad_task in gamepad_tasks {
+            gamepad_task.abort();
+        }
+        result
+    }
 
-        // Cancel the network task if input capture ends
-        network_task.abort();
+    /// Relay the gamepads plugged in now, which libinput doesn't capture
+    fn spawn_gamepad_tasks(&self, packet_sender: &mpsc::Sender<Packet>) -> Vec<JoinHandle<()>> {
+        if !self.config.relay_gamepads {
+            return Vec::new();
+        }
 
-        // Wait for the network task to complete
-        let _ = network_task.await;
+        let gamepads = find_gamepads();
+        if gamepads.is_empty() {
+            debug!("No gamepad found");
+        }
 
-        input_result
+        gamepads
+            .into_iter()
+            .map(|gamepad| {
+                info!("Relaying gamepad {}", gamepad.name());
+                tokio::spawn(gamepad.relay(Arc::clone(&self.relay_state), packet_sender.clone()))
+            })
+            .collect()
     }
 
     /// Capture input events from libinput
-    async fn capture_input_events(&mut self, packet_sender: mpsc::Sender<Packet>) -> Result<()> {
+    async fn capture_input_events(
+        &mut self,
+        packet_queue: PacketQueue,
+        reconnect: Arc<Notify>,
+    ) -> Result<()> {
         info!("Starting input event capture loop...");
         info!(
-            "Press the toggle key (0x{:02x}) to enable/disable relay",
-            self.toggle_key
+            "Press the toggle key ({}) to enable/disable relay",
+            self.toggle_combo
         );
 
+        let mut dispatch_failures = 0;
+
         loop {
+            if let Some(slept) = self.resume_detector.check() {
+                info!("System resumed after sleeping for {:?}", slept);
+                self.reinitialize(&reconnect).await;
+                dispatch_failures = 0;
+            }
+
             // Dispatch libinput events
             if let Err(e) = self.libinput.dispatch() {
                 error!("libinput dispatch error: {:?}", e);
+                dispatch_failures += 1;
+                if dispatch_failures >= MAX_DISPATCH_FAILURES {
+                    warn!("libinput keeps failing, assuming the seat was lost");
+                    self.reinitialize(&reconnect).await;
+                    dispatch_failures = 0;
+                }
                 tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
                 continue;
             }
+            dispatch_failures = 0;
+
+            if let Err(e) = self.apply_pause_request().await {
+                error!("Failed to resume relay: {}", e);
+            }
+            let paused = self.relay_state.read().await.paused;
+
+            if self.toggle_requested.swap(false, Ordering::SeqCst) && !paused {
+                info!("Relay toggle requested over the control socket");
+                if let Err(e) = self.toggle_relay().await {
+                    error!("Failed to toggle relay: {}", e);
+                }
+            }
 
             // Process all available events
             while let Some(event) = self.libinput.next() {
-                let relay_state = self.relay_state.read().await;
+                // The panic key works whatever state capture is in
+                if let Event::Keyboard(ref keyboard_event) = event
+                    && panic_key_pressed(
+                        &mut self.panic_combo,
+                        keyboard_event.key(),
+                        keyboard_event.key_state() == KeyState::Pressed,
+                    )
+                {
+                    self.panic().await;
+                    continue;
+                }
+
+                let category = event_category(&event);
+                if let Some(category) = category {
+                    self.stats.record_captured(category);
+                }
+
+                if let Event::Device(ref device_event) = event {
+                    self.handle_hotplug(device_event).await;
+                }
+
+                // Drain but ignore everything, including the toggle key, while paused
+                if paused {
+                    continue;
+                }
+
+                // Skip disabled categories before any other work; keyboard events
+                // still reach the toggle key check below
+                let route = route_event(&self.config, category, relay_switch(&event));
+                if route == EventRoute::Skip {
+                    continue;
+                }
+
+                let relay_enabled = self.relay_state.read().await.relay_enabled;
 
                 // ALWAYS process the toggle key, even when relay is enabled
-                if let Event::Keyboard(ref keyboard_event) = event {
-                    if keyboard_event.key() == self.toggle_key
-                        && keyboard_event.key_state() == KeyState::Pressed
-                    {
-                        // Drop the read lock before calling toggle_relay
-                        drop(relay_state);
-
-                        if let Err(e) = self.toggle_relay().await {
-                            error!("Failed to toggle relay: {}", e);
-                        }
-                        continue; // Don't process the toggle key itself
+                if let Event::Keyboard(ref keyboard_event) = event
+                    && self.toggle_combo.update(
+                        keyboard_event.key(),
+                        keyboard_event.key_state() == KeyState::Pressed,
+                    )
+                {
+                    if let Err(e) = self.toggle_relay().await {
+                        error!("Failed to toggle relay: {}", e);
                     }
+                    continue; // Don't process the toggle key itself
                 }
 
-                // Only process and relay other events if relay is enabled
-                if relay_state.relay_enabled {
-                    if let Some(packet) = self.convert_event_to_packet(event) {
-                        if let Err(e) = packet_sender.send(packet).await {
-                            error!("Failed to send packet: {}", e);
-                            return Err(anyhow::anyhow!("Packet sender channel closed"));
-                        }
+                if route == EventRoute::ToggleKeyOnly {
+                    continue;
+                }
+
+                // Pick up the screen from the server's handshake before it's crossed into
+                if self
+                    .server_screen
+                    .as_ref()
+                    .is_some_and(|screen| screen.has_changed().unwrap_or(false))
+                {
+                    self.apply_server_screen();
+                }
+
+                // The motion that crosses the screen edge switches relay
+                // rather than moving the pointer
+                if let Event::Pointer(PointerEvent::Motion(ref motion_event)) = event
+                    && let Some(edge_switch) = &mut self.edge_switch
+                    && let Some(crossing) =
+                        edge_switch.motion(motion_event.dx(), motion_event.dy(), relay_enabled)
+                {
+                    if let Err(e) = self.cross_edge(&packet_queue, crossing).await {
+                        error!("Failed to switch relay at the screen edge: {}", e);
                     }
+                    continue;
                 }
-            }
 
-            // Yield control to allow other tasks to run
-            tokio::task::yield_now().await;
-        }
-    }
+                // Only process and relay other events if relay is enabled
+                if !relay_enabled {
+                    continue;
+                }
 
-    // ...existing code...
+                if let Event::Keyboard(ref keyboard_event) = event {
+                    self.track_key_repeat(keyboard_event);
+                }
 
-    /// Check if device is safe to grab (not used by our own libinput instance)
-    fn is_safe_to_grab(&self, device_path: &str) -> bool {
-        // Get the device name to check if it's something we should avoid
-        if let Ok(file) = OpenOptions::new().read(true).open(device_path) {
-            use std::os::unix::io::AsRawFd;
-            let fd = file.as_raw_fd();
-
-            // Get device name
-            let mut name_buf = [0u8; 256];
-            let name_result = unsafe { libc::ioctl(fd, EVIOCGNAME, name_buf.as_mut_ptr()) };
-
-            if name_result >= 0 {
-                if let Ok(name) = std::str::from_utf8(&name_buf[..name_result as usize]) {
-                    let name = name.trim_end_matches('\0');
-                    debug!("Device {} name: {}", device_path, name);
-
-                    // Skip virtual devices and special devices
-                    if name.to_lowercase().contains("virtual")
-                        || name.to_lowercase().contains("uinput")
-                        || name.to_lowercase().contains("asteria")
-                    {
-                        debug!("Skipping virtual/special device: {}", name);
-                        return false;
+                // Motion is coalesced; anything else first sends the motion
+                // before it so e.g. clicks land where the pointer moved to
+                let is_motion = matches!(event, Event::Pointer(PointerEvent::Motion(_)));
+                if !is_motion {
+                    while let Some(packet) = self.take_motion() {
+                        self.relay_packet(&packet_queue, packet).await?;
+                    }
+                }
+
+                let latency_offset = self
+                    .config
+                    .device_latency_offset(&libinput_device_name(&event.device()));
+                if is_motion {
+                    self.motion_latency_offset = latency_offset;
+                }
+
+                let mut packet = self.convert_event_to_packet(event);
+                if !is_motion && let Some(packet) = &mut packet {
+                    compensate_latency(packet, latency_offset);
+                }
+                if packet.is_some() || is_motion {
+                    self.relay_active_at = Instant::now();
+                    if let Some(category) = category {
+                        self.stats.record_relayed(category);
                     }
                 }
+                if let Some(packet) = packet {
+                    self.relay_packet(&packet_queue, packet).await?;
+                }
             }
-        }
 
-        true
-    }
+            // Repeat a held key once its repeat is due
+            if let Some(packet) = self.next_key_repeat(Instant::now()) {
+                self.relay_packet(&packet_queue, packet).await?;
+            }
 
-    /// Grab a specific input device with selective grabbing
-    async fn grab_device(&mut self, device_path: &str) -> Result<()> {
-        use std::os::unix::io::AsRawFd;
+            // Send accumulated motion once per flush interval
+            let now = Instant::now();
+            let motion_interval = Duration::from_millis(self.config.motion_flush_interval_ms);
+            if now.duration_since(self.motion_flushed_at) >= motion_interval {
+                self.motion_flushed_at = now;
+                if let Some(packet) = self.take_motion() {
+                    self.relay_packet(&packet_queue, packet).await?;
+                }
+            }
 
-        // Open the device
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(device_path)
-            .map_err(|e| anyhow::anyhow!("Failed to open device {}: {}", device_path, e))?;
+            // Everything from this dispatch cycle leaves together in per dispatch mode
+            let batch = if self.config.batch_per_dispatch {
+                self.batcher.flush()
+            } else {
+                self.batcher.flush_if_due(Instant::now())
+            };
+            if let Some(packet) = batch {
+                packet_queue.push(packet)?;
+            }
 
-        let fd = file.as_raw_fd();
+            if let Some(packet) = self.lock_state_if_due(Instant::now()).await {
+                self.relay_packet(&packet_queue, packet).await?;
+            }
+            if let Some(packet) = self.input_reset_if_due(Instant::now()).await {
+                self.relay_packet(&packet_queue, packet).await?;
+            }
 
-        // For now, don't actually grab devices to avoid lock-out
-        // Instead, we'll rely on libinput's event handling
-        // This is a safer approach until we implement proper device filtering
+            self.disable_relay_if_idle(Instant::now()).await;
 
-        // Store the file descriptor for tracking, but don't grab
-        debug!("Tracking device (not grabbing): {}", device_path);
-        self.grabbed_devices
-            .insert(device_path.to_string(), file.into());
+            if self.stats_requested.swap(false, Ordering::SeqCst) {
+                self.log_stats();
+            }
+            if self.reload_requested.swap(false, Ordering::SeqCst)
+                && let Err(e) = self.reload_config(&packet_queue).await
+            {
+                error!(
+                    "Failed to reload the config, keeping the current one: {:#}",
+                    e
+                );
+            }
+            self.log_stats_if_due(Instant::now());
 
-        Ok(())
+            // Yield control to allow other tasks to run
+            tokio::task::yield_now().await;
+        }
     }
 
-    /// Convert a libinput event to a protocol packet
-    fn convert_event_to_packet(&self, event: Event) -> Option<Packet> {
-        match event {
-            Event::Keyboard(keyboard_event) => self.convert_keyboard_event(keyboard_event),
-            Event::Pointer(pointer_event) => self.convert_pointer_event(pointer_event),
-            _ => {
-                debug!("Ignoring unsupported event type: {:?}", event);
-                None
+    /// Rebuild capture after a suspend/resume cycle or a lost seat: disable
+    /// relay, re-create libinput and re-establish the server connection
+    async fn reinitialize(&mut self, reconnect: &Notify) {
+        info!("Reinitializing input capture");
+
+        self.disable_relay().await;
+        self.pending_motion = (0.0, 0.0);
+        self.toggle_combo.reset();
+        // Events captured before the break are stale now
+        let _ = self.batcher.flush();
+
+        match create_libinput(&self.config.seat) {
+            Ok(libinput) => {
+                self.libinput = libinput;
+                // The new context reports every device again
+                self.device_names.clear();
             }
+            Err(e) => error!("Failed to re-create libinput: {}", e),
         }
+
+        reconnect.notify_one();
+        info!("Input capture reinitialized, relay is disabled");
     }
 
-    /// Convert keyboard events to protocol packets
-    fn convert_keyboard_event(&self, keyboard_event: KeyboardEvent) -> Option<Packet> {
-        let key_code = keyboard_event.key();
-        let state = keyboard_event.key_state();
+    /// Queue a typed event packet in the current batch, sending whatever the
+    /// batcher releases. Other packets flush the batch first to keep ordering.
+    async fn relay_packet(&mut self, packet_queue: &PacketQueue, packet: Packet) -> Result<()> {
+        // Overlapping devices can report the same press twice, which would
+        // leave the key stuck on the server after the single release
+        if let Message::InputEventTyped(event) = &packet.message
+            && !self.pressed.track(event)
+        {
+            debug!("Dropping redundant {:?}", event);
+            return Ok(());
+        }
+
+        let ready = match packet.message {
+            Message::InputEventTyped(event) => {
+                self.batcher.push(event, packet.timestamp, Instant::now())
+            }
+            message => {
+                if let Some(batch) = self.batcher.flush() {
+                    packet_queue.push(batch)?;
+                }
+                Some(Packet::new(message))
+            }
+        };
+
+        if let Some(packet) = ready {
+            packet_queue.push(packet)?;
+        }
+        Ok(())
+    }
+
+    /// Send a release for every key and button still held down on the server
+    async fn release_pressed(&mut self) -> Result<()> {
+        let Some(packet_queue) = self.packet_queue.clone() else {
+            return Ok(());
+        };
+
+        // Relaying the releases takes each key and button off the held sets
+        let releases = self.pressed.releases();
+        if releases.is_empty() {
+            return Ok(());
+        }
+
+        debug!("Releasing {} held inputs on the server", releases.len());
+        for event in releases {
+            let packet = Packet::new(Message::InputEventTyped(event));
+            self.relay_packet(&packet_queue, packet).await?;
+        }
+        if let Some(batch) = self.batcher.flush() {
+            packet_queue.push(batch)?;
+        }
+        Ok(())
+    }
+
+    /// Tell the server the client is leaving and give the relay a moment to send it
+    async fn disconnect(&mut self, reason: &str) {
+        let Some(mut network_task) = self.network_task.take() else {
+            return;
+        };
+        // Queued behind everything captured, so the disconnect is the last thing sent
+        if let Some(packet_queue) = self.packet_queue.take() {
+            if let Some(batch) = self.batcher.flush() {
+                let _ = packet_queue.push(batch);
+            }
+            let disconnect = Packet::new(Message::Disconnect {
+                reason: reason.to_string(),
+            });
+            let _ = packet_queue.push(disconnect);
+            packet_queue.close();
+        }
+
+        let finished = async {
+            let _ = (&mut network_task).await;
+        };
+
+        // The relay may be stuck reconnecting, so don't wait on it for long
+        if tokio::time::timeout(DISCONNECT_TIMEOUT, finished)
+            .await
+            .is_err()
+        {
+            warn!("Relay did not disconnect in time, stopping it");
+            network_task.abort();
+        }
+    }
+
+    /// Log capture statistics once the configured interval has passed
+    fn log_stats_if_due(&mut self, now: Instant) {
+        let interval = self.config.stats_log_interval_secs;
+        if interval == 0 || now.duration_since(self.stats_logged_at).as_secs() < interval {
+            return;
+        }
+
+        self.stats_logged_at = now;
+        info!("Capture stats: {}", self.stats);
+    }
+
+    /// Log capture statistics and, while relaying, the relay's traffic
+    fn log_stats(&self) {
+        info!("Capture stats: {}", self.capture_stats());
+        if let Some(counters) = &self.network_counters {
+            info!("Network stats: {}", counters);
+        }
+    }
+
+    /// Turn relay off once nothing was relayed for the configured idle timeout,
+    /// so a forgotten relay doesn't keep local input suppressed
+    async fn disable_relay_if_idle(&mut self, now: Instant) {
+        let timeout = Duration::from_secs(self.config.relay_idle_timeout_secs);
+        if !idle_expired(self.relay_active_at, timeout, now)
+            || !self.relay_state.read().await.relay_enabled
+        {
+            return;
+        }
+
+        self.disable_relay().await;
+        // Otherwise the next start would turn the forgotten relay back on
+        self.save_relay_state(false);
+        info!(
+            "🔄 Relay disabled after {:?} without input - Linux input restored",
+            timeout
+        );
+    }
+
+    /// Check the keyboard lock state once the configured interval has passed,
+    /// returning a packet if it differs from what the server was last sent
+    async fn lock_state_if_due(&mut self, now: Instant) -> Option<Packet> {
+        let interval = Duration::from_millis(self.config.lock_state_interval_ms);
+        if interval.is_zero()
+            || self.monitor_only
+            || now.duration_since(self.lock_state_checked_at) < interval
+            || !self.relay_state.read().await.relay_enabled
+        {
+            return None;
+        }
+        self.lock_state_checked_at = now;
+
+        let state = self.read_lock_state()?;
+        if self.sent_lock_state == Some(state) {
+            return None;
+        }
+
+        debug!("Keyboard lock state changed: {:?}", state);
+        self.sent_lock_state = Some(state);
+        Some(Packet::new(Message::LockState(state)))
+    }
+
+    /// Tell the server to release everything it holds once the configured
+    /// interval has passed, as long as nothing is held down here, so a release
+    /// lost on the way doesn't leave a key stuck
+    async fn input_reset_if_due(&mut self, now: Instant) -> Option<Packet> {
+        let interval = Duration::from_secs(self.config.input_reset_interval_secs);
+        if interval.is_zero()
+            || self.monitor_only
+            || now.duration_since(self.input_reset_at) < interval
+            || !self.pressed.is_empty()
+            || !self.relay_state.read().await.relay_enabled
+        {
+            return None;
+        }
+        self.input_reset_at = now;
+        Some(Packet::new(Message::ResetInputState))
+    }
+
+    /// Lock state shown by the LEDs of the grabbed keyboards, `None` if no
+    /// device reports LEDs
+    fn read_lock_state(&self) -> Option<LockState> {
+        let mut leds = None;
+        for fd in self.grabbed_devices.values() {
+            let fd = fd.as_raw_fd();
+            let mut event_types = [0u8; 8];
+            let result = unsafe { libc::ioctl(fd, EVIOCGBIT_EV, event_types.as_mut_ptr()) };
+            let index = usize::from(EV_LED);
+            if result < 0 || event_types[index / 8] & (1 << (index % 8)) == 0 {
+                continue;
+            }
+
+            let mut bits = [0u8; 8];
+            if unsafe { libc::ioctl(fd, EVIOCGLED, bits.as_mut_ptr()) } >= 0 {
+                // Compositors keep the LEDs of every keyboard in sync, but a
+                // lock shown on any of them counts
+                *leds.get_or_insert(0u8) |= bits[0];
+            }
+        }
+
+        leds.map(|leds| LockState {
+            caps: leds & (1 << LED_CAPSL) != 0,
+            num: leds & (1 << LED_NUML) != 0,
+            scroll: leds & (1 << LED_SCROLLL) != 0,
+        })
+    }
+
+    /// Start or stop synthesized autorepeat as keys are pressed and released
+    fn track_key_repeat(&mut self, keyboard_event: &KeyboardEvent) {
+        if !self.config.forward_key_repeat {
+            return;
+        }
+
+        let key_code = self.config.relayed_key(keyboard_event.key()) as u16;
+
+        match keyboard_event.key_state() {
+            KeyState::Pressed => {
+                let delay = Duration::from_millis(self.config.key_repeat_delay_ms);
+                self.key_repeat = Some(KeyRepeat::new(key_code, delay, Instant::now()));
+            }
+            KeyState::Released => {
+                if self
+                    .key_repeat
+                    .as_ref()
+                    .is_some_and(|repeat| repeat.key_code == key_code)
+                {
+                    self.key_repeat = None;
+                }
+            }
+        }
+    }
+
+    /// Build a repeat packet for the held key if its next repeat is due
+    fn next_key_repeat(&mut self, now: Instant) -> Option<Packet> {
+        let interval = Duration::from_millis(self.config.key_repeat_interval_ms);
+        let repeat = self.key_repeat.as_mut()?;
+        if !repeat.due(now, interval) {
+            return None;
+        }
+
+        let input_event_type = InputEventType::KeyRepeat {
+            key_code: repeat.key_code,
+        };
+        Some(Packet::new(Message::InputEventTyped(input_event_type)))
+    }
+
+    /// Build a move packet from the whole pixels of the accumulated motion,
+    /// holding back the fraction and whatever exceeds the motion delta cap
+    /// for the next one
+    fn take_motion(&mut self) -> Option<Packet> {
+        let (step, carry) = cap_motion(self.pending_motion, self.config.max_motion_delta);
+        self.pending_motion = carry;
+
+        if step == (0, 0) {
+            return None;
+        }
+
+        let input_event_type = InputEventType::MouseMove {
+            x: step.0,
+            y: step.1,
+        };
+        let mut packet = Packet::new(Message::InputEventTyped(input_event_type));
+        compensate_latency(&mut packet, self.motion_latency_offset);
+        Some(packet)
+    }
+
+    /// Check if device is safe to grab (not used by our own libinput instance)
+    fn is_safe_to_grab(&self, device_name: Option<&str>) -> bool {
+        // Skip virtual devices and special devices
+        if let Some(name) = device_name
+            && (name.to_lowercase().contains("virtual")
+                || name.to_lowercase().contains("uinput")
+                || name.to_lowercase().contains("asteria"))
+        {
+            debug!("Skipping virtual/special device: {}", name);
+            return false;
+        }
+
+        true
+    }
+
+    /// Grab a specific input device with selective grabbing
+    async fn grab_device(&mut self, device_path: &str) -> Result<()> {
+        // Open the device
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(device_path)
+            .map_err(|e| anyhow::anyhow!("Failed to open device {}: {}", device_path, e))?;
+
+        // For now, don't actually grab devices to avoid lock-out
+        // Instead, we'll rely on libinput's event handling
+        // This is a safer approach until we implement proper device filtering
+
+        // Store the file descriptor for tracking, but don't grab
+        debug!("Tracking device (not grabbing): {}", device_path);
+        self.grabbed_devices
+            .insert(device_path.to_string(), file.into());
+        self.update_grabbed_count();
+
+        Ok(())
+    }
+
+    fn update_grabbed_count(&self) {
+        self.grabbed_count
+            .store(self.grabbed_devices.len(), Ordering::Relaxed);
+    }
+
+    /// Note the names of added devices, grab those plugged in while relaying
+    /// and forget removed ones
+    async fn handle_hotplug(&mut self, device_event: &DeviceEvent) {
+        let device = device_event.device();
+        let device_path = format!("/dev/input/{}", device.sysname());
+        let device_name = libinput_device_name(&device);
+
+        let change = match device_event {
+            DeviceEvent::Added(_) => HotplugChange::Added,
+            DeviceEvent::Removed(_) => HotplugChange::Removed,
+            _ => return,
+        };
+        let relaying = !self.monitor_only && self.relay_state.read().await.relay_enabled;
+
+        match track_hotplug(
+            &mut self.device_names,
+            &mut self.grabbed_devices,
+            &device_path,
+            &device_name,
+            change,
+            relaying,
+        ) {
+            HotplugOutcome::Grab => match self.should_grab_device(&device_path) {
+                Ok(true) => match self.grab_device(&device_path).await {
+                    Ok(()) => info!(
+                        "Grabbed hotplugged device {} ({})",
+                        device_name, device_path
+                    ),
+                    Err(e) => warn!("Failed to grab device {}: {}", device_path, e),
+                },
+                Ok(false) => debug!("Not grabbing hotplugged device {}", device_path),
+                Err(e) => warn!("Failed to inspect device {}: {}", device_path, e),
+            },
+            HotplugOutcome::Released => {
+                self.update_grabbed_count();
+                info!("Released removed device {} ({})", device_name, device_path);
+            }
+            HotplugOutcome::Unchanged => {}
+        }
+    }
+
+    /// Convert a libinput event to a protocol packet
+    #[instrument(level = "trace", skip_all, fields(packet_id = field::Empty, kind = field::Empty))]
+    fn convert_event_to_packet(&mut self, event: Event) -> Option<Packet> {
+        let packet = match event {
+            Event::Keyboard(keyboard_event) => self.convert_keyboard_event(keyboard_event),
+            Event::Pointer(pointer_event) => self.convert_pointer_event(pointer_event),
+            Event::Gesture(gesture_event) => convert_gesture_event(&gesture_event),
+            _ => {
+                debug!("Ignoring unsupported event type: {:?}", event);
+                None
+            }
+        };
+        if let Some(packet) = &packet {
+            Span::current()
+                .record("packet_id", packet.id.as_str())
+                .record("kind", packet.message.kind());
+        }
+        packet
+    }
+
+    /// Convert keyboard events to protocol packets
+    fn convert_keyboard_event(&self, keyboard_event: KeyboardEvent) -> Option<Packet> {
+        let key_code = self.config.relayed_key(keyboard_event.key());
+        let state = keyboard_event.key_state();
 
         debug!("Keyboard event - Key: {}, State: {:?}", key_code, state);
 
@@ -313,7 +1492,7 @@ impl InputCapture {
     }
 
     /// Convert pointer events to protocol packets
-    fn convert_pointer_event(&self, pointer_event: PointerEvent) -> Option<Packet> {
+    fn convert_pointer_event(&mut self, pointer_event: PointerEvent) -> Option<Packet> {
         match pointer_event {
             PointerEvent::Motion(motion_event) => {
                 let dx = motion_event.dx();
@@ -321,16 +1500,26 @@ impl InputCapture {
 
                 debug!("Pointer motion - dx: {}, dy: {}", dx, dy);
 
-                if dx != 0.0 || dy != 0.0 {
-                    let input_event_type = InputEventType::MouseMove {
-                        x: dx as i32,
-                        y: dy as i32,
-                    };
-                    Some(Packet::new(Message::InputEventTyped(input_event_type)))
+                self.pending_motion.0 += dx;
+                self.pending_motion.1 += dy;
+
+                if self.config.motion_flush_interval_ms == 0 {
+                    self.take_motion()
                 } else {
                     None
                 }
             }
+            PointerEvent::MotionAbsolute(motion_event) => {
+                // Transform into a normalized range so the server can scale to its own screen
+                let extent = ABSOLUTE_AXIS_MAX as u32 + 1;
+                let x = normalize_absolute(motion_event.absolute_x_transformed(extent), extent);
+                let y = normalize_absolute(motion_event.absolute_y_transformed(extent), extent);
+
+                debug!("Pointer absolute motion - x: {}, y: {}", x, y);
+
+                let input_event_type = InputEventType::MouseMoveAbsolute { x, y };
+                Some(Packet::new(Message::InputEventTyped(input_event_type)))
+            }
             PointerEvent::Button(button_event) => {
                 let button = button_event.button();
                 let state = button_event.button_state();
@@ -342,15 +1531,9 @@ impl InputCapture {
                     ButtonState::Released => false,
                 };
 
-                // Convert libinput button codes to standard mouse button codes
-                let button_code = match button {
-                    0x110 => 1, // BTN_LEFT
-                    0x111 => 2, // BTN_RIGHT
-                    0x112 => 3, // BTN_MIDDLE
-                    _ => {
-                        warn!("Unsupported mouse button: {}", button);
-                        return None;
-                    }
+                let Some(button_code) = libinput_button_to_protocol(button) else {
+                    warn!("Unsupported mouse button: {}", button);
+                    return None;
                 };
 
                 let input_event_type = InputEventType::MouseButton {
@@ -360,20 +1543,22 @@ impl InputCapture {
                 Some(Packet::new(Message::InputEventTyped(input_event_type)))
             }
             PointerEvent::ScrollWheel(scroll_event) => {
-                let dx = scroll_event.scroll_value(Axis::Horizontal);
-                let dy = scroll_event.scroll_value(Axis::Vertical);
-
-                debug!("Pointer scroll - dx: {}, dy: {}", dx, dy);
-
-                if dx != 0.0 || dy != 0.0 {
-                    let input_event_type = InputEventType::MouseScroll {
-                        dx: dx as i32,
-                        dy: -(dy as i32), // Invert vertical scroll
-                    };
-                    Some(Packet::new(Message::InputEventTyped(input_event_type)))
-                } else {
-                    None
-                }
+                // v120 reports high resolution wheels in fractions of a click
+                let dx = scroll_axis(&scroll_event, Axis::Horizontal, |axis| {
+                    scroll_event.scroll_value_v120(axis)
+                });
+                let dy = scroll_axis(&scroll_event, Axis::Vertical, |axis| {
+                    scroll_event.scroll_value_v120(axis)
+                });
+
+                debug!("Pointer wheel scroll - dx: {}, dy: {}", dx, dy);
+
+                let (dx, dy) = self.scroll.wheel(dx, dy)?;
+                Some(scroll_packet(dx, dy))
+            }
+            PointerEvent::ScrollFinger(scroll_event) => self.convert_smooth_scroll(&scroll_event),
+            PointerEvent::ScrollContinuous(scroll_event) => {
+                self.convert_smooth_scroll(&scroll_event)
             }
             _ => {
                 debug!("Ignoring unsupported pointer event: {:?}", pointer_event);
@@ -382,25 +1567,56 @@ impl InputCapture {
         }
     }
 
+    /// Convert finger or continuous scroll, which report distance rather than clicks
+    fn convert_smooth_scroll(&mut self, scroll_event: &impl PointerScrollEvent) -> Option<Packet> {
+        let dx = scroll_axis(scroll_event, Axis::Horizontal, |axis| {
+            scroll_event.scroll_value(axis)
+        });
+        let dy = scroll_axis(scroll_event, Axis::Vertical, |axis| {
+            scroll_event.scroll_value(axis)
+        });
+
+        debug!("Pointer smooth scroll - dx: {}, dy: {}", dx, dy);
+
+        // A zero value ends the scroll on that axis, so no partial step lingers
+        // into the next one
+        if scroll_event.has_axis(Axis::Horizontal) && dx == 0.0 {
+            self.scroll.stop_horizontal();
+        }
+        if scroll_event.has_axis(Axis::Vertical) && dy == 0.0 {
+            self.scroll.stop_vertical();
+        }
+
+        let (dx, dy) = self.scroll.smooth(dx, dy)?;
+        Some(scroll_packet(dx, dy))
+    }
+
     /// Grab all input devices to suppress local input
-    async fn grab_input_devices(&mut self) -> Result<()> {
+    async fn grab_input_devices(&mut self) -> Result<GrabSummary> {
         info!("Grabbing input devices for suppression...");
 
         // Get list of input devices
         let device_paths = self.get_input_device_paths()?;
 
+        let mut summary = GrabSummary::default();
         for device_path in device_paths {
-            if let Err(e) = self.grab_device(&device_path).await {
-                warn!("Failed to grab device {}: {}", device_path, e);
-                // Continue with other devices even if one fails
-            }
+            let opened = match self.grab_device(&device_path).await {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Failed to open device {}: {}", device_path, e);
+                    // Continue with other devices even if one fails
+                    false
+                }
+            };
+            summary.record(&device_path, opened);
         }
 
         info!(
-            "Successfully grabbed {} input devices",
-            self.grabbed_devices.len()
+            "Opened {} of {} input devices",
+            summary.opened,
+            summary.attempted()
         );
-        Ok(())
+        Ok(summary)
     }
 
     /// Release all grabbed input devices
@@ -412,6 +1628,7 @@ impl InputCapture {
             drop(fd);
             debug!("Released device: {}", device_path);
         }
+        self.update_grabbed_count();
 
         info!("All input devices released");
         Ok(())
@@ -428,16 +1645,14 @@ impl InputCapture {
             let entry = entry?;
             let path = entry.path();
 
-            if let Some(filename) = path.file_name() {
-                if let Some(filename_str) = filename.to_str() {
-                    if filename_str.starts_with("event") {
-                        if let Some(path_str) = path.to_str() {
-                            // Check if this device should be grabbed
-                            if self.should_grab_device(path_str)? {
-                                device_paths.push(path_str.to_string());
-                            }
-                        }
-                    }
+            if let Some(filename) = path.file_name()
+                && let Some(filename_str) = filename.to_str()
+                && filename_str.starts_with("event")
+                && let Some(path_str) = path.to_str()
+            {
+                // Check if this device should be grabbed
+                if self.should_grab_device(path_str)? {
+                    device_paths.push(path_str.to_string());
                 }
             }
         }
@@ -451,11 +1666,6 @@ impl InputCapture {
     fn should_grab_device(&self, device_path: &str) -> Result<bool> {
         use std::os::unix::io::AsRawFd;
 
-        // First check if it's safe to grab this device
-        if !self.is_safe_to_grab(device_path) {
-            return Ok(false);
-        }
-
         // Try to open the device to check its capabilities
         let file = match OpenOptions::new().read(true).open(device_path) {
             Ok(file) => file,
@@ -470,6 +1680,37 @@ impl InputCapture {
 
         let fd = file.as_raw_fd();
 
+        // libinput knows the name of every device it reported, the kernel is
+        // only asked for devices it hasn't reported yet
+        let device_name = device_name(&self.device_names, device_path, || read_device_name(fd));
+        if let Some(name) = &device_name {
+            debug!("Device {} name: {}", device_path, name);
+
+            // Configured patterns win over capability detection
+            match self.grab.override_for(name) {
+                Some(GrabOverride::Exclude(pattern)) => {
+                    info!(
+                        "Not grabbing {} ({}), excluded by {:?}",
+                        name, device_path, pattern
+                    );
+                    return Ok(false);
+                }
+                Some(GrabOverride::Include(pattern)) => {
+                    info!(
+                        "Grabbing {} ({}), included by {:?}",
+                        name, device_path, pattern
+                    );
+                    return Ok(true);
+                }
+                None => {}
+            }
+        }
+
+        // Then check if it's safe to grab this device
+        if !self.is_safe_to_grab(device_name.as_deref()) {
+            return Ok(false);
+        }
+
         // Check if device has keyboard or mouse capabilities
         let mut key_bits = [0u8; 96]; // EV_KEY bitmap (768 bits / 8 = 96 bytes)
         let mut rel_bits = [0u8; 8]; // EV_REL bitmap (64 bits / 8 = 8 bytes)
@@ -515,25 +1756,432 @@ impl InputCapture {
             state.relay_enabled
         };
 
-        if should_release {
-            // Update state first
-            {
-                let mut state = self.relay_state.write().await;
-                state.relay_enabled = false;
-                state.suppress_local_input = false;
-            }
+        // Saved before relay is disabled below, so the next start resumes it
+        self.save_relay_state(should_release);
 
-            // Then release devices
-            if let Err(e) = self.release_input_devices().await {
-                error!("Failed to release input devices during shutdown: {}", e);
-            }
+        if should_release {
+            self.disable_relay().await;
         }
 
+        self.disconnect("client shutting down").await;
+
+        self.log_stats();
         info!("Input capture system shutdown complete");
         Ok(())
     }
 }
 
+/// Create a libinput context and assign it to `seat`
+fn create_libinput(seat: &str) -> Result<Libinput> {
+    let mut libinput = Libinput::new_with_udev(Interface);
+
+    if libinput.udev_assign_seat(seat).is_err() {
+        let hint = seat_hint(seat, &available_seats(Path::new(SEATS_DIR)));
+        bail!("Failed to assign libinput to seat {}: {}", seat, hint);
+    }
+
+    info!(
+        "Successfully initialized libinput and assigned seat {}",
+        seat
+    );
+    Ok(libinput)
+}
+
+/// What to try when assigning `seat` failed, given the `seats` that exist
+fn seat_hint(seat: &str, seats: &[String]) -> String {
+    if seats.is_empty() || seats.iter().any(|available| available == seat) {
+        "check that this user may read /dev/input, e.g. add it to the input group with \
+         `sudo usermod -aG input $USER` and log in again"
+            .to_string()
+    } else {
+        format!(
+            "available seats are {}, set capture.seat to one of them",
+            seats.join(", ")
+        )
+    }
+}
+
+/// Seats known to systemd-logind, listed in `seats_dir`; empty where it isn't running
+fn available_seats(seats_dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(seats_dir) else {
+        return Vec::new();
+    };
+
+    let mut seats: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+    seats.sort();
+    seats
+}
+
+/// Flip the pause request on every SIGUSR1
+async fn listen_for_pause_signal(pause_requested: Arc<AtomicBool>) -> Result<()> {
+    let mut signals = signal(SignalKind::user_defined1())?;
+
+    while signals.recv().await.is_some() {
+        let was_paused = pause_requested.fetch_xor(true, Ordering::SeqCst);
+        debug!(
+            "Received SIGUSR1, {} capture",
+            if was_paused { "resuming" } else { "pausing" }
+        );
+    }
+
+    Ok(())
+}
+
+/// Request a statistics summary on every SIGUSR2
+async fn listen_for_stats_signal(stats_requested: Arc<AtomicBool>) -> Result<()> {
+    let mut signals = signal(SignalKind::user_defined2())?;
+
+    while signals.recv().await.is_some() {
+        debug!("Received SIGUSR2, logging statistics");
+        stats_requested.store(true, Ordering::SeqCst);
+    }
+
+    Ok(())
+}
+
+/// Request a config reload on every SIGHUP
+async fn listen_for_reload_signal(reload_requested: Arc<AtomicBool>) -> Result<()> {
+    let mut signals = signal(SignalKind::hangup())?;
+
+    while signals.recv().await.is_some() {
+        debug!("Received SIGHUP, reloading the config");
+        reload_requested.store(true, Ordering::SeqCst);
+    }
+
+    Ok(())
+}
+
+/// Batcher for the batching settings in `config`
+fn event_batcher(config: &CaptureConfig) -> EventBatcher {
+    if config.batch_per_dispatch {
+        EventBatcher::unwindowed(config.batch_max_events)
+    } else {
+        EventBatcher::new(
+            Duration::from_millis(config.batch_window_ms),
+            config.batch_max_events,
+        )
+    }
+}
+
+/// What a pause request means for capture in `state`, where `resume_relay`
+/// is whether the current pause turned relay off
+fn pause_transition(requested: bool, state: &RelayState, resume_relay: bool) -> PauseTransition {
+    match (requested, state.paused) {
+        (false, false) | (true, true) => PauseTransition::Unchanged,
+        (true, false) => PauseTransition::Pause {
+            disable_relay: state.relay_enabled,
+        },
+        (false, true) => PauseTransition::Resume {
+            enable_relay: resume_relay,
+        },
+    }
+}
+
+/// Note a hotplugged device in the device name cache and the `grabbed` devices.
+///
+/// Added devices are only grabbed while `relaying`, since devices present when
+/// relay is enabled are grabbed by the initial scan. The grab of a removed
+/// device is dropped, which closes its descriptor.
+fn track_hotplug<T>(
+    device_names: &mut HashMap<String, String>,
+    grabbed: &mut HashMap<String, T>,
+    device_path: &str,
+    device_name: &str,
+    change: HotplugChange,
+    relaying: bool,
+) -> HotplugOutcome {
+    match change {
+        HotplugChange::Added => {
+            device_names.insert(device_path.to_string(), device_name.to_string());
+            if relaying && !grabbed.contains_key(device_path) {
+                HotplugOutcome::Grab
+            } else {
+                HotplugOutcome::Unchanged
+            }
+        }
+        HotplugChange::Removed => {
+            device_names.remove(device_path);
+            match grabbed.remove(device_path) {
+                Some(_) => HotplugOutcome::Released,
+                None => HotplugOutcome::Unchanged,
+            }
+        }
+    }
+}
+
+/// Panic key combination configured in `config`, if any
+fn panic_combo(config: &CaptureConfig) -> Result<Option<ToggleCombo>> {
+    config
+        .panic_key
+        .as_deref()
+        .map(ToggleCombo::parse)
+        .transpose()
+}
+
+/// Track a key for the panic combination, returning true when it completes it
+fn panic_key_pressed(panic_combo: &mut Option<ToggleCombo>, key: u32, pressed: bool) -> bool {
+    panic_combo
+        .as_mut()
+        .is_some_and(|panic_combo| panic_combo.update(key, pressed))
+}
+
+/// Run the capture `future`, turning a panic raised while polling it into an error
+async fn watch_for_panic(future: impl Future<Output = Result<()>>) -> Result<()> {
+    catch_panic(future).await.unwrap_or_else(|panic| {
+        let message = panic_message(panic.as_ref());
+        error!(
+            "💥 Input capture panicked ({}), releasing all devices",
+            message
+        );
+        Err(anyhow!("Input capture panicked: {}", message))
+    })
+}
+
+/// Run `future`, returning the payload of a panic raised while polling it
+/// instead of unwinding further
+async fn catch_panic<F: Future>(future: F) -> std::thread::Result<F::Output> {
+    let mut future = pin!(future);
+    // Whatever the future borrowed may be inconsistent after a panic; the
+    // caller only uses it to release devices
+    poll_fn(
+        |cx| match catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        },
+    )
+    .await
+}
+
+/// Message a panic was raised with, if it was raised with one
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message")
+}
+
+/// Convert a libinput button code to the protocol's button numbering
+fn libinput_button_to_protocol(button: u32) -> Option<u8> {
+    match button {
+        0x110 => Some(mouse_buttons::LEFT),   // BTN_LEFT
+        0x111 => Some(mouse_buttons::RIGHT),  // BTN_RIGHT
+        0x112 => Some(mouse_buttons::MIDDLE), // BTN_MIDDLE
+        // Mice report their thumb buttons as side/extra or as back/forward
+        0x113 | 0x116 => Some(mouse_buttons::BACK), // BTN_SIDE, BTN_BACK
+        0x114 | 0x115 => Some(mouse_buttons::FORWARD), // BTN_EXTRA, BTN_FORWARD
+        _ => None,
+    }
+}
+
+/// Date `packet` back to when its input happened on a device that takes
+/// `offset_ms` to report it
+fn compensate_latency(packet: &mut Packet, offset_ms: u64) {
+    packet.timestamp = packet.timestamp.saturating_sub(offset_ms);
+}
+
+/// Whether `timeout` passed since `active_at`, with a zero timeout never expiring
+fn idle_expired(active_at: Instant, timeout: Duration, now: Instant) -> bool {
+    !timeout.is_zero() && now.duration_since(active_at) >= timeout
+}
+
+/// Split a motion into the whole pixels within `max` per axis and the
+/// remainder to carry, with 0 meaning no cap
+fn cap_motion(motion: (f64, f64), max: i32) -> ((i32, i32), (f64, f64)) {
+    let whole = |value: f64| {
+        let value = value.trunc();
+        if max > 0 {
+            value.clamp(-f64::from(max), f64::from(max))
+        } else {
+            value
+        }
+    };
+
+    let step = (whole(motion.0), whole(motion.1));
+    (
+        (step.0 as i32, step.1 as i32),
+        (motion.0 - step.0, motion.1 - step.1),
+    )
+}
+
+/// Name the kernel reports for the input device open at `fd`
+/// Name of the device at `device_path`, from the names libinput reported or
+/// else by asking the kernel with `read`
+fn device_name(
+    device_names: &HashMap<String, String>,
+    device_path: &str,
+    read: impl FnOnce() -> Option<String>,
+) -> Option<String> {
+    device_names.get(device_path).cloned().or_else(read)
+}
+
+fn read_device_name(fd: RawFd) -> Option<String> {
+    let mut name_buf = [0u8; 256];
+    let name_result = unsafe { libc::ioctl(fd, EVIOCGNAME, name_buf.as_mut_ptr()) };
+    if name_result <= 0 {
+        return None;
+    }
+
+    Some(decode_device_name(&name_buf[..name_result as usize]))
+}
+
+/// Device name from the bytes the kernel reported, which end in a NUL and
+/// aren't necessarily UTF-8
+pub fn decode_device_name(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Name of a libinput device. `Device::name` panics on names that aren't
+/// UTF-8, so the name is read directly and decoded lossily.
+fn libinput_device_name(device: &Device) -> Cow<'_, str> {
+    let name = unsafe { input::ffi::libinput_device_get_name(device.as_raw_mut()) };
+    if name.is_null() {
+        return Cow::Borrowed("");
+    }
+    unsafe { CStr::from_ptr(name) }.to_string_lossy()
+}
+
+/// Read one scroll axis, libinput only has values for the axes the event carries
+fn scroll_axis(
+    scroll_event: &impl PointerScrollEvent,
+    axis: Axis,
+    value: impl Fn(Axis) -> f64,
+) -> f64 {
+    if scroll_event.has_axis(axis) {
+        value(axis)
+    } else {
+        0.0
+    }
+}
+
+fn scroll_packet(dx: i32, dy: i32) -> Packet {
+    Packet::new(Message::InputEventTyped(InputEventType::MouseScroll {
+        dx,
+        dy,
+    }))
+}
+
+/// Convert a touchpad swipe or pinch to a protocol packet, one per phase
+fn convert_gesture_event(gesture_event: &GestureEvent) -> Option<Packet> {
+    let (kind, phase, (dx, dy), scale) = match gesture_event {
+        GestureEvent::Swipe(GestureSwipeEvent::Begin(_)) => {
+            (GestureKind::Swipe, GesturePhase::Begin, (0.0, 0.0), 1.0)
+        }
+        GestureEvent::Swipe(GestureSwipeEvent::Update(update)) => (
+            GestureKind::Swipe,
+            GesturePhase::Update,
+            (update.dx(), update.dy()),
+            1.0,
+        ),
+        GestureEvent::Swipe(GestureSwipeEvent::End(end)) => {
+            (GestureKind::Swipe, gesture_end_phase(end), (0.0, 0.0), 1.0)
+        }
+        GestureEvent::Pinch(GesturePinchEvent::Begin(_)) => {
+            (GestureKind::Pinch, GesturePhase::Begin, (0.0, 0.0), 1.0)
+        }
+        GestureEvent::Pinch(GesturePinchEvent::Update(update)) => (
+            GestureKind::Pinch,
+            GesturePhase::Update,
+            (update.dx(), update.dy()),
+            update.scale(),
+        ),
+        GestureEvent::Pinch(GesturePinchEvent::End(end)) => (
+            GestureKind::Pinch,
+            gesture_end_phase(end),
+            (0.0, 0.0),
+            end.scale(),
+        ),
+        _ => {
+            debug!("Ignoring unsupported gesture: {:?}", gesture_event);
+            return None;
+        }
+    };
+
+    let fingers = gesture_event.finger_count().clamp(0, i32::from(u8::MAX)) as u8;
+    debug!(
+        "Gesture - {:?} {:?}, fingers: {}, dx: {}, dy: {}, scale: {}",
+        kind, phase, fingers, dx, dy, scale
+    );
+
+    let input_event_type = InputEventType::Gesture {
+        kind,
+        phase,
+        fingers,
+        dx: dx as f32,
+        dy: dy as f32,
+        scale: scale as f32,
+    };
+    Some(Packet::new(Message::InputEventTyped(input_event_type)))
+}
+
+fn gesture_end_phase(end: &impl GestureEndEvent) -> GesturePhase {
+    if end.cancelled() {
+        GesturePhase::Cancel
+    } else {
+        GesturePhase::End
+    }
+}
+
+/// Map a libinput event to its configurable category
+/// Setting that decides whether `event` is relayed
+fn relay_switch(event: &Event) -> RelaySwitch {
+    match event {
+        Event::Keyboard(_) => RelaySwitch::Keyboard,
+        Event::Pointer(
+            PointerEvent::ScrollWheel(_)
+            | PointerEvent::ScrollFinger(_)
+            | PointerEvent::ScrollContinuous(_),
+        ) => RelaySwitch::Scroll,
+        Event::Pointer(_) => RelaySwitch::Mouse,
+        Event::Gesture(_) => RelaySwitch::Scroll,
+        _ => RelaySwitch::Always,
+    }
+}
+
+/// Decide what happens to an event of `category` under `switch`.
+///
+/// Keyboard events are never skipped outright, so the toggle key keeps working
+/// with the keyboard category or keyboard relay turned off.
+fn route_event(
+    config: &CaptureConfig,
+    category: Option<EventCategory>,
+    switch: RelaySwitch,
+) -> EventRoute {
+    let category_enabled =
+        category.is_none_or(|category| config.event_categories.contains(&category));
+    let relayed = category_enabled
+        && match switch {
+            RelaySwitch::Keyboard => config.relay_keyboard,
+            RelaySwitch::Mouse => config.relay_mouse,
+            RelaySwitch::Scroll => config.relay_scroll,
+            RelaySwitch::Always => true,
+        };
+
+    match (relayed, switch) {
+        (true, _) => EventRoute::Relay,
+        (false, RelaySwitch::Keyboard) => EventRoute::ToggleKeyOnly,
+        (false, _) => EventRoute::Skip,
+    }
+}
+
+fn event_category(event: &Event) -> Option<EventCategory> {
+    match event {
+        Event::Device(_) => Some(EventCategory::Device),
+        Event::Keyboard(_) => Some(EventCategory::Keyboard),
+        Event::Pointer(_) => Some(EventCategory::Pointer),
+        Event::Touch(_) => Some(EventCategory::Touch),
+        Event::Tablet(_) => Some(EventCategory::Tablet),
+        Event::TabletPad(_) => Some(EventCategory::TabletPad),
+        Event::Gesture(_) => Some(EventCategory::Gesture),
+        Event::Switch(_) => Some(EventCategory::Switch),
+        _ => None,
+    }
+}
+
 impl Default for InputCapture {
     fn default() -> Self {
         Self::new().expect("Failed to create input capture")
@@ -555,3 +2203,719 @@ impl Drop for InputCapture {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::key_codes;
+    use asteria_core::config::DeviceLatencyConfig;
+
+    /// Feed deltas through the accumulator the way the capture loop does,
+    /// flushing after every `flush_every` deltas, and return the moves sent
+    fn coalesce(deltas: &[(f64, f64)], flush_every: usize, max: i32) -> Vec<(i32, i32)> {
+        let mut pending = (0.0, 0.0);
+        let mut sent = Vec::new();
+        let mut flush = |pending: &mut (f64, f64)| {
+            let (step, carry) = cap_motion(*pending, max);
+            *pending = carry;
+            if step != (0, 0) {
+                sent.push(step);
+            }
+        };
+
+        for (index, (dx, dy)) in deltas.iter().enumerate() {
+            pending.0 += dx;
+            pending.1 += dy;
+            if (index + 1) % flush_every == 0 {
+                flush(&mut pending);
+            }
+        }
+        flush(&mut pending);
+        sent
+    }
+
+    fn total(moves: &[(i32, i32)]) -> (i32, i32) {
+        moves
+            .iter()
+            .fold((0, 0), |sum, step| (sum.0 + step.0, sum.1 + step.1))
+    }
+
+    #[test]
+    fn coalesced_moves_sum_to_the_input() {
+        let deltas = [(3.0, -2.0), (0.0, 0.0), (5.0, 1.0), (-1.0, 4.0), (0.0, 0.0)];
+        let sent = coalesce(&deltas, 2, 0);
+        assert_eq!(total(&sent), (7, 3));
+    }
+
+    #[test]
+    fn sub_pixel_motion_is_carried_between_flushes() {
+        let deltas = [(0.4, -0.4); 10];
+        let sent = coalesce(&deltas, 1, 0);
+        assert_eq!(total(&sent), (4, -4));
+    }
+
+    #[test]
+    fn zero_motion_sends_nothing() {
+        let deltas = [(0.0, 0.0); 5];
+        assert!(coalesce(&deltas, 1, 0).is_empty());
+    }
+
+    #[test]
+    fn motion_beyond_the_cap_is_split_across_moves() {
+        let sent = coalesce(&[(25.0, -7.5)], 1, 10);
+        assert_eq!(sent, vec![(10, -7), (10, 0)]);
+
+        let (step, carry) = cap_motion((25.0, -7.5), 10);
+        assert_eq!(step, (10, -7));
+        assert_eq!(carry, (15.0, -0.5));
+    }
+
+    #[test]
+    fn grab_summary_counts_mixed_outcomes() {
+        let mut summary = GrabSummary::default();
+        summary.record("/dev/input/event0", true);
+        summary.record("/dev/input/event1", false);
+        summary.record("/dev/input/event2", true);
+
+        assert_eq!(summary.opened, 2);
+        assert_eq!(summary.failed, vec!["/dev/input/event1".to_string()]);
+        assert_eq!(summary.attempted(), 3);
+    }
+
+    #[test]
+    fn empty_grab_summary_opened_nothing() {
+        let mut summary = GrabSummary::default();
+        summary.record("/dev/input/event0", false);
+
+        assert_eq!(summary.opened, 0);
+        assert_eq!(summary.attempted(), 1);
+    }
+
+    #[test]
+    fn relay_idle_timeout_fires_and_resets_on_activity() {
+        let timeout = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert!(!idle_expired(
+            start,
+            timeout,
+            start + Duration::from_secs(59)
+        ));
+        assert!(idle_expired(
+            start,
+            timeout,
+            start + Duration::from_secs(60)
+        ));
+
+        // Relaying an event moves the activity time forward
+        let active_at = start + Duration::from_secs(30);
+        assert!(!idle_expired(
+            active_at,
+            timeout,
+            start + Duration::from_secs(60)
+        ));
+        assert!(idle_expired(
+            active_at,
+            timeout,
+            start + Duration::from_secs(90)
+        ));
+    }
+
+    #[test]
+    fn zero_relay_idle_timeout_never_fires() {
+        let start = Instant::now();
+        assert!(!idle_expired(
+            start,
+            Duration::ZERO,
+            start + Duration::from_secs(3600)
+        ));
+    }
+
+    #[test]
+    fn per_dispatch_batches_ignore_the_window() {
+        let config = CaptureConfig {
+            batch_window_ms: 2,
+            batch_per_dispatch: true,
+            ..CaptureConfig::default()
+        };
+        let mut batcher = event_batcher(&config);
+        let start = Instant::now();
+
+        batcher.push(InputEventType::KeyPress { key_code: 30 }, 1, start);
+        assert!(
+            batcher
+                .flush_if_due(start + Duration::from_secs(1))
+                .is_none()
+        );
+        assert!(batcher.flush().is_some());
+    }
+
+    fn capture_config(panic_key: Option<&str>) -> CaptureConfig {
+        CaptureConfig {
+            panic_key: panic_key.map(str::to_string),
+            ..CaptureConfig::default()
+        }
+    }
+
+    #[test]
+    fn panic_key_is_parsed_from_the_config() {
+        assert!(panic_combo(&capture_config(None)).unwrap().is_none());
+
+        let combo = panic_combo(&capture_config(Some("Ctrl+Alt+Esc")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            combo.to_string(),
+            ToggleCombo::parse("29+56+1").unwrap().to_string()
+        );
+
+        assert!(panic_combo(&capture_config(Some("Ctrl+NoSuchKey"))).is_err());
+    }
+
+    #[test]
+    fn panic_key_fires_once_the_whole_combination_is_held() {
+        let mut combo = panic_combo(&capture_config(Some("Ctrl+Esc"))).unwrap();
+        let (ctrl, esc) = (key_codes::KEY_LEFTCTRL, key_codes::KEY_ESC);
+
+        assert!(!panic_key_pressed(&mut combo, esc, true));
+        assert!(!panic_key_pressed(&mut combo, esc, false));
+        assert!(!panic_key_pressed(&mut combo, ctrl, true));
+        assert!(panic_key_pressed(&mut combo, esc, true));
+        // Other keys don't count towards it
+        assert!(!panic_key_pressed(&mut combo, key_codes::KEY_A, true));
+    }
+
+    #[test]
+    fn no_key_fires_without_a_panic_key() {
+        let mut combo = None;
+        assert!(!panic_key_pressed(&mut combo, key_codes::KEY_ESC, true));
+    }
+
+    #[tokio::test]
+    async fn a_panicking_capture_becomes_an_error() {
+        let error = watch_for_panic(async {
+            tokio::task::yield_now().await;
+            panic!("device vanished");
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Input capture panicked: device vanished");
+
+        let code = 7;
+        let error = watch_for_panic(async move { panic!("bad key {}", code) })
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Input capture panicked: bad key 7");
+    }
+
+    #[tokio::test]
+    async fn capture_results_pass_through() {
+        assert!(watch_for_panic(async { Ok(()) }).await.is_ok());
+
+        let error = watch_for_panic(async { Err(anyhow!("seat lost")) })
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "seat lost");
+    }
+
+    #[test]
+    fn panics_without_a_message_are_named() {
+        let payload: Box<dyn Any + Send> = Box::new(42);
+        assert_eq!(panic_message(payload.as_ref()), "no message");
+    }
+
+    #[test]
+    fn a_doubled_press_is_relayed_once() {
+        let mut pressed = PressedInputs::default();
+        let events = [
+            InputEventType::KeyPress { key_code: 30 },
+            InputEventType::KeyPress { key_code: 30 },
+            InputEventType::KeyRelease { key_code: 30 },
+            InputEventType::KeyRelease { key_code: 30 },
+        ];
+        let relayed: Vec<String> = events
+            .iter()
+            .filter(|event| pressed.track(event))
+            .map(|event| format!("{:?}", event))
+            .collect();
+
+        assert_eq!(
+            relayed,
+            ["KeyPress { key_code: 30 }", "KeyRelease { key_code: 30 }"]
+        );
+        assert!(pressed.is_empty());
+    }
+
+    #[test]
+    fn mouse_buttons_are_deduplicated_apart_from_keys() {
+        let mut pressed = PressedInputs::default();
+        let press = InputEventType::MouseButton {
+            button: 1,
+            pressed: true,
+        };
+        assert!(pressed.track(&press));
+        assert!(!pressed.track(&press));
+        // Key 1 is not mouse button 1
+        assert!(!pressed.track(&InputEventType::KeyRelease { key_code: 1 }));
+        assert!(pressed.track(&InputEventType::MouseMove { x: 1, y: 1 }));
+        assert!(!pressed.is_empty());
+    }
+
+    #[test]
+    fn releases_cover_everything_held() {
+        let mut pressed = PressedInputs::default();
+        pressed.track(&InputEventType::KeyPress { key_code: 42 });
+        pressed.track(&InputEventType::MouseButton {
+            button: 0,
+            pressed: true,
+        });
+        assert_eq!(
+            format!("{:?}", pressed.releases()),
+            "[KeyRelease { key_code: 42 }, MouseButton { button: 0, pressed: false }]"
+        );
+
+        for release in pressed.releases() {
+            assert!(pressed.track(&release));
+        }
+        assert!(pressed.is_empty());
+        assert!(pressed.releases().is_empty());
+    }
+
+    #[test]
+    fn everything_is_relayed_by_default() {
+        let config = CaptureConfig::default();
+        for (category, switch) in [
+            (EventCategory::Keyboard, RelaySwitch::Keyboard),
+            (EventCategory::Pointer, RelaySwitch::Mouse),
+            (EventCategory::Pointer, RelaySwitch::Scroll),
+            (EventCategory::Gesture, RelaySwitch::Scroll),
+            (EventCategory::Switch, RelaySwitch::Always),
+        ] {
+            assert_eq!(
+                route_event(&config, Some(category), switch),
+                EventRoute::Relay,
+                "{:?}",
+                category
+            );
+        }
+        assert_eq!(
+            route_event(&config, None, RelaySwitch::Always),
+            EventRoute::Relay
+        );
+    }
+
+    #[test]
+    fn relay_settings_pick_out_mouse_and_scroll() {
+        let config = CaptureConfig {
+            relay_scroll: false,
+            ..CaptureConfig::default()
+        };
+        assert_eq!(
+            route_event(&config, Some(EventCategory::Pointer), RelaySwitch::Scroll),
+            EventRoute::Skip
+        );
+        assert_eq!(
+            route_event(&config, Some(EventCategory::Pointer), RelaySwitch::Mouse),
+            EventRoute::Relay
+        );
+    }
+
+    #[test]
+    fn the_toggle_key_works_without_keyboard_relay() {
+        let without_relay = CaptureConfig {
+            relay_keyboard: false,
+            ..CaptureConfig::default()
+        };
+        let without_category = CaptureConfig {
+            event_categories: vec![EventCategory::Pointer],
+            ..CaptureConfig::default()
+        };
+        for config in [without_relay, without_category] {
+            assert_eq!(
+                route_event(
+                    &config,
+                    Some(EventCategory::Keyboard),
+                    RelaySwitch::Keyboard
+                ),
+                EventRoute::ToggleKeyOnly
+            );
+        }
+    }
+
+    #[test]
+    fn a_disabled_category_is_skipped() {
+        let config = CaptureConfig {
+            event_categories: vec![EventCategory::Keyboard],
+            ..CaptureConfig::default()
+        };
+        assert_eq!(
+            route_event(&config, Some(EventCategory::Pointer), RelaySwitch::Mouse),
+            EventRoute::Skip
+        );
+        assert_eq!(
+            route_event(&config, Some(EventCategory::Touch), RelaySwitch::Always),
+            EventRoute::Skip
+        );
+        assert_eq!(
+            route_event(
+                &config,
+                Some(EventCategory::Keyboard),
+                RelaySwitch::Keyboard
+            ),
+            EventRoute::Relay
+        );
+    }
+
+    #[test]
+    fn key_repeat_waits_for_the_delay_then_follows_the_interval() {
+        let pressed_at = Instant::now();
+        let at = |ms| pressed_at + Duration::from_millis(ms);
+        let interval = Duration::from_millis(30);
+        let mut repeat = KeyRepeat::new(30, Duration::from_millis(500), pressed_at);
+
+        assert!(!repeat.due(at(0), interval));
+        assert!(!repeat.due(at(499), interval));
+        assert!(repeat.due(at(500), interval));
+        assert!(!repeat.due(at(520), interval));
+        assert!(repeat.due(at(530), interval));
+        // A late check schedules the next repeat from when it ran
+        assert!(repeat.due(at(600), interval));
+        assert!(!repeat.due(at(620), interval));
+        assert!(repeat.due(at(630), interval));
+    }
+
+    #[test]
+    fn pausing_turns_relay_off_and_resuming_back_on() {
+        let relaying = RelayState {
+            relay_enabled: true,
+            ..RelayState::default()
+        };
+        assert_eq!(
+            pause_transition(false, &relaying, false),
+            PauseTransition::Unchanged
+        );
+        assert_eq!(
+            pause_transition(true, &relaying, false),
+            PauseTransition::Pause {
+                disable_relay: true
+            }
+        );
+
+        // A repeated request while paused changes nothing
+        let paused = RelayState {
+            paused: true,
+            ..RelayState::default()
+        };
+        assert_eq!(
+            pause_transition(true, &paused, true),
+            PauseTransition::Unchanged
+        );
+        assert_eq!(
+            pause_transition(false, &paused, true),
+            PauseTransition::Resume { enable_relay: true }
+        );
+    }
+
+    #[test]
+    fn resuming_leaves_relay_off_if_it_was_off() {
+        let idle = RelayState::default();
+        assert_eq!(
+            pause_transition(true, &idle, true),
+            PauseTransition::Pause {
+                disable_relay: false
+            }
+        );
+
+        let paused = RelayState {
+            paused: true,
+            ..RelayState::default()
+        };
+        assert_eq!(
+            pause_transition(false, &paused, false),
+            PauseTransition::Resume {
+                enable_relay: false
+            }
+        );
+    }
+
+    #[test]
+    fn coalesced_moves_never_exceed_the_cap() {
+        // A burst coalesced into one move, drained the way a click drains it
+        let mut pending = (-54.0, 24.0);
+        let mut sent = Vec::new();
+        loop {
+            let (step, carry) = cap_motion(pending, 16);
+            pending = carry;
+            if step == (0, 0) {
+                break;
+            }
+            sent.push(step);
+        }
+        assert!(
+            sent.iter().all(|(x, y)| x.abs() <= 16 && y.abs() <= 16),
+            "{:?}",
+            sent
+        );
+        assert_eq!(total(&sent), (-54, 24));
+    }
+
+    #[test]
+    fn a_zero_cap_sends_the_whole_move() {
+        assert_eq!(coalesce(&[(250.0, -90.0)], 1, 0), vec![(250, -90)]);
+    }
+
+    #[test]
+    fn resume_is_detected_from_the_wall_clock_jumping_ahead() {
+        let mut detector = ResumeDetector::new();
+        let (wall, monotonic) = (detector.wall, detector.monotonic);
+
+        // Both clocks moved together: no sleep
+        let second = Duration::from_secs(1);
+        assert_eq!(detector.check_at(wall + second, monotonic + second), None);
+
+        // The wall clock moved ten minutes while the monotonic one moved a second more
+        let slept = detector.check_at(wall + Duration::from_secs(601), monotonic + 2 * second);
+        assert_eq!(slept, Some(Duration::from_secs(599)));
+
+        // Checks go on from the resumed times
+        assert_eq!(
+            detector.check_at(wall + Duration::from_secs(602), monotonic + 3 * second),
+            None
+        );
+    }
+
+    #[test]
+    fn short_gaps_and_clock_changes_are_not_a_resume() {
+        let mut detector = ResumeDetector::new();
+        let (wall, monotonic) = (detector.wall, detector.monotonic);
+
+        // Drift below the threshold, e.g. an NTP adjustment
+        assert_eq!(
+            detector.check_at(wall + Duration::from_secs(3), monotonic),
+            None
+        );
+        // The wall clock set back
+        assert_eq!(
+            detector.check_at(wall - Duration::from_secs(3600), monotonic),
+            None
+        );
+    }
+
+    #[test]
+    fn device_latency_moves_the_timestamp_back() {
+        let config = CaptureConfig {
+            device_latency: vec![DeviceLatencyConfig {
+                device: "Slow Wireless Mouse".to_string(),
+                offset_ms: 12,
+            }],
+            ..CaptureConfig::default()
+        };
+
+        let mut packet = Packet::new(Message::InputEventTyped(InputEventType::MouseMove {
+            x: 1,
+            y: 0,
+        }));
+        packet.timestamp = 1_000;
+        compensate_latency(
+            &mut packet,
+            config.device_latency_offset("Slow Wireless Mouse"),
+        );
+        assert_eq!(packet.timestamp, 988);
+
+        // Other devices are taken at their word
+        compensate_latency(&mut packet, config.device_latency_offset("Keyboard"));
+        assert_eq!(packet.timestamp, 988);
+    }
+
+    #[test]
+    fn latency_offset_stops_at_the_epoch() {
+        let mut packet = Packet::new(Message::ResetInputState);
+        packet.timestamp = 5;
+        compensate_latency(&mut packet, 12);
+        assert_eq!(packet.timestamp, 0);
+    }
+
+    #[test]
+    fn keys_held_at_toggle_off_are_released_once() {
+        let mut pressed = PressedInputs::default();
+        // Ctrl is relayed, the F12 completing the toggle combination is not
+        assert!(pressed.track(&InputEventType::KeyPress {
+            key_code: key_codes::KEY_LEFTCTRL as u16
+        }));
+
+        let releases = pressed.releases();
+        assert_eq!(
+            format!("{:?}", releases),
+            format!("[KeyRelease {{ key_code: {} }}]", key_codes::KEY_LEFTCTRL)
+        );
+        for release in &releases {
+            assert!(pressed.track(release));
+        }
+
+        // The physical release, should it still be relayed, is not sent again
+        assert!(!pressed.track(&InputEventType::KeyRelease {
+            key_code: key_codes::KEY_LEFTCTRL as u16
+        }));
+        // and the key can be pressed afresh once relay is back on
+        assert!(pressed.track(&InputEventType::KeyPress {
+            key_code: key_codes::KEY_LEFTCTRL as u16
+        }));
+    }
+
+    #[test]
+    fn hotplugged_devices_are_grabbed_only_while_relaying() {
+        let mut names = HashMap::new();
+        let mut grabbed: HashMap<String, ()> = HashMap::new();
+        let path = "/dev/input/event7";
+
+        assert_eq!(
+            track_hotplug(
+                &mut names,
+                &mut grabbed,
+                path,
+                "USB Keyboard",
+                HotplugChange::Added,
+                false
+            ),
+            HotplugOutcome::Unchanged
+        );
+        assert_eq!(names[path], "USB Keyboard");
+
+        assert_eq!(
+            track_hotplug(
+                &mut names,
+                &mut grabbed,
+                path,
+                "USB Keyboard",
+                HotplugChange::Added,
+                true
+            ),
+            HotplugOutcome::Grab
+        );
+
+        // Already grabbed, e.g. by the initial scan
+        grabbed.insert(path.to_string(), ());
+        assert_eq!(
+            track_hotplug(
+                &mut names,
+                &mut grabbed,
+                path,
+                "USB Keyboard",
+                HotplugChange::Added,
+                true
+            ),
+            HotplugOutcome::Unchanged
+        );
+    }
+
+    #[test]
+    fn removed_devices_are_forgotten_and_released() {
+        let mut names = HashMap::new();
+        let mut grabbed: HashMap<String, ()> = HashMap::new();
+        let (kept, removed) = ("/dev/input/event3", "/dev/input/event4");
+        for path in [kept, removed] {
+            track_hotplug(
+                &mut names,
+                &mut grabbed,
+                path,
+                "Mouse",
+                HotplugChange::Added,
+                true,
+            );
+            grabbed.insert(path.to_string(), ());
+        }
+
+        assert_eq!(
+            track_hotplug(
+                &mut names,
+                &mut grabbed,
+                removed,
+                "Mouse",
+                HotplugChange::Removed,
+                true
+            ),
+            HotplugOutcome::Released
+        );
+        assert!(!names.contains_key(removed));
+        assert!(!grabbed.contains_key(removed));
+        assert!(grabbed.contains_key(kept));
+
+        // A device removed without being grabbed has nothing to release
+        assert_eq!(
+            track_hotplug(
+                &mut names,
+                &mut grabbed,
+                removed,
+                "Mouse",
+                HotplugChange::Removed,
+                true
+            ),
+            HotplugOutcome::Unchanged
+        );
+    }
+
+    #[test]
+    fn device_names_end_at_the_first_nul() {
+        assert_eq!(decode_device_name(b"AT Keyboard\0\0garbage"), "AT Keyboard");
+        assert_eq!(decode_device_name(b"No terminator"), "No terminator");
+        assert_eq!(decode_device_name(b"\0"), "");
+    }
+
+    #[test]
+    fn invalid_utf8_in_device_names_is_replaced() {
+        assert_eq!(
+            decode_device_name(b"Mouse \xff\xfe\0"),
+            "Mouse \u{fffd}\u{fffd}"
+        );
+    }
+
+    #[test]
+    fn device_names_come_from_libinput_before_the_kernel() {
+        let device_names =
+            HashMap::from([("/dev/input/event2".to_string(), "Logitech MX".to_string())]);
+
+        let name = device_name(&device_names, "/dev/input/event2", || {
+            panic!("the kernel is not asked for a known device")
+        });
+        assert_eq!(name.as_deref(), Some("Logitech MX"));
+
+        let name = device_name(&device_names, "/dev/input/event9", || {
+            Some("Power Button".to_string())
+        });
+        assert_eq!(name.as_deref(), Some("Power Button"));
+        assert_eq!(
+            device_name(&device_names, "/dev/input/event9", || None),
+            None
+        );
+    }
+
+    #[test]
+    fn an_unknown_seat_lists_the_available_ones() {
+        let seats = vec!["seat0".to_string(), "seat1".to_string()];
+        assert_eq!(
+            seat_hint("seat9", &seats),
+            "available seats are seat0, seat1, set capture.seat to one of them"
+        );
+    }
+
+    #[test]
+    fn a_known_seat_points_at_permissions() {
+        let seats = vec!["seat0".to_string()];
+        assert!(seat_hint("seat0", &seats).contains("input group"));
+        // Without logind there is nothing to compare the seat against
+        assert!(seat_hint("seat0", &[]).contains("input group"));
+    }
+
+    #[test]
+    fn seats_are_listed_in_order() {
+        let dir = std::env::temp_dir().join(format!("asteria-seats-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for seat in ["seat1", "seat0"] {
+            std::fs::write(dir.join(seat), "").unwrap();
+        }
+
+        assert_eq!(available_seats(&dir), ["seat0", "seat1"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(available_seats(&dir).is_empty());
+    }
+}
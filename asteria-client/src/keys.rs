@@ -1,5 +1,5 @@
-/// Common Linux input key codes
-/// These correspond to the constants defined in linux/input-event-codes.h
+//! Common Linux input key codes
+//! These correspond to the constants defined in linux/input-event-codes.h
 
 #[allow(dead_code)]
 pub mod key_codes {
@@ -73,9 +73,24 @@ pub mod key_codes {
     pub const KEY_F10: u32 = 68;
     pub const KEY_NUMLOCK: u32 = 69;
     pub const KEY_SCROLLLOCK: u32 = 70;
+    pub const KEY_KP7: u32 = 71;
+    pub const KEY_KP8: u32 = 72;
+    pub const KEY_KP9: u32 = 73;
+    pub const KEY_KPMINUS: u32 = 74;
+    pub const KEY_KP4: u32 = 75;
+    pub const KEY_KP5: u32 = 76;
+    pub const KEY_KP6: u32 = 77;
+    pub const KEY_KPPLUS: u32 = 78;
+    pub const KEY_KP1: u32 = 79;
+    pub const KEY_KP2: u32 = 80;
+    pub const KEY_KP3: u32 = 81;
+    pub const KEY_KP0: u32 = 82;
+    pub const KEY_KPDOT: u32 = 83;
     pub const KEY_F11: u32 = 87;
     pub const KEY_F12: u32 = 88;
+    pub const KEY_KPENTER: u32 = 96;
     pub const KEY_RIGHTCTRL: u32 = 97;
+    pub const KEY_KPSLASH: u32 = 98;
     pub const KEY_RIGHTALT: u32 = 100;
     pub const KEY_HOME: u32 = 102;
     pub const KEY_UP: u32 = 103;
@@ -91,35 +106,217 @@ pub mod key_codes {
     pub const KEY_RIGHTMETA: u32 = 126;
 }
 
+/// Every named key code with its human readable name, which with whitespace
+/// removed matches the constant name without the `KEY_` prefix
+const KEY_TABLE: &[(u32, &str)] = &[
+    (key_codes::KEY_ESC, "Esc"),
+    (key_codes::KEY_1, "1"),
+    (key_codes::KEY_2, "2"),
+    (key_codes::KEY_3, "3"),
+    (key_codes::KEY_4, "4"),
+    (key_codes::KEY_5, "5"),
+    (key_codes::KEY_6, "6"),
+    (key_codes::KEY_7, "7"),
+    (key_codes::KEY_8, "8"),
+    (key_codes::KEY_9, "9"),
+    (key_codes::KEY_0, "0"),
+    (key_codes::KEY_MINUS, "Minus"),
+    (key_codes::KEY_EQUAL, "Equal"),
+    (key_codes::KEY_BACKSPACE, "Backspace"),
+    (key_codes::KEY_TAB, "Tab"),
+    (key_codes::KEY_Q, "Q"),
+    (key_codes::KEY_W, "W"),
+    (key_codes::KEY_E, "E"),
+    (key_codes::KEY_R, "R"),
+    (key_codes::KEY_T, "T"),
+    (key_codes::KEY_Y, "Y"),
+    (key_codes::KEY_U, "U"),
+    (key_codes::KEY_I, "I"),
+    (key_codes::KEY_O, "O"),
+    (key_codes::KEY_P, "P"),
+    (key_codes::KEY_LEFTBRACE, "Left Brace"),
+    (key_codes::KEY_RIGHTBRACE, "Right Brace"),
+    (key_codes::KEY_ENTER, "Enter"),
+    (key_codes::KEY_LEFTCTRL, "Left Ctrl"),
+    (key_codes::KEY_A, "A"),
+    (key_codes::KEY_S, "S"),
+    (key_codes::KEY_D, "D"),
+    (key_codes::KEY_F, "F"),
+    (key_codes::KEY_G, "G"),
+    (key_codes::KEY_H, "H"),
+    (key_codes::KEY_J, "J"),
+    (key_codes::KEY_K, "K"),
+    (key_codes::KEY_L, "L"),
+    (key_codes::KEY_SEMICOLON, "Semicolon"),
+    (key_codes::KEY_APOSTROPHE, "Apostrophe"),
+    (key_codes::KEY_GRAVE, "Grave"),
+    (key_codes::KEY_LEFTSHIFT, "Left Shift"),
+    (key_codes::KEY_BACKSLASH, "Backslash"),
+    (key_codes::KEY_Z, "Z"),
+    (key_codes::KEY_X, "X"),
+    (key_codes::KEY_C, "C"),
+    (key_codes::KEY_V, "V"),
+    (key_codes::KEY_B, "B"),
+    (key_codes::KEY_N, "N"),
+    (key_codes::KEY_M, "M"),
+    (key_codes::KEY_COMMA, "Comma"),
+    (key_codes::KEY_DOT, "Dot"),
+    (key_codes::KEY_SLASH, "Slash"),
+    (key_codes::KEY_RIGHTSHIFT, "Right Shift"),
+    (key_codes::KEY_KPASTERISK, "KP Asterisk"),
+    (key_codes::KEY_LEFTALT, "Left Alt"),
+    (key_codes::KEY_SPACE, "Space"),
+    (key_codes::KEY_CAPSLOCK, "Caps Lock"),
+    (key_codes::KEY_F1, "F1"),
+    (key_codes::KEY_F2, "F2"),
+    (key_codes::KEY_F3, "F3"),
+    (key_codes::KEY_F4, "F4"),
+    (key_codes::KEY_F5, "F5"),
+    (key_codes::KEY_F6, "F6"),
+    (key_codes::KEY_F7, "F7"),
+    (key_codes::KEY_F8, "F8"),
+    (key_codes::KEY_F9, "F9"),
+    (key_codes::KEY_F10, "F10"),
+    (key_codes::KEY_NUMLOCK, "Num Lock"),
+    (key_codes::KEY_SCROLLLOCK, "Scroll Lock"),
+    (key_codes::KEY_KP7, "KP 7"),
+    (key_codes::KEY_KP8, "KP 8"),
+    (key_codes::KEY_KP9, "KP 9"),
+    (key_codes::KEY_KPMINUS, "KP Minus"),
+    (key_codes::KEY_KP4, "KP 4"),
+    (key_codes::KEY_KP5, "KP 5"),
+    (key_codes::KEY_KP6, "KP 6"),
+    (key_codes::KEY_KPPLUS, "KP Plus"),
+    (key_codes::KEY_KP1, "KP 1"),
+    (key_codes::KEY_KP2, "KP 2"),
+    (key_codes::KEY_KP3, "KP 3"),
+    (key_codes::KEY_KP0, "KP 0"),
+    (key_codes::KEY_KPDOT, "KP Dot"),
+    (key_codes::KEY_F11, "F11"),
+    (key_codes::KEY_F12, "F12"),
+    (key_codes::KEY_KPENTER, "KP Enter"),
+    (key_codes::KEY_RIGHTCTRL, "Right Ctrl"),
+    (key_codes::KEY_KPSLASH, "KP Slash"),
+    (key_codes::KEY_RIGHTALT, "Right Alt"),
+    (key_codes::KEY_HOME, "Home"),
+    (key_codes::KEY_UP, "Up"),
+    (key_codes::KEY_PAGEUP, "Page Up"),
+    (key_codes::KEY_LEFT, "Left"),
+    (key_codes::KEY_RIGHT, "Right"),
+    (key_codes::KEY_END, "End"),
+    (key_codes::KEY_DOWN, "Down"),
+    (key_codes::KEY_PAGEDOWN, "Page Down"),
+    (key_codes::KEY_INSERT, "Insert"),
+    (key_codes::KEY_DELETE, "Delete"),
+    (key_codes::KEY_LEFTMETA, "Left Meta"),
+    (key_codes::KEY_RIGHTMETA, "Right Meta"),
+];
+
+/// All named key codes and their names
+pub fn all_key_codes() -> &'static [(u32, &'static str)] {
+    KEY_TABLE
+}
+
+/// Short names for modifiers, resolving to the left hand key
+const KEY_ALIASES: &[(&str, u32)] = &[
+    ("CTRL", key_codes::KEY_LEFTCTRL),
+    ("ALT", key_codes::KEY_LEFTALT),
+    ("SHIFT", key_codes::KEY_LEFTSHIFT),
+    ("META", key_codes::KEY_LEFTMETA),
+    ("SUPER", key_codes::KEY_LEFTMETA),
+];
+
+/// Look up a key code by its symbolic name, e.g. `LEFTCTRL`, `KEY_F12` or `Left Ctrl`.
+///
+/// Matching ignores case, whitespace and an optional `KEY_` prefix. Digit keys
+/// need the prefix (`KEY_1`) so they aren't mistaken for numeric key codes.
+pub fn key_code_from_name(name: &str) -> Option<u32> {
+    let normalized = normalize(name);
+
+    let symbol = match normalized.strip_prefix("KEY_") {
+        Some(symbol) => symbol,
+        None if normalized.chars().all(|c| c.is_ascii_digit()) => return None,
+        None => normalized.as_str(),
+    };
+
+    all_key_codes()
+        .iter()
+        .find(|(_, key_name)| normalize(key_name) == symbol)
+        .map(|(code, _)| *code)
+        .or_else(|| {
+            KEY_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == symbol)
+                .map(|(_, code)| *code)
+        })
+}
+
 /// Get a human-readable name for a key code
 pub fn key_name(key_code: u32) -> &'static str {
-    match key_code {
-        key_codes::KEY_ESC => "Escape",
-        key_codes::KEY_LEFTCTRL => "Left Ctrl",
-        key_codes::KEY_RIGHTCTRL => "Right Ctrl",
-        key_codes::KEY_LEFTALT => "Left Alt",
-        key_codes::KEY_RIGHTALT => "Right Alt",
-        key_codes::KEY_LEFTSHIFT => "Left Shift",
-        key_codes::KEY_RIGHTSHIFT => "Right Shift",
-        key_codes::KEY_LEFTMETA => "Left Meta/Super",
-        key_codes::KEY_RIGHTMETA => "Right Meta/Super",
-        key_codes::KEY_SPACE => "Space",
-        key_codes::KEY_ENTER => "Enter",
-        key_codes::KEY_TAB => "Tab",
-        key_codes::KEY_BACKSPACE => "Backspace",
-        key_codes::KEY_DELETE => "Delete",
-        key_codes::KEY_F1 => "F1",
-        key_codes::KEY_F2 => "F2",
-        key_codes::KEY_F3 => "F3",
-        key_codes::KEY_F4 => "F4",
-        key_codes::KEY_F5 => "F5",
-        key_codes::KEY_F6 => "F6",
-        key_codes::KEY_F7 => "F7",
-        key_codes::KEY_F8 => "F8",
-        key_codes::KEY_F9 => "F9",
-        key_codes::KEY_F10 => "F10",
-        key_codes::KEY_F11 => "F11",
-        key_codes::KEY_F12 => "F12",
-        _ => "Unknown",
+    all_key_codes()
+        .iter()
+        .find(|(code, _)| *code == key_code)
+        .map_or("Unknown", |(_, name)| *name)
+}
+
+/// Uppercase a key name and drop its whitespace
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_key_code_has_a_name() {
+        for (code, name) in all_key_codes() {
+            assert_ne!(*name, "Unknown");
+            assert_eq!(key_name(*code), *name);
+        }
+        assert_eq!(key_name(key_codes::KEY_A), "A");
+        assert_eq!(key_name(key_codes::KEY_1), "1");
+        assert_eq!(key_name(0xffff), "Unknown");
+    }
+
+    #[test]
+    fn names_match_ignoring_case_space_and_prefix() {
+        assert_eq!(
+            key_code_from_name("LEFTCTRL"),
+            Some(key_codes::KEY_LEFTCTRL)
+        );
+        assert_eq!(
+            key_code_from_name("left ctrl"),
+            Some(key_codes::KEY_LEFTCTRL)
+        );
+        assert_eq!(key_code_from_name("f1"), Some(key_codes::KEY_F1));
+        assert_eq!(key_code_from_name("KEY_F12"), Some(key_codes::KEY_F12));
+        assert_eq!(
+            key_code_from_name("RightAlt"),
+            Some(key_codes::KEY_RIGHTALT)
+        );
+        assert_eq!(
+            key_code_from_name("CapsLock"),
+            Some(key_codes::KEY_CAPSLOCK)
+        );
+        assert_eq!(key_code_from_name("ctrl"), Some(key_codes::KEY_LEFTCTRL));
+        assert_eq!(key_code_from_name("NotAKey"), None);
+    }
+
+    #[test]
+    fn bare_digits_are_left_to_numeric_parsing() {
+        assert_eq!(key_code_from_name("29"), None);
+        assert_eq!(key_code_from_name("KEY_1"), Some(key_codes::KEY_1));
+    }
+
+    #[test]
+    fn every_name_leads_back_to_its_key_code() {
+        for (code, name) in all_key_codes() {
+            let prefixed = format!("KEY_{}", name);
+            assert_eq!(key_code_from_name(&prefixed), Some(*code), "{}", name);
+        }
     }
 }
@@ -1,20 +1,54 @@
+mod batch;
+mod clipboard;
+mod control;
+mod edge;
+mod flaky;
+mod gamepad;
 mod input;
 mod keys;
+mod monitor;
 mod network;
+mod persist;
+mod queue;
+mod reload;
+mod replay;
+mod resolve;
+mod scroll;
+mod socks;
+mod stats;
+mod status;
+mod toggle;
 
 use anyhow::{Ok, Result};
-use asteria_core::init_logging;
-use clap::{Arg, ArgMatches, Command};
-use tracing::{error, info};
+use asteria_core::{
+    config::{ClientConfig, LoadableConfig},
+    init_logging, verbosity_level,
+};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
 
+use crate::clipboard::{ClipboardMonitor, CommandClipboardSource};
 use crate::input::InputCapture;
 use crate::network::NetworkClient;
+use crate::reload::ConfigReloader;
+use crate::replay::{
+    PacketRecorder, ReplayPace, parse_max_rate, parse_speed, read_recording, replay,
+};
+use crate::status::{finish_status, show_status};
+use crate::toggle::ToggleCombo;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let matches: ArgMatches = build_cli().get_matches();
 
-    init_logging();
+    let config_path = match matches.get_one::<String>("config") {
+        Some(path) => PathBuf::from(path),
+        None => ClientConfig::config_path()?,
+    };
+    let config = ClientConfig::load_from(&config_path)?;
+    let verbosity = verbosity_level(matches.get_count("verbose"), matches.get_flag("quiet"));
+    let _log_guard = init_logging(&config.logging, "asteria-client", verbosity);
 
     match matches.subcommand() {
         Some(("start", sub_m)) => {
@@ -22,18 +56,10 @@ async fn main() -> Result<()> {
 
             // Parse the toggle key
             let toggle_key_str = sub_m.get_one::<String>("toggle-key").unwrap();
-            let toggle_key = if toggle_key_str.starts_with("0x") {
-                u32::from_str_radix(&toggle_key_str[2..], 16).map_err(|_| {
-                    anyhow::anyhow!("Invalid hexadecimal key code: {}", toggle_key_str)
-                })?
-            } else {
-                toggle_key_str
-                    .parse::<u32>()
-                    .map_err(|_| anyhow::anyhow!("Invalid key code: {}", toggle_key_str))?
-            };
+            let toggle_combo = ToggleCombo::parse(toggle_key_str)?;
 
             info!("=== Asteria Client Started ===");
-            info!("Toggle key set to: 0x{:02x}", toggle_key);
+            info!("Toggle key set to: {}", toggle_combo);
             info!("Press the toggle key to enable/disable relay");
             info!("When relay is enabled:");
             info!("  - Your input is sent to Windows");
@@ -42,12 +68,58 @@ async fn main() -> Result<()> {
             info!("================================");
 
             // Create network client and input capture
-            let network_client = NetworkClient::new()?;
-            let mut input_capture = InputCapture::new_with_toggle_key(toggle_key)?;
+            let mut input_capture =
+                InputCapture::new_with_toggle_key(toggle_combo, config.capture.clone())?;
+            if sub_m.get_flag("clipboard-sync") {
+                input_capture.set_clipboard_monitor(ClipboardMonitor::new(
+                    Box::new(CommandClipboardSource::new()),
+                    &config.clipboard,
+                ));
+            }
+            if config.control.enabled {
+                match config.control.socket_path() {
+                    Some(path) => input_capture.set_control_socket(path),
+                    None => warn!(
+                        "No runtime directory for the control socket, set control.socket_path to serve it"
+                    ),
+                }
+            }
+            input_capture.set_grab_config(config.grab.clone());
+            input_capture.set_config_reloader(ConfigReloader::new(
+                config_path.clone(),
+                config.clone(),
+                verbosity.is_some(),
+            ));
 
-            // Start the client
+            let monitor = sub_m.get_flag("monitor");
+            let network_client = if monitor {
+                None
+            } else {
+                let mut network_client = NetworkClient::new(config)?;
+                if let Some(path) = sub_m.get_one::<String>("record") {
+                    info!("Recording relayed packets to {}", path);
+                    network_client.record_to(PacketRecorder::create(Path::new(path))?);
+                }
+                Some(network_client)
+            };
+            let status_task = sub_m.get_flag("status").then(|| {
+                tokio::spawn(show_status(
+                    input_capture.relay_state_handle(),
+                    input_capture.grabbed_device_count(),
+                    network_client.as_ref().map(NetworkClient::counters),
+                    network_client.as_ref().map(NetworkClient::subscribe),
+                ))
+            });
+
+            // Start the client, or only log captured events in monitor mode
+            let capture = async {
+                match network_client {
+                    Some(network_client) => input_capture.start_and_relay(network_client).await,
+                    None => input_capture.start_monitor().await,
+                }
+            };
             tokio::select! {
-                result = input_capture.start_and_relay(network_client) => {
+                result = capture => {
                     if let Err(e) = result {
                         error!("Input capture failed: {}", e);
                     }
@@ -56,17 +128,44 @@ async fn main() -> Result<()> {
                     info!("Received shutdown signal");
                 }
             }
+
+            if let Some(status_task) = status_task {
+                status_task.abort();
+                finish_status();
+            }
+
+            input_capture.shutdown().await?;
+        }
+        Some(("config", sub_m)) => {
+            println!("{}", config_path.display());
+            if !sub_m.get_flag("path") {
+                println!();
+                print!("{}", config.to_toml()?);
+            }
         }
         Some(("ping", sub_m)) => {
-            let mut network_client = NetworkClient::new()?;
+            let mut network_client = NetworkClient::new(config)?;
             let host = sub_m.get_one::<String>("host");
 
             if let Some(host) = host {
                 info!("Pinging host: {}", host);
-                // TODO: Override config with specific host
+                network_client.set_server_host(host.clone());
             }
 
-            network_client.ping().await?;
+            let result = network_client.ping().await;
+            info!("Network stats: {}", network_client.counters());
+            result?;
+        }
+        Some(("replay", sub_m)) => {
+            let path = sub_m.get_one::<String>("file").expect("file is required");
+            let pace = ReplayPace {
+                speed: *sub_m.get_one::<f64>("speed").expect("speed has a default"),
+                max_rate: sub_m.get_one::<f64>("max-rate").copied(),
+            };
+
+            let packets = read_recording(Path::new(path))?;
+            info!("Replaying {} packets from {}", packets.len(), path);
+            replay(NetworkClient::new(config)?, packets, pace).await?;
         }
         _ => {
             error!("Invalid command. Use --help for usage information.");
@@ -80,18 +179,104 @@ fn build_cli() -> Command {
     Command::new("asteria-client")
         .version(env!("CARGO_PKG_VERSION"))
         .about("Asteria client application")
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .help("Config file to use instead of the default location")
+                .value_name("PATH")
+                .global(true),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Log more, -v for debug and -vv for trace output")
+                .action(ArgAction::Count)
+                .global(true),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only log warnings and errors")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose")
+                .global(true),
+        )
         .subcommand(
-            Command::new("start").about("Start the Asteria client").arg(
-                Arg::new("toggle-key")
-                    .long("toggle-key")
-                    .help("Hexadecimal key code for the toggle key (e.g., 0x1D for Left Ctrl)")
-                    .value_name("KEY_CODE")
-                    .default_value("0x1D"),
-            ),
+            Command::new("start")
+                .about("Start the Asteria client")
+                .arg(
+                    Arg::new("toggle-key")
+                        .long("toggle-key")
+                        .help("Toggle key name or code, or a combination (e.g., LEFTCTRL, 0x1D or Ctrl+Alt+F12)")
+                        .value_name("KEY")
+                        .default_value("0x1D"),
+                )
+                .arg(
+                    Arg::new("clipboard-sync")
+                        .long("clipboard-sync")
+                        .help("Keep the clipboard text in sync with the server")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("monitor")
+                        .long("monitor")
+                        .help("Log captured events without connecting to a server or grabbing devices")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("status")
+                        .long("status")
+                        .help("Show relay state, grabbed devices and connection state on a status line")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("record")
+                        .long("record")
+                        .help("Write every relayed packet to a file, for the replay command")
+                        .value_name("FILE")
+                        .conflicts_with("monitor"),
+                ),
         )
         .subcommand(
             Command::new("ping")
                 .about("Send a ping to test connectivity")
                 .arg(Arg::new("host").help("Specific host to ping").index(1)),
         )
+        .subcommand(
+            Command::new("replay")
+                .about("Send the packets of a recording made with start --record to the server")
+                .arg(
+                    Arg::new("file")
+                        .help("Recording with one JSON packet per line")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("speed")
+                        .long("speed")
+                        .help("Playback speed relative to the recording, 0 sends without waiting")
+                        .value_name("FACTOR")
+                        .value_parser(parse_speed)
+                        .default_value("1.0"),
+                )
+                .arg(
+                    Arg::new("max-rate")
+                        .long("max-rate")
+                        .help("Most packets sent per second, whatever the speed")
+                        .value_name("PER_SECOND")
+                        .value_parser(parse_max_rate),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Print the config file location and the effective configuration")
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .help("Only print the config file location")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
 }
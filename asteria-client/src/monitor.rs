@@ -0,0 +1,92 @@
+use asteria_core::protocol::{InputEventType, Message, Packet};
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::keys::key_name;
+
+/// Log every packet the capture loop would relay, in place of a network client
+pub async fn log_packets(mut packet_receiver: mpsc::Receiver<Packet>) {
+    while let Some(packet) = packet_receiver.recv().await {
+        match packet.message {
+            Message::InputEventTyped(event) => info!("{}", describe_event(&event)),
+            Message::Batch(events) => {
+                for event in &events {
+                    info!("{}", describe_event(event));
+                }
+            }
+            message => info!("{:?}", message),
+        }
+    }
+}
+
+/// Describe an event, naming the key for keyboard events
+fn describe_event(event: &InputEventType) -> String {
+    match *event {
+        InputEventType::KeyPress { key_code } => describe_key("KeyPress", key_code),
+        InputEventType::KeyRelease { key_code } => describe_key("KeyRelease", key_code),
+        InputEventType::KeyRepeat { key_code } => describe_key("KeyRepeat", key_code),
+        _ => format!("{:?}", event),
+    }
+}
+
+fn describe_key(action: &str, key_code: u16) -> String {
+    format!(
+        "{} {} (0x{:02x})",
+        action,
+        key_name(u32::from(key_code)),
+        key_code
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_events_name_the_key() {
+        assert_eq!(
+            describe_event(&InputEventType::KeyPress { key_code: 30 }),
+            "KeyPress A (0x1e)"
+        );
+        assert_eq!(
+            describe_event(&InputEventType::KeyRelease { key_code: 1 }),
+            "KeyRelease Esc (0x01)"
+        );
+        assert_eq!(
+            describe_event(&InputEventType::KeyRepeat { key_code: 0x1c0 }),
+            format!("KeyRepeat {} (0x1c0)", key_name(0x1c0))
+        );
+    }
+
+    #[test]
+    fn other_events_are_debug_printed() {
+        assert_eq!(
+            describe_event(&InputEventType::MouseMove { x: 4, y: -2 }),
+            "MouseMove { x: 4, y: -2 }"
+        );
+    }
+
+    #[tokio::test]
+    async fn logging_ends_with_the_capture() {
+        let (sender, receiver) = mpsc::channel(4);
+        let logger = tokio::spawn(log_packets(receiver));
+
+        sender
+            .send(Packet::new(Message::Batch(vec![
+                InputEventType::KeyPress { key_code: 30 },
+                InputEventType::KeyRelease { key_code: 30 },
+            ])))
+            .await
+            .unwrap();
+        sender
+            .send(Packet::new(Message::ResetInputState))
+            .await
+            .unwrap();
+        drop(sender);
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), logger)
+            .await
+            .expect("logging stops once capture drops its sender")
+            .unwrap();
+    }
+}
@@ -1,97 +1,1497 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
 use asteria_core::{
-    config::{ClientConfig, LoadableConfig},
-    protocol::Packet,
+    auth::sign_nonce,
+    clipboard::{ClipboardAssembler, ClipboardPayload, negotiate_formats},
+    config::{ClientConfig, ReconnectConfig, TransportKind, WireFormat},
+    protocol::{
+        AuthResponse, ChecksumMismatch, ClipboardChunk, ClipboardFormat, Handshake,
+        MAX_DATAGRAM_SIZE, Message, PROTOCOL_VERSION, Packet, ScreenInfo, decode_frame,
+        encode_frame, read_frame,
+    },
+    tls::{tls_connector, tls_server_name},
+};
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
 };
 use tokio::{
-    io::{AsyncWriteExt, BufWriter},
-    net::TcpStream,
-    sync::mpsc,
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter, ReadHalf, WriteHalf},
+    net::{TcpStream, UdpSocket},
+    sync::{Notify, broadcast, mpsc, oneshot, watch},
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
 };
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::flaky::FlakyLink;
+use crate::queue::is_droppable;
+use crate::replay::PacketRecorder;
+use crate::resolve::AddressCache;
+use crate::socks::connect_through;
+
+/// How long to wait for the server's authentication challenge
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `ping` waits for the pong
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connection events kept for subscribers that fall behind
+const EVENT_CAPACITY: usize = 16;
+
+/// Packets from the server waiting to be handled by the relay
+const INCOMING_CAPACITY: usize = 64;
+
+/// Clipboard content from the server waiting to be put on the local clipboard
+const CLIPBOARD_CAPACITY: usize = 4;
+
+/// Part of each server's queue pointer motion is kept out of, as one over
+/// this many, so keys and buttons rarely wait for room
+const RESERVED_QUEUE_SHARE: usize = 4;
+
+/// Byte stream to the server
+trait Connection: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> Connection for T {}
+
+/// Connection to the server, either plain TCP or TLS over TCP
+type Transport = Box<dyn Connection>;
 
-/// Network client that handles TCP communication with the server
+/// Network client that relays input to the configured server and every mirror
 pub struct NetworkClient {
-    config: ClientConfig,
-    stream: Option<BufWriter<TcpStream>>,
+    targets: Vec<ServerConnection>,
+    /// Signalled when the connections should be re-established, e.g. after resume
+    reconnect_requested: Arc<Notify>,
+    link_check_sender: mpsc::Sender<LinkCheck>,
+    link_checks: mpsc::Receiver<LinkCheck>,
+    counters: Arc<NetworkCounters>,
+    events: broadcast::Sender<ConnectionEvent>,
+    /// Screen the configured server reported in its handshake
+    server_screen: watch::Sender<Option<ScreenInfo>>,
+    /// Where relayed packets are written, if they are recorded
+    recorder: Option<PacketRecorder>,
+}
+
+/// Change in a server connection, published to [`NetworkClient::subscribe`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Connected,
+    /// The connection was lost or closed, with the reason
+    Disconnected(String),
+    /// About to retry the connection, counting attempts from 1
+    Reconnecting {
+        attempt: u32,
+    },
+}
+
+/// Connection state and traffic of the relay, shared with the status line
+#[derive(Debug, Default)]
+pub struct NetworkCounters {
+    servers: usize,
+    connected_servers: AtomicUsize,
+    packets_sent: AtomicU64,
+    send_failures: AtomicU64,
+    reconnects: AtomicU64,
+}
+
+impl NetworkCounters {
+    /// Number of servers relayed to, the configured one and its mirrors
+    pub fn servers(&self) -> usize {
+        self.servers
+    }
+
+    pub fn connected_servers(&self) -> usize {
+        self.connected_servers.load(Ordering::Relaxed)
+    }
+
+    /// Packets written to any server, counted once per server
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent.load(Ordering::Relaxed)
+    }
+
+    /// Packets that failed to write and dropped their connection
+    pub fn send_failures(&self) -> u64 {
+        self.send_failures.load(Ordering::Relaxed)
+    }
+
+    /// Connections established by retrying, after one was lost, reset or refused
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for NetworkCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} servers connected, {} packets sent, {} send failures, {} reconnects",
+            self.connected_servers(),
+            self.servers(),
+            self.packets_sent(),
+            self.send_failures(),
+            self.reconnects()
+        )
+    }
+}
+
+/// Request for the relay to ping the server, answered with whether it replied in time
+type LinkCheck = (Duration, oneshot::Sender<bool>);
+
+/// Asks the relay task to confirm the server is answering
+#[derive(Clone)]
+pub struct LinkValidator {
+    requests: mpsc::Sender<LinkCheck>,
+    timeout: Duration,
+}
+
+impl LinkValidator {
+    /// Ping the servers through the relay, returning whether any answered in time
+    pub async fn validate(&self) -> bool {
+        let check = async {
+            let (reply, answered) = oneshot::channel();
+            if self.requests.send((self.timeout, reply)).await.is_err() {
+                return false;
+            }
+            answered.await.unwrap_or(false)
+        };
+
+        // The relay may be busy reconnecting, so bound the wait here as well
+        tokio::time::timeout(self.timeout, check)
+            .await
+            .unwrap_or(false)
+    }
+}
+
+/// Relay task of one server and the channels that feed it
+struct TargetHandle {
+    address: String,
+    packets: mpsc::Sender<Packet>,
+    /// Set when input was dropped for this server, which then resets its input state
+    input_dropped: Arc<AtomicBool>,
+    reconnect_requested: Arc<Notify>,
+    link_checks: mpsc::Sender<LinkCheck>,
+    task: JoinHandle<()>,
+}
+
+impl TargetHandle {
+    /// Queue `packet` for this server without waiting for room.
+    ///
+    /// A server that is behind or reconnecting must not hold up the others,
+    /// so what doesn't fit is dropped.
+    fn offer(&self, packet: &Packet) {
+        if is_droppable(packet) {
+            // Motion is only meaningful live, so a server that can't keep up
+            // misses it. It stays out of the reserved part of the queue.
+            let reserved = self.packets.max_capacity() / RESERVED_QUEUE_SHARE;
+            if self.packets.capacity() <= reserved || self.packets.try_send(packet.clone()).is_err()
+            {
+                warn!(
+                    "Dropped pointer motion {} for {}, relay is behind",
+                    packet.id, self.address
+                );
+            }
+        } else if self.packets.try_send(packet.clone()).is_err() {
+            // A lost release would leave its key stuck on the server, so the
+            // server's input state is reset before it gets input again
+            warn!(
+                "Dropped packet {} for {}, relay is behind",
+                packet.id, self.address
+            );
+            self.input_dropped.store(true, Ordering::Relaxed);
+        }
+    }
 }
 
 impl NetworkClient {
-    pub fn new() -> Result<Self> {
-        let config = ClientConfig::load()?;
+    pub fn new(config: ClientConfig) -> Result<Self> {
+        if config.relay_queue_size != config.relay_queue_size() {
+            warn!(
+                "relay_queue_size {} is too small, using {}",
+                config.relay_queue_size,
+                config.relay_queue_size()
+            );
+        }
+
+        let (link_check_sender, link_checks) = mpsc::channel(1);
+        let counters = Arc::new(NetworkCounters {
+            servers: 1 + config.network.mirror_hosts.len(),
+            ..NetworkCounters::default()
+        });
+
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let (server_screen, _) = watch::channel(None);
+
+        let mut targets = vec![ServerConnection::new(config.clone(), &counters, &events)];
+        // Edge switching leads to the configured server, so the mirrors'
+        // screens are of no interest
+        targets[0].peer.screen = Some(server_screen.clone());
+        for mirror in &config.network.mirror_hosts {
+            let mut mirror_config = config.clone();
+            let (host, port) = split_host_port(mirror, config.network.port)?;
+            mirror_config.network.server_host = Some(host);
+            mirror_config.network.port = port;
+            targets.push(ServerConnection::new(mirror_config, &counters, &events));
+        }
+
         Ok(Self {
+            targets,
+            reconnect_requested: Arc::new(Notify::new()),
+            link_check_sender,
+            link_checks,
+            counters,
+            events,
+            server_screen,
+            recorder: None,
+        })
+    }
+
+    /// Receive connection events of every server from now on.
+    ///
+    /// Events are only published while someone is subscribed, so the relay
+    /// behaves the same without subscribers.
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Screen the configured server reported in its handshake, `None` while
+    /// it is not connected or hasn't reported one
+    pub fn server_screen(&self) -> watch::Receiver<Option<ScreenInfo>> {
+        self.server_screen.subscribe()
+    }
+
+    /// Counters of the relay, updated while it runs
+    pub fn counters(&self) -> Arc<NetworkCounters> {
+        Arc::clone(&self.counters)
+    }
+
+    /// Handle that makes the relay drop and re-establish its connections when notified
+    pub fn reconnect_handle(&self) -> Arc<Notify> {
+        Arc::clone(&self.reconnect_requested)
+    }
+
+    /// Handle that pings the servers through the relay, waiting at most `timeout` for an answer
+    pub fn link_validator(&self, timeout: Duration) -> LinkValidator {
+        LinkValidator {
+            requests: self.link_check_sender.clone(),
+            timeout,
+        }
+    }
+
+    /// Write every packet relayed from now on to `recorder`
+    pub fn record_to(&mut self, recorder: PacketRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Announce the configured clipboard formats to every server and receive
+    /// the clipboard content the configured server sends.
+    ///
+    /// Without it no clipboard formats are agreed on, so clipboard content is
+    /// neither sent nor received.
+    pub fn sync_clipboard(&mut self) -> mpsc::Receiver<ClipboardPayload> {
+        let (sender, received) = mpsc::channel(CLIPBOARD_CAPACITY);
+        for target in &mut self.targets {
+            target.peer.local_formats = target.config.clipboard.formats.clone();
+        }
+        self.targets[0].peer.clipboard_sink = Some(sender);
+        received
+    }
+
+    /// Override the configured server host for this session, mirrors are kept
+    pub fn set_server_host(&mut self, host: String) {
+        self.targets[0].config.network.server_host = Some(host);
+    }
+
+    /// Start the network client that listens for packets from the input capture
+    /// and sends each one to every server.
+    ///
+    /// Every server is relayed to from its own task and reconnects on its own,
+    /// so one that is down doesn't hold up the others.
+    pub async fn start_relay(&mut self, mut packet_receiver: mpsc::Receiver<Packet>) -> Result<()> {
+        let mut first_error = None;
+        let mut connected = 0;
+        for target in &mut self.targets {
+            match target.connect_for_relay().await {
+                Ok(()) => connected += 1,
+                Err(e) => {
+                    error!("Failed to connect to {}: {}", target.address(), e);
+                    target.publish(ConnectionEvent::Disconnected(e.to_string()));
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if connected == 0
+            && let Some(e) = first_error
+        {
+            return Err(e);
+        }
+
+        let targets: Vec<TargetHandle> = self.targets.drain(..).map(spawn_target).collect();
+
+        loop {
+            tokio::select! {
+                packet = packet_receiver.recv() => {
+                    let Some(packet) = packet else {
+                        break;
+                    };
+
+                    if let Some(recorder) = &mut self.recorder
+                        && let Err(e) = recorder.record(&packet)
+                    {
+                        error!("Failed to record packet, recording stopped: {}", e);
+                        self.recorder = None;
+                    }
+
+                    let disconnecting = matches!(packet.message, Message::Disconnect { .. });
+                    for target in &targets {
+                        target.offer(&packet);
+                    }
+                    if disconnecting {
+                        break;
+                    }
+                }
+                _ = self.reconnect_requested.notified() => {
+                    for target in &targets {
+                        target.reconnect_requested.notify_one();
+                    }
+                }
+                Some((timeout, reply)) = self.link_checks.recv() => {
+                    let answers: Vec<oneshot::Receiver<bool>> = targets
+                        .iter()
+                        .filter_map(|target| {
+                            let (answer, answered) = oneshot::channel();
+                            target.link_checks.try_send((timeout, answer)).ok()?;
+                            Some(answered)
+                        })
+                        .collect();
+
+                    tokio::spawn(async move {
+                        let mut any_answered = false;
+                        for answered in answers {
+                            any_answered |= answered.await.unwrap_or(false);
+                        }
+                        let _ = reply.send(any_answered);
+                    });
+                }
+            }
+        }
+
+        // Closing the channels ends each relay once it has sent what it was given
+        for target in targets {
+            drop(target.packets);
+            let _ = target.task.await;
+        }
+
+        Ok(())
+    }
+
+    /// Test connectivity to every server
+    pub async fn ping(&mut self) -> Result<()> {
+        let mut unanswered = 0;
+        for target in &mut self.targets {
+            if let Err(e) = target.ping().await {
+                error!("{}", e);
+                unanswered += 1;
+            }
+        }
+
+        if unanswered > 0 {
+            bail!(
+                "{} of {} servers did not answer",
+                unanswered,
+                self.targets.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Run the relay of one server on its own task
+fn spawn_target(target: ServerConnection) -> TargetHandle {
+    let (packets, packet_receiver) = mpsc::channel(target.config.relay_queue_size());
+    let (link_checks, link_check_receiver) = mpsc::channel(1);
+    let reconnect_requested = Arc::new(Notify::new());
+
+    let address = target.address();
+    let input_dropped = Arc::clone(&target.input_dropped);
+    let task = tokio::spawn(target.relay(
+        packet_receiver,
+        Arc::clone(&reconnect_requested),
+        link_check_receiver,
+    ));
+
+    TargetHandle {
+        address,
+        packets,
+        input_dropped,
+        reconnect_requested,
+        link_checks,
+        task,
+    }
+}
+
+/// Split a mirror's `host:port`, using `default_port` when it has none
+fn split_host_port(mirror: &str, default_port: u16) -> Result<(String, u16)> {
+    // A bracketed IPv6 address, optionally followed by a port
+    if let Some(rest) = mirror.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| anyhow!("Unclosed bracket in mirror host {}", mirror))?;
+        let port = match rest.strip_prefix(':') {
+            Some(port) => port
+                .parse()
+                .map_err(|_| anyhow!("Invalid port in mirror host {}", mirror))?,
+            None if rest.is_empty() => default_port,
+            None => bail!("Invalid mirror host {}", mirror),
+        };
+        return Ok((host.to_string(), port));
+    }
+
+    match mirror.rsplit_once(':') {
+        // A bare IPv6 address has colons but no port
+        Some((host, port)) if !host.contains(':') => {
+            let port = port
+                .parse()
+                .map_err(|_| anyhow!("Invalid port in mirror host {}", mirror))?;
+            Ok((host.to_string(), port))
+        }
+        _ => Ok((mirror.to_string(), default_port)),
+    }
+}
+
+/// Connection to a single server
+struct ServerConnection {
+    config: ClientConfig,
+    /// Write half of the connection, the read half belongs to `reader`
+    stream: Option<BufWriter<WriteHalf<Transport>>>,
+    /// Socket used instead of `stream` when relaying over UDP
+    datagram: Option<Arc<UdpSocket>>,
+    /// Sequence number for the next packet sent on the current connection
+    next_seq: u64,
+    /// Packets decoded by `reader`, closed once the server goes away
+    incoming: Option<mpsc::Receiver<Packet>>,
+    /// Task reading packets from the server on the current connection
+    reader: Option<JoinHandle<()>>,
+    counters: Arc<NetworkCounters>,
+    /// Whether this connection is counted in `counters.connected_servers`
+    counted_connected: bool,
+    events: broadcast::Sender<ConnectionEvent>,
+    /// Simulated packet loss and latency, if configured
+    flaky: Option<FlakyLink>,
+    /// Addresses the server resolved to
+    addresses: AddressCache,
+    /// What the server told about itself on the current connection
+    peer: PeerState,
+    /// Set when the relay dropped input meant for this server
+    input_dropped: Arc<AtomicBool>,
+}
+
+/// What a connection learned from the server's side of it
+struct PeerState {
+    /// Where the screen from the server's handshake is reported, if anywhere
+    screen: Option<watch::Sender<Option<ScreenInfo>>>,
+    /// Clipboard formats announced in the handshake, none unless clipboard sync is on
+    local_formats: Vec<ClipboardFormat>,
+    /// Clipboard formats both sides agreed on, none until the server's handshake arrives
+    clipboard_formats: Vec<ClipboardFormat>,
+    /// Largest clipboard payload this client accepts
+    max_clipboard_size: usize,
+    /// Reassembles clipboard content the server sends in chunks
+    clipboard: ClipboardAssembler,
+    /// Where clipboard content from the server is delivered, if anywhere
+    clipboard_sink: Option<mpsc::Sender<ClipboardPayload>>,
+}
+
+impl PeerState {
+    fn new(config: &ClientConfig) -> Self {
+        Self {
+            screen: None,
+            local_formats: Vec::new(),
+            clipboard_formats: Vec::new(),
+            max_clipboard_size: config.clipboard.max_payload_size,
+            clipboard: ClipboardAssembler::new(config.clipboard.max_payload_size),
+            clipboard_sink: None,
+        }
+    }
+
+    /// Handle a packet the server sent on its own rather than in answer to a request
+    fn handle(&mut self, packet: Packet) {
+        match packet.message {
+            Message::Pong { .. } => debug!("Ignoring stale pong"),
+            Message::Handshake(handshake) => {
+                if handshake.protocol_version != PROTOCOL_VERSION {
+                    warn!(
+                        "Server speaks protocol version {}, client speaks {}",
+                        handshake.protocol_version, PROTOCOL_VERSION
+                    );
+                }
+                self.clipboard_formats =
+                    negotiate_formats(&self.local_formats, &handshake.clipboard_formats);
+                self.clipboard
+                    .set_max_size(self.max_clipboard_size.min(handshake.max_clipboard_size));
+                if let Some(info) = handshake.screen {
+                    info!("Server screen is {}x{}", info.width, info.height);
+                }
+                if let Some(screen) = &self.screen {
+                    screen.send_replace(handshake.screen);
+                }
+            }
+            Message::ClipboardChunk(chunk) => self.handle_clipboard_chunk(chunk),
+            Message::Disconnect { reason } => warn!("Server is closing the connection: {}", reason),
+            message => debug!("Ignoring unexpected {:?} from server", message),
+        }
+    }
+
+    /// Add a chunk of the server's clipboard, delivering the content once it's complete
+    fn handle_clipboard_chunk(&mut self, chunk: ClipboardChunk) {
+        let Some(sink) = &self.clipboard_sink else {
+            debug!("Ignoring clipboard chunk, clipboard content from this server isn't used");
+            return;
+        };
+        if !self.clipboard_formats.contains(&chunk.format) {
+            warn!(
+                "Ignoring clipboard chunk in unnegotiated format {:?}",
+                chunk.format
+            );
+            return;
+        }
+
+        match self.clipboard.push(chunk) {
+            Ok(Some(payload)) => {
+                debug!(
+                    "Received {:?} clipboard payload ({} bytes)",
+                    payload.format,
+                    payload.data.len()
+                );
+                if sink.try_send(payload).is_err() {
+                    warn!("Dropping clipboard content from the server, the clipboard is busy");
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Dropping clipboard transfer: {}", e),
+        }
+    }
+
+    /// Forget what the server told, as the next one to answer may differ
+    fn reset(&mut self) {
+        self.clipboard_formats.clear();
+        self.clipboard = ClipboardAssembler::new(self.max_clipboard_size);
+        if let Some(screen) = &self.screen {
+            screen.send_if_modified(|screen| screen.take().is_some());
+        }
+    }
+}
+
+impl ServerConnection {
+    fn new(
+        config: ClientConfig,
+        counters: &Arc<NetworkCounters>,
+        events: &broadcast::Sender<ConnectionEvent>,
+    ) -> Self {
+        Self {
+            flaky: FlakyLink::new(&config.debug_network),
+            addresses: AddressCache::new(Duration::from_secs(config.reconnect.dns_cache_secs)),
+            peer: PeerState::new(&config),
             config,
             stream: None,
-        })
+            datagram: None,
+            next_seq: 0,
+            incoming: None,
+            reader: None,
+            counters: Arc::clone(counters),
+            counted_connected: false,
+            events: events.clone(),
+            input_dropped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Tell subscribers about a connection change; without any it goes nowhere
+    fn publish(&self, event: ConnectionEvent) {
+        let _ = self.events.send(event);
+    }
+
+    fn address(&self) -> String {
+        self.config.network.server_address()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.stream.is_some() || self.datagram.is_some()
+    }
+
+    /// Drop the current connection for `reason`
+    fn close(&mut self, reason: &str) {
+        if self.is_connected() {
+            self.publish(ConnectionEvent::Disconnected(reason.to_string()));
+        }
+        self.stream = None;
+        self.datagram = None;
+        self.incoming = None;
+        if let Some(reader) = self.reader.take() {
+            reader.abort();
+        }
+        self.peer.reset();
+        self.update_connected();
+    }
+
+    /// Bring the connected server count in line with this connection's state
+    fn update_connected(&mut self) {
+        let connected = self.is_connected();
+        if connected == self.counted_connected {
+            return;
+        }
+
+        if connected {
+            self.counters
+                .connected_servers
+                .fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.counters
+                .connected_servers
+                .fetch_sub(1, Ordering::Relaxed);
+        }
+        self.counted_connected = connected;
     }
 
     /// Connect to the server
-    pub async fn connect(&mut self) -> Result<()> {
-        let address = format!("{}:{}", "192.168.137.1", self.config.network.port);
-        info!("Connecting to server at {}", address);
+    async fn connect(&mut self) -> Result<()> {
+        info!("Connecting to server at {}", self.address());
 
-        let stream = TcpStream::connect(&address).await?;
-        self.stream = Some(BufWriter::new(stream));
+        // Whatever is left of the previous connection is replaced
+        if let Some(reader) = self.reader.take() {
+            reader.abort();
+        }
 
-        info!("Successfully connected to server");
-        Ok(())
+        let (incoming_sender, incoming) = mpsc::channel(INCOMING_CAPACITY);
+        let wire_format = self.config.network.wire_format;
+        let reader = match self.config.network.transport {
+            TransportKind::Tcp => {
+                let (read_half, write_half) = tokio::io::split(self.open_transport().await?);
+                self.stream = Some(BufWriter::new(write_half));
+                tokio::spawn(read_stream(read_half, wire_format, incoming_sender))
+            }
+            TransportKind::Udp => {
+                let socket = Arc::new(self.open_datagram().await?);
+                self.datagram = Some(Arc::clone(&socket));
+                tokio::spawn(read_datagrams(socket, wire_format, incoming_sender))
+            }
+        };
+        self.incoming = Some(incoming);
+        self.reader = Some(reader);
+        self.next_seq = 0;
+        self.update_connected();
+
+        info!("Successfully connected to {}", self.address());
+        self.publish(ConnectionEvent::Connected);
+
+        let handshake = Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            clipboard_formats: self.peer.local_formats.clone(),
+            max_clipboard_size: self.config.clipboard.max_payload_size,
+            screen: None,
+        };
+        self.send_packet(Packet::new(Message::Handshake(handshake)))
+            .await
     }
 
-    /// Send a packet to the server
-    pub async fn send_packet(&mut self, packet: Packet) -> Result<()> {
-        if let Some(ref mut stream) = self.stream {
-            let serialized = bincode::serde::encode_to_vec(&packet, bincode::config::standard())?;
-            stream.write_all(&serialized).await?;
-            stream.flush().await?;
-            debug!("Sent packet: {}", packet.id);
+    /// Connect to relay input, resetting whatever the server still holds for
+    /// this client from before, which is stale by now
+    async fn connect_for_relay(&mut self) -> Result<()> {
+        self.connect().await?;
+        self.reset_input_state().await
+    }
+
+    /// Release everything the server holds for this client
+    async fn reset_input_state(&mut self) -> Result<()> {
+        self.input_dropped.store(false, Ordering::Relaxed);
+        self.send_packet(Packet::new(Message::ResetInputState))
+            .await
+    }
+
+    /// Open a TCP connection to the server, wrapped in TLS when enabled and
+    /// authenticated when a shared secret is configured
+    async fn open_transport(&mut self) -> Result<Transport> {
+        let stream = match &self.config.proxy {
+            Some(proxy) => {
+                let network = &self.config.network;
+                let host = network.server_host.as_deref().unwrap_or(&network.host);
+                let stream = connect_through(proxy, host, network.port).await?;
+                debug!("Connected through proxy at {}", proxy.address);
+                stream
+            }
+            None => self.connect_tcp().await?,
+        };
+
+        let network = &self.config.network;
+
+        let mut transport: Transport = if network.tls {
+            let connector = tls_connector(&self.config.tls)?;
+            let host = network.server_host.as_deref().unwrap_or(&network.host);
+            let server_name = tls_server_name(&self.config.tls, host)?;
+            let stream = connector.connect(server_name, stream).await?;
+            debug!("TLS handshake complete");
+            Box::new(stream)
+        } else {
+            Box::new(stream)
+        };
+
+        if let Some(secret) = self.config.auth.secret() {
+            authenticate(&mut transport, &secret, network.wire_format).await?;
+            debug!("Authenticated with server");
+        }
+
+        Ok(transport)
+    }
+
+    /// Connect to the first of the server's addresses that accepts the
+    /// connection, trying them in the order they resolved to
+    async fn connect_tcp(&mut self) -> Result<TcpStream> {
+        let mut last_error = None;
+        for address in self
+            .addresses
+            .resolve(&self.config.network.server_address())
+            .await?
+        {
+            match TcpStream::connect(address).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    debug!("Could not connect to {}: {}", address, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        // The server may have moved to another address since it was resolved
+        self.addresses.invalidate();
+        Err(last_error
+            .expect("resolve returns at least one address")
+            .into())
+    }
+
+    /// Bind a UDP socket connected to the server
+    async fn open_datagram(&mut self) -> Result<UdpSocket> {
+        let network = &self.config.network;
+        if network.tls {
+            bail!("TLS is not supported over UDP, use the tcp transport");
+        }
+        if self.config.auth.secret().is_some() {
+            bail!("Authentication is not supported over UDP, use the tcp transport");
+        }
+        if self.config.proxy.is_some() {
+            bail!("Proxies are not supported over UDP, use the tcp transport");
+        }
+
+        let server = self.addresses.resolve(&network.server_address()).await?[0];
+        // The local socket has to be of the same address family as the server
+        let local = if server.is_ipv6() {
+            "[::]:0"
         } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(server).await?;
+        Ok(socket)
+    }
+
+    /// Send a packet to the server
+    #[instrument(level = "debug", skip_all, fields(packet_id = %packet.id, kind = packet.message.kind()))]
+    pub async fn send_packet(&mut self, mut packet: Packet) -> Result<()> {
+        if self.datagram.is_none() && self.stream.is_none() {
             warn!("Attempted to send packet without connection");
+            return Ok(());
+        }
+
+        packet.seq = self.next_seq;
+        self.next_seq += 1;
+        let serialized = encode_frame(&packet, self.config.network.wire_format)?;
+
+        // Lost packets still take up a sequence number, like on a real network
+        if let Some(flaky) = &mut self.flaky {
+            if flaky.drops_next() {
+                debug!("Dropping packet {} to simulate packet loss", packet.id);
+                return Ok(());
+            }
+            tokio::time::sleep(flaky.remaining_delay(packet.timestamp)).await;
+        }
+
+        if let Some(ref socket) = self.datagram {
+            if serialized.len() > MAX_DATAGRAM_SIZE {
+                warn!(
+                    "Dropping packet {} of {} bytes, too large for a datagram",
+                    packet.id,
+                    serialized.len()
+                );
+                return Ok(());
+            }
+            if let Err(e) = socket.send(&serialized).await {
+                self.counters.send_failures.fetch_add(1, Ordering::Relaxed);
+                self.close(&e.to_string());
+                return Err(e.into());
+            }
+            debug!("Sent datagram: {}", packet.id);
+        } else if let Some(ref mut stream) = self.stream {
+            if let Err(e) = write_frame(stream, &serialized).await {
+                // The stream is unusable after a failed write
+                self.counters.send_failures.fetch_add(1, Ordering::Relaxed);
+                self.close(&e.to_string());
+                return Err(e);
+            }
+            debug!("Sent packet: {}", packet.id);
         }
+
+        self.counters.packets_sent.fetch_add(1, Ordering::Relaxed);
         Ok(())
     }
 
-    /// Start the network client that listens for packets from the input capture
-    pub async fn start_relay(&mut self, mut packet_receiver: mpsc::Receiver<Packet>) -> Result<()> {
-        self.connect().await?;
+    /// Send the packets from `packet_receiver` until it closes or a disconnect
+    /// is sent, reconnecting whenever the connection drops
+    async fn relay(
+        mut self,
+        mut packet_receiver: mpsc::Receiver<Packet>,
+        reconnect_requested: Arc<Notify>,
+        mut link_checks: mpsc::Receiver<LinkCheck>,
+    ) {
+        if !self.is_connected() && self.recover(&mut packet_receiver).await {
+            return;
+        }
+
+        let heartbeat_interval = Duration::from_secs(self.config.heartbeat.interval_secs);
+        let heartbeat_enabled = !heartbeat_interval.is_zero();
+        let mut heartbeat = tokio::time::interval_at(
+            Instant::now() + heartbeat_interval,
+            heartbeat_interval.max(Duration::from_secs(1)),
+        );
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
         // Handle incoming packets and relay them to the server
-        while let Some(packet) = packet_receiver.recv().await {
-            if let Err(e) = self.send_packet(packet).await {
-                error!("Failed to send packet: {}", e);
+        loop {
+            let packet = tokio::select! {
+                packet = packet_receiver.recv() => match packet {
+                    Some(packet) => {
+                        // Input shows the server the connection is alive just as well
+                        heartbeat.reset();
+                        packet
+                    }
+                    None => break,
+                },
+                _ = heartbeat.tick(), if heartbeat_enabled => Packet::new(Message::Heartbeat),
+                _ = reconnect_requested.notified() => {
+                    info!("Connection reset requested, reconnecting");
+                    self.close("connection reset requested");
+                    if self.recover(&mut packet_receiver).await {
+                        break;
+                    }
+                    continue;
+                }
+                Some((timeout, reply)) = link_checks.recv() => {
+                    let answered = self.validate_link(timeout).await.is_some();
+                    let _ = reply.send(answered);
+                    continue;
+                }
+                packet = next_incoming(&mut self.incoming) => {
+                    match packet {
+                        Some(packet) => self.peer.handle(packet),
+                        None => {
+                            warn!("{} closed the connection", self.address());
+                            self.close("server closed the connection");
+                            if self.recover(&mut packet_receiver).await {
+                                break;
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            if let Message::ClipboardChunk(chunk) = &packet.message
+                && !self.peer.clipboard_formats.contains(&chunk.format)
+            {
+                debug!(
+                    "Not sending {:?} clipboard content, {} didn't agree to the format",
+                    chunk.format,
+                    self.address()
+                );
+                continue;
+            }
+
+            let packet_id = packet.id.clone();
+            let disconnecting = matches!(packet.message, Message::Disconnect { .. });
+            let mut sent = Ok(());
+            if self.input_dropped.load(Ordering::Relaxed) {
+                debug!(
+                    "Resetting input state of {}, input for it was dropped",
+                    self.address()
+                );
+                sent = self.reset_input_state().await;
+            }
+            if sent.is_ok() {
+                sent = self.send_packet(packet).await;
+            }
+            if let Err(e) = sent {
+                error!("Failed to send packet to {}: {}", self.address(), e);
+                if disconnecting {
+                    break;
+                }
+
+                // Input is only meaningful live, so the failed packet is dropped
+                // rather than replayed late after the outage
+                warn!("Dropped packet {} while the connection was down", packet_id);
+
+                // Stop reading from the channel until the connection is back
+                if self.recover(&mut packet_receiver).await {
+                    break;
+                }
+            } else if disconnecting {
+                info!("Disconnected from {}", self.address());
+                self.close("client disconnected");
+                break;
+            }
+        }
+    }
+
+    /// Ping the server and wait up to `timeout` for the matching pong,
+    /// returning the version the server reported
+    async fn validate_link(&mut self, timeout: Duration) -> Option<String> {
+        let token = rand::random::<u64>();
+        let ping = Packet::new(Message::Ping { token });
+        let is_pong = |packet: &Packet| matches!(packet.message, Message::Pong { token: answered, .. } if answered == token);
+
+        match self.send_and_await(ping, timeout, is_pong).await {
+            Ok(Packet {
+                message: Message::Pong { version, .. },
+                ..
+            }) => {
+                debug!("Server answered link check");
+                Some(version)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Link check failed: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Send `request` and wait up to `timeout` for the first packet from the
+    /// server that `is_response` accepts.
+    ///
+    /// Other packets arriving in the meantime are handled as usual.
+    async fn send_and_await(
+        &mut self,
+        request: Packet,
+        timeout: Duration,
+        is_response: impl Fn(&Packet) -> bool,
+    ) -> Result<Packet> {
+        self.send_packet(request).await?;
 
-                // Try to reconnect if the connection is lost
-                if let Err(reconnect_err) = self.connect().await {
-                    error!("Failed to reconnect: {}", reconnect_err);
-                    // Wait before trying to reconnect
-                    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        let peer = &mut self.peer;
+        let incoming = self
+            .incoming
+            .as_mut()
+            .ok_or_else(|| anyhow!("Not connected to the server"))?;
+        let response = tokio::time::timeout(timeout, async {
+            while let Some(packet) = incoming.recv().await {
+                if is_response(&packet) {
+                    return Some(packet);
                 }
+                peer.handle(packet);
             }
+            None
+        });
+
+        match response.await {
+            Ok(Some(packet)) => Ok(packet),
+            Ok(None) => bail!("Server closed the connection"),
+            Err(_) => bail!("Server did not answer within {:?}", timeout),
         }
+    }
 
-        Ok(())
+    /// Reconnect to the server, retrying with exponential backoff until it succeeds
+    async fn reconnect(&mut self) {
+        let mut backoff = Backoff::new(&self.config.reconnect);
+        retry_with_backoff(&mut backoff, self).await;
+        self.counters.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reconnect, then drop the input queued in the meantime, which is stale
+    /// by now. Reconnecting reset what the server held for this client.
+    ///
+    /// Returns whether the client asked to disconnect in the meantime.
+    async fn recover(&mut self, packet_receiver: &mut mpsc::Receiver<Packet>) -> bool {
+        self.reconnect().await;
+
+        let mut stale = 0;
+        while let Ok(packet) = packet_receiver.try_recv() {
+            if matches!(packet.message, Message::Disconnect { .. }) {
+                let _ = self.send_packet(packet).await;
+                self.close("client disconnected");
+                return true;
+            }
+            stale += 1;
+        }
+        if stale > 0 {
+            warn!(
+                "Dropped {} packets queued while {} was unreachable",
+                stale,
+                self.address()
+            );
+        }
+        false
     }
 
     /// Test connectivity to the server
-    pub async fn ping(&mut self) -> Result<()> {
-        let address = format!("{}:{}", self.config.network.host, self.config.network.port);
+    async fn ping(&mut self) -> Result<()> {
+        let address = self.address();
         info!("Testing connectivity to {}", address);
 
-        let stream = TcpStream::connect(&address).await?;
-        let mut writer = BufWriter::new(stream);
-
-        // Send a ping packet
-        let ping_packet = Packet::input_event("PING".to_string(), 0, 0);
-        let serialized = bincode::serde::encode_to_vec(&ping_packet, bincode::config::standard())?;
-        writer.write_all(&serialized).await?;
-        writer.flush().await?;
+        self.connect().await?;
+        let Some(version) = self.validate_link(PING_TIMEOUT).await else {
+            bail!("{} did not answer the ping", address);
+        };
 
-        info!("Ping sent successfully");
+        info!("Ping answered by {} (asteria-server {})", address, version);
         Ok(())
     }
 }
 
+/// Decode packets from the server's side of the stream until it closes
+async fn read_stream(
+    mut reader: ReadHalf<Transport>,
+    wire_format: WireFormat,
+    incoming: mpsc::Sender<Packet>,
+) {
+    let mut buffer = Vec::new();
+    loop {
+        match read_frame(&mut reader, &mut buffer, wire_format).await {
+            Ok(Some(packet)) => {
+                if incoming.send(packet).await.is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                // Only a corrupt frame leaves the stream readable
+                if !e.is::<ChecksumMismatch>() {
+                    debug!("Stopped reading from server: {}", e);
+                    break;
+                }
+                warn!("Dropping packet from server: {}", e);
+            }
+        }
+    }
+}
+
+/// Decode datagrams from the server until the socket fails
+async fn read_datagrams(
+    socket: Arc<UdpSocket>,
+    wire_format: WireFormat,
+    incoming: mpsc::Sender<Packet>,
+) {
+    let mut datagram = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let len = match socket.recv(&mut datagram).await {
+            Ok(len) => len,
+            Err(e) => {
+                debug!("Stopped reading from server: {}", e);
+                break;
+            }
+        };
+
+        let mut frame = datagram[..len].to_vec();
+        match decode_frame(&mut frame, wire_format) {
+            Ok(Some(packet)) => {
+                if incoming.send(packet).await.is_err() {
+                    break;
+                }
+            }
+            Ok(None) => warn!("Dropping truncated datagram from server"),
+            Err(e) => warn!("Dropping datagram from server: {}", e),
+        }
+    }
+}
+
+/// Next packet the connection's reader decoded, waiting forever while disconnected
+async fn next_incoming(incoming: &mut Option<mpsc::Receiver<Packet>>) -> Option<Packet> {
+    match incoming {
+        Some(incoming) => incoming.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Answer the server's authentication challenge with the shared secret
+async fn authenticate(stream: &mut Transport, secret: &str, wire_format: WireFormat) -> Result<()> {
+    let mut buffer = Vec::new();
+    let packet = tokio::time::timeout(AUTH_TIMEOUT, read_frame(stream, &mut buffer, wire_format))
+        .await
+        .map_err(|_| anyhow!("Timed out waiting for the server's authentication challenge"))??;
+
+    let Some(Message::AuthChallenge(challenge)) = packet.map(|packet| packet.message) else {
+        bail!("Server did not send an authentication challenge");
+    };
+
+    let response = Packet::new(Message::AuthResponse(AuthResponse {
+        mac: sign_nonce(secret, &challenge.nonce),
+    }));
+    stream
+        .write_all(&encode_frame(&response, wire_format)?)
+        .await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Write a frame and flush it to the socket
+async fn write_frame(stream: &mut BufWriter<WriteHalf<Transport>>, frame: &[u8]) -> Result<()> {
+    stream.write_all(frame).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Exponential backoff with jitter for reconnect attempts
+struct Backoff {
+    delay: Duration,
+    max_delay: Duration,
+}
+
+impl Backoff {
+    fn new(config: &ReconnectConfig) -> Self {
+        Self {
+            delay: Duration::from_millis(config.initial_delay_ms),
+            max_delay: Duration::from_millis(config.max_delay_ms),
+        }
+    }
+
+    /// Delay before the next attempt, doubling the base delay up to the cap
+    fn next_delay(&mut self) -> Duration {
+        let base = self.delay.min(self.max_delay);
+        self.delay = (self.delay * 2).min(self.max_delay);
+
+        // Spread attempts by up to 20% either way so clients don't retry in lockstep
+        base.mul_f64(rand::random_range(0.8..=1.2))
+            .min(self.max_delay)
+    }
+}
+
+/// Something [`retry_with_backoff`] reconnects
+trait Reconnect {
+    /// Wait out the delay before the next attempt
+    async fn wait(&mut self, delay: Duration) {
+        tokio::time::sleep(delay).await;
+    }
+
+    /// Make connection attempt number `attempt`, counting from 1
+    async fn attempt(&mut self, attempt: u32) -> Result<()>;
+}
+
+impl Reconnect for ServerConnection {
+    async fn attempt(&mut self, attempt: u32) -> Result<()> {
+        self.publish(ConnectionEvent::Reconnecting { attempt });
+        self.connect_for_relay().await
+    }
+}
+
+/// Reconnect `target` until an attempt succeeds, waiting out the next
+/// backoff delay before each one
+async fn retry_with_backoff(backoff: &mut Backoff, target: &mut impl Reconnect) {
+    for attempt in 1.. {
+        let delay = backoff.next_delay();
+        warn!("Reconnecting in {:?}", delay);
+        target.wait(delay).await;
+
+        match target.attempt(attempt).await {
+            Ok(()) => return,
+            Err(e) => error!("Failed to reconnect: {}", e),
+        }
+    }
+}
+
 impl Default for NetworkClient {
     fn default() -> Self {
-        Self::new().expect("Failed to create network client")
+        Self::new(ClientConfig::default()).expect("Failed to create network client")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asteria_core::protocol::InputEventType;
+
+    /// Target whose relay is replaced by the returned receiver
+    fn target(address: &str, capacity: usize) -> (TargetHandle, mpsc::Receiver<Packet>) {
+        let (packets, received) = mpsc::channel(capacity);
+        let (link_checks, _) = mpsc::channel(1);
+        let handle = TargetHandle {
+            address: address.to_string(),
+            packets,
+            input_dropped: Arc::new(AtomicBool::new(false)),
+            reconnect_requested: Arc::new(Notify::new()),
+            link_checks,
+            task: tokio::spawn(async {}),
+        };
+        (handle, received)
+    }
+
+    fn key_press(key_code: u16) -> Packet {
+        Packet::new(Message::InputEventTyped(InputEventType::KeyPress {
+            key_code,
+        }))
+    }
+
+    #[tokio::test]
+    async fn every_target_receives_each_packet() {
+        let (first, mut first_received) = target("first", 8);
+        let (second, mut second_received) = target("second", 8);
+
+        let packets = [key_press(30), key_press(48)];
+        for packet in &packets {
+            first.offer(packet);
+            second.offer(packet);
+        }
+
+        for received in [&mut first_received, &mut second_received] {
+            for packet in &packets {
+                assert_eq!(received.recv().await.unwrap().id, packet.id);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn stuck_target_does_not_hold_up_the_others() {
+        let (healthy, mut healthy_received) = target("healthy", 8);
+        // Never read from, like a server that is reconnecting
+        let (stuck, _stuck_received) = target("stuck", 1);
+        let (gone, gone_received) = target("gone", 8);
+        drop(gone_received);
+
+        let packets = [key_press(30), key_press(48), key_press(46)];
+        for packet in &packets {
+            for target in [&healthy, &stuck, &gone] {
+                target.offer(packet);
+            }
+        }
+
+        for packet in &packets {
+            assert_eq!(healthy_received.recv().await.unwrap().id, packet.id);
+        }
+        assert!(!healthy.input_dropped.load(Ordering::Relaxed));
+        // The targets that missed input get their input state reset
+        assert!(stuck.input_dropped.load(Ordering::Relaxed));
+        assert!(gone.input_dropped.load(Ordering::Relaxed));
+    }
+
+    /// Connector failing its first `failures` attempts, recording the
+    /// attempts and the delays waited out before them
+    struct FlakyConnector {
+        failures: u32,
+        attempts: Vec<u32>,
+        delays: Vec<Duration>,
+    }
+
+    impl Reconnect for FlakyConnector {
+        async fn wait(&mut self, delay: Duration) {
+            self.delays.push(delay);
+        }
+
+        async fn attempt(&mut self, attempt: u32) -> Result<()> {
+            self.attempts.push(attempt);
+            if attempt <= self.failures {
+                bail!("connection refused");
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_backs_off_until_the_connector_succeeds() {
+        let config = ReconnectConfig {
+            initial_delay_ms: 100,
+            max_delay_ms: 1000,
+            ..ReconnectConfig::default()
+        };
+        let mut backoff = Backoff::new(&config);
+        let mut connector = FlakyConnector {
+            failures: 5,
+            attempts: Vec::new(),
+            delays: Vec::new(),
+        };
+
+        retry_with_backoff(&mut backoff, &mut connector).await;
+
+        assert_eq!(connector.attempts, [1, 2, 3, 4, 5, 6]);
+        let delays = connector.delays;
+        // Doubling from 100ms and capped at 1s, give or take the 20% jitter
+        let bases = [100, 200, 400, 800, 1000, 1000];
+        assert_eq!(delays.len(), bases.len());
+        for (delay, base) in delays.iter().zip(bases) {
+            let base = Duration::from_millis(base);
+            assert!(
+                *delay >= base.mul_f64(0.8) && *delay <= base.mul_f64(1.2),
+                "{:?} is not within 20% of {:?}",
+                delay,
+                base
+            );
+            assert!(*delay <= Duration::from_millis(1000));
+        }
+    }
+
+    /// Connection to a server listening on a loopback port
+    async fn loopback_connection() -> (ServerConnection, tokio::net::TcpListener) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = ClientConfig::default();
+        config.network.server_host = Some("127.0.0.1".to_string());
+        config.network.port = listener.local_addr().unwrap().port();
+
+        let counters = Arc::new(NetworkCounters::default());
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        (ServerConnection::new(config, &counters, &events), listener)
+    }
+
+    #[tokio::test]
+    async fn reader_decodes_packets_the_server_sends() {
+        let (mut connection, listener) = loopback_connection().await;
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let pong = Packet::new(Message::Pong {
+                token: 7,
+                version: "1.2.3".to_string(),
+            });
+            let frame = encode_frame(&pong, WireFormat::default()).unwrap();
+            stream.write_all(&frame).await.unwrap();
+            (stream, pong.id)
+        });
+
+        connection.connect().await.unwrap();
+        let (_stream, pong_id) = server.await.unwrap();
+
+        let packet = next_incoming(&mut connection.incoming).await.unwrap();
+        assert_eq!(packet.id, pong_id);
+        assert!(matches!(
+            packet.message,
+            Message::Pong { token: 7, ref version } if version == "1.2.3"
+        ));
+    }
+
+    #[tokio::test]
+    async fn reconnect_cycle_publishes_ordered_events() {
+        let (mut connection, listener) = loopback_connection().await;
+        connection.config.reconnect.initial_delay_ms = 1;
+        let mut events = connection.events.subscribe();
+        let server = tokio::spawn(async move {
+            let first = listener.accept().await.unwrap();
+            let second = listener.accept().await.unwrap();
+            (first, second)
+        });
+
+        connection.connect().await.unwrap();
+        connection.close("connection reset");
+        let mut backoff = Backoff::new(&connection.config.reconnect);
+        retry_with_backoff(&mut backoff, &mut connection).await;
+        let _streams = server.await.unwrap();
+
+        let mut published = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            published.push(event);
+        }
+        assert_eq!(
+            published,
+            [
+                ConnectionEvent::Connected,
+                ConnectionEvent::Disconnected("connection reset".to_string()),
+                ConnectionEvent::Reconnecting { attempt: 1 },
+                ConnectionEvent::Connected,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unresponsive_server_fails_the_link_check() {
+        let (mut connection, listener) = loopback_connection().await;
+        // Accepts the connection but never answers a ping
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+        connection.connect().await.unwrap();
+        let _stream = server.await.unwrap();
+
+        assert_eq!(
+            connection.validate_link(Duration::from_millis(50)).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn link_validator_refuses_without_an_answer_in_time() {
+        let (requests, mut checks) = mpsc::channel(1);
+        let validator = LinkValidator {
+            requests,
+            timeout: Duration::from_millis(50),
+        };
+        // The relay takes the request but no server answers before the timeout
+        let relay = tokio::spawn(async move {
+            let (_, reply) = checks.recv().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            let _ = reply.send(true);
+        });
+
+        assert!(!validator.validate().await);
+        relay.abort();
+    }
+
+    #[tokio::test]
+    async fn link_validator_passes_once_a_server_answers() {
+        let (requests, mut checks) = mpsc::channel(1);
+        let validator = LinkValidator {
+            requests,
+            timeout: Duration::from_millis(500),
+        };
+        tokio::spawn(async move {
+            let (_, reply): LinkCheck = checks.recv().await.unwrap();
+            let _ = reply.send(true);
+        });
+
+        assert!(validator.validate().await);
+    }
+
+    #[tokio::test]
+    async fn sent_packets_and_failed_sends_are_counted() {
+        let (mut connection, listener) = loopback_connection().await;
+        let server = tokio::spawn(async move { listener.accept().await.unwrap().0 });
+        connection.connect().await.unwrap();
+        let stream = server.await.unwrap();
+        let counters = Arc::clone(&connection.counters);
+        // Connecting already sent the handshake
+        let handshake_sent = counters.packets_sent();
+
+        connection.send_packet(key_press(30)).await.unwrap();
+        assert_eq!(counters.connected_servers(), 1);
+        assert_eq!(counters.packets_sent(), handshake_sent + 1);
+
+        // Writes fail once the server's reset of the connection arrives
+        drop(stream);
+        let mut sends = 0;
+        while connection.send_packet(key_press(30)).await.is_ok() {
+            sends += 1;
+            assert!(sends < 100, "sends kept succeeding after the server left");
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(counters.send_failures(), 1);
+        assert_eq!(counters.packets_sent(), handshake_sent + 1 + sends);
+        assert_eq!(counters.connected_servers(), 0);
+    }
+
+    #[test]
+    fn network_stats_read_as_one_line() {
+        let counters = NetworkCounters {
+            servers: 2,
+            ..NetworkCounters::default()
+        };
+        counters.connected_servers.store(1, Ordering::Relaxed);
+        counters.packets_sent.store(40, Ordering::Relaxed);
+        counters.send_failures.store(2, Ordering::Relaxed);
+        counters.reconnects.store(1, Ordering::Relaxed);
+
+        assert_eq!(
+            counters.to_string(),
+            "1/2 servers connected, 40 packets sent, 2 send failures, 1 reconnects"
+        );
     }
 }
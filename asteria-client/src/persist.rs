@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use asteria_core::config::config_dir;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Relay state kept between runs of the client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedRelayState {
+    pub relay_enabled: bool,
+}
+
+/// Where the relay state is saved, `relay_state.json` in the config directory
+pub fn relay_state_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join("relay_state.json"))
+}
+
+/// Read the saved relay state, falling back to relay disabled when the file
+/// is missing or can't be read
+pub fn load_relay_state(path: &Path) -> SavedRelayState {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return SavedRelayState::default(),
+        Err(e) => {
+            warn!("Failed to read relay state from {}: {}", path.display(), e);
+            return SavedRelayState::default();
+        }
+    };
+
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        warn!("Ignoring corrupt relay state in {}: {}", path.display(), e);
+        SavedRelayState::default()
+    })
+}
+
+/// Save the relay state, replacing the file at once so a crash halfway
+/// through can't leave it corrupt
+pub fn save_relay_state(path: &Path, state: SavedRelayState) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let partial = path.with_extension("json.tmp");
+    fs::write(&partial, serde_json::to_vec(&state)?)
+        .with_context(|| format!("Failed to write {}", partial.display()))?;
+    fs::rename(&partial, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("asteria-persist-{}-{}", name, std::process::id()))
+            .join("relay_state.json")
+    }
+
+    #[test]
+    fn saved_state_is_restored() {
+        let path = state_path("roundtrip");
+        for relay_enabled in [true, false] {
+            save_relay_state(&path, SavedRelayState { relay_enabled }).unwrap();
+            assert_eq!(load_relay_state(&path), SavedRelayState { relay_enabled });
+        }
+        // Nothing half written is left next to it
+        assert!(!path.with_extension("json.tmp").exists());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn missing_or_corrupt_state_restores_relay_disabled() {
+        let path = state_path("corrupt");
+        assert_eq!(load_relay_state(&path), SavedRelayState::default());
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{ not json").unwrap();
+        assert!(!load_relay_state(&path).relay_enabled);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
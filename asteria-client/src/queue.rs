@@ -0,0 +1,235 @@
+use anyhow::{Result, bail};
+use asteria_core::protocol::{InputEventType, Message, Packet};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Packets from the capture loop on their way to the relay.
+///
+/// Pushing never waits, so a slow network can't stall capture and with it the
+/// toggle key. Once `capacity` packets are waiting, the oldest pointer motion
+/// is dropped to make room; keys, buttons and everything else are always
+/// queued.
+#[derive(Clone)]
+pub struct PacketQueue {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<QueueState>,
+    available: Notify,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct QueueState {
+    packets: VecDeque<Packet>,
+    closed: bool,
+    /// Motion packets dropped since the queue last ran empty
+    dropped: u64,
+}
+
+impl PacketQueue {
+    /// Queue holding up to `capacity` packets, fed into `sender` by the returned task
+    pub fn forward_to(sender: mpsc::Sender<Packet>, capacity: usize) -> (Self, JoinHandle<()>) {
+        let queue = Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(QueueState::default()),
+                available: Notify::new(),
+                capacity: capacity.max(1),
+            }),
+        };
+
+        let forwarder = queue.clone();
+        let task = tokio::spawn(async move {
+            while let Some(packet) = forwarder.pop().await {
+                if sender.send(packet).await.is_err() {
+                    // The relay is gone, let capture find out on its next push
+                    forwarder.close();
+                    break;
+                }
+            }
+        });
+
+        (queue, task)
+    }
+
+    /// Queue a packet, dropping pointer motion if the queue is full
+    pub fn push(&self, packet: Packet) -> Result<()> {
+        let mut state = self.shared.state.lock().expect("packet queue lock");
+        if state.closed {
+            bail!("Packet queue closed, the relay has stopped");
+        }
+
+        if state.packets.len() >= self.shared.capacity {
+            if let Some(index) = state.packets.iter().position(is_droppable) {
+                state.packets.remove(index);
+                record_drop(&mut state);
+            } else if is_droppable(&packet) {
+                record_drop(&mut state);
+                return Ok(());
+            }
+            // With nothing to drop the packet goes over capacity rather than
+            // losing a key or button
+        }
+
+        state.packets.push_back(packet);
+        drop(state);
+        self.shared.available.notify_one();
+        Ok(())
+    }
+
+    /// Stop accepting packets; those already queued are still forwarded
+    pub fn close(&self) {
+        self.shared.state.lock().expect("packet queue lock").closed = true;
+        self.shared.available.notify_one();
+    }
+
+    /// Wait for the next packet, `None` once the queue is closed and empty
+    async fn pop(&self) -> Option<Packet> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().expect("packet queue lock");
+                if let Some(packet) = state.packets.pop_front() {
+                    if state.packets.is_empty() && state.dropped > 0 {
+                        info!(
+                            "Relay caught up after dropping {} pointer motion packets",
+                            state.dropped
+                        );
+                        state.dropped = 0;
+                    }
+                    return Some(packet);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.shared.available.notified().await;
+        }
+    }
+}
+
+fn record_drop(state: &mut QueueState) {
+    if state.dropped == 0 {
+        warn!("Relay is falling behind, dropping pointer motion until it catches up");
+    }
+    state.dropped += 1;
+}
+
+/// Whether a packet only carries pointer motion, which later motion makes up for
+pub fn is_droppable(packet: &Packet) -> bool {
+    let is_motion = |event: &InputEventType| {
+        matches!(
+            event,
+            InputEventType::MouseMove { .. } | InputEventType::MouseMoveAbsolute { .. }
+        )
+    };
+
+    match &packet.message {
+        Message::InputEventTyped(event) => is_motion(event),
+        Message::Batch(events) => events.iter().all(is_motion),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Queue nobody forwards from, so packets stay queued
+    fn queue(capacity: usize) -> PacketQueue {
+        PacketQueue {
+            shared: Arc::new(Shared {
+                state: Mutex::new(QueueState::default()),
+                available: Notify::new(),
+                capacity,
+            }),
+        }
+    }
+
+    fn typed(event: InputEventType) -> Packet {
+        Packet::new(Message::InputEventTyped(event))
+    }
+
+    /// Events waiting in the queue, formatted for comparison
+    fn queued(queue: &PacketQueue) -> Vec<String> {
+        let state = queue.shared.state.lock().unwrap();
+        state
+            .packets
+            .iter()
+            .map(|packet| match &packet.message {
+                Message::InputEventTyped(event) => format!("{:?}", event),
+                message => panic!("unexpected {:?}", message),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keys_survive_while_motion_is_dropped() {
+        let queue = queue(3);
+        let press = InputEventType::KeyPress { key_code: 30 };
+        let release = InputEventType::KeyRelease { key_code: 30 };
+
+        queue
+            .push(typed(InputEventType::MouseMove { x: 1, y: 0 }))
+            .unwrap();
+        queue.push(typed(press.clone())).unwrap();
+        queue
+            .push(typed(InputEventType::MouseMove { x: 2, y: 0 }))
+            .unwrap();
+        // Full, so the oldest motion makes room for the release
+        queue.push(typed(release.clone())).unwrap();
+        // Newer motion makes up for the older motion it replaces
+        queue
+            .push(typed(InputEventType::MouseMove { x: 3, y: 0 }))
+            .unwrap();
+
+        let expected = [press, release, InputEventType::MouseMove { x: 3, y: 0 }];
+        assert_eq!(queued(&queue), expected.map(|event| format!("{:?}", event)));
+        assert_eq!(queue.shared.state.lock().unwrap().dropped, 2);
+    }
+
+    #[test]
+    fn motion_is_dropped_when_only_keys_are_queued() {
+        let queue = queue(1);
+        queue
+            .push(typed(InputEventType::KeyPress { key_code: 30 }))
+            .unwrap();
+        queue
+            .push(typed(InputEventType::MouseMove { x: 1, y: 0 }))
+            .unwrap();
+
+        assert_eq!(queued(&queue), ["KeyPress { key_code: 30 }"]);
+    }
+
+    #[test]
+    fn keys_go_over_capacity_rather_than_being_dropped() {
+        let queue = queue(1);
+        for key_code in [30, 48, 46] {
+            queue
+                .push(typed(InputEventType::KeyPress { key_code }))
+                .unwrap();
+        }
+
+        assert_eq!(queued(&queue).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn closed_queue_forwards_what_is_left_and_refuses_more() {
+        let queue = queue(4);
+        queue
+            .push(typed(InputEventType::KeyPress { key_code: 30 }))
+            .unwrap();
+        queue.close();
+
+        assert!(
+            queue
+                .push(typed(InputEventType::KeyPress { key_code: 48 }))
+                .is_err()
+        );
+        assert!(queue.pop().await.is_some());
+        assert!(queue.pop().await.is_none());
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{Result, bail};
+use asteria_core::config::{CaptureConfig, ClientConfig, LoadableConfig};
+use asteria_core::{parse_log_level, set_log_level};
+use serde_json::Value;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+use crate::toggle::ToggleCombo;
+
+/// Capture settings that are only read when capture starts
+const CAPTURE_RESTART_FIELDS: [&str; 3] = [
+    "capture.seat",
+    "capture.queue_capacity",
+    "capture.remember_relay_state",
+];
+
+/// Loads the config file again on request and works out which of the
+/// changes can be applied to the running client
+pub struct ConfigReloader {
+    path: PathBuf,
+    /// Config the client is running with
+    current: ClientConfig,
+    /// Set when `-v` or `--quiet` picked the log level, which the config then doesn't change
+    log_level_fixed: bool,
+}
+
+impl ConfigReloader {
+    pub fn new(path: PathBuf, current: ClientConfig, log_level_fixed: bool) -> Self {
+        Self {
+            path,
+            current,
+            log_level_fixed,
+        }
+    }
+
+    /// Load the config file again, log what changed and apply the log level.
+    ///
+    /// Returns the new capture settings when any of them changed, for the
+    /// caller to apply. Changes that need a restart are only logged.
+    pub fn reload(&mut self) -> Result<Option<CaptureConfig>> {
+        let new = ClientConfig::load_from(&self.path)?;
+        // Rejected before anything is applied, so a typo leaves the running config alone
+        if let Some(panic_key) = &new.capture.panic_key {
+            ToggleCombo::parse(panic_key)?;
+        }
+        if parse_log_level(&new.logging.log_level).is_none() {
+            bail!("Invalid log level '{}'", new.logging.log_level);
+        }
+
+        let changed = changed_fields(&self.current, &new)?;
+        if changed.is_empty() {
+            info!(
+                "Config reloaded from {}, nothing changed",
+                self.path.display()
+            );
+            return Ok(None);
+        }
+
+        let mut capture_changed = false;
+        for field in &changed {
+            if field == "logging.log_level" && !self.log_level_fixed {
+                set_log_level(&new.logging.log_level)?;
+                info!("Config reload: {} applied", field);
+            } else if field.starts_with("capture.")
+                && !CAPTURE_RESTART_FIELDS.contains(&field.as_str())
+            {
+                capture_changed = true;
+                info!("Config reload: {} applied", field);
+            } else {
+                warn!(
+                    "Config reload: {} changed, restart the client to apply it",
+                    field
+                );
+            }
+        }
+
+        let capture = capture_changed.then(|| new.capture.clone());
+        self.current = new;
+        Ok(capture)
+    }
+}
+
+/// Dotted paths of the settings that differ between `old` and `new`
+pub fn changed_fields(old: &ClientConfig, new: &ClientConfig) -> Result<Vec<String>> {
+    let mut changed = Vec::new();
+    diff_values(
+        "",
+        &serde_json::to_value(old)?,
+        &serde_json::to_value(new)?,
+        &mut changed,
+    );
+    Ok(changed)
+}
+
+fn diff_values(path: &str, old: &Value, new: &Value, changed: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old_fields), Value::Object(new_fields)) => {
+            let mut keys: Vec<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let field = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match (old_fields.get(key), new_fields.get(key)) {
+                    (Some(old), Some(new)) => diff_values(&field, old, new, changed),
+                    // Optional settings are left out while unset
+                    _ => changed.push(field),
+                }
+            }
+        }
+        _ if old != new => changed.push(path.to_string()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asteria_core::config::ProxyConfig;
+
+    fn scratch_config(name: &str, config: &ClientConfig) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "asteria-reload-{}-{}.toml",
+            name,
+            std::process::id()
+        ));
+        config.save_to(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn identical_configs_have_no_changes() {
+        let config = ClientConfig::default();
+        assert!(changed_fields(&config, &config.clone()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn nested_changes_are_reported_by_path() {
+        let old = ClientConfig::default();
+        let mut new = old.clone();
+        new.network.port += 1;
+        new.capture.batch_max_events += 1;
+        new.relay_queue_size += 1;
+
+        assert_eq!(
+            changed_fields(&old, &new).unwrap(),
+            [
+                "capture.batch_max_events",
+                "network.port",
+                "relay_queue_size"
+            ]
+        );
+    }
+
+    #[test]
+    fn optional_settings_are_reported_when_set_or_unset() {
+        let old = ClientConfig::default();
+        let mut new = old.clone();
+        new.capture.panic_key = Some("Ctrl+Alt+Escape".to_string());
+        new.proxy = Some(ProxyConfig {
+            address: "127.0.0.1:1080".to_string(),
+            username: None,
+            password: None,
+        });
+
+        let expected = ["capture.panic_key", "proxy"];
+        assert_eq!(changed_fields(&old, &new).unwrap(), expected);
+        assert_eq!(changed_fields(&new, &old).unwrap(), expected);
+    }
+
+    #[test]
+    fn reload_returns_changed_capture_settings() {
+        let current = ClientConfig::default();
+        let mut new = current.clone();
+        new.capture.batch_max_events += 1;
+        let path = scratch_config("capture", &new);
+
+        let mut reloader = ConfigReloader::new(path, current, true);
+        let capture = reloader.reload().unwrap().unwrap();
+        assert_eq!(capture.batch_max_events, new.capture.batch_max_events);
+        // Nothing left to apply the second time
+        assert!(reloader.reload().unwrap().is_none());
+    }
+
+    #[test]
+    fn reload_ignores_restart_only_changes() {
+        let current = ClientConfig::default();
+        let mut new = current.clone();
+        new.capture.seat = "seat1".to_string();
+        new.network.port += 1;
+        let path = scratch_config("restart", &new);
+
+        let mut reloader = ConfigReloader::new(path, current, true);
+        assert!(reloader.reload().unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_reload_keeps_the_running_config() {
+        let current = ClientConfig::default();
+        let mut new = current.clone();
+        new.capture.panic_key = Some("Ctrl+Nope".to_string());
+        new.capture.batch_max_events += 1;
+        let path = scratch_config("invalid", &new);
+
+        let mut reloader = ConfigReloader::new(path, current.clone(), true);
+        assert!(reloader.reload().is_err());
+        assert!(
+            changed_fields(&reloader.current, &current)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
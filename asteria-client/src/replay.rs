@@ -0,0 +1,223 @@
+use anyhow::{Context, Result};
+use asteria_core::protocol::{Message, Packet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::network::NetworkClient;
+
+/// Packets waiting for the relay during a replay
+const REPLAY_CHANNEL_CAPACITY: usize = 1000;
+
+/// Writes every relayed packet to a file as one JSON object per line
+pub struct PacketRecorder {
+    writer: BufWriter<File>,
+}
+
+impl PacketRecorder {
+    /// Record into `path`, replacing whatever it held
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Append `packet`, flushed right away so a crash keeps what led up to it
+    pub fn record(&mut self, packet: &Packet) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, packet)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Read the packets of a recording, skipping lines that don't hold one
+pub fn read_recording(path: &Path) -> Result<Vec<Packet>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read recording {}", path.display()))?;
+
+    let mut packets = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(packet) => packets.push(packet),
+            Err(e) => warn!(
+                "Skipping malformed line {} of the recording: {}",
+                index + 1,
+                e
+            ),
+        }
+    }
+    Ok(packets)
+}
+
+/// How fast a recording is played back
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayPace {
+    /// Factor the recorded gaps between packets are divided by, 0 to not wait at all
+    pub speed: f64,
+    /// Most packets sent per second, whatever the recorded gaps
+    pub max_rate: Option<f64>,
+}
+
+impl ReplayPace {
+    /// Wait before sending a packet captured `recorded_gap` after the previous one
+    pub fn gap(&self, recorded_gap: Duration) -> Duration {
+        let gap = if self.speed > 0.0 {
+            recorded_gap.div_f64(self.speed)
+        } else {
+            Duration::ZERO
+        };
+        match self.max_rate {
+            Some(rate) => gap.max(Duration::from_secs_f64(1.0 / rate)),
+            None => gap,
+        }
+    }
+
+    /// When each packet recorded at `timestamps` is due, counted from the
+    /// start of the replay
+    pub fn schedule(&self, timestamps: impl IntoIterator<Item = u64>) -> Vec<Duration> {
+        let mut due = Duration::ZERO;
+        let mut previous = None;
+        timestamps
+            .into_iter()
+            .map(|timestamp| {
+                if let Some(previous) = previous {
+                    due += self.gap(Duration::from_millis(timestamp.saturating_sub(previous)));
+                }
+                previous = Some(timestamp);
+                due
+            })
+            .collect()
+    }
+}
+
+/// Parse `--speed`, a factor of 0 or more
+pub fn parse_speed(value: &str) -> Result<f64, String> {
+    let speed: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if !speed.is_finite() || speed < 0.0 {
+        return Err(format!("speed must be 0 or more, got {}", value));
+    }
+    Ok(speed)
+}
+
+/// Parse `--max-rate`, a positive number of packets per second
+pub fn parse_max_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(format!("max rate must be more than 0, got {}", value));
+    }
+    Ok(rate)
+}
+
+/// Send the `packets` of a recording to the server, spaced out like they
+/// were captured and scaled by `pace`.
+///
+/// Packets are stamped with the time they are sent instead of the recorded
+/// one, so the server sees them as live input.
+pub async fn replay(
+    mut network_client: NetworkClient,
+    packets: Vec<Packet>,
+    pace: ReplayPace,
+) -> Result<()> {
+    let (packet_sender, packet_receiver) = mpsc::channel(REPLAY_CHANNEL_CAPACITY);
+    let relay = tokio::spawn(async move { network_client.start_relay(packet_receiver).await });
+
+    let mut sent = 0;
+    let schedule = pace.schedule(packets.iter().map(|packet| packet.timestamp));
+    let started_at = Instant::now();
+    for (mut packet, due) in packets.into_iter().zip(schedule) {
+        tokio::time::sleep_until(started_at + due).await;
+
+        let disconnecting = matches!(packet.message, Message::Disconnect { .. });
+        packet.timestamp = now_millis();
+        if packet_sender.send(packet).await.is_err() {
+            break;
+        }
+        sent += 1;
+        // The relay stops at a disconnect, anything recorded after it is never sent
+        if disconnecting {
+            break;
+        }
+    }
+
+    // Closing the channel lets the relay finish sending and stop
+    drop(packet_sender);
+    relay.await??;
+
+    info!("Replayed {} packets in {:?}", sent, started_at.elapsed());
+    Ok(())
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORDED: [u64; 4] = [1_000, 1_100, 1_300, 1_400];
+
+    #[test]
+    fn speed_two_sends_at_twice_the_recorded_rate() {
+        let pace = ReplayPace {
+            speed: 2.0,
+            max_rate: None,
+        };
+        let schedule = pace.schedule(RECORDED);
+        assert_eq!(
+            schedule,
+            [0, 50, 150, 200].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn speed_zero_sends_without_waiting() {
+        let pace = ReplayPace {
+            speed: 0.0,
+            max_rate: None,
+        };
+        assert!(pace.schedule(RECORDED).iter().all(Duration::is_zero));
+    }
+
+    #[test]
+    fn max_rate_stretches_gaps_shorter_than_its_interval() {
+        let pace = ReplayPace {
+            speed: 0.0,
+            max_rate: Some(10.0),
+        };
+        assert_eq!(
+            pace.schedule(RECORDED),
+            [0, 100, 200, 300].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_speed() {
+        assert_eq!(parse_speed("2.5"), Ok(2.5));
+        assert_eq!(parse_speed("0"), Ok(0.0));
+        for value in ["-1", "NaN", "inf", "fast"] {
+            assert!(parse_speed(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_max_rate() {
+        assert_eq!(parse_max_rate("120"), Ok(120.0));
+        for value in ["0", "-5", "NaN", "inf", "many"] {
+            assert!(parse_max_rate(value).is_err(), "{} was accepted", value);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{Result, bail};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// Remembers what a server's `host:port` resolved to, so reconnects don't
+/// wait on DNS every time.
+///
+/// The addresses are resolved again once they are older than the TTL, and
+/// after a connection to all of them failed, in case the server moved.
+#[derive(Debug)]
+pub struct AddressCache {
+    ttl: Duration,
+    /// Address that was resolved, its records in order and when they were looked up
+    cached: Option<(String, Vec<SocketAddr>, Instant)>,
+}
+
+impl AddressCache {
+    /// Cache keeping resolved addresses for `ttl`, zero to resolve every time
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, cached: None }
+    }
+
+    /// Every address `address` resolves to, in the order the resolver gave them
+    pub async fn resolve(&mut self, address: &str) -> Result<Vec<SocketAddr>> {
+        self.resolve_at(address, Instant::now(), async |address| {
+            Ok(tokio::net::lookup_host(address).await?.collect())
+        })
+        .await
+    }
+
+    /// [`Self::resolve`] at `now`, looking addresses up with `lookup`
+    async fn resolve_at(
+        &mut self,
+        address: &str,
+        now: Instant,
+        lookup: impl AsyncFnOnce(&str) -> Result<Vec<SocketAddr>>,
+    ) -> Result<Vec<SocketAddr>> {
+        if let Some((cached_address, addresses, resolved_at)) = &self.cached
+            && cached_address == address
+            && now.duration_since(*resolved_at) < self.ttl
+        {
+            return Ok(addresses.clone());
+        }
+
+        let addresses = lookup(address).await?;
+        if addresses.is_empty() {
+            bail!("{} did not resolve", address);
+        }
+        debug!("Resolved {} to {:?}", address, addresses);
+
+        self.cached = Some((address.to_string(), addresses.clone(), now));
+        Ok(addresses)
+    }
+
+    /// Forget the resolved addresses, so the next connection looks them up again
+    pub fn invalidate(&mut self) {
+        self.cached = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Resolver answering with a different address on every lookup, counting them
+    struct CountingResolver {
+        lookups: Cell<u16>,
+    }
+
+    impl CountingResolver {
+        fn new() -> Self {
+            Self {
+                lookups: Cell::new(0),
+            }
+        }
+
+        async fn lookup(&self, _address: &str) -> Result<Vec<SocketAddr>> {
+            self.lookups.set(self.lookups.get() + 1);
+            Ok(vec![SocketAddr::from((
+                [192, 168, 1, 10],
+                24800 + self.lookups.get(),
+            ))])
+        }
+    }
+
+    #[tokio::test]
+    async fn cached_addresses_are_reused_within_the_ttl() {
+        let resolver = CountingResolver::new();
+        let mut cache = AddressCache::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        let first = cache
+            .resolve_at("server:24800", start, async |a| resolver.lookup(a).await)
+            .await
+            .unwrap();
+        let second = cache
+            .resolve_at("server:24800", start + Duration::from_secs(59), async |a| {
+                resolver.lookup(a).await
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(resolver.lookups.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn addresses_are_resolved_again_after_the_ttl() {
+        let resolver = CountingResolver::new();
+        let mut cache = AddressCache::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        let first = cache
+            .resolve_at("server:24800", start, async |a| resolver.lookup(a).await)
+            .await
+            .unwrap();
+        let second = cache
+            .resolve_at("server:24800", start + Duration::from_secs(60), async |a| {
+                resolver.lookup(a).await
+            })
+            .await
+            .unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(resolver.lookups.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn invalidated_or_other_addresses_are_resolved_again() {
+        let resolver = CountingResolver::new();
+        let mut cache = AddressCache::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        for address in ["server:24800", "other:24800", "other:24800"] {
+            cache
+                .resolve_at(address, now, async |a| resolver.lookup(a).await)
+                .await
+                .unwrap();
+        }
+        assert_eq!(resolver.lookups.get(), 2);
+
+        cache.invalidate();
+        cache
+            .resolve_at("other:24800", now, async |a| resolver.lookup(a).await)
+            .await
+            .unwrap();
+        assert_eq!(resolver.lookups.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn empty_lookup_is_an_error() {
+        let mut cache = AddressCache::new(Duration::from_secs(60));
+        let result = cache
+            .resolve_at("server:24800", Instant::now(), async |_| Ok(Vec::new()))
+            .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use asteria_core::config::{CaptureConfig, ScrollDirection};
+
+/// libinput's v120 units per logical wheel click
+const V120_PER_STEP: f64 = 120.0;
+
+/// Turns libinput scroll values into whole wheel clicks, carrying the fraction
+/// of a click that hasn't been reached yet into later events
+pub struct ScrollAccumulator {
+    /// Finger and continuous scroll distance that makes up one step
+    distance_per_step: f64,
+    /// Wheel clicks sent for every step
+    clicks_per_step: f64,
+    horizontal: ScrollDirection,
+    vertical: ScrollDirection,
+    /// Clicks accumulated but not sent yet, always less than one per axis
+    pending: (f64, f64),
+}
+
+impl ScrollAccumulator {
+    pub fn new(config: &CaptureConfig) -> Self {
+        Self {
+            distance_per_step: config.scroll_distance_per_step.max(f64::EPSILON),
+            clicks_per_step: config.scroll_clicks_per_step.max(0.0),
+            horizontal: config.scroll_horizontal,
+            vertical: config.scroll_vertical,
+            pending: (0.0, 0.0),
+        }
+    }
+
+    /// Add wheel movement in v120 units, which high resolution wheels report
+    /// in fractions of a click
+    pub fn wheel(&mut self, dx_v120: f64, dy_v120: f64) -> Option<(i32, i32)> {
+        self.accumulate(dx_v120 / V120_PER_STEP, dy_v120 / V120_PER_STEP)
+    }
+
+    /// Add finger or continuous scroll movement in libinput's pixel-like units
+    pub fn smooth(&mut self, dx: f64, dy: f64) -> Option<(i32, i32)> {
+        self.accumulate(dx / self.distance_per_step, dy / self.distance_per_step)
+    }
+
+    /// Drop the partial click of the horizontal axis, e.g. when a finger scroll ends
+    pub fn stop_horizontal(&mut self) {
+        self.pending.0 = 0.0;
+    }
+
+    /// Drop the partial click of the vertical axis, e.g. when a finger scroll ends
+    pub fn stop_vertical(&mut self) {
+        self.pending.1 = 0.0;
+    }
+
+    /// Add scroll movement in steps, returning the whole clicks it completed
+    fn accumulate(&mut self, dx: f64, dy: f64) -> Option<(i32, i32)> {
+        self.pending.0 += dx * self.clicks_per_step;
+        self.pending.1 += dy * self.clicks_per_step;
+
+        // Truncate towards zero so the carried fraction keeps the scroll's sign
+        let clicks = (self.pending.0.trunc(), self.pending.1.trunc());
+        self.pending.0 -= clicks.0;
+        self.pending.1 -= clicks.1;
+
+        let dx = self.horizontal.apply(clicks.0 as i32);
+        let dy = self.vertical.apply(clicks.1 as i32);
+        (dx != 0 || dy != 0).then_some((dx, dy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accumulator(horizontal: ScrollDirection, vertical: ScrollDirection) -> ScrollAccumulator {
+        let config = CaptureConfig {
+            scroll_distance_per_step: 16.0,
+            scroll_clicks_per_step: 1.0,
+            scroll_horizontal: horizontal,
+            scroll_vertical: vertical,
+            ..CaptureConfig::default()
+        };
+        ScrollAccumulator::new(&config)
+    }
+
+    fn natural() -> ScrollAccumulator {
+        accumulator(ScrollDirection::Natural, ScrollDirection::Natural)
+    }
+
+    #[test]
+    fn high_resolution_wheel_adds_up_to_whole_clicks() {
+        let mut scroll = natural();
+
+        assert_eq!(scroll.wheel(0.0, 40.0), None);
+        assert_eq!(scroll.wheel(0.0, 40.0), None);
+        assert_eq!(scroll.wheel(0.0, 40.0), Some((0, 1)));
+        // The remainder starts from zero again
+        assert_eq!(scroll.wheel(0.0, 60.0), None);
+        assert_eq!(scroll.wheel(0.0, 60.0), Some((0, 1)));
+    }
+
+    #[test]
+    fn smooth_scroll_carries_the_fraction_with_its_sign() {
+        let mut scroll = natural();
+
+        assert_eq!(scroll.smooth(-12.0, 0.0), None);
+        assert_eq!(scroll.smooth(-12.0, 0.0), Some((-1, 0)));
+        // Half a click is still pending, and then a quarter
+        assert_eq!(scroll.smooth(-12.0, 0.0), Some((-1, 0)));
+        // Four times three quarters of a click add up to exactly three
+        assert_eq!(scroll.smooth(-12.0, 0.0), Some((-1, 0)));
+        assert_eq!(scroll.smooth(-4.0, 0.0), None);
+    }
+
+    #[test]
+    fn stopping_an_axis_drops_its_partial_click() {
+        let mut scroll = natural();
+
+        assert_eq!(scroll.smooth(12.0, 12.0), None);
+        scroll.stop_vertical();
+        assert_eq!(scroll.smooth(12.0, 12.0), Some((1, 0)));
+        scroll.stop_horizontal();
+        assert_eq!(scroll.smooth(12.0, 12.0), Some((0, 1)));
+    }
+
+    #[test]
+    fn reversed_axes_flip_the_clicks() {
+        let mut scroll = accumulator(ScrollDirection::Reverse, ScrollDirection::Natural);
+        assert_eq!(scroll.wheel(120.0, 120.0), Some((-1, 1)));
+
+        let mut scroll = accumulator(ScrollDirection::Natural, ScrollDirection::Reverse);
+        assert_eq!(scroll.wheel(120.0, -240.0), Some((1, 2)));
+    }
+
+    #[test]
+    fn reversing_keeps_the_pending_fraction() {
+        let mut scroll = accumulator(ScrollDirection::Natural, ScrollDirection::Reverse);
+        assert_eq!(scroll.smooth(0.0, 8.0), None);
+        assert_eq!(scroll.smooth(0.0, 8.0), Some((0, -1)));
+    }
+}
@@ -0,0 +1,351 @@
+use anyhow::{Result, anyhow, bail};
+use asteria_core::config::{ProxyConfig, join_host_port};
+use std::{fmt, net::IpAddr};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const AUTH_PASSWORD: u8 = 0x02;
+const AUTH_UNACCEPTABLE: u8 = 0xFF;
+/// Version of the username/password subnegotiation, RFC 1929
+const PASSWORD_VERSION: u8 = 0x01;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_CONNECTION_REFUSED: u8 = 0x05;
+
+/// Proxy turned the connection down
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyError {
+    /// Proxy wants credentials other than the configured ones, or none at all
+    NoAcceptableAuth,
+    /// Proxy rejected the configured username and password
+    AuthRejected,
+    /// Proxy reached the server's host but nothing accepted the connection
+    ConnectionRefused { target: String },
+    /// Proxy failed the CONNECT request for another reason
+    Failed { target: String, reply: u8 },
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoAcceptableAuth => write!(
+                f,
+                "proxy accepts none of the offered authentication methods, check whether it needs a username and password"
+            ),
+            Self::AuthRejected => write!(f, "proxy rejected the configured username and password"),
+            Self::ConnectionRefused { target } => write!(
+                f,
+                "proxy reached {} but the connection was refused, is asteria-server running?",
+                target
+            ),
+            Self::Failed { target, reply } => write!(
+                f,
+                "proxy could not connect to {}: {}",
+                target,
+                reply_message(*reply)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+/// Open a TCP connection to `host:port` through the SOCKS5 proxy in `proxy`.
+///
+/// Host names are handed to the proxy to resolve, so servers only known by
+/// name on the proxy's side of the network can be reached.
+pub async fn connect_through(proxy: &ProxyConfig, host: &str, port: u16) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(&proxy.address)
+        .await
+        .map_err(|e| anyhow!("Could not reach proxy at {}: {}", proxy.address, e))?;
+
+    negotiate_auth(&mut stream, proxy).await?;
+    request_connect(&mut stream, host, port).await?;
+    Ok(stream)
+}
+
+async fn negotiate_auth(stream: &mut TcpStream, proxy: &ProxyConfig) -> Result<()> {
+    let credentials = proxy
+        .username
+        .as_deref()
+        .map(|username| (username, proxy.password.as_deref().unwrap_or_default()));
+
+    let greeting: &[u8] = if credentials.is_some() {
+        &[VERSION, 2, AUTH_NONE, AUTH_PASSWORD]
+    } else {
+        &[VERSION, 1, AUTH_NONE]
+    };
+    stream.write_all(greeting).await?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != VERSION {
+        bail!("Proxy answered as SOCKS version {}, not 5", choice[0]);
+    }
+
+    match (choice[1], credentials) {
+        (AUTH_NONE, _) => Ok(()),
+        (AUTH_PASSWORD, Some((username, password))) => {
+            if username.len() > 255 || password.len() > 255 {
+                bail!("Proxy username and password are limited to 255 bytes each");
+            }
+            let mut request = vec![PASSWORD_VERSION, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0 {
+                return Err(ProxyError::AuthRejected.into());
+            }
+            Ok(())
+        }
+        (AUTH_UNACCEPTABLE, _) | (AUTH_PASSWORD, None) => Err(ProxyError::NoAcceptableAuth.into()),
+        (method, _) => bail!(
+            "Proxy chose authentication method {:#04x}, which was not offered",
+            method
+        ),
+    }
+}
+
+async fn request_connect(stream: &mut TcpStream, host: &str, port: u16) -> Result<()> {
+    let mut request = vec![VERSION, CMD_CONNECT, 0x00];
+    // Bracketed IPv6 literals are accepted the same as in `server_host`
+    match host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                bail!("Host name {} is too long to send to the proxy", host);
+            }
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        bail!("Proxy answered as SOCKS version {}, not 5", reply[0]);
+    }
+    let target = join_host_port(host, port);
+    match reply[1] {
+        REPLY_SUCCEEDED => {}
+        REPLY_CONNECTION_REFUSED => return Err(ProxyError::ConnectionRefused { target }.into()),
+        reply => return Err(ProxyError::Failed { target, reply }.into()),
+    }
+
+    // The address the proxy bound for us is of no use, but has to be read
+    // past before the connection carries the server's bytes
+    let address_length = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        atyp => bail!("Proxy replied with unknown address type {:#04x}", atyp),
+    };
+    let mut bound = vec![0u8; address_length + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// Meaning of a SOCKS5 reply code, RFC 1928 section 6
+fn reply_message(reply: u8) -> &'static str {
+    match reply {
+        0x01 => "general SOCKS server failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// How the test proxy answers a CONNECT request
+    struct Responder {
+        /// Username and password it requires, if any
+        credentials: Option<(&'static str, &'static str)>,
+        /// Reply code to the CONNECT request
+        reply: u8,
+    }
+
+    impl Responder {
+        /// Serve one client on a loopback port, returning its address and the
+        /// request bytes the client sent after authenticating
+        async fn serve(self) -> (String, tokio::task::JoinHandle<Vec<u8>>) {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+
+            let task = tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut header = [0u8; 2];
+                stream.read_exact(&mut header).await.unwrap();
+                let mut methods = vec![0u8; header[1] as usize];
+                stream.read_exact(&mut methods).await.unwrap();
+
+                match self.credentials {
+                    None => stream.write_all(&[VERSION, AUTH_NONE]).await.unwrap(),
+                    Some((username, password)) => {
+                        assert!(methods.contains(&AUTH_PASSWORD));
+                        stream.write_all(&[VERSION, AUTH_PASSWORD]).await.unwrap();
+
+                        let mut version_and_len = [0u8; 2];
+                        stream.read_exact(&mut version_and_len).await.unwrap();
+                        let mut got_username = vec![0u8; version_and_len[1] as usize];
+                        stream.read_exact(&mut got_username).await.unwrap();
+                        let mut got_password = vec![0u8; stream.read_u8().await.unwrap() as usize];
+                        stream.read_exact(&mut got_password).await.unwrap();
+
+                        let accepted = got_username == username.as_bytes()
+                            && got_password == password.as_bytes();
+                        let status = if accepted { 0x00 } else { 0x01 };
+                        stream.write_all(&[PASSWORD_VERSION, status]).await.unwrap();
+                        if !accepted {
+                            return Vec::new();
+                        }
+                    }
+                }
+
+                // CONNECT to an IPv4 address
+                let mut request = vec![0u8; 10];
+                stream.read_exact(&mut request).await.unwrap();
+                stream
+                    .write_all(&[VERSION, self.reply, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                    .await
+                    .unwrap();
+                request
+            });
+
+            (address, task)
+        }
+    }
+
+    fn proxy(address: String, credentials: Option<(&str, &str)>) -> ProxyConfig {
+        ProxyConfig {
+            address,
+            username: credentials.map(|(username, _)| username.to_string()),
+            password: credentials.map(|(_, password)| password.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn connects_without_authentication() {
+        let (address, responder) = Responder {
+            credentials: None,
+            reply: REPLY_SUCCEEDED,
+        }
+        .serve()
+        .await;
+
+        connect_through(&proxy(address, None), "192.168.1.10", 24800)
+            .await
+            .unwrap();
+
+        let port = 24800u16.to_be_bytes();
+        assert_eq!(
+            responder.await.unwrap(),
+            [
+                VERSION,
+                CMD_CONNECT,
+                0x00,
+                ATYP_IPV4,
+                192,
+                168,
+                1,
+                10,
+                port[0],
+                port[1]
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn connects_with_username_and_password() {
+        let (address, responder) = Responder {
+            credentials: Some(("alice", "secret")),
+            reply: REPLY_SUCCEEDED,
+        }
+        .serve()
+        .await;
+
+        connect_through(
+            &proxy(address, Some(("alice", "secret"))),
+            "192.168.1.10",
+            24800,
+        )
+        .await
+        .unwrap();
+        assert!(!responder.await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn wrong_password_is_rejected() {
+        let (address, _responder) = Responder {
+            credentials: Some(("alice", "secret")),
+            reply: REPLY_SUCCEEDED,
+        }
+        .serve()
+        .await;
+
+        let error = connect_through(
+            &proxy(address, Some(("alice", "wrong"))),
+            "192.168.1.10",
+            24800,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ProxyError>(),
+            Some(&ProxyError::AuthRejected)
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_connect_is_reported() {
+        let (address, _responder) = Responder {
+            credentials: None,
+            reply: REPLY_CONNECTION_REFUSED,
+        }
+        .serve()
+        .await;
+
+        let error = connect_through(&proxy(address, None), "192.168.1.10", 24800)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ProxyError>(),
+            Some(&ProxyError::ConnectionRefused {
+                target: "192.168.1.10:24800".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use asteria_core::config::EventCategory;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Events received from libinput versus events relayed to the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventCounts {
+    pub captured: u64,
+    pub relayed: u64,
+}
+
+impl EventCounts {
+    /// Events that were captured but filtered out or suppressed before sending
+    pub fn not_relayed(&self) -> u64 {
+        self.captured.saturating_sub(self.relayed)
+    }
+}
+
+/// Capture statistics per event category, to tell capture-side loss from network loss
+#[derive(Debug, Clone, Default)]
+pub struct CaptureStats {
+    counts: BTreeMap<EventCategory, EventCounts>,
+}
+
+impl CaptureStats {
+    pub fn record_captured(&mut self, category: EventCategory) {
+        self.counts.entry(category).or_default().captured += 1;
+    }
+
+    pub fn record_relayed(&mut self, category: EventCategory) {
+        self.counts.entry(category).or_default().relayed += 1;
+    }
+
+    /// Counts for a single category
+    #[allow(dead_code)]
+    pub fn get(&self, category: EventCategory) -> EventCounts {
+        self.counts.get(&category).copied().unwrap_or_default()
+    }
+
+    /// Counts summed over all categories
+    pub fn total(&self) -> EventCounts {
+        self.counts
+            .values()
+            .fold(EventCounts::default(), |total, counts| EventCounts {
+                captured: total.captured + counts.captured,
+                relayed: total.relayed + counts.relayed,
+            })
+    }
+}
+
+impl fmt::Display for CaptureStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        write!(
+            f,
+            "{} captured, {} relayed, {} not relayed",
+            total.captured,
+            total.relayed,
+            total.not_relayed()
+        )?;
+
+        for (category, counts) in &self.counts {
+            write!(
+                f,
+                "; {:?}: {}/{}",
+                category, counts.relayed, counts.captured
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captured_and_relayed_events_are_counted_per_category() {
+        let mut stats = CaptureStats::default();
+        for _ in 0..3 {
+            stats.record_captured(EventCategory::Keyboard);
+        }
+        stats.record_relayed(EventCategory::Keyboard);
+        stats.record_captured(EventCategory::Pointer);
+        stats.record_relayed(EventCategory::Pointer);
+
+        assert_eq!(
+            stats.get(EventCategory::Keyboard),
+            EventCounts {
+                captured: 3,
+                relayed: 1
+            }
+        );
+        assert_eq!(stats.get(EventCategory::Keyboard).not_relayed(), 2);
+        assert_eq!(stats.get(EventCategory::Touch), EventCounts::default());
+        assert_eq!(
+            stats.total(),
+            EventCounts {
+                captured: 4,
+                relayed: 2
+            }
+        );
+    }
+
+    #[test]
+    fn stats_list_the_categories_seen() {
+        let mut stats = CaptureStats::default();
+        assert_eq!(stats.to_string(), "0 captured, 0 relayed, 0 not relayed");
+
+        stats.record_captured(EventCategory::Pointer);
+        stats.record_captured(EventCategory::Keyboard);
+        stats.record_relayed(EventCategory::Keyboard);
+        assert_eq!(
+            stats.to_string(),
+            "2 captured, 1 relayed, 1 not relayed; Keyboard: 1/1; Pointer: 0/1"
+        );
+    }
+
+    #[test]
+    fn not_relayed_saturates_at_zero() {
+        let counts = EventCounts {
+            captured: 1,
+            relayed: 4,
+        };
+        assert_eq!(counts.not_relayed(), 0);
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{RwLock, broadcast};
+
+use crate::input::RelayState;
+use crate::network::{ConnectionEvent, NetworkCounters};
+
+/// How often the status line is redrawn
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keep a one-line summary of the client's state on stderr, redrawn in place
+/// until the task is aborted.
+///
+/// `network` is `None` in monitor mode, where nothing is sent, and `events`
+/// the relay's connection events while it is sending.
+pub async fn show_status(
+    relay_state: Arc<RwLock<RelayState>>,
+    grabbed_devices: Arc<AtomicUsize>,
+    network: Option<Arc<NetworkCounters>>,
+    mut events: Option<broadcast::Receiver<ConnectionEvent>>,
+) {
+    let mut interval = tokio::time::interval(STATUS_INTERVAL);
+    let mut reconnect_attempt = None;
+
+    loop {
+        interval.tick().await;
+
+        if let Some(events) = &mut events {
+            reconnect_attempt = follow_reconnects(events, reconnect_attempt);
+        }
+
+        let line = status_line(
+            &*relay_state.read().await,
+            grabbed_devices.load(Ordering::Relaxed),
+            network.as_deref(),
+            reconnect_attempt,
+        );
+
+        // Clear the line first, the previous one may have been longer
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", line);
+        let _ = stderr.flush();
+    }
+}
+
+/// Summary of the client's state shown by [`show_status`]
+fn status_line(
+    state: &RelayState,
+    grabbed_devices: usize,
+    network: Option<&NetworkCounters>,
+    reconnect_attempt: Option<u32>,
+) -> String {
+    let relay = if state.paused {
+        "paused"
+    } else if state.relay_enabled {
+        "on"
+    } else {
+        "off"
+    };
+    let connection = match network {
+        Some(counters) => {
+            let reconnecting = reconnect_attempt
+                .map(|attempt| format!(" (reconnecting, attempt {})", attempt))
+                .unwrap_or_default();
+            format!(
+                "{}/{} servers connected{} | {} packets sent",
+                counters.connected_servers(),
+                counters.servers(),
+                reconnecting,
+                counters.packets_sent()
+            )
+        }
+        None => "monitor only".to_string(),
+    };
+
+    format!(
+        "relay {} | {} devices grabbed | {}",
+        relay, grabbed_devices, connection
+    )
+}
+
+/// Reconnect attempt in progress after the events published since the last
+/// check, `None` once a connection succeeded
+fn follow_reconnects(
+    events: &mut broadcast::Receiver<ConnectionEvent>,
+    mut attempt: Option<u32>,
+) -> Option<u32> {
+    loop {
+        match events.try_recv() {
+            Ok(ConnectionEvent::Reconnecting { attempt: next }) => attempt = Some(next),
+            Ok(ConnectionEvent::Connected) => attempt = None,
+            Ok(ConnectionEvent::Disconnected(_)) => {}
+            // Missed events only matter in that later ones tell the current state
+            Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+            Err(_) => return attempt,
+        }
+    }
+}
+
+/// Move past the status line so the shell prompt doesn't overwrite it
+pub fn finish_status() {
+    eprintln!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_attempt_follows_the_latest_event() {
+        let (sender, mut events) = broadcast::channel(16);
+
+        sender
+            .send(ConnectionEvent::Disconnected("reset".to_string()))
+            .unwrap();
+        sender
+            .send(ConnectionEvent::Reconnecting { attempt: 1 })
+            .unwrap();
+        sender
+            .send(ConnectionEvent::Reconnecting { attempt: 2 })
+            .unwrap();
+        assert_eq!(follow_reconnects(&mut events, None), Some(2));
+
+        // Nothing new keeps the attempt shown
+        assert_eq!(follow_reconnects(&mut events, Some(2)), Some(2));
+
+        sender.send(ConnectionEvent::Connected).unwrap();
+        assert_eq!(follow_reconnects(&mut events, Some(2)), None);
+    }
+
+    #[test]
+    fn lagging_behind_keeps_the_latest_events() {
+        let (sender, mut events) = broadcast::channel(2);
+        for attempt in 1..=5 {
+            sender
+                .send(ConnectionEvent::Reconnecting { attempt })
+                .unwrap();
+        }
+
+        assert_eq!(follow_reconnects(&mut events, None), Some(5));
+    }
+
+    #[test]
+    fn status_line_shows_the_relay_state() {
+        let off = RelayState::default();
+        let on = RelayState {
+            relay_enabled: true,
+            ..RelayState::default()
+        };
+        let paused = RelayState {
+            relay_enabled: true,
+            paused: true,
+            ..RelayState::default()
+        };
+
+        assert_eq!(
+            status_line(&off, 0, None, None),
+            "relay off | 0 devices grabbed | monitor only"
+        );
+        assert_eq!(
+            status_line(&on, 3, None, None),
+            "relay on | 3 devices grabbed | monitor only"
+        );
+        assert!(status_line(&paused, 3, None, None).starts_with("relay paused |"));
+    }
+
+    #[test]
+    fn status_line_shows_the_connection() {
+        let counters = NetworkCounters::default();
+        let state = RelayState::default();
+
+        assert_eq!(
+            status_line(&state, 2, Some(&counters), None),
+            "relay off | 2 devices grabbed | 0/0 servers connected | 0 packets sent"
+        );
+        assert_eq!(
+            status_line(&state, 2, Some(&counters), Some(4)),
+            "relay off | 2 devices grabbed | 0/0 servers connected (reconnecting, attempt 4) | 0 packets sent"
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{Result, anyhow, bail};
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::keys::{key_code_from_name, key_name};
+
+/// Keys that toggle relay when all of them are held at once
+#[derive(Debug, Clone)]
+pub struct ToggleCombo {
+    keys: Vec<u32>,
+    held: HashSet<u32>,
+    /// Whether the combo fired since the last time one of its keys was released
+    fired: bool,
+}
+
+impl ToggleCombo {
+    pub fn new(keys: Vec<u32>) -> Self {
+        Self {
+            keys,
+            held: HashSet::new(),
+            fired: false,
+        }
+    }
+
+    /// Parse a `+` or `,` separated list of key names or codes, e.g. `Ctrl+Alt+F12`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut keys = Vec::new();
+        for part in spec.split(['+', ',']).map(str::trim) {
+            if part.is_empty() {
+                bail!("Empty key in toggle combination: {}", spec);
+            }
+            let key = parse_key(part)?;
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        Ok(Self::new(keys))
+    }
+
+    /// Whether `key` is one of the keys of the combination
+    pub fn contains(&self, key: u32) -> bool {
+        self.keys.contains(&key)
+    }
+
+    /// Track a key press or release, returning true when it completes the combination.
+    ///
+    /// The combination fires once per hold; releasing any of its keys, in any
+    /// order, arms it again.
+    pub fn update(&mut self, key: u32, pressed: bool) -> bool {
+        if !self.keys.contains(&key) {
+            return false;
+        }
+
+        if !pressed {
+            self.held.remove(&key);
+            self.fired = false;
+            return false;
+        }
+
+        self.held.insert(key);
+        if self.fired || self.held.len() < self.keys.len() {
+            return false;
+        }
+
+        self.fired = true;
+        true
+    }
+
+    /// Forget which keys are held, e.g. after key releases may have been missed
+    pub fn reset(&mut self) {
+        self.held.clear();
+        self.fired = false;
+    }
+}
+
+impl fmt::Display for ToggleCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, key) in self.keys.iter().enumerate() {
+            if index > 0 {
+                write!(f, " + ")?;
+            }
+            write!(f, "{} (0x{:02x})", key_name(*key), key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a key name, falling back to a decimal or `0x` prefixed hex key code
+fn parse_key(value: &str) -> Result<u32> {
+    if let Some(key_code) = key_code_from_name(value) {
+        return Ok(key_code);
+    }
+
+    if let Some(hex) = value.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).map_err(|_| anyhow!("Invalid hexadecimal key code: {}", value))
+    } else {
+        value
+            .parse::<u32>()
+            .map_err(|_| anyhow!("Unknown key name or code: {}", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::key_codes;
+
+    #[test]
+    fn key_is_parsed_by_name_or_code() {
+        for spec in ["LEFTCTRL", "0x1d", "29", "Left Ctrl"] {
+            let combo = ToggleCombo::parse(spec).unwrap();
+            assert_eq!(combo.keys, vec![key_codes::KEY_LEFTCTRL], "{}", spec);
+        }
+        assert_eq!(
+            ToggleCombo::parse("f1").unwrap().keys,
+            vec![key_codes::KEY_F1]
+        );
+    }
+
+    #[test]
+    fn combination_is_parsed_with_either_separator() {
+        let expected = vec![
+            key_codes::KEY_LEFTCTRL,
+            key_codes::KEY_LEFTALT,
+            key_codes::KEY_F12,
+        ];
+        assert_eq!(ToggleCombo::parse("Ctrl+Alt+F12").unwrap().keys, expected);
+        assert_eq!(ToggleCombo::parse("29, 56, 0x58").unwrap().keys, expected);
+        // Repeated keys count once
+        assert_eq!(ToggleCombo::parse("Ctrl+Ctrl").unwrap().keys.len(), 1);
+        assert!(ToggleCombo::parse("Ctrl++F12").is_err());
+    }
+
+    #[test]
+    fn two_key_combo_fires_once_per_hold() {
+        let mut combo = ToggleCombo::parse("Ctrl+F12").unwrap();
+        let (ctrl, f12) = (key_codes::KEY_LEFTCTRL, key_codes::KEY_F12);
+
+        assert!(!combo.update(ctrl, true));
+        assert!(combo.update(f12, true));
+        // Autorepeat while held doesn't fire again
+        assert!(!combo.update(f12, true));
+
+        assert!(!combo.update(f12, false));
+        assert!(combo.update(f12, true));
+    }
+
+    #[test]
+    fn three_key_combo_fires_in_any_order() {
+        let mut combo = ToggleCombo::parse("Ctrl+Alt+F12").unwrap();
+        let (ctrl, alt, f12) = (
+            key_codes::KEY_LEFTCTRL,
+            key_codes::KEY_LEFTALT,
+            key_codes::KEY_F12,
+        );
+
+        assert!(!combo.update(f12, true));
+        assert!(!combo.update(alt, true));
+        assert!(combo.update(ctrl, true));
+
+        // Released out of order, then pressed again in another order
+        for key in [alt, f12, ctrl] {
+            assert!(!combo.update(key, false));
+        }
+        assert!(!combo.update(alt, true));
+        assert!(!combo.update(ctrl, true));
+        assert!(combo.update(f12, true));
+    }
+
+    #[test]
+    fn other_keys_and_resets_are_handled() {
+        let mut combo = ToggleCombo::parse("Ctrl+F12").unwrap();
+        let (ctrl, f12) = (key_codes::KEY_LEFTCTRL, key_codes::KEY_F12);
+
+        assert!(!combo.update(key_codes::KEY_A, true));
+        assert!(!combo.update(ctrl, true));
+        // A release that was missed while relay was off is forgotten
+        combo.reset();
+        assert!(!combo.update(f12, true));
+        assert!(combo.update(ctrl, true));
+    }
+
+    #[test]
+    fn invalid_keys_are_refused() {
+        assert!(ToggleCombo::parse("NotAKey").is_err());
+        assert!(ToggleCombo::parse("0xZZ").is_err());
+        assert!(ToggleCombo::parse("").is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::{Result, anyhow};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+
+/// Environment variable that overrides the configured shared secret
+pub const SECRET_ENV_VAR: &str = "ASTERIA_SECRET";
+
+/// Length of the challenge nonce sent by the server
+pub const NONCE_LEN: usize = 32;
+
+/// Shared secret from `ASTERIA_SECRET`, falling back to the configured one
+pub fn resolve_secret(configured: Option<&str>) -> Option<String> {
+    std::env::var(SECRET_ENV_VAR)
+        .ok()
+        .or_else(|| configured.map(str::to_string))
+        .filter(|secret| !secret.is_empty())
+}
+
+/// Fresh random nonce for an authentication challenge
+pub fn generate_nonce() -> Result<Vec<u8>> {
+    let mut nonce = vec![0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow!("Failed to generate authentication nonce"))?;
+    Ok(nonce)
+}
+
+/// HMAC-SHA256 of the nonce keyed with the shared secret
+pub fn sign_nonce(secret: &str, nonce: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::sign(&key, nonce).as_ref().to_vec()
+}
+
+/// Check a nonce signature in constant time
+pub fn verify_nonce(secret: &str, nonce: &[u8], mac: &[u8]) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::verify(&key, nonce, mac).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_with_the_shared_secret_verifies() {
+        let nonce = generate_nonce().unwrap();
+        let mac = sign_nonce("hunter2", &nonce);
+        assert!(verify_nonce("hunter2", &nonce, &mac));
+    }
+
+    #[test]
+    fn signature_with_another_secret_or_nonce_is_rejected() {
+        let nonce = generate_nonce().unwrap();
+        let mac = sign_nonce("hunter2", &nonce);
+        assert!(!verify_nonce("hunter3", &nonce, &mac));
+
+        let other_nonce = generate_nonce().unwrap();
+        assert_ne!(nonce, other_nonce);
+        assert!(!verify_nonce("hunter2", &other_nonce, &mac));
+    }
+}
@@ -0,0 +1,308 @@
+use anyhow::{Result, bail};
+use std::time::{Duration, Instant};
+
+use crate::protocol::{ClipboardChunk, ClipboardFormat};
+
+/// Largest amount of clipboard data carried by a single chunk
+pub const CLIPBOARD_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Clipboard content of a single format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardPayload {
+    pub format: ClipboardFormat,
+    pub data: Vec<u8>,
+}
+
+impl ClipboardPayload {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            format: ClipboardFormat::Text,
+            data: text.into().into_bytes(),
+        }
+    }
+
+    /// Borrow the content as text, if it is valid UTF-8 text
+    pub fn as_text(&self) -> Option<&str> {
+        match self.format {
+            ClipboardFormat::Text => std::str::from_utf8(&self.data).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Formats supported by both peers, in local preference order
+pub fn negotiate_formats(
+    local: &[ClipboardFormat],
+    remote: &[ClipboardFormat],
+) -> Vec<ClipboardFormat> {
+    local
+        .iter()
+        .filter(|format| remote.contains(format))
+        .copied()
+        .collect()
+}
+
+/// Split a payload into chunks, rejecting payloads above `max_size`
+pub fn chunk_payload(
+    transfer_id: u32,
+    payload: &ClipboardPayload,
+    max_size: usize,
+) -> Result<Vec<ClipboardChunk>> {
+    if payload.data.len() > max_size {
+        bail!(
+            "Clipboard payload of {} bytes exceeds the {} byte limit",
+            payload.data.len(),
+            max_size
+        );
+    }
+
+    let pieces: Vec<&[u8]> = if payload.data.is_empty() {
+        vec![&[]]
+    } else {
+        payload.data.chunks(CLIPBOARD_CHUNK_SIZE).collect()
+    };
+    let total = pieces.len() as u32;
+
+    Ok(pieces
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| ClipboardChunk {
+            transfer_id,
+            format: payload.format,
+            index: index as u32,
+            total,
+            data: data.to_vec(),
+        })
+        .collect())
+}
+
+/// Reassembles chunked clipboard transfers received from a peer
+#[derive(Debug)]
+pub struct ClipboardAssembler {
+    max_size: usize,
+    pending: Option<PendingTransfer>,
+}
+
+#[derive(Debug)]
+struct PendingTransfer {
+    transfer_id: u32,
+    format: ClipboardFormat,
+    next_index: u32,
+    total: u32,
+    data: Vec<u8>,
+}
+
+impl ClipboardAssembler {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            pending: None,
+        }
+    }
+
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+    }
+
+    /// Largest payload accepted, and the largest worth sending to the peer
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Add a chunk, returning the payload once its last chunk has arrived.
+    ///
+    /// A chunk starting a new transfer discards any unfinished one. Transfers
+    /// that are out of order or grow beyond the size limit are dropped.
+    pub fn push(&mut self, chunk: ClipboardChunk) -> Result<Option<ClipboardPayload>> {
+        if chunk.index == 0 {
+            self.pending = Some(PendingTransfer {
+                transfer_id: chunk.transfer_id,
+                format: chunk.format,
+                next_index: 0,
+                total: chunk.total,
+                data: Vec::new(),
+            });
+        }
+
+        let Some(pending) = self.pending.as_mut() else {
+            bail!(
+                "Clipboard chunk {} of transfer {} has no transfer in progress",
+                chunk.index,
+                chunk.transfer_id
+            );
+        };
+
+        if chunk.transfer_id != pending.transfer_id || chunk.index != pending.next_index {
+            self.pending = None;
+            bail!(
+                "Clipboard chunk {} of transfer {} arrived out of order",
+                chunk.index,
+                chunk.transfer_id
+            );
+        }
+
+        if pending.data.len() + chunk.data.len() > self.max_size {
+            self.pending = None;
+            bail!(
+                "Clipboard transfer {} exceeds the {} byte limit",
+                chunk.transfer_id,
+                self.max_size
+            );
+        }
+
+        pending.data.extend_from_slice(&chunk.data);
+        pending.next_index += 1;
+
+        if pending.next_index < pending.total {
+            return Ok(None);
+        }
+
+        let finished = self.pending.take().expect("pending transfer");
+        Ok(Some(ClipboardPayload {
+            format: finished.format,
+            data: finished.data,
+        }))
+    }
+}
+
+/// Holds back clipboard changes until they stop changing for a while
+#[derive(Debug)]
+pub struct ClipboardDebouncer {
+    delay: Duration,
+    pending: Option<(String, Instant)>,
+    last_seen: Option<String>,
+}
+
+impl ClipboardDebouncer {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            pending: None,
+            last_seen: None,
+        }
+    }
+
+    /// Record the clipboard content seen at `now`, returning it once it has settled
+    pub fn observe(&mut self, content: String, now: Instant) -> Option<String> {
+        if self.last_seen.as_ref() == Some(&content) {
+            self.pending = None;
+            return None;
+        }
+
+        match &self.pending {
+            Some((pending, since)) if *pending == content => {
+                if now.duration_since(*since) < self.delay {
+                    return None;
+                }
+            }
+            _ => {
+                self.pending = Some((content, now));
+                return None;
+            }
+        }
+
+        self.pending = None;
+        self.last_seen = Some(content.clone());
+        Some(content)
+    }
+
+    /// Treat `content` as already synced, so it isn't sent back where it came from
+    pub fn mark_seen(&mut self, content: String) {
+        self.pending = None;
+        self.last_seen = Some(content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reassemble(
+        chunks: Vec<ClipboardChunk>,
+        max_size: usize,
+    ) -> Result<Option<ClipboardPayload>> {
+        let mut assembler = ClipboardAssembler::new(max_size);
+        let mut payload = None;
+        for chunk in chunks {
+            payload = assembler.push(chunk)?;
+        }
+        Ok(payload)
+    }
+
+    #[test]
+    fn payload_under_the_limit_round_trips() {
+        let text = "x".repeat(CLIPBOARD_CHUNK_SIZE * 2 + 10);
+        let payload = ClipboardPayload::text(text.clone());
+
+        let chunks = chunk_payload(7, &payload, text.len()).unwrap();
+        assert_eq!(chunks.len(), 3);
+
+        let received = reassemble(chunks, text.len()).unwrap().unwrap();
+        assert_eq!(received, payload);
+        assert_eq!(received.as_text(), Some(text.as_str()));
+    }
+
+    #[test]
+    fn empty_payload_round_trips_in_one_chunk() {
+        let payload = ClipboardPayload::text("");
+        let chunks = chunk_payload(0, &payload, 16).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(reassemble(chunks, 16).unwrap(), Some(payload));
+    }
+
+    #[test]
+    fn payload_over_the_limit_is_rejected() {
+        let payload = ClipboardPayload::text("too long");
+        assert!(chunk_payload(0, &payload, 7).is_err());
+
+        // The receiving side enforces its own limit too
+        let chunks = chunk_payload(0, &payload, 8).unwrap();
+        assert!(reassemble(chunks, 7).is_err());
+    }
+
+    #[test]
+    fn out_of_order_chunk_drops_the_transfer() {
+        let payload = ClipboardPayload::text("y".repeat(CLIPBOARD_CHUNK_SIZE * 2 + 1));
+        let chunks = chunk_payload(1, &payload, usize::MAX).unwrap();
+
+        let mut assembler = ClipboardAssembler::new(usize::MAX);
+        assert_eq!(assembler.push(chunks[0].clone()).unwrap(), None);
+        assert!(assembler.push(chunks[2].clone()).is_err());
+        // Nothing is left to continue
+        assert!(assembler.push(chunks[1].clone()).is_err());
+    }
+
+    #[test]
+    fn negotiation_keeps_local_order() {
+        let local = [ClipboardFormat::Image, ClipboardFormat::Text];
+        let remote = [ClipboardFormat::Text, ClipboardFormat::Image];
+        assert_eq!(negotiate_formats(&local, &remote), local);
+        assert!(negotiate_formats(&local, &[]).is_empty());
+    }
+
+    #[test]
+    fn debouncer_waits_for_content_to_settle() {
+        let delay = Duration::from_millis(100);
+        let mut debouncer = ClipboardDebouncer::new(delay);
+        let start = Instant::now();
+
+        assert_eq!(debouncer.observe("a".into(), start), None);
+        assert_eq!(debouncer.observe("a".into(), start + delay / 2), None);
+        assert_eq!(
+            debouncer.observe("a".into(), start + delay),
+            Some("a".to_string())
+        );
+        // Already synced
+        assert_eq!(debouncer.observe("a".into(), start + delay * 3), None);
+    }
+
+    #[test]
+    fn debouncer_skips_content_marked_seen() {
+        let mut debouncer = ClipboardDebouncer::new(Duration::ZERO);
+        let now = Instant::now();
+        debouncer.mark_seen("from peer".into());
+
+        assert_eq!(debouncer.observe("from peer".into(), now), None);
+        assert_eq!(debouncer.observe("from peer".into(), now), None);
+    }
+}
@@ -1,8 +1,13 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+use crate::protocol::ClipboardFormat;
+
 pub trait LoadableConfig: Sized + Default + for<'de> Deserialize<'de> {
     fn file_name() -> &'static str;
 
@@ -10,15 +15,21 @@ pub trait LoadableConfig: Sized + Default + for<'de> Deserialize<'de> {
     where
         Self: Serialize,
     {
-        let config_path = Self::config_path()?;
+        Self::load_from(&Self::config_path()?)
+    }
 
-        if config_path.exists() {
-            let content: String = std::fs::read_to_string(&config_path)?;
+    /// Load the config from `path`, writing the defaults there if it doesn't exist yet
+    fn load_from(path: &Path) -> Result<Self>
+    where
+        Self: Serialize,
+    {
+        if path.exists() {
+            let content: String = std::fs::read_to_string(path)?;
             let config: Self = toml::from_str(&content)?;
             Ok(config)
         } else {
             let default_config = Self::default();
-            default_config.save()?;
+            default_config.save_to(path)?;
             Ok(default_config)
         }
     }
@@ -27,26 +38,123 @@ pub trait LoadableConfig: Sized + Default + for<'de> Deserialize<'de> {
     where
         Self: Serialize,
     {
-        let config_path = Self::config_path()?;
-        if let Some(parent) = config_path.parent() {
+        self.save_to(&Self::config_path()?)
+    }
+
+    fn save_to(&self, path: &Path) -> Result<()>
+    where
+        Self: Serialize,
+    {
+        if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        let content = toml::to_string_pretty(self)?;
-        fs::write(&config_path, content)?;
+        fs::write(path, self.to_toml()?)?;
         Ok(())
     }
 
+    /// Serialize the config in the same format it is saved in
+    fn to_toml(&self) -> Result<String>
+    where
+        Self: Serialize,
+    {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
     fn config_path() -> Result<PathBuf> {
-        let config_dir: PathBuf = dirs::config_dir()
-            .ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?;
-        Ok(config_dir.join("asteria").join(Self::file_name()))
+        Ok(config_dir()?.join(Self::file_name()))
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+/// Directory holding Asteria's configuration and related files
+pub fn config_dir() -> Result<PathBuf> {
+    let config_dir: PathBuf = dirs::config_dir()
+        .ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?;
+    Ok(config_dir.join("asteria"))
+}
+
+/// Smallest read buffer a server connection uses, whatever is configured
+pub const MIN_READ_BUFFER_SIZE: usize = 512;
+
+/// Smallest queue of packets waiting for each server, whatever is configured
+pub const MIN_RELAY_QUEUE_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub network: NetworkConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub clipboard: ClipboardConfig,
+    #[serde(default)]
+    pub simulation: SimulationConfig,
+    #[serde(default)]
+    pub scope: RelayScopeConfig,
+    #[serde(default)]
+    pub tls: ServerTlsConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub bind_retry: BindRetryConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// IP addresses or CIDR ranges clients may connect from, empty to allow any
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+    /// Bytes read from a client connection at once
+    #[serde(default = "default_read_buffer_size")]
+    pub read_buffer_size: usize,
+    /// PID file written by `start --daemon`, `asteria/server.pid` under the runtime directory if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid_file: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            network: NetworkConfig::default(),
+            logging: LoggingConfig::default(),
+            clipboard: ClipboardConfig::default(),
+            simulation: SimulationConfig::default(),
+            scope: RelayScopeConfig::default(),
+            tls: ServerTlsConfig::default(),
+            auth: AuthConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            metrics: MetricsConfig::default(),
+            bind_retry: BindRetryConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            allowed_clients: Vec::new(),
+            read_buffer_size: default_read_buffer_size(),
+            pid_file: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Read buffer size in effect, at least [`MIN_READ_BUFFER_SIZE`] and at
+    /// most a whole frame
+    pub fn read_buffer_size(&self) -> usize {
+        self.read_buffer_size
+            .clamp(MIN_READ_BUFFER_SIZE, crate::protocol::MAX_FRAME_SIZE)
+    }
+
+    /// PID file location in effect
+    pub fn pid_file(&self) -> PathBuf {
+        self.pid_file.clone().unwrap_or_else(|| {
+            dirs::runtime_dir()
+                .unwrap_or_else(std::env::temp_dir)
+                .join("asteria")
+                .join("server.pid")
+        })
+    }
+}
+
+fn default_read_buffer_size() -> usize {
+    4096
 }
 
 impl LoadableConfig for ServerConfig {
@@ -55,9 +163,69 @@ impl LoadableConfig for ServerConfig {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientConfig {
     pub network: NetworkConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub clipboard: ClipboardConfig,
+    #[serde(default)]
+    pub capture: CaptureConfig,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    #[serde(default)]
+    pub tls: ClientTlsConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub control: ControlConfig,
+    #[serde(default)]
+    pub grab: GrabConfig,
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    #[serde(default)]
+    pub debug_network: DebugNetworkConfig,
+    /// SOCKS5 proxy the TCP connection to the server goes through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+    /// Packets waiting to be sent to each server before new ones are dropped
+    #[serde(default = "default_relay_queue_size")]
+    pub relay_queue_size: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            network: NetworkConfig {
+                host: "127.0.0.1".to_string(),
+                ..NetworkConfig::default()
+            },
+            logging: LoggingConfig::default(),
+            clipboard: ClipboardConfig::default(),
+            capture: CaptureConfig::default(),
+            reconnect: ReconnectConfig::default(),
+            tls: ClientTlsConfig::default(),
+            auth: AuthConfig::default(),
+            control: ControlConfig::default(),
+            grab: GrabConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            debug_network: DebugNetworkConfig::default(),
+            proxy: None,
+            relay_queue_size: default_relay_queue_size(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Relay queue size in effect, at least [`MIN_RELAY_QUEUE_SIZE`]
+    pub fn relay_queue_size(&self) -> usize {
+        self.relay_queue_size.max(MIN_RELAY_QUEUE_SIZE)
+    }
+}
+
+fn default_relay_queue_size() -> usize {
+    1000
 }
 
 impl LoadableConfig for ClientConfig {
@@ -70,6 +238,38 @@ impl LoadableConfig for ClientConfig {
 pub struct NetworkConfig {
     pub host: String,
     pub port: u16,
+    /// Host the client connects to, when it differs from `host`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_host: Option<String>,
+    /// More servers the client sends the same input to, as `host` or `host:port`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirror_hosts: Vec<String>,
+    /// Encrypt the connection with TLS
+    #[serde(default)]
+    pub tls: bool,
+    /// Protocol input is relayed over
+    #[serde(default)]
+    pub transport: TransportKind,
+    /// Encoding of packets on the wire, which both sides have to agree on
+    #[serde(default)]
+    pub wire_format: WireFormat,
+}
+
+impl NetworkConfig {
+    /// Resolve the `host:port` address a client should connect to
+    pub fn server_address(&self) -> String {
+        let host = self.server_host.as_deref().unwrap_or(&self.host);
+        join_host_port(host, self.port)
+    }
+}
+
+/// Format `host:port`, bracketing IPv6 literals so the port stays separate
+pub fn join_host_port(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
 }
 
 impl Default for NetworkConfig {
@@ -77,6 +277,889 @@ impl Default for NetworkConfig {
         Self {
             host: "0.0.0.0".to_string(),
             port: 3100,
+            server_host: None,
+            mirror_hosts: Vec::new(),
+            tls: false,
+            transport: TransportKind::default(),
+            wire_format: WireFormat::default(),
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportKind {
+    /// Reliable, ordered stream, supports TLS and authentication
+    #[default]
+    Tcp,
+    /// One datagram per packet, lower latency but lost packets stay lost
+    Udp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WireFormat {
+    /// Compact binary encoding
+    #[default]
+    Bincode,
+    /// Readable JSON, for inspecting captured traffic
+    Json,
+}
+
+impl fmt::Display for WireFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireFormat::Bincode => f.write_str("bincode"),
+            WireFormat::Json => f.write_str("json"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Pre-shared key clients must prove knowledge of, overridden by `ASTERIA_SECRET`
+    pub secret: Option<String>,
+}
+
+impl AuthConfig {
+    /// Secret in effect, if authentication is enabled
+    pub fn secret(&self) -> Option<String> {
+        crate::auth::resolve_secret(self.secret.as_deref())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ControlConfig {
+    /// Serve relay status and toggle requests on a local Unix socket, off by default
+    pub enabled: bool,
+    /// Socket location, `asteria/client.sock` under `$XDG_RUNTIME_DIR` if unset
+    pub socket_path: Option<PathBuf>,
+}
+
+impl ControlConfig {
+    /// Socket location in effect, `None` without a configured path or runtime directory
+    pub fn socket_path(&self) -> Option<PathBuf> {
+        self.socket_path
+            .clone()
+            .or_else(|| dirs::runtime_dir().map(|dir| dir.join("asteria").join("client.sock")))
+    }
+}
+
+/// Device name patterns overriding which devices are grabbed while relaying.
+///
+/// A pattern containing `*` or `?` is a glob matched against the whole name,
+/// any other pattern matches anywhere in it. Matching ignores case.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct GrabConfig {
+    /// Devices to grab even if they look virtual or unsuitable
+    pub include: Vec<String>,
+    /// Devices never to grab, wins over `include`
+    pub exclude: Vec<String>,
+}
+
+/// Why a device is or isn't grabbed regardless of its detected capabilities
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrabOverride {
+    Include(String),
+    Exclude(String),
+}
+
+impl GrabConfig {
+    /// The pattern forcing a device in or out, if any matches its name
+    pub fn override_for(&self, device_name: &str) -> Option<GrabOverride> {
+        let matching = |patterns: &[String]| {
+            patterns
+                .iter()
+                .find(|pattern| device_name_matches(pattern, device_name))
+                .cloned()
+        };
+
+        matching(&self.exclude)
+            .map(GrabOverride::Exclude)
+            .or_else(|| matching(&self.include).map(GrabOverride::Include))
+    }
+}
+
+fn device_name_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+
+    if pattern.contains(['*', '?']) {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        glob_matches(&pattern, &name)
+    } else {
+        name.contains(&pattern)
+    }
+}
+
+/// Match `*` against any run of characters and `?` against any single one
+fn glob_matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| glob_matches(rest, &name[skip..])),
+        Some((first, rest)) => match name.split_first() {
+            Some((c, name_rest)) if *first == '?' || first == c => glob_matches(rest, name_rest),
+            _ => false,
+        },
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ServerTlsConfig {
+    /// PEM file with the server certificate chain
+    pub cert_path: Option<PathBuf>,
+    /// PEM file with the server private key
+    pub key_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ClientTlsConfig {
+    /// PEM file with the certificates trusted to sign the server certificate
+    pub ca_cert_path: Option<PathBuf>,
+    /// SHA-256 fingerprint the server certificate must match, replacing CA verification
+    pub pinned_sha256: Option<String>,
+    /// Name checked against the server certificate, defaults to the server host
+    pub server_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Default level when `RUST_LOG` is not set
+    pub log_level: String,
+    /// Also write logs to a daily rotated file
+    pub to_file: bool,
+    /// Log file directory, defaults to `logs` under the config directory
+    pub directory: Option<PathBuf>,
+    /// Number of rotated log files to keep
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            log_level: "info".to_string(),
+            to_file: false,
+            directory: None,
+            max_files: 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    /// Clipboard formats this side can send and receive
+    pub formats: Vec<ClipboardFormat>,
+    /// Largest clipboard payload accepted or sent, in bytes
+    pub max_payload_size: usize,
+    /// How often each side checks its clipboard for changes when syncing
+    pub sync_poll_interval_ms: u64,
+    /// How long clipboard content must stay unchanged before it is synced
+    pub sync_debounce_ms: u64,
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            formats: vec![ClipboardFormat::Text],
+            max_payload_size: 1024 * 1024,
+            sync_poll_interval_ms: 250,
+            sync_debounce_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfig {
+    /// Emulate absolute moves with relative ones when the backend can't position absolutely
+    pub absolute_fallback: bool,
+    /// Screen size absolute coordinates are scaled to, detected from the main display if unset
+    pub screen_size: Option<ScreenSize>,
+    /// Screen size used when the main display can't be queried, also reported to clients
+    pub fallback_screen_size: ScreenSize,
+    /// Monitors absolute coordinates are spread across, overriding `screen_size` when set
+    pub screen_layout: ScreenLayout,
+    /// Attempts at creating the input backend before giving up
+    pub init_attempts: u32,
+    /// Delay before the first retry, doubled after each failed attempt
+    pub init_retry_delay_ms: u64,
+    /// Minimum gap between consecutive injected key events, for apps that drop fast input
+    pub inter_key_delay_ms: u64,
+    /// Minimum time a key is held between its press and release
+    pub press_release_delay_ms: u64,
+    /// What to do with raw events whose event type the server doesn't recognize
+    pub unknown_event_policy: UnknownEventPolicy,
+    /// How often each client's input latency is logged, 0 to only log it when the client leaves
+    pub latency_log_interval_secs: u64,
+    /// How long pointer motion is held back to play it out evenly despite network jitter, 0 passes it straight through
+    pub motion_buffer_ms: u64,
+    /// Window over which consecutive pointer moves or scrolls are summed into one simulated event, 0 simulates each as it arrives
+    pub coalesce_window_ms: u64,
+    /// Layout of the client's keyboard, deciding which character each key types
+    pub keyboard_layout: KeyboardLayout,
+    /// How input is injected into the system
+    pub backend: InputBackend,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            absolute_fallback: true,
+            screen_size: None,
+            fallback_screen_size: ScreenSize {
+                width: 1920,
+                height: 1080,
+            },
+            screen_layout: ScreenLayout::default(),
+            init_attempts: 5,
+            init_retry_delay_ms: 500,
+            inter_key_delay_ms: 0,
+            press_release_delay_ms: 0,
+            unknown_event_policy: UnknownEventPolicy::default(),
+            latency_log_interval_secs: 60,
+            motion_buffer_ms: 0,
+            coalesce_window_ms: 0,
+            keyboard_layout: KeyboardLayout::default(),
+            backend: InputBackend::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyboardLayout {
+    #[default]
+    Qwerty,
+    Qwertz,
+    Azerty,
+    Dvorak,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputBackend {
+    /// Whichever backend the server was built with
+    #[default]
+    Auto,
+    /// enigo, injecting through the platform's input API (SendInput, XTest, ...)
+    Enigo,
+    /// Wayland's virtual keyboard and pointer protocols, needs the `wayland` cargo feature
+    Wayland,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RelayScopeConfig {
+    /// Executable or window class names allowed to receive input, empty allows every app
+    pub allowed_apps: Vec<String>,
+    /// What happens to input while an app outside the scope has focus
+    pub blocked_action: BlockedInputAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockedInputAction {
+    #[default]
+    Drop,
+    Hold,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownEventPolicy {
+    /// Skip the event, logging it at debug level only
+    Ignore,
+    /// Skip the event with a warning
+    #[default]
+    Warn,
+    /// Fail processing of the event
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScreenSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ScreenLayout {
+    /// Every monitor of the desktop, empty to scale to a single screen
+    pub monitors: Vec<MonitorBounds>,
+}
+
+/// Where a monitor sits on the desktop, in pixels relative to the top left
+/// corner of the primary monitor. Monitors left of or above the primary one
+/// have negative coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonitorBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    /// How often the client tells the server it is still there, 0 to never
+    pub interval_secs: u64,
+    /// How long the server waits for any packet before dropping a client, 0 to wait forever
+    pub timeout_secs: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 5,
+            timeout_secs: 15,
+        }
+    }
+}
+
+/// Simulated packet loss and latency on the way to the server, for testing
+/// how the client and server cope with a bad network. Off unless set.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct DebugNetworkConfig {
+    /// Share of packets dropped instead of sent, from 0.0 to 1.0
+    pub drop_rate: f32,
+    /// Delay added to every packet after it was captured
+    pub extra_latency_ms: u64,
+    /// Most random delay added on top of `extra_latency_ms`
+    pub jitter_ms: u64,
+    /// Seed for the drops and delays, random if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// Proxy to connect through, as `host:port`
+    pub address: String,
+    /// User name, when the proxy requires username/password authentication
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Serve connection and event counters over HTTP in the Prometheus text format
+    pub enabled: bool,
+    /// Address the metrics endpoint listens on
+    pub bind_address: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_address: "127.0.0.1:9464".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Most input events simulated per second for one client, 0 for no limit.
+    /// Releases are always simulated so nothing gets stuck.
+    pub max_events_per_sec: u32,
+    /// Events let through at once before the limit kicks in
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_events_per_sec: 5000,
+            burst: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BindRetryConfig {
+    /// How often a failed bind is retried before the server gives up, e.g. while
+    /// the network interface isn't up yet
+    pub max_retries: u32,
+    /// Keep retrying for as long as it takes, ignoring `max_retries`
+    pub forever: bool,
+    /// Delay before the first retry
+    pub initial_delay_ms: u64,
+    /// Upper bound the doubling delay is capped at
+    pub max_delay_ms: u64,
+}
+
+impl Default for BindRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 10,
+            forever: false,
+            initial_delay_ms: 500,
+            max_delay_ms: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// Delay before the first reconnect attempt
+    pub initial_delay_ms: u64,
+    /// Upper bound the doubling delay is capped at
+    pub max_delay_ms: u64,
+    /// How long the server's resolved addresses are reused, 0 resolves them on every connect
+    pub dns_cache_secs: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 100,
+            max_delay_ms: 10_000,
+            dns_cache_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    /// Seat whose input devices are captured
+    pub seat: String,
+    /// Physical keys that emit an ambiguous code, e.g. Esc/grave or Fn-layer media keys
+    pub shared_keys: Vec<SharedKeyConfig>,
+    /// Keys relayed as a different key, applied after the shared key table.
+    /// The toggle combination still matches the physical keys.
+    pub key_remap: Vec<KeyRemapConfig>,
+    /// Key combination that turns relay off and releases every device in any
+    /// state, e.g. `Ctrl+Alt+Shift+Escape`, for when the client gets stuck
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub panic_key: Option<String>,
+    /// libinput event categories the capture loop processes at all
+    pub event_categories: Vec<EventCategory>,
+    /// Relay autorepeat for held keys, since injected keys don't repeat on their own
+    pub forward_key_repeat: bool,
+    /// How long a key is held before it starts repeating
+    pub key_repeat_delay_ms: u64,
+    /// Interval between repeats of a held key
+    pub key_repeat_interval_ms: u64,
+    /// How often capture statistics are logged, 0 to only log them at shutdown
+    pub stats_log_interval_secs: u64,
+    /// Largest relative move per axis in one packet, excess is carried into later packets; 0 disables the cap
+    pub max_motion_delta: i32,
+    /// How often accumulated relative motion is sent as one move, 0 sends every motion
+    pub motion_flush_interval_ms: u64,
+    /// How long events are collected into one packet, 0 sends every event on its own
+    pub batch_window_ms: u64,
+    /// Most events sent in one packet
+    pub batch_max_events: usize,
+    /// Send the events of each libinput dispatch as one packet instead of batching by time
+    pub batch_per_dispatch: bool,
+    /// Ping the server when relay is toggled on and stay in local control if it doesn't answer
+    pub validate_link_on_enable: bool,
+    /// How long to wait for the server to answer that ping
+    pub link_validation_timeout_ms: u64,
+    /// Known input lag of specific devices, subtracted from their events' timestamps
+    pub device_latency: Vec<DeviceLatencyConfig>,
+    /// Direction horizontal scroll is relayed in
+    pub scroll_horizontal: ScrollDirection,
+    /// Direction vertical scroll is relayed in
+    pub scroll_vertical: ScrollDirection,
+    /// Touchpad and continuous scroll distance relayed as one wheel step
+    pub scroll_distance_per_step: f64,
+    /// Wheel clicks relayed per scroll step, below 1 scrolls slower and above 1 faster than locally
+    pub scroll_clicks_per_step: f64,
+    /// Relay key presses; the toggle key works either way
+    pub relay_keyboard: bool,
+    /// Relay pointer motion and mouse buttons, which edge switching relies on
+    pub relay_mouse: bool,
+    /// Relay wheel and touchpad scrolling as well as touchpad gestures
+    pub relay_scroll: bool,
+    /// Relay game controllers plugged in when capture starts
+    pub relay_gamepads: bool,
+    /// How often the Caps, Num and Scroll Lock state is checked and synced to the server, 0 disables it
+    pub lock_state_interval_ms: u64,
+    /// How often the server is told to release everything it holds while nothing is held here, 0 only does so on connecting
+    pub input_reset_interval_secs: u64,
+    /// Turn relay off after this long without relayed keyboard or pointer input, 0 never does
+    pub relay_idle_timeout_secs: u64,
+    /// Captured packets waiting for the network before pointer motion is dropped
+    pub queue_capacity: usize,
+    /// Switch relay on and off as the pointer crosses a screen edge
+    pub edge_switch: EdgeSwitchConfig,
+    /// Save whether relay is enabled and turn it back on at startup if it was when the client exited
+    pub remember_relay_state: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            seat: "seat0".to_string(),
+            shared_keys: Vec::new(),
+            key_remap: Vec::new(),
+            panic_key: None,
+            event_categories: EventCategory::ALL.to_vec(),
+            forward_key_repeat: true,
+            key_repeat_delay_ms: 500,
+            key_repeat_interval_ms: 33,
+            stats_log_interval_secs: 60,
+            max_motion_delta: 0,
+            motion_flush_interval_ms: 8,
+            batch_window_ms: 2,
+            batch_max_events: 16,
+            batch_per_dispatch: false,
+            validate_link_on_enable: true,
+            link_validation_timeout_ms: 500,
+            device_latency: Vec::new(),
+            scroll_horizontal: ScrollDirection::Reverse,
+            scroll_vertical: ScrollDirection::Reverse,
+            scroll_distance_per_step: 15.0,
+            scroll_clicks_per_step: 1.0,
+            relay_keyboard: true,
+            relay_mouse: true,
+            relay_scroll: true,
+            relay_gamepads: true,
+            lock_state_interval_ms: 1000,
+            input_reset_interval_secs: 60,
+            relay_idle_timeout_secs: 0,
+            queue_capacity: 1000,
+            edge_switch: EdgeSwitchConfig::default(),
+            remember_relay_state: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EdgeSwitchConfig {
+    /// Enable relay when the pointer leaves the local screen across `edge` and
+    /// disable it when the pointer comes back across the same edge
+    pub enabled: bool,
+    /// Edge of the local screen the server's screen lies beyond
+    pub edge: ScreenEdge,
+    /// Size of the local desktop, spanning every monitor
+    pub local_screen: ScreenSize,
+    /// Size of the server's desktop
+    pub remote_screen: ScreenSize,
+}
+
+impl Default for EdgeSwitchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            edge: ScreenEdge::Right,
+            local_screen: ScreenSize {
+                width: 1920,
+                height: 1080,
+            },
+            remote_screen: ScreenSize {
+                width: 1920,
+                height: 1080,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreenEdge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventCategory {
+    Device,
+    Keyboard,
+    Pointer,
+    Touch,
+    Tablet,
+    TabletPad,
+    Gesture,
+    Switch,
+}
+
+impl EventCategory {
+    pub const ALL: [EventCategory; 8] = [
+        EventCategory::Device,
+        EventCategory::Keyboard,
+        EventCategory::Pointer,
+        EventCategory::Touch,
+        EventCategory::Tablet,
+        EventCategory::TabletPad,
+        EventCategory::Gesture,
+        EventCategory::Switch,
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedKeyConfig {
+    /// Code emitted by the shared physical key
+    pub code: u32,
+    /// Code of the key on the Fn layer
+    pub fn_code: u32,
+    /// Which layer the emitted code should be relayed as
+    pub interpret_as: KeyLayer,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRemapConfig {
+    /// Code of the captured key
+    pub from: u32,
+    /// Code relayed in its place
+    pub to: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceLatencyConfig {
+    /// Device name as reported by libinput
+    pub device: String,
+    /// How long the device takes to report input
+    pub offset_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyLayer {
+    Base,
+    Fn,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrollDirection {
+    /// Relay scroll in the direction libinput reports it
+    Natural,
+    /// Flip the direction, the server scrolls opposite to libinput's convention
+    Reverse,
+}
+
+impl ScrollDirection {
+    /// Apply the direction to scroll steps reported by libinput
+    pub fn apply(self, steps: i32) -> i32 {
+        match self {
+            ScrollDirection::Natural => steps,
+            ScrollDirection::Reverse => -steps,
+        }
+    }
+}
+
+impl CaptureConfig {
+    /// Resolve a captured key code through the shared key table
+    pub fn resolve_shared_key(&self, code: u32) -> u32 {
+        match self.shared_keys.iter().find(|shared| shared.code == code) {
+            Some(shared) if shared.interpret_as == KeyLayer::Fn => shared.fn_code,
+            _ => code,
+        }
+    }
+
+    /// Code relayed for a captured key, resolved through the shared key table
+    /// and then the remap table. Presses and releases map alike, so a
+    /// remapped key is released as the same key it was pressed as.
+    pub fn relayed_key(&self, code: u32) -> u32 {
+        let code = self.resolve_shared_key(code);
+        self.key_remap
+            .iter()
+            .find(|remap| remap.from == code)
+            .map_or(code, |remap| remap.to)
+    }
+
+    /// Configured latency offset of the named device, in milliseconds
+    pub fn device_latency_offset(&self, device: &str) -> u64 {
+        self.device_latency
+            .iter()
+            .find(|latency| latency.device == device)
+            .map_or(0, |latency| latency.offset_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_without_server_host_loads() {
+        let config: ClientConfig = toml::from_str(
+            r#"
+            [network]
+            host = "192.168.1.100"
+            port = 3100
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.network.server_host, None);
+        assert_eq!(config.network.server_address(), "192.168.1.100:3100");
+    }
+
+    #[test]
+    fn server_host_overrides_host() {
+        let mut network = ClientConfig::default().network;
+        assert_eq!(network.server_address(), "127.0.0.1:3100");
+
+        network.server_host = Some("192.168.137.1".to_string());
+        assert_eq!(network.server_address(), "192.168.137.1:3100");
+    }
+
+    #[test]
+    fn ipv6_hosts_are_bracketed() {
+        let mut network = NetworkConfig {
+            server_host: Some("fe80::1".to_string()),
+            ..NetworkConfig::default()
+        };
+        assert_eq!(network.server_address(), "[fe80::1]:3100");
+
+        network.server_host = Some("[::1]".to_string());
+        assert_eq!(network.server_address(), "[::1]:3100");
+    }
+
+    /// Serialize `config`, parse it back and serialize it again
+    fn round_trip<T: LoadableConfig + Serialize>(config: &T) -> (String, String) {
+        let saved = config.to_toml().unwrap();
+        let loaded: T = toml::from_str(&saved).unwrap();
+        (saved, loaded.to_toml().unwrap())
+    }
+
+    #[test]
+    fn default_client_config_round_trips() {
+        let (saved, reloaded) = round_trip(&ClientConfig::default());
+        assert_eq!(saved, reloaded);
+    }
+
+    #[test]
+    fn default_server_config_round_trips() {
+        let (saved, reloaded) = round_trip(&ServerConfig::default());
+        assert_eq!(saved, reloaded);
+    }
+
+    #[test]
+    fn changed_values_survive_the_round_trip() {
+        let mut config = ClientConfig::default();
+        config.network.server_host = Some("fe80::1".to_string());
+        config.network.mirror_hosts = vec!["192.168.1.20:3101".to_string()];
+
+        let (saved, reloaded) = round_trip(&config);
+        assert_eq!(saved, reloaded);
+        let loaded: ClientConfig = toml::from_str(&saved).unwrap();
+        assert_eq!(loaded.network.server_address(), "[fe80::1]:3100");
+        assert_eq!(loaded.network.mirror_hosts, ["192.168.1.20:3101"]);
+    }
+
+    fn config_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("asteria-config-{}-{}", name, std::process::id()))
+            .join("client.toml")
+    }
+
+    #[test]
+    fn missing_config_file_is_written_with_the_defaults() {
+        let path = config_path("missing");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+
+        let config = ClientConfig::load_from(&path).unwrap();
+
+        assert_eq!(
+            config.to_toml().unwrap(),
+            ClientConfig::default().to_toml().unwrap()
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            ClientConfig::default().to_toml().unwrap()
+        );
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn existing_config_file_is_loaded_and_saved_back_in_place() {
+        let path = config_path("existing");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            r#"
+            [network]
+            host = "0.0.0.0"
+            port = 4100
+            server_host = "192.168.1.50"
+            "#,
+        )
+        .unwrap();
+
+        let mut config = ClientConfig::load_from(&path).unwrap();
+        assert_eq!(config.network.server_address(), "192.168.1.50:4100");
+
+        config.network.port = 4200;
+        config.save_to(&path).unwrap();
+        let reloaded = ClientConfig::load_from(&path).unwrap();
+        assert_eq!(reloaded.network.server_address(), "192.168.1.50:4200");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    fn remap(from: u32, to: u32) -> KeyRemapConfig {
+        KeyRemapConfig { from, to }
+    }
+
+    #[test]
+    fn swap_remap_exchanges_both_keys() {
+        // Caps Lock and left Ctrl
+        let config = CaptureConfig {
+            key_remap: vec![remap(58, 29), remap(29, 58)],
+            ..CaptureConfig::default()
+        };
+
+        assert_eq!(config.relayed_key(58), 29);
+        assert_eq!(config.relayed_key(29), 58);
+        assert_eq!(config.relayed_key(30), 30);
+    }
+
+    #[test]
+    fn remap_applies_after_the_shared_key_table() {
+        let config = CaptureConfig {
+            shared_keys: vec![SharedKeyConfig {
+                code: 113,
+                fn_code: 224,
+                interpret_as: KeyLayer::Fn,
+            }],
+            key_remap: vec![remap(113, 1), remap(224, 225)],
+            ..CaptureConfig::default()
+        };
+
+        // The shared key resolves to its Fn code first, which is then remapped
+        assert_eq!(config.relayed_key(113), 225);
+    }
+
+    #[test]
+    fn press_and_release_relay_the_same_key() {
+        let config = CaptureConfig {
+            key_remap: vec![remap(58, 29), remap(29, 58)],
+            ..CaptureConfig::default()
+        };
+
+        // Relayed keys of a press and its release, in capture order
+        let captured = [58, 29, 29, 58];
+        let relayed: Vec<u32> = captured
+            .iter()
+            .map(|&code| config.relayed_key(code))
+            .collect();
+        assert_eq!(relayed, [29, 58, 58, 29]);
+        assert_eq!(relayed[0], relayed[3]);
+        assert_eq!(relayed[1], relayed[2]);
+    }
+}
@@ -1,7 +1,13 @@
+pub mod auth;
+pub mod clipboard;
 pub mod config;
 pub mod logging;
 pub mod protocol;
+pub mod tls;
 
+pub use auth::*;
+pub use clipboard::*;
 pub use config::*;
 pub use logging::*;
 pub use protocol::*;
+pub use tls::*;
@@ -1,11 +1,252 @@
-use tracing_subscriber::fmt;
+use std::{fs, path::PathBuf, sync::OnceLock};
 
-pub fn init_logging() {
-    let subscriber: fmt::SubscriberBuilder = fmt()
+use anyhow::{Result, anyhow};
+use tracing::warn;
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{EnvFilter, Registry, filter::LevelFilter, fmt, prelude::*, reload};
+
+use crate::config::{LoggingConfig, config_dir};
+
+/// Swaps the level filter of the subscriber installed by [`init_logging`]
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Install the global tracing subscriber.
+///
+/// `RUST_LOG` takes precedence, then `verbosity` picked on the command line;
+/// otherwise the configured level is used, falling back to `info` when it
+/// can't be parsed. When file logging is enabled the returned guard must be
+/// kept alive to flush buffered lines.
+pub fn init_logging(
+    config: &LoggingConfig,
+    file_prefix: &str,
+    verbosity: Option<LevelFilter>,
+) -> Option<WorkerGuard> {
+    let (default_level, invalid_level) = default_level(config, verbosity);
+
+    let filter = EnvFilter::builder()
+        .with_default_directive(default_level.into())
+        .from_env_lossy();
+    let (filter, filter_handle) = reload::Layer::new(filter);
+    let _ = FILTER_HANDLE.set(filter_handle);
+
+    let console_layer = fmt::layer()
         .with_target(false)
         .with_thread_ids(false)
         .with_file(false)
         .with_line_number(false);
 
-    subscriber.init();
+    let mut file_error = None;
+    let (file_layer, guard) = if config.to_file {
+        match file_appender(config, file_prefix) {
+            Ok(appender) => {
+                let (writer, guard) = tracing_appender::non_blocking(appender);
+                let layer = fmt::layer()
+                    .with_writer(writer)
+                    .with_ansi(false)
+                    .with_target(false);
+                (Some(layer), Some(guard))
+            }
+            Err(e) => {
+                file_error = Some(e);
+                (None, None)
+            }
+        }
+    } else {
+        (None, None)
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(console_layer)
+        .with(file_layer)
+        .init();
+
+    if let Some(level) = invalid_level {
+        warn!("Invalid log level '{}', falling back to info", level);
+    }
+
+    if let Some(e) = file_error {
+        warn!("File logging disabled, continuing with stdout only: {}", e);
+    }
+
+    guard
+}
+
+/// Change the level of the subscriber installed by [`init_logging`], with
+/// `RUST_LOG` still taking precedence
+pub fn set_log_level(level: &str) -> Result<()> {
+    let level = parse_log_level(level).ok_or_else(|| anyhow!("Invalid log level '{}'", level))?;
+    let handle = FILTER_HANDLE
+        .get()
+        .ok_or_else(|| anyhow!("Logging is not initialized"))?;
+    handle.reload(
+        EnvFilter::builder()
+            .with_default_directive(level.into())
+            .from_env_lossy(),
+    )?;
+    Ok(())
+}
+
+/// Level asked for with repeated `-v` or `--quiet`, `None` when neither was given.
+///
+/// One `-v` logs debug output and two or more trace output; `--quiet` only
+/// logs warnings and errors.
+pub fn verbosity_level(verbose: u8, quiet: bool) -> Option<LevelFilter> {
+    match (verbose, quiet) {
+        (_, true) => Some(LevelFilter::WARN),
+        (0, false) => None,
+        (1, false) => Some(LevelFilter::DEBUG),
+        (_, false) => Some(LevelFilter::TRACE),
+    }
+}
+
+/// Level used when `RUST_LOG` is not set, along with the configured level
+/// if it was invalid and `info` was used instead
+fn default_level(
+    config: &LoggingConfig,
+    verbosity: Option<LevelFilter>,
+) -> (LevelFilter, Option<&str>) {
+    match verbosity {
+        Some(level) => (level, None),
+        None => match parse_log_level(&config.log_level) {
+            Some(level) => (level, None),
+            None => (LevelFilter::INFO, Some(&config.log_level)),
+        },
+    }
+}
+
+/// Parse a log level name such as `debug` or `WARN`
+pub fn parse_log_level(level: &str) -> Option<LevelFilter> {
+    level.trim().parse().ok()
+}
+
+/// Build a daily rotating appender in the configured log directory
+fn file_appender(config: &LoggingConfig, file_prefix: &str) -> Result<RollingFileAppender> {
+    let directory = match &config.directory {
+        Some(directory) => directory.clone(),
+        None => log_dir()?,
+    };
+    fs::create_dir_all(&directory)?;
+
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(file_prefix)
+        .filename_suffix("log")
+        .max_log_files(config.max_files.max(1))
+        .build(&directory)?;
+
+    Ok(appender)
+}
+
+/// Default directory for log files
+fn log_dir() -> Result<PathBuf> {
+    Ok(config_dir()?.join("logs"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbose_flags_raise_the_level() {
+        assert_eq!(verbosity_level(0, false), None);
+        assert_eq!(verbosity_level(1, false), Some(LevelFilter::DEBUG));
+        assert_eq!(verbosity_level(2, false), Some(LevelFilter::TRACE));
+        assert_eq!(verbosity_level(5, false), Some(LevelFilter::TRACE));
+    }
+
+    #[test]
+    fn quiet_wins_over_verbose() {
+        assert_eq!(verbosity_level(0, true), Some(LevelFilter::WARN));
+        assert_eq!(verbosity_level(2, true), Some(LevelFilter::WARN));
+    }
+
+    fn logging_config(log_level: &str) -> LoggingConfig {
+        LoggingConfig {
+            log_level: log_level.to_string(),
+            ..LoggingConfig::default()
+        }
+    }
+
+    #[test]
+    fn level_names_parse_in_any_case() {
+        assert_eq!(parse_log_level("debug"), Some(LevelFilter::DEBUG));
+        assert_eq!(parse_log_level(" WARN "), Some(LevelFilter::WARN));
+        assert_eq!(parse_log_level("off"), Some(LevelFilter::OFF));
+        assert_eq!(parse_log_level("loud"), None);
+    }
+
+    #[test]
+    fn an_invalid_level_falls_back_to_info() {
+        let config = logging_config("loud");
+        assert_eq!(
+            default_level(&config, None),
+            (LevelFilter::INFO, Some("loud"))
+        );
+    }
+
+    #[test]
+    fn verbosity_overrides_the_configured_level() {
+        let config = logging_config("error");
+        assert_eq!(default_level(&config, None), (LevelFilter::ERROR, None));
+        assert_eq!(
+            default_level(&config, Some(LevelFilter::TRACE)),
+            (LevelFilter::TRACE, None)
+        );
+
+        // An invalid configured level isn't warned about when it isn't used
+        let config = logging_config("loud");
+        assert_eq!(
+            default_level(&config, Some(LevelFilter::DEBUG)),
+            (LevelFilter::DEBUG, None)
+        );
+    }
+
+    fn log_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("asteria-logs-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn log_files_are_written_to_the_configured_directory() {
+        use std::io::Write;
+
+        let directory = log_dir("written").join("nested");
+        let config = LoggingConfig {
+            to_file: true,
+            directory: Some(directory.clone()),
+            ..LoggingConfig::default()
+        };
+
+        let mut appender = file_appender(&config, "asteria-test").unwrap();
+        appender.write_all(b"hello\n").unwrap();
+        appender.flush().unwrap();
+
+        let files: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(files.len(), 1, "{:?}", files);
+        assert!(files[0].starts_with("asteria-test."), "{:?}", files);
+        assert!(files[0].ends_with(".log"), "{:?}", files);
+
+        fs::remove_dir_all(log_dir("written")).unwrap();
+    }
+
+    #[test]
+    fn an_unusable_directory_is_an_error() {
+        // A file where the directory should be can't be created, even as root
+        let blocker = log_dir("blocked");
+        fs::write(&blocker, "").unwrap();
+        let config = LoggingConfig {
+            to_file: true,
+            directory: Some(blocker.join("logs")),
+            ..LoggingConfig::default()
+        };
+
+        assert!(file_appender(&config, "asteria-test").is_err());
+        fs::remove_file(&blocker).unwrap();
+    }
 }
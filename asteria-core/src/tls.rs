@@ -0,0 +1,289 @@
+use anyhow::{Context, Result, anyhow, bail};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::{
+    self, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::config::{ClientTlsConfig, ServerTlsConfig};
+
+/// Build the acceptor the server wraps incoming connections in
+pub fn tls_acceptor(config: &ServerTlsConfig) -> Result<TlsAcceptor> {
+    let cert_path = config
+        .cert_path
+        .as_deref()
+        .ok_or_else(|| anyhow!("TLS is enabled but tls.cert_path is not set"))?;
+    let key_path = config
+        .key_path
+        .as_deref()
+        .ok_or_else(|| anyhow!("TLS is enabled but tls.key_path is not set"))?;
+
+    let certs = load_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("Failed to read private key from {}", key_path.display()))?;
+
+    let server_config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Build the connector the client wraps its connection in
+pub fn tls_connector(config: &ClientTlsConfig) -> Result<TlsConnector> {
+    let client_config = if let Some(fingerprint) = &config.pinned_sha256 {
+        rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier::new(fingerprint)?))
+            .with_no_client_auth()
+    } else if let Some(ca_cert_path) = &config.ca_cert_path {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(ca_cert_path)? {
+            roots.add(cert)?;
+        }
+        rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth()
+    } else {
+        bail!("TLS is enabled but neither tls.ca_cert_path nor tls.pinned_sha256 is set");
+    };
+
+    Ok(TlsConnector::from(Arc::new(client_config)))
+}
+
+/// Name the server certificate is checked against
+pub fn tls_server_name(config: &ClientTlsConfig, host: &str) -> Result<ServerName<'static>> {
+    let name = config.server_name.as_deref().unwrap_or(host);
+    ServerName::try_from(name.to_string()).map_err(|_| anyhow!("Invalid TLS server name: {}", name))
+}
+
+/// Lowercase hex SHA-256 fingerprint of a DER certificate
+pub fn cert_fingerprint(cert: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, cert)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates from {}", path.display()))?;
+
+    if certs.is_empty() {
+        bail!("No certificates found in {}", path.display());
+    }
+
+    Ok(certs)
+}
+
+/// Accepts only the server certificate with a known fingerprint
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedCertVerifier {
+    fn new(fingerprint: &str) -> Result<Self> {
+        // Accept fingerprints in the colon separated form tools like openssl print
+        let fingerprint: String = fingerprint
+            .chars()
+            .filter(|c| *c != ':')
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if fingerprint.len() != 64 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("tls.pinned_sha256 must be a hex encoded SHA-256 fingerprint");
+        }
+
+        Ok(Self {
+            fingerprint,
+            provider: Arc::new(rustls::crypto::ring::default_provider()),
+        })
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if cert_fingerprint(end_entity) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "Server certificate does not match the pinned fingerprint".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Self-signed certificate for `localhost` written out as PEM files,
+    /// along with its DER encoding
+    struct SelfSigned {
+        cert_path: PathBuf,
+        key_path: PathBuf,
+        der: Vec<u8>,
+    }
+
+    impl SelfSigned {
+        fn new(name: &str) -> Self {
+            let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+                .expect("generate certificate");
+            let dir =
+                std::env::temp_dir().join(format!("asteria-tls-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let cert_path = dir.join("cert.pem");
+            let key_path = dir.join("key.pem");
+            std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+            std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+
+            Self {
+                cert_path,
+                key_path,
+                der: certified.cert.der().to_vec(),
+            }
+        }
+
+        fn server_config(&self) -> ServerTlsConfig {
+            ServerTlsConfig {
+                cert_path: Some(self.cert_path.clone()),
+                key_path: Some(self.key_path.clone()),
+            }
+        }
+    }
+
+    impl Drop for SelfSigned {
+        fn drop(&mut self) {
+            if let Some(dir) = self.cert_path.parent() {
+                let _ = std::fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    /// Run a handshake over an in-memory stream and echo a byte through it
+    async fn handshake(server: &ServerTlsConfig, client: &ClientTlsConfig) -> Result<()> {
+        let acceptor = tls_acceptor(server)?;
+        let connector = tls_connector(client)?;
+        let server_name = tls_server_name(client, "localhost")?;
+        let (client_stream, server_stream) = tokio::io::duplex(16 * 1024);
+
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server_stream).await?;
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await?;
+            stream.write_all(&byte).await?;
+            stream.flush().await?;
+            Ok::<_, std::io::Error>(())
+        });
+
+        let mut stream = connector.connect(server_name, client_stream).await?;
+        stream.write_all(&[42]).await?;
+        stream.flush().await?;
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await?;
+        assert_eq!(byte, [42]);
+
+        server.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn self_signed_certificate_is_trusted_as_its_own_ca() {
+        let cert = SelfSigned::new("ca");
+        let client = ClientTlsConfig {
+            ca_cert_path: Some(cert.cert_path.clone()),
+            ..ClientTlsConfig::default()
+        };
+
+        handshake(&cert.server_config(), &client).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pinned_fingerprint_is_accepted() {
+        let cert = SelfSigned::new("pinned");
+        // In the colon separated uppercase form openssl prints
+        let fingerprint = cert_fingerprint(&cert.der)
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap().to_ascii_uppercase())
+            .collect::<Vec<_>>()
+            .join(":");
+        let client = ClientTlsConfig {
+            pinned_sha256: Some(fingerprint),
+            ..ClientTlsConfig::default()
+        };
+
+        handshake(&cert.server_config(), &client).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_fingerprint_is_rejected() {
+        let cert = SelfSigned::new("mismatch");
+        let client = ClientTlsConfig {
+            pinned_sha256: Some("00".repeat(32)),
+            ..ClientTlsConfig::default()
+        };
+
+        assert!(handshake(&cert.server_config(), &client).await.is_err());
+    }
+
+    #[test]
+    fn malformed_fingerprint_is_rejected() {
+        let client = ClientTlsConfig {
+            pinned_sha256: Some("not-a-fingerprint".to_string()),
+            ..ClientTlsConfig::default()
+        };
+        assert!(tls_connector(&client).is_err());
+    }
+}
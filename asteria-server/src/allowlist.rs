@@ -0,0 +1,173 @@
+use anyhow::{Context, Result, bail};
+use std::net::IpAddr;
+
+/// Peer addresses the server accepts clients from
+#[derive(Debug, Default)]
+pub struct ClientAllowlist {
+    /// Allowed ranges, empty to allow every address
+    ranges: Vec<IpRange>,
+}
+
+/// Network given as an address and the number of leading bits that must match
+#[derive(Debug, Clone, Copy)]
+struct IpRange {
+    network: IpAddr,
+    prefix_len: u32,
+}
+
+impl ClientAllowlist {
+    /// Parse IP addresses and CIDR ranges such as `192.168.1.0/24` or `fd00::/8`
+    pub fn parse(entries: &[String]) -> Result<Self> {
+        let ranges = entries
+            .iter()
+            .map(|entry| {
+                parse_range(entry.trim())
+                    .with_context(|| format!("Invalid allowed client {:?}", entry))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { ranges })
+    }
+
+    pub fn is_unrestricted(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Whether a client connecting from `address` is let in
+    pub fn allows(&self, address: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as IPv4-mapped IPv6
+        let address = address.to_canonical();
+        self.is_unrestricted() || self.ranges.iter().any(|range| range.contains(address))
+    }
+}
+
+impl IpRange {
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                prefix_matches(network.to_bits(), address.to_bits(), self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                prefix_matches(network.to_bits(), address.to_bits(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn parse_range(entry: &str) -> Result<IpRange> {
+    let (address, prefix_len) = match entry.split_once('/') {
+        Some((address, prefix_len)) => (address, Some(prefix_len)),
+        None => (entry, None),
+    };
+
+    let address: IpAddr = address.parse()?;
+    let max_len: u32 = match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    let prefix_len = match prefix_len {
+        Some(prefix_len) => prefix_len.parse()?,
+        None => max_len,
+    };
+    if prefix_len > max_len {
+        bail!("Prefix length {} is longer than the address", prefix_len);
+    }
+
+    // IPv4-mapped ranges are matched as IPv4, so the prefix has to cover the
+    // 96-bit mapping and is counted from the IPv4 part
+    let network = address.to_canonical();
+    let prefix_len = match (address, network) {
+        (IpAddr::V6(_), IpAddr::V4(_)) => match prefix_len.checked_sub(96) {
+            Some(prefix_len) => prefix_len,
+            None => bail!(
+                "Prefix length {} of an IPv4-mapped address must be at least 96",
+                prefix_len
+            ),
+        },
+        _ => prefix_len,
+    };
+
+    Ok(IpRange {
+        network,
+        prefix_len,
+    })
+}
+
+/// Whether the leading `prefix_len` bits of `network` and `address` are equal
+fn prefix_matches<T>(network: T, address: T, prefix_len: u32) -> bool
+where
+    T: Copy + PartialEq + std::ops::BitXor<Output = T> + Into<u128>,
+{
+    let bits = 8 * std::mem::size_of::<T>() as u32;
+    let differing: u128 = (network ^ address).into();
+    prefix_len == 0 || differing >> (bits - prefix_len) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowlist(entries: &[&str]) -> ClientAllowlist {
+        let entries: Vec<String> = entries.iter().map(|entry| entry.to_string()).collect();
+        ClientAllowlist::parse(&entries).unwrap()
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn empty_allowlist_allows_everyone() {
+        let allowlist = allowlist(&[]);
+        assert!(allowlist.is_unrestricted());
+        assert!(allowlist.allows(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn exact_address_matches_only_itself() {
+        let allowlist = allowlist(&["192.168.1.10"]);
+        assert!(allowlist.allows(ip("192.168.1.10")));
+        assert!(!allowlist.allows(ip("192.168.1.11")));
+    }
+
+    #[test]
+    fn cidr_range_matches_its_network() {
+        let allowlist = allowlist(&["192.168.1.0/24", "fd00::/8"]);
+        assert!(allowlist.allows(ip("192.168.1.200")));
+        assert!(allowlist.allows(ip("fd12::1")));
+        assert!(!allowlist.allows(ip("192.168.2.1")));
+        assert!(!allowlist.allows(ip("fe80::1")));
+    }
+
+    #[test]
+    fn ipv4_mapped_clients_match_ipv4_entries() {
+        let allowlist = allowlist(&["10.0.0.0/8"]);
+        assert!(allowlist.allows(ip("::ffff:10.1.2.3")));
+        assert!(!allowlist.allows(ip("::ffff:11.1.2.3")));
+    }
+
+    #[test]
+    fn ipv4_mapped_entries_count_the_prefix_from_the_ipv4_part() {
+        let allowlist = allowlist(&["::ffff:192.168.1.0/120", "::ffff:10.0.0.1"]);
+        assert!(allowlist.allows(ip("192.168.1.77")));
+        assert!(allowlist.allows(ip("::ffff:192.168.1.77")));
+        assert!(allowlist.allows(ip("10.0.0.1")));
+        assert!(!allowlist.allows(ip("192.168.2.1")));
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        for entry in [
+            "not-an-address",
+            "10.0.0.0/33",
+            "fd00::/129",
+            "::ffff:10.0.0.0/64",
+        ] {
+            assert!(
+                ClientAllowlist::parse(&[entry.to_string()]).is_err(),
+                "{} was accepted",
+                entry
+            );
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use asteria_core::config::InputBackend;
+use enigo::{Enigo, Settings};
+use tracing::{info, warn};
+
+// enigo has no way to pick one of the backends it was built with, it sends
+// every event through all of them
+#[cfg(all(target_os = "linux", feature = "x11", feature = "wayland"))]
+compile_error!(
+    "the `x11` and `wayland` features each select the Linux input backend, enable only one \
+     (`--no-default-features --features wayland` for Wayland)"
+);
+
+/// Whether the server was built with the Wayland backend
+const WAYLAND_AVAILABLE: bool = cfg!(all(feature = "wayland", target_os = "linux"));
+
+/// Backend actually used for a `requested` one.
+///
+/// Only one backend is built in, so `auto` is whichever that is, and asking
+/// for the other one falls back to it.
+pub fn select_backend(requested: InputBackend, wayland_available: bool) -> InputBackend {
+    match requested {
+        InputBackend::Auto if wayland_available => InputBackend::Wayland,
+        InputBackend::Auto => InputBackend::Enigo,
+        InputBackend::Enigo if wayland_available => {
+            warn!(
+                "asteria-server was built with the wayland input backend instead of the platform one, using wayland"
+            );
+            InputBackend::Wayland
+        }
+        InputBackend::Enigo => InputBackend::Enigo,
+        InputBackend::Wayland if wayland_available => InputBackend::Wayland,
+        InputBackend::Wayland => {
+            warn!(
+                "The wayland input backend needs asteria-server built with the `wayland` feature, falling back to enigo"
+            );
+            InputBackend::Enigo
+        }
+    }
+}
+
+/// Connect the input backend the server was built with, after checking the
+/// one `requested` in the config against it
+pub fn open_backend(requested: InputBackend) -> Result<Enigo> {
+    let backend = select_backend(requested, WAYLAND_AVAILABLE);
+    let enigo = Enigo::new(&Settings::default())?;
+    match backend {
+        InputBackend::Wayland => info!("Input backend: wayland virtual keyboard and pointer"),
+        _ => info!("Input backend: enigo"),
+    }
+    Ok(enigo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_uses_the_built_in_backend() {
+        assert_eq!(
+            select_backend(InputBackend::Auto, false),
+            InputBackend::Enigo
+        );
+        assert_eq!(
+            select_backend(InputBackend::Auto, true),
+            InputBackend::Wayland
+        );
+    }
+
+    #[test]
+    fn unavailable_backend_falls_back_to_the_built_in_one() {
+        assert_eq!(
+            select_backend(InputBackend::Wayland, false),
+            InputBackend::Enigo
+        );
+        assert_eq!(
+            select_backend(InputBackend::Enigo, true),
+            InputBackend::Wayland
+        );
+    }
+
+    #[test]
+    fn available_backend_is_used_as_requested() {
+        assert_eq!(
+            select_backend(InputBackend::Enigo, false),
+            InputBackend::Enigo
+        );
+        assert_eq!(
+            select_backend(InputBackend::Wayland, true),
+            InputBackend::Wayland
+        );
+    }
+}
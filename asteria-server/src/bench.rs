@@ -0,0 +1,383 @@
+use anyhow::{Result, anyhow, bail};
+use asteria_core::{
+    config::{ScreenSize, ServerConfig},
+    protocol::{
+        Handshake, InputEvent, InputEventType, LockState, MAX_FRAME_SIZE, Message,
+        PROTOCOL_VERSION, Packet, decode_frame, encode_frame, wire_config,
+    },
+};
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncWriteExt, duplex},
+    sync::Notify,
+};
+use tracing::info;
+
+use crate::input_simulator::InputSink;
+use crate::loopback::{answer_challenge, write_packet};
+use crate::server::InputServer;
+
+/// How long one event may take to come out of the pipeline before the run fails
+const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Events relayed in turn by the benchmark: keys, motion, clicks and scrolling
+const WORKLOAD: [InputEventType; 6] = [
+    InputEventType::KeyPress { key_code: 30 },
+    InputEventType::KeyRelease { key_code: 30 },
+    InputEventType::MouseMove { x: 3, y: -2 },
+    InputEventType::MouseButton {
+        button: 1,
+        pressed: true,
+    },
+    InputEventType::MouseButton {
+        button: 1,
+        pressed: false,
+    },
+    InputEventType::MouseScroll { dx: 0, dy: 1 },
+];
+
+/// Latencies measured by a benchmark run
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    /// Wall time of the whole run
+    pub elapsed: Duration,
+    /// From encoding an event on the client to the server simulating it, sorted
+    pub pipeline: Vec<Duration>,
+    /// Encoding and decoding an event alone, without the server, sorted
+    pub codec: Vec<Duration>,
+    /// The bincode integer encodings compared, the one in use first
+    pub encodings: Vec<EncodingReport>,
+}
+
+/// Payload size and codec latency of the workload under one bincode configuration
+#[derive(Debug, Clone)]
+pub struct EncodingReport {
+    pub name: &'static str,
+    /// Mean payload size of a packet, in bytes
+    pub mean_bytes: f64,
+    /// Encoding and decoding a packet, sorted
+    pub codec: Vec<Duration>,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let events = self.pipeline.len();
+        writeln!(
+            f,
+            "{} events in {:?} ({:.0} events/s)",
+            events,
+            self.elapsed,
+            events as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+        )?;
+        writeln!(f, "pipeline: {}", Percentiles(&self.pipeline))?;
+        write!(f, "codec:    {}", Percentiles(&self.codec))?;
+        for encoding in &self.encodings {
+            write!(
+                f,
+                "\n{}: {:.1} bytes, {}",
+                encoding.name,
+                encoding.mean_bytes,
+                Percentiles(&encoding.codec)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Percentiles of sorted durations
+struct Percentiles<'a>(&'a [Duration]);
+
+impl fmt::Display for Percentiles<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = |quantile: f64| {
+            let index = ((self.0.len() as f64 * quantile).ceil() as usize).saturating_sub(1);
+            self.0.get(index).copied().unwrap_or_default()
+        };
+        write!(
+            f,
+            "p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            at(0.5),
+            at(0.9),
+            at(0.99),
+            at(1.0)
+        )
+    }
+}
+
+/// Sink that notes when each input was simulated instead of injecting it
+#[derive(Clone, Default)]
+struct TimingSink {
+    simulated_at: Arc<Mutex<Vec<Instant>>>,
+    simulated: Arc<Notify>,
+}
+
+impl TimingSink {
+    fn record(&self) {
+        self.simulated_at
+            .lock()
+            .expect("timing lock")
+            .push(Instant::now());
+        self.simulated.notify_one();
+    }
+
+    fn last(&self) -> Option<Instant> {
+        self.simulated_at
+            .lock()
+            .expect("timing lock")
+            .last()
+            .copied()
+    }
+
+    fn count(&self) -> usize {
+        self.simulated_at.lock().expect("timing lock").len()
+    }
+}
+
+impl InputSink for TimingSink {
+    fn simulate_input(&mut self, _event: &InputEvent) -> Result<()> {
+        self.record();
+        Ok(())
+    }
+
+    fn simulate_typed_input(&mut self, _event: &InputEventType) -> Result<()> {
+        self.record();
+        Ok(())
+    }
+
+    fn set_clipboard_text(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn clipboard_text(&mut self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn sync_lock_state(&mut self, _state: &LockState) -> Result<()> {
+        Ok(())
+    }
+
+    fn release_all(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn screen_size(&self) -> Result<ScreenSize> {
+        bail!("Bench has no screen")
+    }
+
+    fn pacing_delay(&self, _event: &InputEventType) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Relay `events` synthetic events one at a time through an in-process
+/// server and measure how long each takes to be simulated.
+///
+/// Events travel the same path as in [`run_loopback`](crate::loopback::run_loopback):
+/// encoded and framed by the client, sent over an in-memory stream, decoded
+/// and simulated by the server. Each event is only sent once the previous one
+/// was simulated, so the measurement is of latency rather than of queueing.
+/// The rate limit is lifted, since it would drop part of the workload.
+pub async fn run_bench(mut config: ServerConfig, events: usize) -> Result<BenchReport> {
+    if events == 0 {
+        bail!("Nothing to measure without any events");
+    }
+    config.rate_limit.max_events_per_sec = 0;
+
+    let auth_secret = config.auth.secret();
+    let wire_format = config.network.wire_format;
+    let handshake = Handshake {
+        protocol_version: PROTOCOL_VERSION,
+        clipboard_formats: config.clipboard.formats.clone(),
+        max_clipboard_size: config.clipboard.max_payload_size,
+        screen: None,
+    };
+
+    let mut codec = (0..events)
+        .map(|index| {
+            let started_at = Instant::now();
+            let packet = Packet::new(Message::InputEventTyped(workload_event(index)));
+            let mut frame = encode_frame(&packet, wire_format)?;
+            decode_frame(&mut frame, wire_format)?
+                .ok_or_else(|| anyhow!("Encoded frame did not decode"))?;
+            Ok(started_at.elapsed())
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Variable length integers keep the small numbers of input events to a
+    // byte; fixed width ones trade size for decoding without branches
+    let encodings = vec![
+        measure_encoding("bincode varint", wire_config(), events)?,
+        measure_encoding(
+            "bincode fixint",
+            bincode::config::standard()
+                .with_fixed_int_encoding()
+                .with_limit::<MAX_FRAME_SIZE>(),
+            events,
+        )?,
+    ];
+
+    let sink = TimingSink::default();
+    let server = InputServer::with_sink(config, sink.clone());
+    let (client_stream, server_stream) = duplex(64 * 1024);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 0));
+
+    let client = {
+        let sink = sink.clone();
+        async move {
+            let mut stream = client_stream;
+            if let Some(secret) = auth_secret {
+                answer_challenge(&mut stream, &secret, wire_format).await?;
+            }
+            write_packet(
+                &mut stream,
+                Packet::new(Message::Handshake(handshake)),
+                wire_format,
+            )
+            .await?;
+
+            let started_at = Instant::now();
+            let mut pipeline = Vec::with_capacity(events);
+            for index in 0..events {
+                let sent_at = Instant::now();
+                let mut packet = Packet::new(Message::InputEventTyped(workload_event(index)));
+                packet.seq = index as u64 + 1;
+                write_packet(&mut stream, packet, wire_format).await?;
+
+                tokio::time::timeout(EVENT_TIMEOUT, sink.simulated.notified())
+                    .await
+                    .map_err(|_| anyhow!("Event {} was never simulated", index + 1))?;
+                let simulated_at = sink.last().unwrap_or_else(Instant::now);
+                pipeline.push(simulated_at.saturating_duration_since(sent_at));
+            }
+            let elapsed = started_at.elapsed();
+
+            stream.shutdown().await?;
+            Ok::<_, anyhow::Error>((pipeline, elapsed))
+        }
+    };
+
+    let (client_result, server_result) =
+        tokio::join!(client, server.serve_connection(server_stream, peer));
+    let (mut pipeline, elapsed) = client_result?;
+    server_result?;
+
+    // Whatever the session releases when it ends comes on top of the workload
+    if sink.count() < events {
+        bail!("Only {} of {} events were simulated", sink.count(), events);
+    }
+
+    pipeline.sort();
+    codec.sort();
+    info!("Benchmark relayed {} events", events);
+    Ok(BenchReport {
+        elapsed,
+        pipeline,
+        codec,
+        encodings,
+    })
+}
+
+/// Encode and decode the workload's packets with a bincode `config`
+fn measure_encoding(
+    name: &'static str,
+    config: impl bincode::config::Config,
+    events: usize,
+) -> Result<EncodingReport> {
+    let mut bytes = 0;
+    let mut codec = (0..events)
+        .map(|index| {
+            let packet = Packet::new(Message::InputEventTyped(workload_event(index)));
+            let started_at = Instant::now();
+            let payload = bincode::serde::encode_to_vec(&packet, config)?;
+            let _: (Packet, usize) = bincode::serde::decode_from_slice(&payload, config)?;
+            let elapsed = started_at.elapsed();
+            bytes += payload.len();
+            Ok(elapsed)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    codec.sort();
+    Ok(EncodingReport {
+        name,
+        mean_bytes: bytes as f64 / events as f64,
+        codec,
+    })
+}
+
+fn workload_event(index: usize) -> InputEventType {
+    WORKLOAD[index % WORKLOAD.len()].clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn percentiles_pick_the_nearest_rank() {
+        let durations = millis(&(1..=10).collect::<Vec<_>>());
+        assert_eq!(
+            Percentiles(&durations).to_string(),
+            "p50 5ms, p90 9ms, p99 10ms, max 10ms"
+        );
+        assert_eq!(
+            Percentiles(&[]).to_string(),
+            "p50 0ns, p90 0ns, p99 0ns, max 0ns"
+        );
+    }
+
+    #[test]
+    fn the_workload_cycles() {
+        assert!(matches!(
+            workload_event(0),
+            InputEventType::KeyPress { key_code: 30 }
+        ));
+        assert!(matches!(
+            workload_event(WORKLOAD.len() + 2),
+            InputEventType::MouseMove { x: 3, y: -2 }
+        ));
+    }
+
+    #[test]
+    fn fixed_width_integers_take_more_bytes() {
+        let varint = measure_encoding("varint", wire_config(), 12).unwrap();
+        let fixint = measure_encoding(
+            "fixint",
+            bincode::config::standard()
+                .with_fixed_int_encoding()
+                .with_limit::<MAX_FRAME_SIZE>(),
+            12,
+        )
+        .unwrap();
+
+        assert_eq!(varint.codec.len(), 12);
+        assert!(varint.codec.is_sorted());
+        assert!(varint.mean_bytes < fixint.mean_bytes);
+    }
+
+    #[tokio::test]
+    async fn every_event_is_measured() {
+        let report = run_bench(ServerConfig::default(), 20).await.unwrap();
+        assert_eq!(report.pipeline.len(), 20);
+        assert_eq!(report.codec.len(), 20);
+        assert!(report.pipeline.is_sorted());
+        assert_eq!(report.encodings[0].name, "bincode varint");
+
+        let rendered = report.to_string();
+        assert!(rendered.starts_with("20 events in "), "{}", rendered);
+        assert!(rendered.contains("\nbincode fixint: "), "{}", rendered);
+    }
+
+    #[tokio::test]
+    async fn an_empty_run_is_refused() {
+        assert!(run_bench(ServerConfig::default(), 0).await.is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use asteria_core::protocol::InputEventType;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Sums pointer motion and scrolling that arrive in quick succession, so a
+/// burst is simulated as one call instead of one per event.
+///
+/// Consecutive events of the same kind are added up until `window` has
+/// passed since the first of them. Anything else ends the window early: the
+/// caller simulates the summed event right before it, so it is never held
+/// back behind the motion.
+#[derive(Debug)]
+pub struct EventCoalescer {
+    window: Duration,
+    /// Summed event and when its window closes
+    pending: Option<(InputEventType, Instant)>,
+}
+
+impl EventCoalescer {
+    /// Coalescer summing events over `window`, zero to pass them straight through
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    /// Whether `event` is summed rather than simulated right away
+    pub fn coalesces(event: &InputEventType) -> bool {
+        matches!(
+            event,
+            InputEventType::MouseMove { .. } | InputEventType::MouseScroll { .. }
+        )
+    }
+
+    /// Add `event`, which [`coalesces`](Self::coalesces), to the pending sum
+    /// that arrived at `now`. Returns the pending event of the other kind it
+    /// takes the place of, which is due right away.
+    pub fn push(&mut self, event: InputEventType, now: Instant) -> Option<InputEventType> {
+        if let Some((pending, _)) = &mut self.pending {
+            match (pending, &event) {
+                (
+                    InputEventType::MouseMove { x, y },
+                    InputEventType::MouseMove { x: dx, y: dy },
+                ) => {
+                    *x = x.saturating_add(*dx);
+                    *y = y.saturating_add(*dy);
+                    return None;
+                }
+                (
+                    InputEventType::MouseScroll { dx, dy },
+                    InputEventType::MouseScroll {
+                        dx: more_dx,
+                        dy: more_dy,
+                    },
+                ) => {
+                    *dx = dx.saturating_add(*more_dx);
+                    *dy = dy.saturating_add(*more_dy);
+                    return None;
+                }
+                _ => {}
+            }
+        }
+
+        self.pending
+            .replace((event, now + self.window))
+            .map(|(pending, _)| pending)
+    }
+
+    /// When the pending event is due, if there is one
+    pub fn next_flush(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|&(_, flush_at)| flush_at)
+    }
+
+    /// Take the pending event if its window closed by `now`
+    pub fn take_due(&mut self, now: Instant) -> Option<InputEventType> {
+        match self.next_flush() {
+            Some(flush_at) if flush_at <= now => self.drain(),
+            _ => None,
+        }
+    }
+
+    /// Take the pending event, due or not
+    pub fn drain(&mut self) -> Option<InputEventType> {
+        self.pending.take().map(|(event, _)| event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn three_moves_coalesce_into_one_summed_move() {
+        let mut coalescer = EventCoalescer::new(ms(8));
+        let start = Instant::now();
+
+        for (x, y) in [(3, -1), (4, 2), (-2, 5)] {
+            assert!(
+                coalescer
+                    .push(InputEventType::MouseMove { x, y }, start + ms(1))
+                    .is_none()
+            );
+        }
+
+        assert!(coalescer.take_due(start + ms(8)).is_none());
+        assert!(matches!(
+            coalescer.take_due(start + ms(9)),
+            Some(InputEventType::MouseMove { x: 5, y: 6 })
+        ));
+        assert!(coalescer.drain().is_none());
+    }
+
+    #[test]
+    fn other_kind_lets_the_pending_sum_out() {
+        let mut coalescer = EventCoalescer::new(ms(8));
+        let now = Instant::now();
+
+        coalescer.push(InputEventType::MouseMove { x: 1, y: 1 }, now);
+        coalescer.push(InputEventType::MouseMove { x: 1, y: 1 }, now);
+        let replaced = coalescer.push(InputEventType::MouseScroll { dx: 0, dy: 1 }, now);
+
+        assert!(matches!(
+            replaced,
+            Some(InputEventType::MouseMove { x: 2, y: 2 })
+        ));
+        assert!(matches!(
+            coalescer.drain(),
+            Some(InputEventType::MouseScroll { dx: 0, dy: 1 })
+        ));
+    }
+
+    #[test]
+    fn only_motion_and_scroll_coalesce() {
+        assert!(EventCoalescer::coalesces(&InputEventType::MouseMove {
+            x: 1,
+            y: 0
+        }));
+        assert!(!EventCoalescer::coalesces(&InputEventType::KeyPress {
+            key_code: 30
+        }));
+        assert!(!EventCoalescer::new(Duration::ZERO).is_enabled());
+    }
+}
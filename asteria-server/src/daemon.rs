@@ -0,0 +1,378 @@
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Hidden flag marking the detached child of `start --daemon`
+pub const DETACHED_FLAG: &str = "--detached";
+
+/// How long `stop` waits for the server to exit
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often `stop` checks whether the server exited, and the server on
+/// Windows whether it was asked to stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a PID file says about the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PidFileState {
+    /// No PID file, so no daemonized server
+    Missing,
+    /// Server with this PID is running
+    Running(u32),
+    /// PID file left behind by a server that is gone
+    Stale(u32),
+}
+
+/// Write `pid` to the PID file at `path`, creating its directory
+pub fn write_pid_file(path: &Path, pid: u32) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{}\n", pid))
+        .with_context(|| format!("Failed to write PID file {}", path.display()))
+}
+
+/// PID in the PID file at `path`, if there is one
+pub fn read_pid_file(path: &Path) -> Result<Option<u32>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read PID file {}", path.display()));
+        }
+    };
+    let pid = contents
+        .trim()
+        .parse()
+        .with_context(|| format!("PID file {} does not hold a PID", path.display()))?;
+    Ok(Some(pid))
+}
+
+/// Read the PID file at `path` and check whether its server still runs
+pub fn check_pid_file(path: &Path) -> Result<PidFileState> {
+    Ok(match read_pid_file(path)? {
+        None => PidFileState::Missing,
+        Some(pid) if process_exists(pid) => PidFileState::Running(pid),
+        Some(pid) => PidFileState::Stale(pid),
+    })
+}
+
+/// Remove the PID file at `path` if it still names `pid`, so a newer
+/// server's file is left alone
+pub fn remove_pid_file(path: &Path, pid: u32) {
+    if let Ok(Some(current)) = read_pid_file(path)
+        && current == pid
+        && let Err(e) = fs::remove_file(path)
+    {
+        warn!("Failed to remove PID file {}: {}", path.display(), e);
+    }
+}
+
+/// Start the server again as a detached background process and record its
+/// PID in `pid_file`.
+///
+/// The child gets the same arguments with `--daemon` swapped for
+/// [`DETACHED_FLAG`], and its output discarded; configure a log file to
+/// keep its logs.
+pub fn daemonize(pid_file: &Path) -> Result<u32> {
+    match check_pid_file(pid_file)? {
+        PidFileState::Running(pid) => bail!(
+            "Server already running with PID {} (PID file {})",
+            pid,
+            pid_file.display()
+        ),
+        PidFileState::Stale(pid) => {
+            warn!(
+                "Removing stale PID file {} left by PID {}",
+                pid_file.display(),
+                pid
+            );
+            fs::remove_file(pid_file)?;
+        }
+        PidFileState::Missing => {}
+    }
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(std::env::args_os().skip(1).filter(|arg| arg != "--daemon"))
+        .arg(DETACHED_FLAG)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    detach(&mut command);
+
+    let child = command
+        .spawn()
+        .context("Failed to start the server in the background")?;
+    let pid = child.id();
+    write_pid_file(pid_file, pid)?;
+    Ok(pid)
+}
+
+/// Ask the server recorded in `pid_file` to shut down and wait for it to exit
+pub async fn stop(pid_file: &Path) -> Result<()> {
+    let pid = match check_pid_file(pid_file)? {
+        PidFileState::Missing => bail!(
+            "No PID file at {}, is the server running with --daemon?",
+            pid_file.display()
+        ),
+        PidFileState::Stale(pid) => {
+            warn!(
+                "Server with PID {} is no longer running, removing its stale PID file",
+                pid
+            );
+            fs::remove_file(pid_file)?;
+            return Ok(());
+        }
+        PidFileState::Running(pid) => pid,
+    };
+
+    info!("Stopping server with PID {}", pid);
+    request_shutdown(pid)?;
+
+    let started_at = Instant::now();
+    while process_exists(pid) {
+        if started_at.elapsed() > STOP_TIMEOUT {
+            bail!(
+                "Server with PID {} did not exit within {:?}",
+                pid,
+                STOP_TIMEOUT
+            );
+        }
+        tokio::time::sleep(STOP_POLL_INTERVAL).await;
+    }
+
+    // The server removes its PID file as it exits, this only catches a crash
+    remove_pid_file(pid_file, pid);
+    info!("Server stopped");
+    Ok(())
+}
+
+/// Wait for a request to shut down: Ctrl-C, or `stop` from another process
+pub async fn shutdown_signal() {
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = stop_requested() => {}
+    }
+}
+
+#[cfg(unix)]
+fn detach(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    // Out of the terminal's process group, so Ctrl-C there leaves it running
+    command.process_group(0);
+}
+
+#[cfg(windows)]
+fn detach(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+    use windows_sys::Win32::System::Threading::{CREATE_NEW_PROCESS_GROUP, DETACHED_PROCESS};
+
+    command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    // Signal 0 only checks whether the process could be signalled; EPERM
+    // means it exists but belongs to someone else
+    let signalled = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    signalled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+fn process_exists(pid: u32) -> bool {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, STILL_ACTIVE},
+        System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+    };
+
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return false;
+        }
+        let mut exit_code = 0u32;
+        let running =
+            GetExitCodeProcess(process, &mut exit_code) != 0 && exit_code == STILL_ACTIVE as u32;
+        CloseHandle(process);
+        running
+    }
+}
+
+#[cfg(unix)]
+fn request_shutdown(pid: u32) -> Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        bail!(
+            "Failed to signal PID {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn stop_requested() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            terminate.recv().await;
+        }
+        Err(e) => {
+            warn!("Failed to listen for SIGTERM: {}", e);
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Name of the event `stop` sets to shut down the server with `pid`, as a
+/// detached process has no console to receive Ctrl-C through
+#[cfg(windows)]
+fn stop_event_name(pid: u32) -> Vec<u16> {
+    format!("Local\\asteria-server-stop-{}", pid)
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+#[cfg(windows)]
+fn request_shutdown(pid: u32) -> Result<()> {
+    use windows_sys::Win32::{
+        Foundation::CloseHandle,
+        System::Threading::{EVENT_MODIFY_STATE, OpenEventW, SetEvent},
+    };
+
+    let name = stop_event_name(pid);
+    unsafe {
+        let event = OpenEventW(EVENT_MODIFY_STATE, 0, name.as_ptr());
+        if event.is_null() {
+            bail!(
+                "Server with PID {} does not accept stop requests: {}",
+                pid,
+                std::io::Error::last_os_error()
+            );
+        }
+        let set = SetEvent(event);
+        CloseHandle(event);
+        if set == 0 {
+            bail!(
+                "Failed to signal PID {}: {}",
+                pid,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+async fn stop_requested() {
+    use windows_sys::Win32::{
+        Foundation::WAIT_OBJECT_0,
+        System::Threading::{CreateEventW, WaitForSingleObject},
+    };
+
+    let name = stop_event_name(std::process::id());
+    let event = unsafe { CreateEventW(std::ptr::null(), 1, 0, name.as_ptr()) };
+    if event.is_null() {
+        warn!(
+            "Failed to create the stop event: {}",
+            std::io::Error::last_os_error()
+        );
+        return std::future::pending().await;
+    }
+
+    // The handle stays open for the life of the process. It is polled rather
+    // than waited on from a blocking thread, which would hold up the runtime's
+    // shutdown after Ctrl-C.
+    let event = event as usize;
+    while unsafe { WaitForSingleObject(event as _, 0) } != WAIT_OBJECT_0 {
+        tokio::time::sleep(STOP_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn pid_file_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("asteria-daemon-{}-{}", name, std::process::id()))
+            .join("asteria-server.pid")
+    }
+
+    /// PID of a process that already exited
+    #[cfg(unix)]
+    fn exited_pid() -> u32 {
+        let mut child = Command::new("true").spawn().expect("spawn true");
+        child.wait().unwrap();
+        child.id()
+    }
+
+    #[test]
+    fn written_pid_is_read_back() {
+        let path = pid_file_path("roundtrip");
+        write_pid_file(&path, 4321).unwrap();
+
+        assert_eq!(read_pid_file(&path).unwrap(), Some(4321));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn missing_pid_file_means_no_server() {
+        let path = pid_file_path("missing");
+        assert_eq!(check_pid_file(&path).unwrap(), PidFileState::Missing);
+    }
+
+    #[test]
+    fn pid_of_a_live_process_is_running() {
+        let path = pid_file_path("running");
+        write_pid_file(&path, std::process::id()).unwrap();
+
+        assert_eq!(
+            check_pid_file(&path).unwrap(),
+            PidFileState::Running(std::process::id())
+        );
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pid_of_an_exited_process_is_stale() {
+        let path = pid_file_path("stale");
+        let pid = exited_pid();
+        write_pid_file(&path, pid).unwrap();
+
+        assert_eq!(check_pid_file(&path).unwrap(), PidFileState::Stale(pid));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn garbage_pid_file_is_an_error() {
+        let path = pid_file_path("garbage");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "not a pid\n").unwrap();
+
+        assert!(read_pid_file(&path).is_err());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn pid_file_of_a_newer_server_is_kept() {
+        let path = pid_file_path("newer");
+        write_pid_file(&path, 200).unwrap();
+
+        remove_pid_file(&path, 100);
+        assert_eq!(read_pid_file(&path).unwrap(), Some(200));
+
+        remove_pid_file(&path, 200);
+        assert_eq!(read_pid_file(&path).unwrap(), None);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
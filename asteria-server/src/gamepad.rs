@@ -0,0 +1,297 @@
+use anyhow::Result;
+use asteria_core::protocol::{GAMEPAD_AXIS_MAX, gamepad_axes, gamepad_buttons};
+use tracing::{debug, info, warn};
+
+/// XInput button bits, as laid out in `XINPUT_GAMEPAD`
+mod xinput_buttons {
+    pub const DPAD_UP: u16 = 0x0001;
+    pub const DPAD_DOWN: u16 = 0x0002;
+    pub const DPAD_LEFT: u16 = 0x0004;
+    pub const DPAD_RIGHT: u16 = 0x0008;
+    pub const START: u16 = 0x0010;
+    pub const BACK: u16 = 0x0020;
+    pub const LEFT_THUMB: u16 = 0x0040;
+    pub const RIGHT_THUMB: u16 = 0x0080;
+    pub const LEFT_SHOULDER: u16 = 0x0100;
+    pub const RIGHT_SHOULDER: u16 = 0x0200;
+    pub const GUIDE: u16 = 0x0400;
+    pub const A: u16 = 0x1000;
+    pub const B: u16 = 0x2000;
+    pub const X: u16 = 0x4000;
+    pub const Y: u16 = 0x8000;
+}
+
+/// State of the virtual controller, in XInput terms
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct GamepadState {
+    buttons: u16,
+    left_trigger: u8,
+    right_trigger: u8,
+    thumb_lx: i16,
+    thumb_ly: i16,
+    thumb_rx: i16,
+    thumb_ry: i16,
+}
+
+/// Simulates a game controller as a virtual Xbox 360 pad.
+///
+/// The virtual pad is only plugged in once the first gamepad event arrives,
+/// so servers whose clients have no controller never show one. Without the
+/// ViGEmBus driver gamepad events are dropped with a single warning.
+pub struct GamepadSimulator {
+    state: GamepadState,
+    backend: Option<backend::VirtualPad>,
+    /// Set once plugging in the virtual pad failed, so it isn't retried for every event
+    unavailable: bool,
+}
+
+impl GamepadSimulator {
+    pub fn new() -> Self {
+        Self {
+            state: GamepadState::default(),
+            backend: None,
+            unavailable: false,
+        }
+    }
+
+    /// Press or release a button, numbered as in [`gamepad_buttons`]
+    pub fn button(&mut self, button: u16, pressed: bool) -> Result<()> {
+        let Some(bit) = xinput_button(button) else {
+            debug!("Unmapped gamepad button: {:#x}", button);
+            return Ok(());
+        };
+
+        if pressed {
+            self.state.buttons |= bit;
+        } else {
+            self.state.buttons &= !bit;
+        }
+        self.update()
+    }
+
+    /// Move an axis to a normalized value, see [`gamepad_axes`]
+    pub fn axis(&mut self, axis: u8, value: i32) -> Result<()> {
+        let state = &mut self.state;
+        match axis {
+            gamepad_axes::LEFT_X => state.thumb_lx = stick_value(value),
+            // XInput's Y axes point up, the protocol's point down
+            gamepad_axes::LEFT_Y => state.thumb_ly = stick_value(-value),
+            gamepad_axes::RIGHT_X => state.thumb_rx = stick_value(value),
+            gamepad_axes::RIGHT_Y => state.thumb_ry = stick_value(-value),
+            gamepad_axes::LEFT_TRIGGER => state.left_trigger = trigger_value(value),
+            gamepad_axes::RIGHT_TRIGGER => state.right_trigger = trigger_value(value),
+            gamepad_axes::DPAD_X => {
+                state.buttons &= !(xinput_buttons::DPAD_LEFT | xinput_buttons::DPAD_RIGHT);
+                state.buttons |= match value.signum() {
+                    -1 => xinput_buttons::DPAD_LEFT,
+                    1 => xinput_buttons::DPAD_RIGHT,
+                    _ => 0,
+                };
+            }
+            gamepad_axes::DPAD_Y => {
+                state.buttons &= !(xinput_buttons::DPAD_UP | xinput_buttons::DPAD_DOWN);
+                state.buttons |= match value.signum() {
+                    -1 => xinput_buttons::DPAD_UP,
+                    1 => xinput_buttons::DPAD_DOWN,
+                    _ => 0,
+                };
+            }
+            _ => {
+                debug!("Unmapped gamepad axis: {}", axis);
+                return Ok(());
+            }
+        }
+        self.update()
+    }
+
+    /// Release every button and center every axis
+    pub fn reset(&mut self) -> Result<()> {
+        if self.state == GamepadState::default() {
+            return Ok(());
+        }
+
+        self.state = GamepadState::default();
+        self.update()
+    }
+
+    fn update(&mut self) -> Result<()> {
+        if self.backend.is_none() && !self.unavailable {
+            match backend::VirtualPad::plug_in() {
+                Ok(pad) => {
+                    info!("Virtual gamepad connected");
+                    self.backend = Some(pad);
+                }
+                Err(e) => {
+                    warn!("Gamepad input is not available: {}", e);
+                    self.unavailable = true;
+                }
+            }
+        }
+
+        match &mut self.backend {
+            Some(pad) => pad.update(&self.state),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for GamepadSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `button` is one the virtual pad has, numbered as in [`gamepad_buttons`]
+pub fn is_known_button(button: u16) -> bool {
+    xinput_button(button).is_some()
+}
+
+/// Whether `axis` is one of [`gamepad_axes`]
+pub fn is_known_axis(axis: u8) -> bool {
+    axis <= gamepad_axes::DPAD_Y
+}
+
+fn xinput_button(button: u16) -> Option<u16> {
+    match button {
+        gamepad_buttons::SOUTH => Some(xinput_buttons::A),
+        gamepad_buttons::EAST => Some(xinput_buttons::B),
+        gamepad_buttons::NORTH => Some(xinput_buttons::Y),
+        gamepad_buttons::WEST => Some(xinput_buttons::X),
+        gamepad_buttons::LEFT_SHOULDER => Some(xinput_buttons::LEFT_SHOULDER),
+        gamepad_buttons::RIGHT_SHOULDER => Some(xinput_buttons::RIGHT_SHOULDER),
+        gamepad_buttons::SELECT => Some(xinput_buttons::BACK),
+        gamepad_buttons::START => Some(xinput_buttons::START),
+        gamepad_buttons::MODE => Some(xinput_buttons::GUIDE),
+        gamepad_buttons::LEFT_THUMB => Some(xinput_buttons::LEFT_THUMB),
+        gamepad_buttons::RIGHT_THUMB => Some(xinput_buttons::RIGHT_THUMB),
+        gamepad_buttons::DPAD_UP => Some(xinput_buttons::DPAD_UP),
+        gamepad_buttons::DPAD_DOWN => Some(xinput_buttons::DPAD_DOWN),
+        gamepad_buttons::DPAD_LEFT => Some(xinput_buttons::DPAD_LEFT),
+        gamepad_buttons::DPAD_RIGHT => Some(xinput_buttons::DPAD_RIGHT),
+        _ => None,
+    }
+}
+
+fn stick_value(value: i32) -> i16 {
+    value.clamp(-GAMEPAD_AXIS_MAX, GAMEPAD_AXIS_MAX) as i16
+}
+
+fn trigger_value(value: i32) -> u8 {
+    (value.clamp(0, GAMEPAD_AXIS_MAX) * u8::MAX as i32 / GAMEPAD_AXIS_MAX) as u8
+}
+
+#[cfg(windows)]
+mod backend {
+    use super::GamepadState;
+    use anyhow::Result;
+    use vigem_client::{Client, TargetId, XButtons, XGamepad, Xbox360Wired};
+
+    /// Xbox 360 pad plugged into the ViGEmBus driver
+    pub struct VirtualPad {
+        target: Xbox360Wired<Client>,
+    }
+
+    impl VirtualPad {
+        pub fn plug_in() -> Result<Self> {
+            let client = Client::connect()?;
+            let mut target = Xbox360Wired::new(client, TargetId::XBOX360_WIRED);
+            target.plugin()?;
+            target.wait_ready()?;
+            Ok(Self { target })
+        }
+
+        pub fn update(&mut self, state: &GamepadState) -> Result<()> {
+            self.target.update(&XGamepad {
+                buttons: XButtons { raw: state.buttons },
+                left_trigger: state.left_trigger,
+                right_trigger: state.right_trigger,
+                thumb_lx: state.thumb_lx,
+                thumb_ly: state.thumb_ly,
+                thumb_rx: state.thumb_rx,
+                thumb_ry: state.thumb_ry,
+            })?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod backend {
+    use super::GamepadState;
+    use anyhow::{Result, bail};
+
+    /// Placeholder for platforms without a virtual gamepad driver
+    pub struct VirtualPad;
+
+    impl VirtualPad {
+        pub fn plug_in() -> Result<Self> {
+            bail!("virtual gamepads are only supported on Windows")
+        }
+
+        pub fn update(&mut self, _state: &GamepadState) -> Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_map_to_xinput_bits() {
+        let mut pad = GamepadSimulator::new();
+        pad.button(gamepad_buttons::SOUTH, true).unwrap();
+        pad.button(gamepad_buttons::MODE, true).unwrap();
+        assert_eq!(pad.state.buttons, xinput_buttons::A | xinput_buttons::GUIDE);
+
+        pad.button(gamepad_buttons::SOUTH, false).unwrap();
+        assert_eq!(pad.state.buttons, xinput_buttons::GUIDE);
+
+        pad.button(0x100, true).unwrap();
+        assert_eq!(pad.state.buttons, xinput_buttons::GUIDE);
+        assert!(!is_known_button(0x100));
+    }
+
+    #[test]
+    fn sticks_flip_the_y_axis_and_triggers_scale_to_a_byte() {
+        let mut pad = GamepadSimulator::new();
+        pad.axis(gamepad_axes::LEFT_X, 40_000).unwrap();
+        pad.axis(gamepad_axes::LEFT_Y, 1_000).unwrap();
+        pad.axis(gamepad_axes::RIGHT_Y, -GAMEPAD_AXIS_MAX).unwrap();
+        pad.axis(gamepad_axes::LEFT_TRIGGER, GAMEPAD_AXIS_MAX)
+            .unwrap();
+        pad.axis(gamepad_axes::RIGHT_TRIGGER, -5).unwrap();
+
+        assert_eq!(pad.state.thumb_lx, i16::MAX);
+        assert_eq!(pad.state.thumb_ly, -1_000);
+        assert_eq!(pad.state.thumb_ry, i16::MAX);
+        assert_eq!(pad.state.left_trigger, u8::MAX);
+        assert_eq!(pad.state.right_trigger, 0);
+    }
+
+    #[test]
+    fn dpad_axes_press_one_direction_at_a_time() {
+        let mut pad = GamepadSimulator::new();
+        pad.axis(gamepad_axes::DPAD_X, -1).unwrap();
+        pad.axis(gamepad_axes::DPAD_Y, 1).unwrap();
+        assert_eq!(
+            pad.state.buttons,
+            xinput_buttons::DPAD_LEFT | xinput_buttons::DPAD_DOWN
+        );
+
+        pad.axis(gamepad_axes::DPAD_X, 1).unwrap();
+        pad.axis(gamepad_axes::DPAD_Y, 0).unwrap();
+        assert_eq!(pad.state.buttons, xinput_buttons::DPAD_RIGHT);
+    }
+
+    #[test]
+    fn reset_centers_the_pad() {
+        let mut pad = GamepadSimulator::new();
+        pad.button(gamepad_buttons::START, true).unwrap();
+        pad.axis(gamepad_axes::RIGHT_X, 500).unwrap();
+
+        pad.reset().unwrap();
+        assert_eq!(pad.state, GamepadState::default());
+    }
+}
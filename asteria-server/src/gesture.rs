@@ -0,0 +1,210 @@
+use asteria_core::protocol::{GestureKind, GesturePhase};
+use tracing::debug;
+
+/// Swipe distance, in libinput's normalized units, that scrolls one step
+const SWIPE_DISTANCE_PER_STEP: f64 = 15.0;
+
+/// Change in pinch scale that zooms one step, e.g. 1.15 for 15% wider or narrower
+const ZOOM_STEP_SCALE: f64 = 1.15;
+
+/// What a gesture step amounts to on the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GestureAction {
+    /// Scroll by whole steps, following the fingers
+    Scroll { dx: i32, dy: i32 },
+    /// Zoom in by positive steps and out by negative ones
+    Zoom { steps: i32 },
+}
+
+/// Follows gestures from begin to end and turns their updates into scroll and
+/// zoom steps.
+///
+/// Only updates of the gesture that began last count, so a gesture whose
+/// begin was never received, e.g. because the relay was enabled halfway
+/// through, is ignored rather than acted on twice or out of context.
+#[derive(Debug, Default)]
+pub struct GestureTracker {
+    active: Option<ActiveGesture>,
+}
+
+#[derive(Debug)]
+struct ActiveGesture {
+    kind: GestureKind,
+    fingers: u8,
+    /// Scroll steps accumulated but not acted on yet, less than one per axis
+    pending_scroll: (f64, f64),
+    /// Pinch scale at which the last zoom step was taken
+    zoomed_at: f64,
+}
+
+impl GestureTracker {
+    /// Advance the current gesture by one step
+    pub fn handle(
+        &mut self,
+        kind: GestureKind,
+        phase: GesturePhase,
+        fingers: u8,
+        delta: (f32, f32),
+        scale: f32,
+    ) -> Option<GestureAction> {
+        match phase {
+            GesturePhase::Begin => {
+                self.active = Some(ActiveGesture {
+                    kind,
+                    fingers,
+                    pending_scroll: (0.0, 0.0),
+                    zoomed_at: 1.0,
+                });
+                None
+            }
+            GesturePhase::Update => {
+                let Some(gesture) = self
+                    .active
+                    .as_mut()
+                    .filter(|gesture| gesture.kind == kind && gesture.fingers == fingers)
+                else {
+                    debug!("Ignoring {:?} update without its begin", kind);
+                    return None;
+                };
+
+                match kind {
+                    GestureKind::Swipe => gesture.swipe(delta),
+                    GestureKind::Pinch => gesture.pinch(scale),
+                }
+            }
+            // Whatever hasn't added up to a whole step by now is dropped
+            GesturePhase::End | GesturePhase::Cancel => {
+                self.active = None;
+                None
+            }
+        }
+    }
+}
+
+impl ActiveGesture {
+    fn swipe(&mut self, (dx, dy): (f32, f32)) -> Option<GestureAction> {
+        self.pending_scroll.0 += f64::from(dx) / SWIPE_DISTANCE_PER_STEP;
+        self.pending_scroll.1 += f64::from(dy) / SWIPE_DISTANCE_PER_STEP;
+
+        // Truncate towards zero so the carried fraction keeps the swipe's sign
+        let steps = (self.pending_scroll.0.trunc(), self.pending_scroll.1.trunc());
+        self.pending_scroll.0 -= steps.0;
+        self.pending_scroll.1 -= steps.1;
+
+        let (dx, dy) = (steps.0 as i32, steps.1 as i32);
+        (dx != 0 || dy != 0).then_some(GestureAction::Scroll { dx, dy })
+    }
+
+    fn pinch(&mut self, scale: f32) -> Option<GestureAction> {
+        let scale = f64::from(scale);
+        if !scale.is_finite() || scale <= 0.0 {
+            return None;
+        }
+
+        // The scale is relative to the start of the pinch, so steps are
+        // counted from where the last one was taken rather than added up
+        let steps = ((scale / self.zoomed_at).ln() / ZOOM_STEP_SCALE.ln()).trunc();
+        if steps == 0.0 {
+            return None;
+        }
+
+        self.zoomed_at *= ZOOM_STEP_SCALE.powf(steps);
+        Some(GestureAction::Zoom {
+            steps: steps as i32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swipe(tracker: &mut GestureTracker, dx: f32, dy: f32) -> Option<GestureAction> {
+        tracker.handle(GestureKind::Swipe, GesturePhase::Update, 3, (dx, dy), 1.0)
+    }
+
+    fn pinch(tracker: &mut GestureTracker, scale: f32) -> Option<GestureAction> {
+        tracker.handle(
+            GestureKind::Pinch,
+            GesturePhase::Update,
+            2,
+            (0.0, 0.0),
+            scale,
+        )
+    }
+
+    fn begin(tracker: &mut GestureTracker, kind: GestureKind, fingers: u8) {
+        assert_eq!(
+            tracker.handle(kind, GesturePhase::Begin, fingers, (0.0, 0.0), 1.0),
+            None
+        );
+    }
+
+    #[test]
+    fn swipes_scroll_in_whole_steps() {
+        let mut tracker = GestureTracker::default();
+        begin(&mut tracker, GestureKind::Swipe, 3);
+
+        assert_eq!(swipe(&mut tracker, 0.0, 7.5), None);
+        assert_eq!(
+            swipe(&mut tracker, 0.0, 7.5),
+            Some(GestureAction::Scroll { dx: 0, dy: 1 })
+        );
+        assert_eq!(
+            swipe(&mut tracker, -22.5, 0.0),
+            Some(GestureAction::Scroll { dx: -1, dy: 0 })
+        );
+        assert_eq!(
+            swipe(&mut tracker, -7.5, 30.0),
+            Some(GestureAction::Scroll { dx: -1, dy: 2 })
+        );
+    }
+
+    #[test]
+    fn pinches_zoom_from_the_last_step() {
+        let mut tracker = GestureTracker::default();
+        begin(&mut tracker, GestureKind::Pinch, 2);
+
+        assert_eq!(pinch(&mut tracker, 1.1), None);
+        assert_eq!(
+            pinch(&mut tracker, 1.33),
+            Some(GestureAction::Zoom { steps: 2 })
+        );
+        assert_eq!(pinch(&mut tracker, 1.4), None);
+        assert_eq!(
+            pinch(&mut tracker, 0.9),
+            Some(GestureAction::Zoom { steps: -2 })
+        );
+        assert_eq!(pinch(&mut tracker, f32::NAN), None);
+        assert_eq!(pinch(&mut tracker, 0.0), None);
+    }
+
+    #[test]
+    fn updates_without_their_begin_are_ignored() {
+        let mut tracker = GestureTracker::default();
+        assert_eq!(swipe(&mut tracker, 0.0, 100.0), None);
+
+        // A pinch doesn't carry on a swipe, nor does a swipe with other fingers
+        begin(&mut tracker, GestureKind::Swipe, 4);
+        assert_eq!(pinch(&mut tracker, 2.0), None);
+        assert_eq!(swipe(&mut tracker, 0.0, 100.0), None);
+
+        begin(&mut tracker, GestureKind::Swipe, 3);
+        assert_eq!(
+            tracker.handle(GestureKind::Swipe, GesturePhase::End, 3, (0.0, 0.0), 1.0),
+            None
+        );
+        assert_eq!(swipe(&mut tracker, 0.0, 100.0), None);
+    }
+
+    #[test]
+    fn fractions_are_dropped_when_the_gesture_ends() {
+        let mut tracker = GestureTracker::default();
+        begin(&mut tracker, GestureKind::Swipe, 3);
+        assert_eq!(swipe(&mut tracker, 0.0, 7.5), None);
+        tracker.handle(GestureKind::Swipe, GesturePhase::Cancel, 3, (0.0, 0.0), 1.0);
+
+        begin(&mut tracker, GestureKind::Swipe, 3);
+        assert_eq!(swipe(&mut tracker, 0.0, 7.5), None);
+    }
+}
@@ -1,17 +1,141 @@
 use anyhow::Result;
-use asteria_core::protocol::{InputEvent, InputEventType};
-use enigo::{Axis, Direction, Enigo, Key, Keyboard, Mouse, Settings};
-use tracing::debug;
+use asteria_core::{
+    config::{ScreenSize, SimulationConfig, UnknownEventPolicy},
+    protocol::{
+        InputEvent, InputEventType, LockState, MAX_TYPE_TEXT_LEN, denormalize_absolute,
+        mouse_buttons, raw_event_types,
+    },
+};
+use enigo::{Axis, Button, Coordinate, Direction, Enigo, Key, Keyboard, Mouse};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tracing::{debug, instrument, warn};
+
+use crate::backend::open_backend;
+use crate::gamepad::GamepadSimulator;
+use crate::gesture::{GestureAction, GestureTracker};
+use crate::keymap::layout_character;
+use crate::layout::map_to_layout;
+
+/// Scroll Lock, which enigo names differently per platform
+#[cfg(windows)]
+const SCROLL_LOCK: Key = Key::Scroll;
+#[cfg(not(windows))]
+const SCROLL_LOCK: Key = Key::ScrollLock;
+
+/// Destination for the input the server decodes from clients
+pub trait InputSink: Send {
+    fn simulate_input(&mut self, event: &InputEvent) -> Result<()>;
+    fn simulate_typed_input(&mut self, event: &InputEventType) -> Result<()>;
+    fn set_clipboard_text(&mut self, text: &str) -> Result<()>;
+    /// Current clipboard text, `None` if the clipboard holds no text
+    fn clipboard_text(&mut self) -> Result<Option<String>>;
+    /// Toggle the lock keys that differ from `state`
+    fn sync_lock_state(&mut self, state: &LockState) -> Result<()>;
+    /// Release every key and button still held down
+    fn release_all(&mut self) -> Result<()>;
+    /// Size of the screen absolute coordinates are scaled to
+    fn screen_size(&self) -> Result<ScreenSize>;
+    /// How long to wait before `event` is simulated to keep the configured key pacing
+    fn pacing_delay(&self, event: &InputEventType) -> Duration;
+}
+
+/// Backend that injects input into the system.
+///
+/// Implemented by `Enigo`; other implementations let the simulator's
+/// translation run without a display.
+pub trait SyntheticInput: Send {
+    fn key(&mut self, key: Key, direction: Direction) -> Result<()>;
+    fn button(&mut self, button: Button, direction: Direction) -> Result<()>;
+    fn move_mouse(&mut self, x: i32, y: i32, coordinate: Coordinate) -> Result<()>;
+    fn scroll(&mut self, length: i32, axis: Axis) -> Result<()>;
+    fn text(&mut self, text: &str) -> Result<()>;
+    /// Size of the main display in pixels
+    fn main_display(&self) -> Result<(i32, i32)>;
+    /// Current pointer position
+    fn location(&self) -> Result<(i32, i32)>;
+    /// Current state of the lock keys
+    fn lock_state(&self) -> Result<LockState>;
+}
+
+impl SyntheticInput for Enigo {
+    fn key(&mut self, key: Key, direction: Direction) -> Result<()> {
+        Ok(Keyboard::key(self, key, direction)?)
+    }
+
+    fn button(&mut self, button: Button, direction: Direction) -> Result<()> {
+        Ok(Mouse::button(self, button, direction)?)
+    }
+
+    fn move_mouse(&mut self, x: i32, y: i32, coordinate: Coordinate) -> Result<()> {
+        Ok(Mouse::move_mouse(self, x, y, coordinate)?)
+    }
+
+    fn scroll(&mut self, length: i32, axis: Axis) -> Result<()> {
+        Ok(Mouse::scroll(self, length, axis)?)
+    }
+
+    fn text(&mut self, text: &str) -> Result<()> {
+        Ok(Keyboard::text(self, text)?)
+    }
+
+    fn main_display(&self) -> Result<(i32, i32)> {
+        Ok(Mouse::main_display(self)?)
+    }
+
+    fn location(&self) -> Result<(i32, i32)> {
+        Ok(Mouse::location(self)?)
+    }
+
+    fn lock_state(&self) -> Result<LockState> {
+        read_lock_state()
+    }
+}
 
 /// Input simulator that translates protocol events into system input
-pub struct InputSimulator {
-    enigo: Enigo,
+pub struct InputSimulator<B: SyntheticInput = Enigo> {
+    backend: B,
+    config: SimulationConfig,
+    /// Last pointer position applied by the simulator, if known
+    last_position: Option<(i32, i32)>,
+    /// Cleared once the backend rejects an absolute move
+    absolute_supported: bool,
+    /// When the last key event was injected, for inter-key pacing
+    last_key_at: Option<Instant>,
+    /// When each held key was pressed, for press-to-release pacing
+    pressed_at: HashMap<u16, Instant>,
+    /// Mouse buttons currently held down
+    pressed_buttons: HashSet<Button>,
+    /// Virtual controller for gamepad events
+    gamepad: GamepadSimulator,
+    /// Touchpad gesture in progress
+    gestures: GestureTracker,
+    /// Set once reading the lock key state failed, so syncing it is skipped from then on
+    lock_state_unreadable: bool,
 }
 
 impl InputSimulator {
-    pub fn new() -> Result<Self> {
-        let enigo = Enigo::new(&Settings::default())?;
-        Ok(Self { enigo })
+    pub fn new(config: SimulationConfig) -> Result<Self> {
+        let enigo = open_backend(config.backend)?;
+        Ok(Self::with_backend(enigo, config))
+    }
+}
+
+impl<B: SyntheticInput> InputSimulator<B> {
+    /// Create a simulator that injects input through `backend`
+    pub fn with_backend(backend: B, config: SimulationConfig) -> Self {
+        Self {
+            backend,
+            config,
+            last_position: None,
+            absolute_supported: true,
+            last_key_at: None,
+            pressed_at: HashMap::new(),
+            pressed_buttons: HashSet::new(),
+            gamepad: GamepadSimulator::new(),
+            gestures: GestureTracker::default(),
+            lock_state_unreadable: false,
+        }
     }
 
     /// Simulate input based on the received event
@@ -20,40 +144,70 @@ impl InputSimulator {
 
         // Convert Linux input event codes to actions
         match event.event_type.as_str() {
-            "EV_KEY" => self.handle_key_event(event.code, event.value),
-            "EV_REL" => self.handle_relative_event(event.code, event.value),
-            "EV_ABS" => self.handle_absolute_event(event.code, event.value),
-            _ => {
-                debug!("Unsupported event type: {}", event.event_type);
+            raw_event_types::EV_KEY => self.handle_key_event(event.code, event.value),
+            raw_event_types::EV_REL => self.handle_relative_event(event.code, event.value),
+            raw_event_types::EV_ABS => self.handle_absolute_event(event.code, event.value),
+            raw_event_types::EV_SYN => Ok(()),
+            event_type => self.handle_unknown_event_type(event_type),
+        }
+    }
+
+    /// Apply the configured policy to a raw event of an unrecognized type
+    fn handle_unknown_event_type(&self, event_type: &str) -> Result<()> {
+        match self.config.unknown_event_policy {
+            UnknownEventPolicy::Ignore => {
+                debug!("Unsupported event type: {}", event_type);
+                Ok(())
+            }
+            UnknownEventPolicy::Warn => {
+                warn!("Unsupported event type: {}", event_type);
                 Ok(())
             }
+            UnknownEventPolicy::Error => {
+                anyhow::bail!("Unsupported event type: {}", event_type)
+            }
         }
     }
 
     /// Simulate input based on typed event
+    #[instrument(level = "trace", skip_all, fields(kind = event.kind()))]
     pub fn simulate_typed_input(&mut self, event: &InputEventType) -> Result<()> {
         debug!("Simulating typed input event: {:?}", event);
 
         match event {
             InputEventType::KeyPress { key_code } => {
-                if let Some(key) = self.linux_key_to_enigo(*key_code) {
-                    self.enigo.key(key, Direction::Press)?;
-                }
+                self.key(*key_code, Direction::Press)?;
             }
             InputEventType::KeyRelease { key_code } => {
-                if let Some(key) = self.linux_key_to_enigo(*key_code) {
-                    self.enigo.key(key, Direction::Release)?;
-                }
+                self.key(*key_code, Direction::Release)?;
+            }
+            InputEventType::KeyRepeat { key_code } => {
+                // Windows autorepeats a held key by re-sending key down
+                self.key(*key_code, Direction::Press)?;
             }
             InputEventType::MouseMove { x, y } => {
-                self.enigo.move_mouse(*x, *y, enigo::Coordinate::Rel)?;
+                self.move_mouse_relative(*x, *y)?;
+            }
+            InputEventType::MouseMoveAbsolute { x, y } => {
+                let (x, y) = match map_to_layout(&self.config.screen_layout, *x, *y) {
+                    Some(position) => position,
+                    None => {
+                        let screen = self.screen_size().unwrap_or_else(|e| {
+                            debug!("Could not query the screen size, using the fallback: {}", e);
+                            self.config.fallback_screen_size
+                        });
+                        (
+                            denormalize_absolute(*x, screen.width),
+                            denormalize_absolute(*y, screen.height),
+                        )
+                    }
+                };
+                self.move_mouse_absolute(x, y)?;
             }
             InputEventType::MouseButton { button, pressed } => {
-                let mouse_button = match button {
-                    0 => enigo::Button::Left,
-                    1 => enigo::Button::Right,
-                    2 => enigo::Button::Middle,
-                    _ => return Ok(()),
+                let Some(mouse_button) = protocol_button_to_enigo(*button) else {
+                    debug!("Unknown mouse button: {}", button);
+                    return Ok(());
                 };
 
                 let direction = if *pressed {
@@ -62,57 +216,201 @@ impl InputSimulator {
                     Direction::Release
                 };
 
-                self.enigo.button(mouse_button, direction)?;
+                self.button(mouse_button, direction)?;
             }
             InputEventType::MouseScroll { dx, dy } => {
-                if *dx != 0 {
-                    self.enigo.scroll(*dx, Axis::Horizontal)?;
-                }
-                if *dy != 0 {
-                    self.enigo.scroll(*dy, Axis::Vertical)?;
+                self.scroll(*dx, *dy)?;
+            }
+            InputEventType::GamepadButton { button, pressed } => {
+                self.gamepad.button(*button, *pressed)?;
+            }
+            InputEventType::GamepadAxis { axis, value } => {
+                self.gamepad.axis(*axis, *value)?;
+            }
+            InputEventType::TypeText { text } => {
+                if text.len() > MAX_TYPE_TEXT_LEN {
+                    warn!(
+                        "Not typing text of {} bytes, limit is {} bytes",
+                        text.len(),
+                        MAX_TYPE_TEXT_LEN
+                    );
+                    return Ok(());
                 }
+
+                // Characters outside the BMP go out as UTF-16 surrogate pairs,
+                // which Windows joins back into one character
+                self.backend.text(text)?;
             }
+            InputEventType::Gesture {
+                kind,
+                phase,
+                fingers,
+                dx,
+                dy,
+                scale,
+            } => match self
+                .gestures
+                .handle(*kind, *phase, *fingers, (*dx, *dy), *scale)
+            {
+                Some(GestureAction::Scroll { dx, dy }) => self.scroll(dx, dy)?,
+                Some(GestureAction::Zoom { steps }) => self.zoom(steps)?,
+                None => {}
+            },
         }
 
         Ok(())
     }
 
+    fn scroll(&mut self, dx: i32, dy: i32) -> Result<()> {
+        if dx != 0 {
+            self.backend.scroll(dx, Axis::Horizontal)?;
+        }
+        if dy != 0 {
+            self.backend.scroll(dy, Axis::Vertical)?;
+        }
+        Ok(())
+    }
+
+    /// Zoom by scrolling with Control held, which most applications understand
+    fn zoom(&mut self, steps: i32) -> Result<()> {
+        self.backend.key(Key::Control, Direction::Press)?;
+        // Scrolling up zooms in; Control is released even if the scroll fails
+        let scrolled = self.backend.scroll(-steps, Axis::Vertical);
+        self.backend.key(Key::Control, Direction::Release)?;
+        scrolled
+    }
+
     /// Handle Linux key events (EV_KEY)
     fn handle_key_event(&mut self, code: u16, value: i32) -> Result<()> {
         let direction = match value {
             0 => Direction::Release,
             1 => Direction::Press,
-            2 => return Ok(()), // Key repeat - ignore for now
+            2 => Direction::Press, // Key repeat
             _ => return Ok(()),
         };
 
-        if let Some(key) = self.linux_key_to_enigo(code) {
-            self.enigo.key(key, direction)?;
-        } else {
+        // Mouse buttons arrive as EV_KEY too; they don't autorepeat
+        if let Some(button) = linux_button_to_enigo(code) {
+            if value != 2 {
+                self.button(button, direction)?;
+            }
+            return Ok(());
+        }
+
+        self.key(code, direction)
+    }
+
+    /// Time left before a key event may be injected under the configured pacing.
+    ///
+    /// The caller waits it out, so the simulator isn't blocked in the meantime.
+    pub fn pacing_delay(&self, event: &InputEventType) -> Duration {
+        let (code, direction) = match event {
+            InputEventType::KeyPress { key_code } | InputEventType::KeyRepeat { key_code } => {
+                (*key_code, Direction::Press)
+            }
+            InputEventType::KeyRelease { key_code } => (*key_code, Direction::Release),
+            _ => return Duration::ZERO,
+        };
+        // Mouse buttons sent as key codes aren't paced
+        if linux_button_to_enigo(code).is_some() {
+            return Duration::ZERO;
+        }
+
+        let now = Instant::now();
+        let mut wait = remaining_delay(
+            self.last_key_at,
+            Duration::from_millis(self.config.inter_key_delay_ms),
+            now,
+        );
+        if direction == Direction::Release {
+            wait = wait.max(remaining_delay(
+                self.pressed_at.get(&code).copied(),
+                Duration::from_millis(self.config.press_release_delay_ms),
+                now,
+            ));
+        }
+        wait
+    }
+
+    /// Inject a key event, recording when for the pacing of the next ones
+    fn key(&mut self, code: u16, direction: Direction) -> Result<()> {
+        let Some(key) = self.linux_key_to_enigo(code) else {
             debug!("Unknown key code: {}", code);
+            return Ok(());
+        };
+
+        self.backend.key(key, direction)?;
+
+        let injected_at = Instant::now();
+        self.last_key_at = Some(injected_at);
+        match direction {
+            Direction::Release => {
+                self.pressed_at.remove(&code);
+            }
+            _ => {
+                self.pressed_at.entry(code).or_insert(injected_at);
+            }
         }
 
         Ok(())
     }
 
+    /// Inject a mouse button event, keeping track of held buttons
+    fn button(&mut self, button: Button, direction: Direction) -> Result<()> {
+        self.backend.button(button, direction)?;
+        match direction {
+            Direction::Release => {
+                self.pressed_buttons.remove(&button);
+            }
+            _ => {
+                self.pressed_buttons.insert(button);
+            }
+        }
+        Ok(())
+    }
+
+    /// Release every key and button the simulator left held down
+    pub fn release_all(&mut self) -> Result<()> {
+        self.gamepad.reset()?;
+
+        let keys: Vec<u16> = self.pressed_at.keys().copied().collect();
+        let buttons: Vec<Button> = self.pressed_buttons.iter().copied().collect();
+        if keys.is_empty() && buttons.is_empty() {
+            return Ok(());
+        }
+
+        debug!(
+            "Releasing {} held keys and {} held buttons",
+            keys.len(),
+            buttons.len()
+        );
+        for code in keys {
+            self.key(code, Direction::Release)?;
+        }
+        for button in buttons {
+            self.button(button, Direction::Release)?;
+        }
+        Ok(())
+    }
+
     /// Handle Linux relative events (EV_REL) - mouse movement and scroll
     fn handle_relative_event(&mut self, code: u16, value: i32) -> Result<()> {
         match code {
             0 => {
                 // REL_X - mouse X movement
-                self.enigo.move_mouse(value, 0, enigo::Coordinate::Rel)?;
+                self.move_mouse_relative(value, 0)?;
             }
             1 => {
                 // REL_Y - mouse Y movement
-                self.enigo.move_mouse(0, value, enigo::Coordinate::Rel)?;
+                self.move_mouse_relative(0, value)?;
             }
             8 => {
                 // REL_WHEEL - scroll wheel
-                self.enigo.scroll(value, Axis::Vertical)?;
+                self.backend.scroll(value, Axis::Vertical)?;
             }
             6 => {
                 // REL_HWHEEL - horizontal scroll
-                self.enigo.scroll(value, Axis::Horizontal)?;
+                self.backend.scroll(value, Axis::Horizontal)?;
             }
             _ => {
                 debug!("Unsupported relative event code: {}", code);
@@ -127,13 +425,13 @@ impl InputSimulator {
         match code {
             0 => {
                 // ABS_X - absolute X position
-                // For now, treat as relative movement
-                // In a real implementation, you'd need to track the previous position
-                debug!("Absolute X position: {}", value);
+                let (_, y) = self.current_position();
+                self.move_mouse_absolute(value, y)?;
             }
             1 => {
                 // ABS_Y - absolute Y position
-                debug!("Absolute Y position: {}", value);
+                let (x, _) = self.current_position();
+                self.move_mouse_absolute(x, value)?;
             }
             _ => {
                 debug!("Unsupported absolute event code: {}", code);
@@ -143,97 +441,751 @@ impl InputSimulator {
         Ok(())
     }
 
-    /// Convert Linux key codes to Enigo Key enum
-    fn linux_key_to_enigo(&self, code: u16) -> Option<Key> {
-        match code {
-            // Letters
-            30 => Some(Key::Unicode('a')),
-            48 => Some(Key::Unicode('b')),
-            46 => Some(Key::Unicode('c')),
-            32 => Some(Key::Unicode('d')),
-            18 => Some(Key::Unicode('e')),
-            33 => Some(Key::Unicode('f')),
-            34 => Some(Key::Unicode('g')),
-            35 => Some(Key::Unicode('h')),
-            23 => Some(Key::Unicode('i')),
-            36 => Some(Key::Unicode('j')),
-            37 => Some(Key::Unicode('k')),
-            38 => Some(Key::Unicode('l')),
-            50 => Some(Key::Unicode('m')),
-            49 => Some(Key::Unicode('n')),
-            24 => Some(Key::Unicode('o')),
-            25 => Some(Key::Unicode('p')),
-            16 => Some(Key::Unicode('q')),
-            19 => Some(Key::Unicode('r')),
-            31 => Some(Key::Unicode('s')),
-            20 => Some(Key::Unicode('t')),
-            22 => Some(Key::Unicode('u')),
-            47 => Some(Key::Unicode('v')),
-            17 => Some(Key::Unicode('w')),
-            45 => Some(Key::Unicode('x')),
-            21 => Some(Key::Unicode('y')),
-            44 => Some(Key::Unicode('z')),
-
-            // Numbers
-            2 => Some(Key::Unicode('1')),
-            3 => Some(Key::Unicode('2')),
-            4 => Some(Key::Unicode('3')),
-            5 => Some(Key::Unicode('4')),
-            6 => Some(Key::Unicode('5')),
-            7 => Some(Key::Unicode('6')),
-            8 => Some(Key::Unicode('7')),
-            9 => Some(Key::Unicode('8')),
-            10 => Some(Key::Unicode('9')),
-            11 => Some(Key::Unicode('0')),
-
-            // Special keys
-            57 => Some(Key::Space),
-            28 => Some(Key::Return),
-            1 => Some(Key::Escape),
-            14 => Some(Key::Backspace),
-            15 => Some(Key::Tab),
-            42 => Some(Key::Shift),
-            54 => Some(Key::Shift), // Right shift
-            29 => Some(Key::Control),
-            97 => Some(Key::Control), // Right control
-            56 => Some(Key::Alt),
-            100 => Some(Key::Alt), // Right alt
-
-            // Arrow keys
-            103 => Some(Key::UpArrow),
-            108 => Some(Key::DownArrow),
-            105 => Some(Key::LeftArrow),
-            106 => Some(Key::RightArrow),
-
-            // Function keys
-            59 => Some(Key::F1),
-            60 => Some(Key::F2),
-            61 => Some(Key::F3),
-            62 => Some(Key::F4),
-            63 => Some(Key::F5),
-            64 => Some(Key::F6),
-            65 => Some(Key::F7),
-            66 => Some(Key::F8),
-            67 => Some(Key::F9),
-            68 => Some(Key::F10),
-            87 => Some(Key::F11),
-            88 => Some(Key::F12),
-
-            // Mouse buttons (handled as buttons, but included for completeness)
-            272 => None, // BTN_LEFT
-            273 => None, // BTN_RIGHT
-            274 => None, // BTN_MIDDLE
+    /// Move the pointer by a relative offset, keeping the tracked position in sync
+    fn move_mouse_relative(&mut self, dx: i32, dy: i32) -> Result<()> {
+        self.backend.move_mouse(dx, dy, Coordinate::Rel)?;
 
-            _ => {
-                debug!("Unmapped key code: {}", code);
-                None
+        if let Some((x, y)) = self.last_position {
+            self.last_position = Some((x + dx, y + dy));
+        }
+
+        Ok(())
+    }
+
+    /// Move the pointer to an absolute position, emulating it with a relative
+    /// move from the tracked position when the backend can't position absolutely
+    fn move_mouse_absolute(&mut self, x: i32, y: i32) -> Result<()> {
+        if self.absolute_supported {
+            match self.backend.move_mouse(x, y, Coordinate::Abs) {
+                Ok(()) => {
+                    self.last_position = Some((x, y));
+                    return Ok(());
+                }
+                Err(e) if self.config.absolute_fallback => {
+                    warn!(
+                        "Absolute positioning unsupported ({}), falling back to relative moves",
+                        e
+                    );
+                    self.absolute_supported = false;
+                }
+                Err(e) => return Err(e),
             }
         }
+
+        let (dx, dy) = relative_delta(self.current_position(), (x, y));
+        debug!(
+            "Emulating absolute move to ({}, {}) with ({}, {})",
+            x, y, dx, dy
+        );
+        self.backend.move_mouse(dx, dy, Coordinate::Rel)?;
+        self.last_position = Some((x, y));
+
+        Ok(())
+    }
+
+    /// Replace the system clipboard with the given text
+    pub fn set_clipboard_text(&mut self, text: &str) -> Result<()> {
+        debug!("Setting clipboard text ({} bytes)", text.len());
+        write_clipboard_text(text)
+    }
+
+    /// Text currently on the system clipboard
+    pub fn clipboard_text(&mut self) -> Result<Option<String>> {
+        read_clipboard_text()
+    }
+
+    /// Toggle Caps, Num and Scroll Lock wherever they differ from `target`.
+    ///
+    /// Without a readable lock state nothing is toggled, since pressing lock
+    /// keys blindly is as likely to break the state as to fix it.
+    pub fn sync_lock_state(&mut self, target: &LockState) -> Result<()> {
+        if self.lock_state_unreadable {
+            return Ok(());
+        }
+
+        let current = match self.backend.lock_state() {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("Lock key state is not available, not syncing it: {}", e);
+                self.lock_state_unreadable = true;
+                return Ok(());
+            }
+        };
+
+        let locks = [
+            (Key::CapsLock, current.caps, target.caps),
+            (Key::Numlock, current.num, target.num),
+            (SCROLL_LOCK, current.scroll, target.scroll),
+        ];
+        for (key, current, target) in locks {
+            if current != target {
+                debug!("Toggling {:?} to match the client", key);
+                self.backend.key(key, Direction::Click)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Screen size used to scale normalized absolute coordinates
+    pub fn screen_size(&self) -> Result<ScreenSize> {
+        if let Some(screen_size) = self.config.screen_size {
+            return Ok(screen_size);
+        }
+
+        let (width, height) = self.backend.main_display()?;
+        Ok(ScreenSize {
+            width: width.max(0) as u32,
+            height: height.max(0) as u32,
+        })
+    }
+
+    /// Best known pointer position, asking the backend when nothing is tracked yet
+    fn current_position(&self) -> (i32, i32) {
+        self.last_position
+            .or_else(|| self.backend.location().ok())
+            .unwrap_or((0, 0))
+    }
+
+    /// Convert Linux key codes to Enigo keys, with characters as the configured layout types them
+    fn linux_key_to_enigo(&self, code: u16) -> Option<Key> {
+        layout_character(self.config.keyboard_layout, code)
+            .map(Key::Unicode)
+            .or_else(|| linux_key(code))
+    }
+}
+
+impl<B: SyntheticInput> InputSink for InputSimulator<B> {
+    fn simulate_input(&mut self, event: &InputEvent) -> Result<()> {
+        InputSimulator::simulate_input(self, event)
+    }
+
+    fn simulate_typed_input(&mut self, event: &InputEventType) -> Result<()> {
+        InputSimulator::simulate_typed_input(self, event)
+    }
+
+    fn set_clipboard_text(&mut self, text: &str) -> Result<()> {
+        InputSimulator::set_clipboard_text(self, text)
+    }
+
+    fn clipboard_text(&mut self) -> Result<Option<String>> {
+        InputSimulator::clipboard_text(self)
+    }
+
+    fn sync_lock_state(&mut self, state: &LockState) -> Result<()> {
+        InputSimulator::sync_lock_state(self, state)
+    }
+
+    fn release_all(&mut self) -> Result<()> {
+        InputSimulator::release_all(self)
+    }
+
+    fn screen_size(&self) -> Result<ScreenSize> {
+        InputSimulator::screen_size(self)
+    }
+
+    fn pacing_delay(&self, event: &InputEventType) -> Duration {
+        InputSimulator::pacing_delay(self, event)
     }
 }
 
 impl Default for InputSimulator {
     fn default() -> Self {
-        Self::new().expect("Failed to create input simulator")
+        Self::new(SimulationConfig::default()).expect("Failed to create input simulator")
+    }
+}
+
+/// Convert a Linux key code to the Enigo key it types on a QWERTY layout
+pub fn linux_key(code: u16) -> Option<Key> {
+    match code {
+        // Letters
+        30 => Some(Key::Unicode('a')),
+        48 => Some(Key::Unicode('b')),
+        46 => Some(Key::Unicode('c')),
+        32 => Some(Key::Unicode('d')),
+        18 => Some(Key::Unicode('e')),
+        33 => Some(Key::Unicode('f')),
+        34 => Some(Key::Unicode('g')),
+        35 => Some(Key::Unicode('h')),
+        23 => Some(Key::Unicode('i')),
+        36 => Some(Key::Unicode('j')),
+        37 => Some(Key::Unicode('k')),
+        38 => Some(Key::Unicode('l')),
+        50 => Some(Key::Unicode('m')),
+        49 => Some(Key::Unicode('n')),
+        24 => Some(Key::Unicode('o')),
+        25 => Some(Key::Unicode('p')),
+        16 => Some(Key::Unicode('q')),
+        19 => Some(Key::Unicode('r')),
+        31 => Some(Key::Unicode('s')),
+        20 => Some(Key::Unicode('t')),
+        22 => Some(Key::Unicode('u')),
+        47 => Some(Key::Unicode('v')),
+        17 => Some(Key::Unicode('w')),
+        45 => Some(Key::Unicode('x')),
+        21 => Some(Key::Unicode('y')),
+        44 => Some(Key::Unicode('z')),
+
+        // Numbers
+        2 => Some(Key::Unicode('1')),
+        3 => Some(Key::Unicode('2')),
+        4 => Some(Key::Unicode('3')),
+        5 => Some(Key::Unicode('4')),
+        6 => Some(Key::Unicode('5')),
+        7 => Some(Key::Unicode('6')),
+        8 => Some(Key::Unicode('7')),
+        9 => Some(Key::Unicode('8')),
+        10 => Some(Key::Unicode('9')),
+        11 => Some(Key::Unicode('0')),
+
+        // Punctuation
+        12 => Some(Key::Unicode('-')),
+        13 => Some(Key::Unicode('=')),
+        26 => Some(Key::Unicode('[')),
+        27 => Some(Key::Unicode(']')),
+        39 => Some(Key::Unicode(';')),
+        40 => Some(Key::Unicode('\'')),
+        41 => Some(Key::Unicode('`')),
+        43 => Some(Key::Unicode('\\')),
+        51 => Some(Key::Unicode(',')),
+        52 => Some(Key::Unicode('.')),
+        53 => Some(Key::Unicode('/')),
+
+        // Special keys
+        57 => Some(Key::Space),
+        28 => Some(Key::Return),
+        1 => Some(Key::Escape),
+        14 => Some(Key::Backspace),
+        15 => Some(Key::Tab),
+        42 => Some(Key::Shift),
+        54 => Some(Key::Shift), // Right shift
+        29 => Some(Key::Control),
+        97 => Some(Key::Control), // Right control
+        56 => Some(Key::Alt),
+        100 => Some(Key::Alt), // Right alt
+        125 => Some(Key::Meta),
+        126 => Some(Key::Meta), // Right meta
+        58 => Some(Key::CapsLock),
+
+        // Navigation keys
+        102 => Some(Key::Home),
+        107 => Some(Key::End),
+        104 => Some(Key::PageUp),
+        109 => Some(Key::PageDown),
+        110 => Some(Key::Insert),
+        111 => Some(Key::Delete),
+
+        // Arrow keys
+        103 => Some(Key::UpArrow),
+        108 => Some(Key::DownArrow),
+        105 => Some(Key::LeftArrow),
+        106 => Some(Key::RightArrow),
+
+        // Function keys
+        59 => Some(Key::F1),
+        60 => Some(Key::F2),
+        61 => Some(Key::F3),
+        62 => Some(Key::F4),
+        63 => Some(Key::F5),
+        64 => Some(Key::F6),
+        65 => Some(Key::F7),
+        66 => Some(Key::F8),
+        67 => Some(Key::F9),
+        68 => Some(Key::F10),
+        87 => Some(Key::F11),
+        88 => Some(Key::F12),
+
+        // Numpad
+        69 => Some(Key::Numlock),
+        82 => Some(Key::Numpad0),
+        79 => Some(Key::Numpad1),
+        80 => Some(Key::Numpad2),
+        81 => Some(Key::Numpad3),
+        75 => Some(Key::Numpad4),
+        76 => Some(Key::Numpad5),
+        77 => Some(Key::Numpad6),
+        71 => Some(Key::Numpad7),
+        72 => Some(Key::Numpad8),
+        73 => Some(Key::Numpad9),
+        55 => Some(Key::Multiply),
+        74 => Some(Key::Subtract),
+        78 => Some(Key::Add),
+        83 => Some(Key::Decimal),
+        98 => Some(Key::Divide),
+        96 => Some(Key::Return), // Numpad enter
+
+        // Mouse buttons (handled as buttons, but included for completeness)
+        272 => None,       // BTN_LEFT
+        273 => None,       // BTN_RIGHT
+        274 => None,       // BTN_MIDDLE
+        275..=278 => None, // BTN_SIDE, BTN_EXTRA, BTN_FORWARD, BTN_BACK
+
+        _ => None,
+    }
+}
+
+/// Convert the protocol's button numbering to an Enigo button
+pub fn linux_button_to_enigo(code: u16) -> Option<Button> {
+    match code {
+        272 => Some(Button::Left),          // BTN_LEFT
+        273 => Some(Button::Right),         // BTN_RIGHT
+        274 => Some(Button::Middle),        // BTN_MIDDLE
+        275 | 278 => Some(Button::Back),    // BTN_SIDE, BTN_BACK
+        276 | 277 => Some(Button::Forward), // BTN_EXTRA, BTN_FORWARD
+        _ => None,
+    }
+}
+
+pub fn protocol_button_to_enigo(button: u8) -> Option<Button> {
+    match button {
+        mouse_buttons::LEFT => Some(Button::Left),
+        mouse_buttons::RIGHT => Some(Button::Right),
+        mouse_buttons::MIDDLE => Some(Button::Middle),
+        mouse_buttons::BACK => Some(Button::Back),
+        mouse_buttons::FORWARD => Some(Button::Forward),
+        _ => None,
+    }
+}
+
+#[cfg(windows)]
+fn write_clipboard_text(text: &str) -> Result<()> {
+    use anyhow::bail;
+    use windows_sys::Win32::{
+        Foundation::GlobalFree,
+        System::{
+            DataExchange::{CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData},
+            Memory::{GMEM_MOVEABLE, GlobalAlloc, GlobalLock, GlobalUnlock},
+            Ole::CF_UNICODETEXT,
+        },
+    };
+
+    let wide: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+    let size = wide.len() * std::mem::size_of::<u16>();
+
+    unsafe {
+        if OpenClipboard(std::ptr::null_mut()) == 0 {
+            bail!("Failed to open the clipboard");
+        }
+
+        let result = (|| {
+            if EmptyClipboard() == 0 {
+                bail!("Failed to empty the clipboard");
+            }
+
+            let memory = GlobalAlloc(GMEM_MOVEABLE, size);
+            if memory.is_null() {
+                bail!("Failed to allocate {} bytes for the clipboard", size);
+            }
+
+            let target = GlobalLock(memory) as *mut u16;
+            if target.is_null() {
+                GlobalFree(memory);
+                bail!("Failed to lock clipboard memory");
+            }
+            std::ptr::copy_nonoverlapping(wide.as_ptr(), target, wide.len());
+            GlobalUnlock(memory);
+
+            // The clipboard owns the memory once SetClipboardData succeeds
+            if SetClipboardData(CF_UNICODETEXT as u32, memory).is_null() {
+                GlobalFree(memory);
+                bail!("Failed to set clipboard data");
+            }
+
+            Ok(())
+        })();
+
+        CloseClipboard();
+        result
+    }
+}
+
+#[cfg(not(windows))]
+fn write_clipboard_text(_text: &str) -> Result<()> {
+    anyhow::bail!("Setting the clipboard is only supported on Windows")
+}
+
+#[cfg(windows)]
+fn read_clipboard_text() -> Result<Option<String>> {
+    use anyhow::bail;
+    use windows_sys::Win32::System::{
+        DataExchange::{
+            CloseClipboard, GetClipboardData, IsClipboardFormatAvailable, OpenClipboard,
+        },
+        Memory::{GlobalLock, GlobalUnlock},
+        Ole::CF_UNICODETEXT,
+    };
+
+    unsafe {
+        if IsClipboardFormatAvailable(CF_UNICODETEXT as u32) == 0 {
+            return Ok(None);
+        }
+        if OpenClipboard(std::ptr::null_mut()) == 0 {
+            bail!("Failed to open the clipboard");
+        }
+
+        let result = (|| {
+            // The clipboard keeps owning the memory, it is only borrowed here
+            let memory = GetClipboardData(CF_UNICODETEXT as u32);
+            if memory.is_null() {
+                return Ok(None);
+            }
+
+            let source = GlobalLock(memory) as *const u16;
+            if source.is_null() {
+                bail!("Failed to lock clipboard memory");
+            }
+            let mut len = 0;
+            while *source.add(len) != 0 {
+                len += 1;
+            }
+            let text = String::from_utf16_lossy(std::slice::from_raw_parts(source, len));
+            GlobalUnlock(memory);
+
+            Ok(Some(text))
+        })();
+
+        CloseClipboard();
+        result
+    }
+}
+
+#[cfg(not(windows))]
+fn read_clipboard_text() -> Result<Option<String>> {
+    anyhow::bail!("Reading the clipboard is only supported on Windows")
+}
+
+#[cfg(windows)]
+fn read_lock_state() -> Result<LockState> {
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+        GetKeyState, VK_CAPITAL, VK_NUMLOCK, VK_SCROLL,
+    };
+
+    // The low bit of a key's state is set while it is toggled on
+    let toggled = |key: u16| unsafe { GetKeyState(i32::from(key)) } & 1 != 0;
+    Ok(LockState {
+        caps: toggled(VK_CAPITAL),
+        num: toggled(VK_NUMLOCK),
+        scroll: toggled(VK_SCROLL),
+    })
+}
+
+#[cfg(not(windows))]
+fn read_lock_state() -> Result<LockState> {
+    anyhow::bail!("Reading the lock key state is only supported on Windows")
+}
+
+/// Time left until `delay` has passed since `since`
+fn remaining_delay(since: Option<Instant>, delay: Duration, now: Instant) -> Duration {
+    since
+        .map(|since| (since + delay).saturating_duration_since(now))
+        .unwrap_or_default()
+}
+
+/// Relative offset that moves the pointer from `from` to `to`
+fn relative_delta(from: (i32, i32), to: (i32, i32)) -> (i32, i32) {
+    (to.0 - from.0, to.1 - from.1)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use asteria_core::protocol::normalize_absolute;
+    use std::sync::{Arc, Mutex};
+
+    /// A call the simulator made on its backend
+    #[derive(Debug, Clone, PartialEq)]
+    pub(crate) enum Call {
+        Key(Key, Direction),
+        Button(Button, Direction),
+        Move(i32, i32, Coordinate),
+        Scroll(i32, Axis),
+        Text(String),
+    }
+
+    /// Calls a [`RecordingInput`] recorded, in order
+    pub(crate) type CallLog = Arc<Mutex<Vec<Call>>>;
+
+    /// Backend that records what it is asked to inject instead of injecting it
+    #[derive(Default)]
+    pub(crate) struct RecordingInput {
+        calls: CallLog,
+        lock_state: LockState,
+        /// Reject absolute moves, like backends without absolute positioning do
+        relative_only: bool,
+    }
+
+    impl RecordingInput {
+        /// Backend and a handle to the calls it records
+        pub(crate) fn new() -> (Self, CallLog) {
+            let backend = Self::default();
+            let calls = backend.calls.clone();
+            (backend, calls)
+        }
+
+        fn record(&self, call: Call) -> Result<()> {
+            self.calls.lock().unwrap().push(call);
+            Ok(())
+        }
+    }
+
+    impl SyntheticInput for RecordingInput {
+        fn key(&mut self, key: Key, direction: Direction) -> Result<()> {
+            self.record(Call::Key(key, direction))
+        }
+
+        fn button(&mut self, button: Button, direction: Direction) -> Result<()> {
+            self.record(Call::Button(button, direction))
+        }
+
+        fn move_mouse(&mut self, x: i32, y: i32, coordinate: Coordinate) -> Result<()> {
+            if self.relative_only && coordinate == Coordinate::Abs {
+                anyhow::bail!("absolute moves unsupported");
+            }
+            self.record(Call::Move(x, y, coordinate))
+        }
+
+        fn scroll(&mut self, length: i32, axis: Axis) -> Result<()> {
+            self.record(Call::Scroll(length, axis))
+        }
+
+        fn text(&mut self, text: &str) -> Result<()> {
+            self.record(Call::Text(text.to_string()))
+        }
+
+        fn main_display(&self) -> Result<(i32, i32)> {
+            Ok((1920, 1080))
+        }
+
+        fn location(&self) -> Result<(i32, i32)> {
+            Ok((100, 100))
+        }
+
+        fn lock_state(&self) -> Result<LockState> {
+            Ok(self.lock_state)
+        }
+    }
+
+    fn simulator(
+        backend: RecordingInput,
+        config: SimulationConfig,
+    ) -> InputSimulator<RecordingInput> {
+        InputSimulator::with_backend(backend, config)
+    }
+
+    fn take(calls: &CallLog) -> Vec<Call> {
+        std::mem::take(&mut *calls.lock().unwrap())
+    }
+
+    #[test]
+    fn keys_are_pressed_and_released() {
+        let (backend, calls) = RecordingInput::new();
+        let mut sim = simulator(backend, SimulationConfig::default());
+
+        sim.simulate_typed_input(&InputEventType::KeyPress { key_code: 30 })
+            .unwrap();
+        sim.simulate_typed_input(&InputEventType::KeyRepeat { key_code: 30 })
+            .unwrap();
+        sim.simulate_typed_input(&InputEventType::KeyRelease { key_code: 30 })
+            .unwrap();
+        // Unknown key codes are skipped
+        sim.simulate_typed_input(&InputEventType::KeyPress { key_code: 0x2ff })
+            .unwrap();
+
+        let a = Key::Unicode('a');
+        assert_eq!(
+            take(&calls),
+            [
+                Call::Key(a, Direction::Press),
+                Call::Key(a, Direction::Press),
+                Call::Key(a, Direction::Release),
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_events_are_translated() {
+        let (backend, calls) = RecordingInput::new();
+        let mut sim = simulator(backend, SimulationConfig::default());
+
+        let events = [
+            (raw_event_types::EV_KEY, 0x110, 1),
+            (raw_event_types::EV_KEY, 0x110, 2),
+            (raw_event_types::EV_KEY, 0x110, 0),
+            (raw_event_types::EV_REL, 0, 5),
+            (raw_event_types::EV_REL, 1, -3),
+            (raw_event_types::EV_REL, 8, 1),
+            (raw_event_types::EV_SYN, 0, 0),
+        ];
+        for (event_type, code, value) in events {
+            sim.simulate_input(&InputEvent {
+                event_type: event_type.to_string(),
+                code,
+                value,
+            })
+            .unwrap();
+        }
+
+        assert_eq!(
+            take(&calls),
+            [
+                Call::Button(Button::Left, Direction::Press),
+                Call::Button(Button::Left, Direction::Release),
+                Call::Move(5, 0, Coordinate::Rel),
+                Call::Move(0, -3, Coordinate::Rel),
+                Call::Scroll(1, Axis::Vertical),
+            ]
+        );
+    }
+
+    #[test]
+    fn absolute_moves_are_scaled_to_the_screen() {
+        let (backend, calls) = RecordingInput::new();
+        let mut sim = simulator(backend, SimulationConfig::default());
+
+        sim.simulate_typed_input(&InputEventType::MouseMoveAbsolute {
+            x: normalize_absolute(960.0, 1920),
+            y: normalize_absolute(540.0, 1080),
+        })
+        .unwrap();
+
+        assert_eq!(take(&calls), [Call::Move(960, 540, Coordinate::Abs)]);
+    }
+
+    #[test]
+    fn absolute_moves_fall_back_to_relative() {
+        let (mut backend, calls) = RecordingInput::new();
+        backend.relative_only = true;
+        let config = SimulationConfig {
+            screen_size: Some(ScreenSize {
+                width: 1000,
+                height: 1000,
+            }),
+            ..SimulationConfig::default()
+        };
+        let mut sim = simulator(backend, config);
+
+        let position = normalize_absolute(300.0, 1000);
+        sim.simulate_typed_input(&InputEventType::MouseMoveAbsolute {
+            x: position,
+            y: position,
+        })
+        .unwrap();
+        sim.simulate_typed_input(&InputEventType::MouseMoveAbsolute { x: 0, y: 0 })
+            .unwrap();
+
+        // Moved from where the backend reports the pointer, then from the tracked position
+        assert_eq!(
+            take(&calls),
+            [
+                Call::Move(200, 200, Coordinate::Rel),
+                Call::Move(-300, -300, Coordinate::Rel),
+            ]
+        );
+    }
+
+    #[test]
+    fn scrolls_and_text_are_passed_through() {
+        let (backend, calls) = RecordingInput::new();
+        let mut sim = simulator(backend, SimulationConfig::default());
+
+        sim.simulate_typed_input(&InputEventType::MouseScroll { dx: 2, dy: -1 })
+            .unwrap();
+        sim.simulate_typed_input(&InputEventType::TypeText {
+            text: "héllo".to_string(),
+        })
+        .unwrap();
+        // Over the limit, so nothing is typed
+        sim.simulate_typed_input(&InputEventType::TypeText {
+            text: "x".repeat(MAX_TYPE_TEXT_LEN + 1),
+        })
+        .unwrap();
+
+        assert_eq!(
+            take(&calls),
+            [
+                Call::Scroll(2, Axis::Horizontal),
+                Call::Scroll(-1, Axis::Vertical),
+                Call::Text("héllo".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn release_all_releases_what_is_held() {
+        let (backend, calls) = RecordingInput::new();
+        let mut sim = simulator(backend, SimulationConfig::default());
+
+        sim.simulate_typed_input(&InputEventType::KeyPress { key_code: 30 })
+            .unwrap();
+        sim.simulate_typed_input(&InputEventType::MouseButton {
+            button: mouse_buttons::RIGHT,
+            pressed: true,
+        })
+        .unwrap();
+        take(&calls);
+
+        sim.release_all().unwrap();
+        assert_eq!(
+            take(&calls),
+            [
+                Call::Key(Key::Unicode('a'), Direction::Release),
+                Call::Button(Button::Right, Direction::Release),
+            ]
+        );
+
+        // Nothing is held anymore
+        sim.release_all().unwrap();
+        assert!(take(&calls).is_empty());
+    }
+
+    #[test]
+    fn lock_keys_that_differ_are_toggled() {
+        let (mut backend, calls) = RecordingInput::new();
+        backend.lock_state = LockState {
+            caps: true,
+            num: true,
+            scroll: false,
+        };
+        let mut sim = simulator(backend, SimulationConfig::default());
+
+        sim.sync_lock_state(&LockState {
+            caps: false,
+            num: true,
+            scroll: true,
+        })
+        .unwrap();
+
+        assert_eq!(
+            take(&calls),
+            [
+                Call::Key(Key::CapsLock, Direction::Click),
+                Call::Key(SCROLL_LOCK, Direction::Click),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_events_are_paced() {
+        let (backend, _calls) = RecordingInput::new();
+        let config = SimulationConfig {
+            inter_key_delay_ms: 1_000,
+            ..SimulationConfig::default()
+        };
+        let mut sim = simulator(backend, config);
+
+        let press = InputEventType::KeyPress { key_code: 30 };
+        assert_eq!(sim.pacing_delay(&press), Duration::ZERO);
+
+        sim.simulate_typed_input(&press).unwrap();
+        assert!(sim.pacing_delay(&InputEventType::KeyPress { key_code: 48 }) > Duration::ZERO);
+        // Mouse buttons and motion aren't held back
+        assert_eq!(
+            sim.pacing_delay(&InputEventType::KeyPress { key_code: 0x110 }),
+            Duration::ZERO
+        );
+        assert_eq!(
+            sim.pacing_delay(&InputEventType::MouseMove { x: 1, y: 1 }),
+            Duration::ZERO
+        );
     }
 }
@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Motion pause after which the clock anchor is picked anew, as the client may
+/// have been suspended or its clock adjusted in the meantime
+const REANCHOR_AFTER: Duration = Duration::from_secs(1);
+
+/// Holds relative pointer motion back for a fixed delay and releases it on the
+/// schedule it was captured on, so motion that network jitter delivered in
+/// bursts plays back evenly.
+///
+/// Capture timestamps come from the client's clock, so they are anchored to
+/// the motion that arrived quickest relative to its timestamp, i.e. with the
+/// least network delay. Every move is released `delay` after the time it would
+/// have arrived with that same delay, and never later than `delay` after it
+/// actually arrived, so the buffer drains within `delay` once input stops.
+#[derive(Debug)]
+pub struct MotionBuffer {
+    delay: Duration,
+    /// Arrival and capture timestamp of the quickest motion so far
+    anchor: Option<(Instant, u64)>,
+    /// When the last motion arrived
+    last_arrival: Option<Instant>,
+    /// Moves waiting for their release time, in the order they arrived
+    pending: VecDeque<(Instant, (i32, i32))>,
+}
+
+impl MotionBuffer {
+    /// Buffer delaying motion by `delay`, zero to pass it straight through
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            anchor: None,
+            last_arrival: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.delay.is_zero()
+    }
+
+    /// Buffer a move captured at `timestamp`, in milliseconds since the Unix
+    /// epoch, that arrived at `now`
+    pub fn push(&mut self, delta: (i32, i32), timestamp: u64, now: Instant) {
+        if self
+            .last_arrival
+            .is_none_or(|arrival| now.saturating_duration_since(arrival) > REANCHOR_AFTER)
+        {
+            self.anchor = None;
+        }
+        self.last_arrival = Some(now);
+
+        let expected = match self.anchor {
+            Some(anchor) => expected_arrival(anchor, timestamp),
+            None => now,
+        };
+        // Arriving early means arriving quicker than the anchor did
+        if now <= expected {
+            self.anchor = Some((now, timestamp));
+        }
+
+        // A move that arrived late is due right away or soon, to catch up
+        let mut release_at = expected.min(now) + self.delay;
+        // Moves come out in the order they went in
+        if let Some(&(last, _)) = self.pending.back() {
+            release_at = release_at.max(last);
+        }
+        self.pending.push_back((release_at, delta));
+    }
+
+    /// When the next buffered move is due, if any is buffered
+    pub fn next_release(&self) -> Option<Instant> {
+        self.pending.front().map(|&(release_at, _)| release_at)
+    }
+
+    /// Take the moves due at `now`
+    pub fn take_due(&mut self, now: Instant) -> Vec<(i32, i32)> {
+        let due = self
+            .pending
+            .iter()
+            .take_while(|&&(release_at, _)| release_at <= now)
+            .count();
+        self.pending.drain(..due).map(|(_, delta)| delta).collect()
+    }
+
+    /// Take every buffered move, due or not
+    pub fn drain(&mut self) -> Vec<(i32, i32)> {
+        self.pending.drain(..).map(|(_, delta)| delta).collect()
+    }
+}
+
+/// When motion captured at `timestamp` arrives with the anchor's network delay
+fn expected_arrival((arrival, anchor_timestamp): (Instant, u64), timestamp: u64) -> Instant {
+    if timestamp >= anchor_timestamp {
+        arrival + Duration::from_millis(timestamp - anchor_timestamp)
+    } else {
+        arrival
+            .checked_sub(Duration::from_millis(anchor_timestamp - timestamp))
+            .unwrap_or(arrival)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn burst_plays_back_on_the_capture_schedule() {
+        let mut buffer = MotionBuffer::new(ms(20));
+        let start = Instant::now();
+
+        // Captured 10ms apart, the last two delivered together
+        buffer.push((1, 0), 1000, start);
+        buffer.push((2, 0), 1010, start + ms(25));
+        buffer.push((3, 0), 1020, start + ms(25));
+
+        assert_eq!(buffer.next_release(), Some(start + ms(20)));
+        assert!(buffer.take_due(start + ms(19)).is_empty());
+        assert_eq!(buffer.take_due(start + ms(20)), [(1, 0)]);
+        assert_eq!(buffer.take_due(start + ms(30)), [(2, 0)]);
+        assert_eq!(buffer.take_due(start + ms(40)), [(3, 0)]);
+        assert_eq!(buffer.next_release(), None);
+    }
+
+    #[test]
+    fn quicker_motion_moves_the_anchor() {
+        let mut buffer = MotionBuffer::new(ms(20));
+        let start = Instant::now();
+
+        buffer.push((1, 0), 1000, start);
+        // Arrives 5ms sooner than the first move's network delay predicts
+        buffer.push((2, 0), 1010, start + ms(5));
+        buffer.push((3, 0), 1020, start + ms(15));
+
+        assert_eq!(buffer.take_due(start + ms(25)), [(1, 0), (2, 0)]);
+        assert_eq!(buffer.take_due(start + ms(35)), [(3, 0)]);
+    }
+
+    #[test]
+    fn zero_delay_is_disabled_and_drain_empties_the_buffer() {
+        assert!(!MotionBuffer::new(Duration::ZERO).is_enabled());
+
+        let mut buffer = MotionBuffer::new(ms(20));
+        let now = Instant::now();
+        buffer.push((1, 0), 1000, now);
+        buffer.push((0, 1), 1001, now);
+
+        assert_eq!(buffer.drain(), [(1, 0), (0, 1)]);
+        assert!(buffer.take_due(now + ms(100)).is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+use asteria_core::config::KeyboardLayout;
+
+/// Keys typing a different character on a QWERTZ (German) keyboard than on QWERTY
+const QWERTZ: &[(u16, char)] = &[
+    (12, 'ß'),
+    (13, '´'),
+    (21, 'z'),
+    (26, 'ü'),
+    (27, '+'),
+    (39, 'ö'),
+    (40, 'ä'),
+    (41, '^'),
+    (43, '#'),
+    (44, 'y'),
+    (53, '-'),
+];
+
+/// Keys typing a different character on an AZERTY (French) keyboard than on QWERTY
+const AZERTY: &[(u16, char)] = &[
+    (2, '&'),
+    (3, 'é'),
+    (4, '"'),
+    (5, '\''),
+    (6, '('),
+    (7, '-'),
+    (8, 'è'),
+    (9, '_'),
+    (10, 'ç'),
+    (11, 'à'),
+    (12, ')'),
+    (13, '='),
+    (16, 'a'),
+    (17, 'z'),
+    (26, '^'),
+    (27, '$'),
+    (30, 'q'),
+    (39, 'm'),
+    (40, 'ù'),
+    (41, '²'),
+    (43, '*'),
+    (44, 'w'),
+    (50, ','),
+    (51, ';'),
+    (52, ':'),
+    (53, '!'),
+];
+
+/// Keys typing a different character on a Dvorak keyboard than on QWERTY
+const DVORAK: &[(u16, char)] = &[
+    (12, '['),
+    (13, ']'),
+    (16, '\''),
+    (17, ','),
+    (18, '.'),
+    (19, 'p'),
+    (20, 'y'),
+    (21, 'f'),
+    (22, 'g'),
+    (23, 'c'),
+    (24, 'r'),
+    (25, 'l'),
+    (26, '/'),
+    (27, '='),
+    (31, 'o'),
+    (32, 'e'),
+    (33, 'u'),
+    (34, 'i'),
+    (35, 'd'),
+    (36, 'h'),
+    (37, 't'),
+    (38, 'n'),
+    (39, 's'),
+    (40, '-'),
+    (44, ';'),
+    (45, 'q'),
+    (46, 'j'),
+    (47, 'k'),
+    (48, 'x'),
+    (49, 'b'),
+    (50, 'm'),
+    (51, 'w'),
+    (52, 'v'),
+    (53, 'z'),
+];
+
+/// Character the key at Linux key code `code` types under `layout`, `None`
+/// where it types the same as on QWERTY
+pub fn layout_character(layout: KeyboardLayout, code: u16) -> Option<char> {
+    let table = match layout {
+        KeyboardLayout::Qwerty => return None,
+        KeyboardLayout::Qwertz => QWERTZ,
+        KeyboardLayout::Azerty => AZERTY,
+        KeyboardLayout::Dvorak => DVORAK,
+    };
+    table
+        .iter()
+        .find(|(key, _)| *key == code)
+        .map(|&(_, character)| character)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qwerty_types_the_key_labels() {
+        for code in 0..=255 {
+            assert_eq!(layout_character(KeyboardLayout::Qwerty, code), None);
+        }
+    }
+
+    #[test]
+    fn azerty_digit_row_types_unshifted_symbols() {
+        let row: String = (2..=11)
+            .map(|code| layout_character(KeyboardLayout::Azerty, code).unwrap())
+            .collect();
+        assert_eq!(row, "&é\"'(-è_çà");
+        assert_eq!(layout_character(KeyboardLayout::Azerty, 12), Some(')'));
+        assert_eq!(layout_character(KeyboardLayout::Azerty, 13), Some('='));
+        assert_eq!(layout_character(KeyboardLayout::Azerty, 26), Some('^'));
+        assert_eq!(layout_character(KeyboardLayout::Azerty, 27), Some('$'));
+        assert_eq!(layout_character(KeyboardLayout::Azerty, 43), Some('*'));
+        assert_eq!(layout_character(KeyboardLayout::Azerty, 16), Some('a'));
+    }
+
+    #[test]
+    fn qwertz_moves_punctuation() {
+        assert_eq!(layout_character(KeyboardLayout::Qwertz, 13), Some('´'));
+        assert_eq!(layout_character(KeyboardLayout::Qwertz, 27), Some('+'));
+        assert_eq!(layout_character(KeyboardLayout::Qwertz, 41), Some('^'));
+        assert_eq!(layout_character(KeyboardLayout::Qwertz, 43), Some('#'));
+        assert_eq!(layout_character(KeyboardLayout::Qwertz, 53), Some('-'));
+        assert_eq!(layout_character(KeyboardLayout::Qwertz, 21), Some('z'));
+        // Digits stay where QWERTY has them
+        assert_eq!(layout_character(KeyboardLayout::Qwertz, 2), None);
+    }
+
+    #[test]
+    fn dvorak_keeps_the_digit_row() {
+        assert_eq!(layout_character(KeyboardLayout::Dvorak, 2), None);
+        assert_eq!(layout_character(KeyboardLayout::Dvorak, 16), Some('\''));
+        assert_eq!(layout_character(KeyboardLayout::Dvorak, 53), Some('z'));
+    }
+
+    #[test]
+    fn tables_have_one_entry_per_key() {
+        for table in [QWERTZ, AZERTY, DVORAK] {
+            for pair in table.windows(2) {
+                assert!(pair[0].0 < pair[1].0, "{:?} out of order", pair);
+            }
+        }
+    }
+}
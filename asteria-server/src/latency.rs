@@ -0,0 +1,194 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the histogram buckets in milliseconds, the last bucket takes the rest
+const BUCKET_BOUNDS_MS: [u64; 9] = [1, 2, 5, 10, 20, 50, 100, 200, 500];
+
+/// Running histogram of the time between a client capturing input and the
+/// server receiving it.
+///
+/// Latency is measured against the client's clock, so any offset between the
+/// two machines' clocks shows up in it as well.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKET_BOUNDS_MS.len() + 1],
+    count: u64,
+    total_ms: u64,
+    max_ms: u64,
+    /// Packets stamped later than the server's clock, so their latency is unknown
+    ahead: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: [0; BUCKET_BOUNDS_MS.len() + 1],
+            count: 0,
+            total_ms: 0,
+            max_ms: 0,
+            ahead: 0,
+        }
+    }
+
+    /// Record a packet captured at `timestamp_ms` and received at `now_ms`,
+    /// both in milliseconds since the Unix epoch
+    pub fn record(&mut self, timestamp_ms: u64, now_ms: u64) {
+        let Some(latency_ms) = now_ms.checked_sub(timestamp_ms) else {
+            self.ahead += 1;
+            return;
+        };
+
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|bound| latency_ms <= *bound)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total_ms += latency_ms;
+        self.max_ms = self.max_ms.max(latency_ms);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0 && self.ahead == 0
+    }
+
+    /// Upper bound of the bucket holding the given quantile, `None` for the open last bucket
+    fn quantile_bound(&self, quantile: f64) -> Option<u64> {
+        let target = (self.count as f64 * quantile).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return BUCKET_BOUNDS_MS.get(index).copied();
+            }
+        }
+        None
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "no packets measured, {} stamped ahead", self.ahead);
+        }
+
+        let bound = |quantile| match self.quantile_bound(quantile) {
+            Some(bound) => format!("<={}ms", bound),
+            None => format!(">{}ms", BUCKET_BOUNDS_MS[BUCKET_BOUNDS_MS.len() - 1]),
+        };
+        write!(
+            f,
+            "{} packets, mean {}ms, p50 {}, p99 {}, max {}ms",
+            self.count,
+            self.total_ms / self.count,
+            bound(0.5),
+            bound(0.99),
+            self.max_ms
+        )?;
+        if self.ahead > 0 {
+            write!(f, ", {} stamped ahead of the server clock", self.ahead)?;
+        }
+        Ok(())
+    }
+}
+
+/// Latency histogram of one connection, logged every `interval`
+pub struct LatencyStats {
+    histogram: LatencyHistogram,
+    interval: Duration,
+    logged_at: Instant,
+}
+
+impl LatencyStats {
+    /// Stats logged every `interval`, or only when the connection ends if it is zero
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            histogram: LatencyHistogram::new(),
+            interval,
+            logged_at: Instant::now(),
+        }
+    }
+
+    /// Record a packet captured at `timestamp_ms`, returning the histogram when it is due to be logged
+    pub fn record(&mut self, timestamp_ms: u64) -> Option<&LatencyHistogram> {
+        self.histogram.record(timestamp_ms, now_ms());
+
+        if self.interval.is_zero() || self.logged_at.elapsed() < self.interval {
+            return None;
+        }
+        self.logged_at = Instant::now();
+        Some(&self.histogram)
+    }
+
+    pub fn histogram(&self) -> &LatencyHistogram {
+        &self.histogram
+    }
+}
+
+/// Milliseconds since the Unix epoch, the unit of `Packet::timestamp`
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_is_measured_from_the_capture_timestamp() {
+        let mut histogram = LatencyHistogram::new();
+        for latency_ms in [1, 3, 3, 8, 40] {
+            histogram.record(10_000, 10_000 + latency_ms);
+        }
+
+        assert_eq!(
+            histogram.to_string(),
+            "5 packets, mean 11ms, p50 <=5ms, p99 <=50ms, max 40ms"
+        );
+    }
+
+    #[test]
+    fn packets_stamped_ahead_are_counted_apart() {
+        let mut histogram = LatencyHistogram::new();
+        assert!(histogram.is_empty());
+
+        histogram.record(10_050, 10_000);
+        assert!(!histogram.is_empty());
+        assert_eq!(
+            histogram.to_string(),
+            "no packets measured, 1 stamped ahead"
+        );
+
+        histogram.record(10_000, 10_002);
+        assert_eq!(
+            histogram.to_string(),
+            "1 packets, mean 2ms, p50 <=2ms, p99 <=2ms, max 2ms, 1 stamped ahead of the server clock"
+        );
+    }
+
+    #[test]
+    fn latency_beyond_the_last_bucket_is_open_ended() {
+        let mut histogram = LatencyHistogram::new();
+        histogram.record(0, 2_000);
+        assert_eq!(
+            histogram.to_string(),
+            "1 packets, mean 2000ms, p50 >500ms, p99 >500ms, max 2000ms"
+        );
+    }
+
+    #[test]
+    fn stats_without_an_interval_are_only_logged_at_the_end() {
+        let mut stats = LatencyStats::new(Duration::ZERO);
+        assert!(stats.record(now_ms()).is_none());
+        assert_eq!(stats.histogram().count, 1);
+    }
+}
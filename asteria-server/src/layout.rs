@@ -0,0 +1,118 @@
+use asteria_core::config::{MonitorBounds, ScreenLayout};
+use asteria_core::protocol::denormalize_absolute;
+
+/// Map a normalized absolute position onto the desktop spanned by `layout`,
+/// `None` when no monitors are configured.
+///
+/// The normalized range covers the bounding box of every monitor, whose origin
+/// is negative when a monitor sits left of or above the primary one. Positions
+/// landing in a gap between monitors, which the pointer can't reach, are moved
+/// to the closest point of the nearest monitor.
+pub fn map_to_layout(layout: &ScreenLayout, x: i32, y: i32) -> Option<(i32, i32)> {
+    let monitors: Vec<&MonitorBounds> = layout
+        .monitors
+        .iter()
+        .filter(|monitor| monitor.width > 0 && monitor.height > 0)
+        .collect();
+    if monitors.is_empty() {
+        return None;
+    }
+
+    let left = monitors.iter().map(|monitor| monitor.x).min()?;
+    let top = monitors.iter().map(|monitor| monitor.y).min()?;
+    let right = monitors.iter().map(|monitor| right_edge(monitor)).max()?;
+    let bottom = monitors.iter().map(|monitor| bottom_edge(monitor)).max()?;
+
+    let position = (
+        left + denormalize_absolute(x, span(left, right)),
+        top + denormalize_absolute(y, span(top, bottom)),
+    );
+
+    monitors
+        .iter()
+        .map(|monitor| clamp_to(monitor, position))
+        .min_by_key(|&clamped| distance_squared(clamped, position))
+}
+
+/// Last pixel column of a monitor
+fn right_edge(monitor: &MonitorBounds) -> i32 {
+    monitor.x.saturating_add_unsigned(monitor.width - 1)
+}
+
+/// Last pixel row of a monitor
+fn bottom_edge(monitor: &MonitorBounds) -> i32 {
+    monitor.y.saturating_add_unsigned(monitor.height - 1)
+}
+
+/// Pixels from `start` to `end`, both included
+fn span(start: i32, end: i32) -> u32 {
+    end.abs_diff(start) + 1
+}
+
+fn clamp_to(monitor: &MonitorBounds, (x, y): (i32, i32)) -> (i32, i32) {
+    (
+        x.clamp(monitor.x, right_edge(monitor)),
+        y.clamp(monitor.y, bottom_edge(monitor)),
+    )
+}
+
+fn distance_squared(a: (i32, i32), b: (i32, i32)) -> u64 {
+    let dx = u64::from(a.0.abs_diff(b.0));
+    let dy = u64::from(a.1.abs_diff(b.1));
+    dx * dx + dy * dy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asteria_core::protocol::ABSOLUTE_AXIS_MAX;
+
+    fn monitor(x: i32, y: i32, width: u32, height: u32) -> MonitorBounds {
+        MonitorBounds {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn center_maps_to_where_two_monitors_meet() {
+        // A second monitor left of the primary one
+        let layout = ScreenLayout {
+            monitors: vec![monitor(0, 0, 1920, 1080), monitor(-1920, 0, 1920, 1080)],
+        };
+        let half = ABSOLUTE_AXIS_MAX / 2;
+
+        // Just short of the middle of the 3840 pixel wide desktop, which is
+        // the last column of the left monitor
+        assert_eq!(map_to_layout(&layout, half, half), Some((-1, 539)));
+        assert_eq!(map_to_layout(&layout, 0, 0), Some((-1920, 0)));
+        assert_eq!(
+            map_to_layout(&layout, ABSOLUTE_AXIS_MAX, ABSOLUTE_AXIS_MAX),
+            Some((1919, 1079))
+        );
+    }
+
+    #[test]
+    fn position_in_a_gap_moves_to_the_nearest_monitor() {
+        // A shorter monitor right of the primary one leaves a gap below it
+        let layout = ScreenLayout {
+            monitors: vec![monitor(0, 0, 1920, 1080), monitor(1920, 0, 1280, 720)],
+        };
+
+        assert_eq!(
+            map_to_layout(&layout, ABSOLUTE_AXIS_MAX, ABSOLUTE_AXIS_MAX),
+            Some((3199, 719))
+        );
+    }
+
+    #[test]
+    fn no_usable_monitor_maps_nothing() {
+        let layout = ScreenLayout {
+            monitors: vec![monitor(0, 0, 0, 1080)],
+        };
+        assert_eq!(map_to_layout(&layout, 0, 0), None);
+        assert_eq!(map_to_layout(&ScreenLayout::default(), 0, 0), None);
+    }
+}
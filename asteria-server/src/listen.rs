@@ -0,0 +1,174 @@
+use anyhow::{Context, Result, bail};
+use asteria_core::config::BindRetryConfig;
+use socket2::{Domain, Socket, Type};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, UdpSocket, lookup_host};
+use tracing::warn;
+
+/// Pending connections a listener queues before they are accepted
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Resolve the configured host to every address the server should listen on.
+///
+/// IPv6 literals may be given with or without brackets, and a hostname that
+/// resolves to several addresses is listened on at each of them.
+pub async fn resolve_listen_addresses(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let host = host.trim();
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.is_empty() {
+        bail!("No host configured to listen on");
+    }
+
+    let mut addresses: Vec<SocketAddr> = lookup_host((host, port))
+        .await
+        .with_context(|| format!("Invalid listen host {}", host))?
+        .collect();
+    addresses.dedup();
+    if addresses.is_empty() {
+        bail!("{} did not resolve to any address", host);
+    }
+
+    Ok(addresses)
+}
+
+/// Bind `address` with `bind`, retrying with a doubling delay while it fails.
+///
+/// A server started at boot may come up before its network interface does,
+/// so the address can't be bound yet but will be shortly.
+pub async fn bind_with_retry<T>(
+    address: SocketAddr,
+    config: &BindRetryConfig,
+    mut bind: impl FnMut(SocketAddr) -> Result<T>,
+) -> Result<T> {
+    let max_delay = Duration::from_millis(config.max_delay_ms);
+    let mut delay = Duration::from_millis(config.initial_delay_ms).min(max_delay);
+    let mut retries = 0;
+
+    loop {
+        match bind(address) {
+            Ok(bound) => return Ok(bound),
+            Err(e) if config.forever || retries < config.max_retries => {
+                retries += 1;
+                warn!(
+                    "Failed to bind {}: {:#}, retrying in {:?} (retry {})",
+                    address, e, delay, retries
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(max_delay);
+            }
+            Err(e) if retries > 0 => {
+                return Err(e.context(format!("Gave up on {} after {} retries", address, retries)));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Listen for TCP connections on `address`
+pub fn bind_tcp(address: SocketAddr) -> Result<TcpListener> {
+    let socket = bind_socket(address, Type::STREAM)?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Bind a UDP socket to `address`
+pub fn bind_udp(address: SocketAddr) -> Result<UdpSocket> {
+    let socket = bind_socket(address, Type::DGRAM)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Socket bound to `address`; the IPv6 wildcard accepts IPv4 clients as well,
+/// which Windows doesn't do by default
+fn bind_socket(address: SocketAddr, kind: Type) -> Result<Socket> {
+    let socket = Socket::new(Domain::for_address(address), kind, None)?;
+    if address.is_ipv6() && address.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    // Matches tokio's own listeners, so a restart doesn't wait out TIME_WAIT
+    #[cfg(unix)]
+    if kind == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&address.into())
+        .with_context(|| format!("Failed to bind {}", address))?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn ipv4_host_resolves_to_itself() {
+        let addresses = resolve_listen_addresses("127.0.0.1", 24800).await.unwrap();
+        assert_eq!(addresses, ["127.0.0.1:24800".parse().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn ipv6_literal_resolves_with_or_without_brackets() {
+        let expected: SocketAddr = "[::1]:24800".parse().unwrap();
+        for host in ["::1", "[::1]"] {
+            let addresses = resolve_listen_addresses(host, 24800).await.unwrap();
+            assert_eq!(addresses, [expected]);
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_host_is_rejected() {
+        assert!(resolve_listen_addresses("", 24800).await.is_err());
+        assert!(resolve_listen_addresses("[]", 24800).await.is_err());
+        assert!(
+            resolve_listen_addresses("not a host name", 24800)
+                .await
+                .is_err()
+        );
+    }
+
+    fn retry_config(max_retries: u32) -> BindRetryConfig {
+        BindRetryConfig {
+            max_retries,
+            forever: false,
+            initial_delay_ms: 1,
+            max_delay_ms: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn bind_is_retried_until_it_succeeds() {
+        let address: SocketAddr = "127.0.0.1:24800".parse().unwrap();
+        let mut attempts = 0;
+        let bound = bind_with_retry(address, &retry_config(5), |address| {
+            attempts += 1;
+            if attempts <= 3 {
+                bail!("Address not available yet");
+            }
+            Ok(address)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(bound, address);
+        assert_eq!(attempts, 4);
+    }
+
+    #[tokio::test]
+    async fn bind_gives_up_after_max_retries() {
+        let address: SocketAddr = "127.0.0.1:24800".parse().unwrap();
+        let mut attempts = 0;
+        let result: Result<()> = bind_with_retry(address, &retry_config(2), |_| {
+            attempts += 1;
+            bail!("Address not available")
+        })
+        .await;
+
+        let error = result.unwrap_err();
+        assert_eq!(attempts, 3);
+        assert!(format!("{:#}", error).contains("after 2 retries"));
+    }
+}
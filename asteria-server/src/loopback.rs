@@ -0,0 +1,456 @@
+use anyhow::{Context, Result, anyhow, bail};
+use asteria_core::{
+    auth::sign_nonce,
+    clipboard::{CLIPBOARD_CHUNK_SIZE, ClipboardPayload, chunk_payload},
+    config::{ScreenSize, ServerConfig, WireFormat},
+    protocol::{
+        AuthResponse, GestureKind, GesturePhase, Handshake, InputEvent, InputEventType, LockState,
+        Message, PROTOCOL_VERSION, Packet, encode_frame, read_frame,
+    },
+};
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::io::{AsyncWriteExt, DuplexStream, duplex};
+use tracing::info;
+
+use crate::input_simulator::InputSink;
+use crate::server::InputServer;
+
+/// Input the server would have simulated
+#[derive(Debug, Clone)]
+pub enum SimulatedInput {
+    Raw(InputEvent),
+    Typed(InputEventType),
+    Clipboard(String),
+    LockState(LockState),
+    /// Everything held was released
+    ReleaseAll,
+}
+
+impl fmt::Display for SimulatedInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulatedInput::Raw(event) => write!(
+                f,
+                "raw {} code={} value={}",
+                event.event_type, event.code, event.value
+            ),
+            SimulatedInput::Typed(event) => write!(f, "{:?}", event),
+            SimulatedInput::Clipboard(text) => write!(f, "clipboard {:?}", text),
+            SimulatedInput::LockState(state) => write!(
+                f,
+                "lock_state caps={} num={} scroll={}",
+                state.caps, state.num, state.scroll
+            ),
+            SimulatedInput::ReleaseAll => write!(f, "release_all"),
+        }
+    }
+}
+
+/// Sink that records input instead of injecting it
+#[derive(Clone, Default)]
+pub struct RecordingSink {
+    recorded: Arc<Mutex<Vec<SimulatedInput>>>,
+}
+
+impl RecordingSink {
+    /// Everything recorded so far, in order
+    pub fn recorded(&self) -> Vec<SimulatedInput> {
+        self.recorded.lock().expect("recording lock").clone()
+    }
+
+    fn record(&self, input: SimulatedInput) {
+        self.recorded.lock().expect("recording lock").push(input);
+    }
+}
+
+impl InputSink for RecordingSink {
+    fn simulate_input(&mut self, event: &InputEvent) -> Result<()> {
+        self.record(SimulatedInput::Raw(event.clone()));
+        Ok(())
+    }
+
+    fn simulate_typed_input(&mut self, event: &InputEventType) -> Result<()> {
+        self.record(SimulatedInput::Typed(event.clone()));
+        Ok(())
+    }
+
+    fn set_clipboard_text(&mut self, text: &str) -> Result<()> {
+        self.record(SimulatedInput::Clipboard(text.to_string()));
+        Ok(())
+    }
+
+    fn clipboard_text(&mut self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn sync_lock_state(&mut self, state: &LockState) -> Result<()> {
+        self.record(SimulatedInput::LockState(*state));
+        Ok(())
+    }
+
+    fn release_all(&mut self) -> Result<()> {
+        self.record(SimulatedInput::ReleaseAll);
+        Ok(())
+    }
+
+    fn screen_size(&self) -> Result<ScreenSize> {
+        bail!("Loopback has no screen")
+    }
+
+    fn pacing_delay(&self, _event: &InputEventType) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Relay a script through an in-process server and return what it simulated.
+///
+/// Each script line is one message, see [`parse_script_line`]. The client and
+/// server talk over an in-memory stream, so no network or input devices are used.
+pub async fn run_loopback(config: ServerConfig, script: &str) -> Result<Vec<SimulatedInput>> {
+    let messages = script
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            parse_script_line(line)
+                .with_context(|| format!("Invalid script line {}", index + 1))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let auth_secret = config.auth.secret();
+    let wire_format = config.network.wire_format;
+    let handshake = Handshake {
+        protocol_version: PROTOCOL_VERSION,
+        clipboard_formats: config.clipboard.formats.clone(),
+        max_clipboard_size: config.clipboard.max_payload_size,
+        screen: None,
+    };
+
+    let sink = RecordingSink::default();
+    let server = InputServer::with_sink(config, sink.clone());
+    let (client_stream, server_stream) = duplex(64 * 1024);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 0));
+
+    let client = async move {
+        let mut stream = client_stream;
+        if let Some(secret) = auth_secret {
+            answer_challenge(&mut stream, &secret, wire_format).await?;
+        }
+
+        let packets = std::iter::once(Message::Handshake(handshake)).chain(messages);
+        for (seq, message) in packets.enumerate() {
+            let mut packet = Packet::new(message);
+            packet.seq = seq as u64;
+            write_packet(&mut stream, packet, wire_format).await?;
+        }
+
+        // Closing the stream ends the server's session once it has drained it.
+        // Only the writing side is closed, the server still answers the handshake.
+        stream.shutdown().await?;
+        Ok::<_, anyhow::Error>(stream)
+    };
+
+    let (client_result, server_result) =
+        tokio::join!(client, server.serve_connection(server_stream, peer));
+    let _stream = client_result?;
+    server_result?;
+
+    let recorded = sink.recorded();
+    info!("Loopback simulated {} events", recorded.len());
+    Ok(recorded)
+}
+
+/// Parse one script line into a message.
+///
+/// Blank lines and `#` comments yield `None`. Supported commands:
+/// `press CODE`, `release CODE`, `repeat CODE`, `move DX DY`, `move_abs X Y`,
+/// `button N down|up`, `scroll DX DY`, `pad_button CODE down|up`,
+/// `pad_axis AXIS VALUE`, `lock_state CAPS NUM SCROLL` with each `on|off`,
+/// `gesture swipe|pinch begin|update|end|cancel FINGERS DX DY SCALE`, `type TEXT`, `clipboard TEXT`, `disconnect REASON`, `reset` and
+/// `raw EV_TYPE CODE VALUE` for a raw evdev event.
+pub fn parse_script_line(line: &str) -> Result<Option<Message>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+
+    if command == "clipboard" {
+        let payload = ClipboardPayload::text(rest);
+        let mut chunks = chunk_payload(0, &payload, CLIPBOARD_CHUNK_SIZE)?;
+        let chunk = chunks.pop().expect("a payload has at least one chunk");
+        return Ok(Some(Message::ClipboardChunk(chunk)));
+    }
+    if command == "type" {
+        return Ok(Some(Message::InputEventTyped(InputEventType::TypeText {
+            text: rest.to_string(),
+        })));
+    }
+    if command == "disconnect" {
+        return Ok(Some(Message::Disconnect {
+            reason: rest.to_string(),
+        }));
+    }
+
+    if command == "reset" {
+        return Ok(Some(Message::ResetInputState));
+    }
+
+    let args: Vec<&str> = rest.split_whitespace().collect();
+    if let ("raw", [event_type, code, value]) = (command, args.as_slice()) {
+        return Ok(Some(Message::InputEvent(InputEvent {
+            event_type: event_type.to_string(),
+            code: parse_number(code)?,
+            value: parse_number(value)?,
+        })));
+    }
+    if let ("lock_state", [caps, num, scroll]) = (command, args.as_slice()) {
+        return Ok(Some(Message::LockState(LockState {
+            caps: parse_toggled(caps)?,
+            num: parse_toggled(num)?,
+            scroll: parse_toggled(scroll)?,
+        })));
+    }
+
+    let event = match (command, args.as_slice()) {
+        ("press", [code]) => InputEventType::KeyPress {
+            key_code: parse_number(code)?,
+        },
+        ("release", [code]) => InputEventType::KeyRelease {
+            key_code: parse_number(code)?,
+        },
+        ("repeat", [code]) => InputEventType::KeyRepeat {
+            key_code: parse_number(code)?,
+        },
+        ("move", [x, y]) => InputEventType::MouseMove {
+            x: parse_number(x)?,
+            y: parse_number(y)?,
+        },
+        ("move_abs", [x, y]) => InputEventType::MouseMoveAbsolute {
+            x: parse_number(x)?,
+            y: parse_number(y)?,
+        },
+        ("button", [button, state]) => InputEventType::MouseButton {
+            button: parse_number(button)?,
+            pressed: parse_pressed(state)?,
+        },
+        ("scroll", [dx, dy]) => InputEventType::MouseScroll {
+            dx: parse_number(dx)?,
+            dy: parse_number(dy)?,
+        },
+        ("pad_button", [button, state]) => InputEventType::GamepadButton {
+            button: parse_number(button)?,
+            pressed: parse_pressed(state)?,
+        },
+        ("pad_axis", [axis, value]) => InputEventType::GamepadAxis {
+            axis: parse_number(axis)?,
+            value: parse_number(value)?,
+        },
+        ("gesture", [kind, phase, fingers, dx, dy, scale]) => InputEventType::Gesture {
+            kind: parse_gesture_kind(kind)?,
+            phase: parse_gesture_phase(phase)?,
+            fingers: parse_number(fingers)?,
+            dx: parse_float(dx)?,
+            dy: parse_float(dy)?,
+            scale: parse_float(scale)?,
+        },
+        _ => bail!("Unrecognized command: {}", line),
+    };
+
+    Ok(Some(Message::InputEventTyped(event)))
+}
+
+fn parse_pressed(state: &str) -> Result<bool> {
+    match state {
+        "down" => Ok(true),
+        "up" => Ok(false),
+        other => bail!("Expected down or up, got {}", other),
+    }
+}
+
+fn parse_gesture_kind(kind: &str) -> Result<GestureKind> {
+    match kind {
+        "swipe" => Ok(GestureKind::Swipe),
+        "pinch" => Ok(GestureKind::Pinch),
+        other => bail!("Expected swipe or pinch, got {}", other),
+    }
+}
+
+fn parse_gesture_phase(phase: &str) -> Result<GesturePhase> {
+    match phase {
+        "begin" => Ok(GesturePhase::Begin),
+        "update" => Ok(GesturePhase::Update),
+        "end" => Ok(GesturePhase::End),
+        "cancel" => Ok(GesturePhase::Cancel),
+        other => bail!("Expected begin, update, end or cancel, got {}", other),
+    }
+}
+
+fn parse_float(value: &str) -> Result<f32> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("Invalid number: {}", value))
+}
+
+fn parse_toggled(state: &str) -> Result<bool> {
+    match state {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        other => bail!("Expected on or off, got {}", other),
+    }
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal number
+fn parse_number<T: TryFrom<i64>>(value: &str) -> Result<T> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => value.parse::<i64>(),
+    }
+    .map_err(|_| anyhow!("Invalid number: {}", value))?;
+
+    T::try_from(parsed).map_err(|_| anyhow!("Number out of range: {}", value))
+}
+
+/// Answer the server's authentication challenge with `secret`
+pub async fn answer_challenge(
+    stream: &mut DuplexStream,
+    secret: &str,
+    wire_format: WireFormat,
+) -> Result<()> {
+    let mut buffer = Vec::new();
+    let Some(Message::AuthChallenge(challenge)) = read_frame(stream, &mut buffer, wire_format)
+        .await?
+        .map(|packet| packet.message)
+    else {
+        bail!("Server did not send an authentication challenge");
+    };
+
+    let response = AuthResponse {
+        mac: sign_nonce(secret, &challenge.nonce),
+    };
+    write_packet(
+        stream,
+        Packet::new(Message::AuthResponse(response)),
+        wire_format,
+    )
+    .await
+}
+
+pub async fn write_packet(
+    stream: &mut DuplexStream,
+    packet: Packet,
+    wire_format: WireFormat,
+) -> Result<()> {
+    stream
+        .write_all(&encode_frame(&packet, wire_format)?)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(line: &str) -> InputEventType {
+        match parse_script_line(line).unwrap() {
+            Some(Message::InputEventTyped(event)) => event,
+            other => panic!("{} parsed to {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn script_lines_parse_to_messages() {
+        assert!(matches!(
+            typed("press 0x1e"),
+            InputEventType::KeyPress { key_code: 30 }
+        ));
+        assert!(matches!(
+            typed("  move -5 12  "),
+            InputEventType::MouseMove { x: -5, y: 12 }
+        ));
+        assert!(matches!(
+            typed("button 1 up"),
+            InputEventType::MouseButton {
+                button: 1,
+                pressed: false
+            }
+        ));
+        assert!(matches!(
+            typed("gesture pinch update 2 0 0 1.5"),
+            InputEventType::Gesture {
+                kind: GestureKind::Pinch,
+                phase: GesturePhase::Update,
+                fingers: 2,
+                ..
+            }
+        ));
+        assert!(matches!(
+            typed("type hello world"),
+            InputEventType::TypeText { text } if text == "hello world"
+        ));
+        assert!(matches!(
+            parse_script_line("lock_state on off on").unwrap(),
+            Some(Message::LockState(LockState {
+                caps: true,
+                num: false,
+                scroll: true
+            }))
+        ));
+        assert!(matches!(
+            parse_script_line("raw EV_KEY 30 1").unwrap(),
+            Some(Message::InputEvent(InputEvent {
+                code: 30,
+                value: 1,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn blank_lines_and_comments_are_skipped() {
+        assert!(parse_script_line("").unwrap().is_none());
+        assert!(parse_script_line("   ").unwrap().is_none());
+        assert!(parse_script_line("# press 30").unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert!(parse_script_line("jump 1").is_err());
+        assert!(parse_script_line("press").is_err());
+        assert!(parse_script_line("press abc").is_err());
+        assert!(parse_script_line("button 1 sideways").is_err());
+        // Key codes are u16
+        assert!(parse_script_line("press 70000").is_err());
+    }
+
+    #[tokio::test]
+    async fn loopback_records_what_the_server_simulates() {
+        let script = "# a key tap and a move\npress 30\nrelease 30\nmove 3 4\n";
+        let recorded = run_loopback(ServerConfig::default(), script).await.unwrap();
+
+        let lines: Vec<String> = recorded.iter().map(ToString::to_string).collect();
+        let position = |needle: &str| {
+            lines
+                .iter()
+                .position(|line| line.contains(needle))
+                .unwrap_or_else(|| panic!("{} not in {:?}", needle, lines))
+        };
+        assert!(position("KeyPress { key_code: 30 }") < position("KeyRelease { key_code: 30 }"));
+        assert!(position("KeyRelease") < position("MouseMove { x: 3, y: 4 }"));
+    }
+
+    #[tokio::test]
+    async fn invalid_scripts_name_the_line() {
+        let error = run_loopback(ServerConfig::default(), "press 30\nnope\n")
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Invalid script line 2");
+    }
+}
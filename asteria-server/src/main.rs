@@ -1,10 +1,32 @@
 use anyhow::{Ok, Result};
-use asteria_core::init_logging;
-use clap::{Arg, ArgMatches, Command};
+use asteria_core::{
+    config::{LoadableConfig, ServerConfig},
+    init_logging, verbosity_level,
+};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::io::Read;
+use std::path::PathBuf;
 use tracing::{error, info};
 
+mod allowlist;
+mod backend;
+mod bench;
+mod coalesce;
+mod daemon;
+mod gamepad;
+mod gesture;
 mod input_simulator;
+mod jitter;
+mod keymap;
+mod latency;
+mod layout;
+mod listen;
+mod loopback;
+mod metrics;
+mod rate_limit;
+mod scope;
 mod server;
+mod validate;
 
 use server::InputServer;
 
@@ -12,18 +34,66 @@ use server::InputServer;
 async fn main() -> Result<()> {
     let matches: ArgMatches = build_cli().get_matches();
 
-    init_logging();
+    let config_path = match matches.get_one::<String>("config") {
+        Some(path) => PathBuf::from(path),
+        None => ServerConfig::config_path()?,
+    };
+    let config = ServerConfig::load_from(&config_path)?;
+    let verbosity = verbosity_level(matches.get_count("verbose"), matches.get_flag("quiet"));
+    let _log_guard = init_logging(&config.logging, "asteria-server", verbosity);
 
     match matches.subcommand() {
-        Some(("start", _)) => {
+        Some(("start", sub_m)) => {
+            let pid_file = config.pid_file();
+            if sub_m.get_flag("daemon") {
+                let pid = daemon::daemonize(&pid_file)?;
+                println!("Asteria server started in the background with PID {}", pid);
+                return Ok(());
+            }
+
             info!("Starting Asteria server...");
-            let server = InputServer::new()?;
-            server.start().await?;
+            let result = match InputServer::new(config).await {
+                Result::Ok(server) => server.start().await,
+                Err(e) => Err(e),
+            };
+            if sub_m.get_flag("detached") {
+                daemon::remove_pid_file(&pid_file, std::process::id());
+            }
+            result?;
+        }
+        Some(("stop", _)) => {
+            daemon::stop(&config.pid_file()).await?;
+        }
+        Some(("loopback", sub_m)) => {
+            let script = match sub_m.get_one::<String>("script") {
+                Some(path) => std::fs::read_to_string(path)?,
+                None => {
+                    let mut script = String::new();
+                    std::io::stdin().read_to_string(&mut script)?;
+                    script
+                }
+            };
+
+            for input in loopback::run_loopback(config, &script).await? {
+                println!("{}", input);
+            }
+        }
+        Some(("bench", sub_m)) => {
+            let events = *sub_m
+                .get_one::<usize>("events")
+                .expect("events has a default");
+            println!("{}", bench::run_bench(config, events).await?);
+        }
+        Some(("config", sub_m)) => {
+            println!("{}", config_path.display());
+            if !sub_m.get_flag("path") {
+                println!();
+                print!("{}", config.to_toml()?);
+            }
         }
         Some(("ping", sub_m)) => {
             let host = sub_m.get_one::<String>("host").cloned();
-            let server = InputServer::new()?;
-            server.ping(host).await?;
+            InputServer::ping(&config, host).await?;
         }
         _ => {
             error!("Invalid command. Use --help for usage information.");
@@ -37,10 +107,81 @@ fn build_cli() -> Command {
     Command::new("asteria-server")
         .version(env!("CARGO_PKG_VERSION"))
         .about("Asteria server application")
-        .subcommand(Command::new("start").about("Start the Asteria server"))
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .help("Config file to use instead of the default location")
+                .value_name("PATH")
+                .global(true),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Log more, -v for debug and -vv for trace output")
+                .action(ArgAction::Count)
+                .global(true),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only log warnings and errors")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose")
+                .global(true),
+        )
+        .subcommand(
+            Command::new("start")
+                .about("Start the Asteria server")
+                .arg(
+                    Arg::new("daemon")
+                        .long("daemon")
+                        .help("Run in the background, recording the PID for `stop`")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("detached")
+                        .long(daemon::DETACHED_FLAG.trim_start_matches('-'))
+                        .action(ArgAction::SetTrue)
+                        .hide(true),
+                ),
+        )
+        .subcommand(Command::new("stop").about("Stop the server started with `start --daemon`"))
+        .subcommand(
+            Command::new("loopback")
+                .about("Relay a script through an in-process client and server and print what would be simulated")
+                .arg(
+                    Arg::new("script")
+                        .help("Script file with one event per line, read from stdin if omitted")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Measure how long events take from the client to simulation, through an in-process client and server")
+                .arg(
+                    Arg::new("events")
+                        .long("events")
+                        .help("Number of synthetic events to relay")
+                        .value_name("COUNT")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10000"),
+                ),
+        )
         .subcommand(
             Command::new("ping")
                 .about("Send a ping to test connectivity")
                 .arg(Arg::new("host").help("Specific host to ping").index(1)),
         )
+        .subcommand(
+            Command::new("config")
+                .about("Print the config file location and the effective configuration")
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .help("Only print the config file location")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
 }
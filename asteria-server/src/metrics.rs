@@ -0,0 +1,242 @@
+use anyhow::Result;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+/// Server-wide counters, updated lock-free from every connection
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    active_connections: AtomicU64,
+    connections: AtomicU64,
+    packets_processed: AtomicU64,
+    events_simulated: AtomicU64,
+    errors: AtomicU64,
+    corrupt_packets: AtomicU64,
+    rejected_events: AtomicU64,
+}
+
+impl ServerMetrics {
+    /// Count a new connection, which stays active until the guard is dropped
+    pub fn open_connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            metrics: Arc::clone(self),
+        }
+    }
+
+    pub fn record_packet(&self) {
+        self.packets_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_event(&self) {
+        self.events_simulated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a packet that failed its checksum, returning the count so far
+    pub fn record_corrupt_packet(&self) -> u64 {
+        self.corrupt_packets.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Count an event dropped for carrying an unknown code, returning the count so far
+    pub fn record_rejected_event(&self) -> u64 {
+        self.rejected_events.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Counters in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let metrics = [
+            (
+                "asteria_active_connections",
+                "gauge",
+                "Clients currently connected",
+                &self.active_connections,
+            ),
+            (
+                "asteria_connections_total",
+                "counter",
+                "Clients connected since the server started",
+                &self.connections,
+            ),
+            (
+                "asteria_packets_processed_total",
+                "counter",
+                "Packets received from clients and processed",
+                &self.packets_processed,
+            ),
+            (
+                "asteria_events_simulated_total",
+                "counter",
+                "Input events simulated",
+                &self.events_simulated,
+            ),
+            (
+                "asteria_errors_total",
+                "counter",
+                "Input that failed to simulate and connections that ended in an error",
+                &self.errors,
+            ),
+            (
+                "asteria_corrupt_packets_total",
+                "counter",
+                "Packets dropped for failing their checksum",
+                &self.corrupt_packets,
+            ),
+            (
+                "asteria_rejected_events_total",
+                "counter",
+                "Input events dropped for carrying a key, button or axis code the server doesn't map",
+                &self.rejected_events,
+            ),
+        ];
+
+        let mut output = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, kind);
+            let _ = writeln!(output, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+        output
+    }
+}
+
+/// Keeps a connection counted as active while it is alive
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    metrics: Arc<ServerMetrics>,
+}
+
+impl ConnectionGuard {
+    pub fn metrics(&self) -> &ServerMetrics {
+        &self.metrics
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Answer every HTTP request on `bind_address` with the current metrics.
+///
+/// The request itself isn't parsed, so any path serves the same page.
+pub async fn serve_metrics(bind_address: &str, metrics: Arc<ServerMetrics>) -> Result<()> {
+    let listener = TcpListener::bind(bind_address).await?;
+    info!("Metrics available at http://{}/metrics", bind_address);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let metrics = Arc::clone(&metrics);
+
+        tokio::spawn(async move {
+            if let Err(e) = answer_metrics_request(stream, &metrics).await {
+                debug!("Metrics request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn answer_metrics_request(mut stream: TcpStream, metrics: &ServerMetrics) -> Result<()> {
+    // Only the start of the request is read, it is answered the same either way
+    let mut request = [0u8; 1024];
+    let _ = stream.read(&mut request).await?;
+
+    let body = metrics.render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Value of the sample `name` in rendered metrics
+    fn sample(rendered: &str, name: &str) -> Option<u64> {
+        rendered
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+    }
+
+    #[test]
+    fn counters_render_in_prometheus_text_format() {
+        let metrics = Arc::new(ServerMetrics::default());
+        let guard = metrics.open_connection();
+        guard.metrics().record_packet();
+        guard.metrics().record_packet();
+        guard.metrics().record_event();
+        assert_eq!(guard.metrics().record_corrupt_packet(), 1);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "# HELP asteria_active_connections Clients currently connected\n\
+             # TYPE asteria_active_connections gauge\n\
+             asteria_active_connections 1\n"
+        ));
+        assert!(rendered.contains("# TYPE asteria_packets_processed_total counter\n"));
+        assert_eq!(sample(&rendered, "asteria_connections_total"), Some(1));
+        assert_eq!(
+            sample(&rendered, "asteria_packets_processed_total"),
+            Some(2)
+        );
+        assert_eq!(sample(&rendered, "asteria_events_simulated_total"), Some(1));
+        assert_eq!(sample(&rendered, "asteria_corrupt_packets_total"), Some(1));
+        assert_eq!(sample(&rendered, "asteria_errors_total"), Some(0));
+    }
+
+    #[test]
+    fn dropped_connection_is_no_longer_active() {
+        let metrics = Arc::new(ServerMetrics::default());
+        drop(metrics.open_connection());
+
+        let rendered = metrics.render();
+        assert_eq!(sample(&rendered, "asteria_active_connections"), Some(0));
+        assert_eq!(sample(&rendered, "asteria_connections_total"), Some(1));
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let metrics = Arc::new(ServerMetrics::default());
+        metrics.record_error();
+
+        let server = {
+            let metrics = Arc::clone(&metrics);
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                answer_metrics_request(stream, &metrics).await.unwrap();
+            })
+        };
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        server.await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(body, metrics.render());
+        assert_eq!(sample(body, "asteria_errors_total"), Some(1));
+    }
+}
@@ -0,0 +1,128 @@
+use asteria_core::config::RateLimitConfig;
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// Token bucket capping how many events of one client are simulated per
+/// second, so a runaway client can't flood the system's input queue.
+///
+/// Events past the limit are dropped rather than delayed, since input is only
+/// meaningful live.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Tokens added per second, 0 when unlimited
+    rate: f64,
+    /// Most tokens the bucket holds, i.e. the largest burst let through at once
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    /// Events dropped since the client last stayed within the limit
+    dropped: u64,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let burst = f64::from(config.burst.max(1));
+        Self {
+            rate: f64::from(config.max_events_per_sec),
+            burst,
+            tokens: burst,
+            refilled_at: Instant::now(),
+            dropped: 0,
+        }
+    }
+
+    /// Whether an event arriving now may be simulated, taking a token if so
+    pub fn allow(&mut self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&mut self, now: Instant) -> bool {
+        if self.rate <= 0.0 {
+            return true;
+        }
+
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            if self.dropped > 0 {
+                info!(
+                    "Client is back within the rate limit after {} events were dropped",
+                    self.dropped
+                );
+                self.dropped = 0;
+            }
+            return true;
+        }
+
+        if self.dropped == 0 {
+            warn!(
+                "Client exceeds {} events per second, dropping input until it slows down",
+                self.rate
+            );
+        }
+        self.dropped += 1;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limiter(max_events_per_sec: u32, burst: u32) -> RateLimiter {
+        RateLimiter::new(&RateLimitConfig {
+            max_events_per_sec,
+            burst,
+        })
+    }
+
+    #[test]
+    fn unlimited_lets_everything_through() {
+        let mut limiter = limiter(0, 1);
+        let now = Instant::now();
+        assert!((0..1000).all(|_| limiter.allow_at(now)));
+    }
+
+    #[test]
+    fn burst_is_let_through_then_events_are_dropped() {
+        let mut limiter = limiter(10, 3);
+        let now = limiter.refilled_at;
+
+        assert!((0..3).all(|_| limiter.allow_at(now)));
+        assert!(!limiter.allow_at(now));
+        assert_eq!(limiter.dropped, 1);
+    }
+
+    #[test]
+    fn tokens_refill_at_the_configured_rate() {
+        let mut limiter = limiter(10, 1);
+        let start = limiter.refilled_at;
+
+        assert!(limiter.allow_at(start));
+        assert!(!limiter.allow_at(start + Duration::from_millis(50)));
+        assert!(limiter.allow_at(start + Duration::from_millis(150)));
+        assert_eq!(limiter.dropped, 0);
+    }
+
+    #[test]
+    fn refill_never_exceeds_the_burst() {
+        let mut limiter = limiter(100, 2);
+        let later = limiter.refilled_at + Duration::from_secs(60);
+
+        assert!(limiter.allow_at(later));
+        assert!(limiter.allow_at(later));
+        assert!(!limiter.allow_at(later));
+    }
+
+    #[test]
+    fn zero_burst_still_lets_one_event_through() {
+        let mut limiter = limiter(10, 0);
+        let now = limiter.refilled_at;
+        assert!(limiter.allow_at(now));
+        assert!(!limiter.allow_at(now));
+    }
+}
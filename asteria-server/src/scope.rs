@@ -0,0 +1,209 @@
+use asteria_core::config::RelayScopeConfig;
+
+/// Identity of the application owning the focused window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FocusedApp {
+    pub executable: String,
+    pub window_class: String,
+}
+
+/// Source of the currently focused application
+pub trait FocusProvider: Send + Sync {
+    fn focused_app(&self) -> Option<FocusedApp>;
+}
+
+/// Queries the foreground window through the Win32 API
+pub struct SystemFocusProvider;
+
+#[cfg(windows)]
+impl FocusProvider for SystemFocusProvider {
+    fn focused_app(&self) -> Option<FocusedApp> {
+        use windows_sys::Win32::{
+            Foundation::CloseHandle,
+            System::Threading::{
+                OpenProcess, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+                QueryFullProcessImageNameW,
+            },
+            UI::WindowsAndMessaging::{
+                GetClassNameW, GetForegroundWindow, GetWindowThreadProcessId,
+            },
+        };
+
+        unsafe {
+            let hwnd = GetForegroundWindow();
+            if hwnd.is_null() {
+                return None;
+            }
+
+            let mut class_buf = [0u16; 256];
+            let class_len = GetClassNameW(hwnd, class_buf.as_mut_ptr(), class_buf.len() as i32);
+            let window_class = String::from_utf16_lossy(&class_buf[..class_len.max(0) as usize]);
+
+            let mut pid = 0u32;
+            GetWindowThreadProcessId(hwnd, &mut pid);
+
+            let mut executable = String::new();
+            let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+            if !process.is_null() {
+                let mut path_buf = [0u16; 1024];
+                let mut path_len = path_buf.len() as u32;
+                if QueryFullProcessImageNameW(
+                    process,
+                    PROCESS_NAME_WIN32,
+                    path_buf.as_mut_ptr(),
+                    &mut path_len,
+                ) != 0
+                {
+                    executable = String::from_utf16_lossy(&path_buf[..path_len as usize]);
+                }
+                CloseHandle(process);
+            }
+
+            Some(FocusedApp {
+                executable,
+                window_class,
+            })
+        }
+    }
+}
+
+#[cfg(not(windows))]
+impl FocusProvider for SystemFocusProvider {
+    fn focused_app(&self) -> Option<FocusedApp> {
+        None
+    }
+}
+
+/// Decides whether the focused application may receive relayed input
+pub struct RelayScope {
+    allowed_apps: Vec<String>,
+    provider: Box<dyn FocusProvider>,
+}
+
+impl RelayScope {
+    pub fn new(config: &RelayScopeConfig, provider: Box<dyn FocusProvider>) -> Self {
+        Self {
+            allowed_apps: config
+                .allowed_apps
+                .iter()
+                .map(|app| app.to_lowercase())
+                .collect(),
+            provider,
+        }
+    }
+
+    pub fn is_unrestricted(&self) -> bool {
+        self.allowed_apps.is_empty()
+    }
+
+    /// Check the currently focused app, returning the blocked app's name if any
+    pub fn check_focus(&self) -> Result<(), String> {
+        if self.is_unrestricted() {
+            return Ok(());
+        }
+
+        let app = self.provider.focused_app();
+        if self.is_allowed(app.as_ref()) {
+            Ok(())
+        } else {
+            Err(app
+                .map(|app| display_name(&app))
+                .unwrap_or_else(|| "<unknown>".to_string()))
+        }
+    }
+
+    /// Whether input may be injected into `app`; an unknown app is only allowed
+    /// when no scope is configured
+    pub fn is_allowed(&self, app: Option<&FocusedApp>) -> bool {
+        if self.is_unrestricted() {
+            return true;
+        }
+
+        let Some(app) = app else {
+            return false;
+        };
+
+        let file_name = executable_file_name(&app.executable).to_lowercase();
+        let stem = file_name.strip_suffix(".exe").unwrap_or(&file_name);
+        let window_class = app.window_class.to_lowercase();
+
+        self.allowed_apps
+            .iter()
+            .any(|allowed| *allowed == file_name || allowed == stem || *allowed == window_class)
+    }
+}
+
+/// File name part of an executable path, accepting either separator
+fn executable_file_name(path: &str) -> &str {
+    path.rsplit(['\\', '/']).next().unwrap_or(path)
+}
+
+fn display_name(app: &FocusedApp) -> String {
+    let file_name = executable_file_name(&app.executable);
+    if file_name.is_empty() {
+        app.window_class.clone()
+    } else {
+        file_name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reports the same focused app every time
+    struct FixedFocus(Option<FocusedApp>);
+
+    impl FocusProvider for FixedFocus {
+        fn focused_app(&self) -> Option<FocusedApp> {
+            self.0.clone()
+        }
+    }
+
+    fn app(executable: &str, window_class: &str) -> FocusedApp {
+        FocusedApp {
+            executable: executable.to_string(),
+            window_class: window_class.to_string(),
+        }
+    }
+
+    fn scope(allowed_apps: &[&str], focused: Option<FocusedApp>) -> RelayScope {
+        let config = RelayScopeConfig {
+            allowed_apps: allowed_apps.iter().map(|app| app.to_string()).collect(),
+            ..RelayScopeConfig::default()
+        };
+        RelayScope::new(&config, Box::new(FixedFocus(focused)))
+    }
+
+    #[test]
+    fn allowed_app_is_matched_by_executable_or_class() {
+        let focused = app(r"C:\Program Files\Notepad++\Notepad++.exe", "Notepad++");
+        for allowed in ["notepad++.exe", "NOTEPAD++", "notepad++"] {
+            assert_eq!(
+                scope(&[allowed], Some(focused.clone())).check_focus(),
+                Ok(())
+            );
+        }
+
+        let by_class = scope(&["chrome_widgetwin_1"], Some(app("", "Chrome_WidgetWin_1")));
+        assert_eq!(by_class.check_focus(), Ok(()));
+    }
+
+    #[test]
+    fn other_app_is_blocked_by_name() {
+        let scope = scope(&["notepad.exe"], Some(app("/usr/bin/game.exe", "Game")));
+        assert_eq!(scope.check_focus(), Err("game.exe".to_string()));
+    }
+
+    #[test]
+    fn unknown_app_is_only_allowed_without_a_scope() {
+        assert_eq!(
+            scope(&["notepad.exe"], None).check_focus(),
+            Err("<unknown>".to_string())
+        );
+
+        let unrestricted = scope(&[], None);
+        assert!(unrestricted.is_unrestricted());
+        assert_eq!(unrestricted.check_focus(), Ok(()));
+    }
+}
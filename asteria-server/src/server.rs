@@ -1,50 +1,239 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
 use asteria_core::{
-    config::{LoadableConfig, ServerConfig},
-    protocol::{Message, Packet},
+    auth::{generate_nonce, verify_nonce},
+    clipboard::{
+        ClipboardAssembler, ClipboardDebouncer, ClipboardPayload, chunk_payload, negotiate_formats,
+    },
+    config::{
+        BlockedInputAction, ClipboardConfig, ScreenSize, ServerConfig, TransportKind, WireFormat,
+        join_host_port,
+    },
+    protocol::{
+        AuthChallenge, ChecksumMismatch, ClipboardChunk, ClipboardFormat, FrameTooLarge, Handshake,
+        InputEvent, InputEventType, MAX_DATAGRAM_SIZE, Message, PROTOCOL_VERSION, Packet,
+        WireFormatMismatch, decode_frame, encode_frame, raw_event_types, read_frame,
+    },
+    tls::tls_acceptor,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
 };
-use std::sync::Arc;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
-    sync::Mutex,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    sync::{Mutex, MutexGuard, mpsc},
+    time::Instant,
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::allowlist::ClientAllowlist;
+use crate::coalesce::EventCoalescer;
+use crate::input_simulator::{InputSimulator, InputSink};
+use crate::jitter::MotionBuffer;
+use crate::latency::LatencyStats;
+use crate::listen::{bind_tcp, bind_udp, bind_with_retry, resolve_listen_addresses};
+use crate::metrics::{ConnectionGuard, ServerMetrics, serve_metrics};
+use crate::rate_limit::RateLimiter;
+use crate::scope::{RelayScope, SystemFocusProvider};
+use crate::validate::{self, RejectedInput};
+
+/// Most events held back while an app outside the relay scope has focus
+const MAX_HELD_EVENTS: usize = 256;
 
-use crate::input_simulator::InputSimulator;
+/// How long a client has to answer the authentication challenge
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `ping` waits for the pong
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// TCP server that receives input events and simulates them
 pub struct InputServer {
     config: ServerConfig,
-    simulator: Arc<Mutex<InputSimulator>>,
+    simulator: Arc<Mutex<dyn InputSink>>,
+    scope: Arc<RelayScope>,
+    /// Shared secret clients must authenticate with, if any
+    auth_secret: Option<String>,
+    metrics: Arc<ServerMetrics>,
 }
 
 impl InputServer {
-    pub fn new() -> Result<Self> {
-        let config = ServerConfig::load()?;
-        let simulator = Arc::new(Mutex::new(InputSimulator::new()?));
+    pub async fn new(config: ServerConfig) -> Result<Self> {
+        let simulation = &config.simulation;
+        let simulator = retry_with_backoff(
+            simulation.init_attempts,
+            Duration::from_millis(simulation.init_retry_delay_ms),
+            || InputSimulator::new(simulation.clone()),
+        )
+        .await?;
+        Ok(Self::with_sink(config, simulator))
+    }
+
+    /// Create a server that hands decoded input to `sink` instead of the system
+    pub fn with_sink(config: ServerConfig, sink: impl InputSink + 'static) -> Self {
+        let simulator: Arc<Mutex<dyn InputSink>> = Arc::new(Mutex::new(sink));
+        let scope = Arc::new(RelayScope::new(
+            &config.scope,
+            Box::new(SystemFocusProvider),
+        ));
+
+        if !scope.is_unrestricted() {
+            info!("Relay scope limited to {:?}", config.scope.allowed_apps);
+        }
+
+        if config.read_buffer_size != config.read_buffer_size() {
+            warn!(
+                "read_buffer_size {} is out of range, using {}",
+                config.read_buffer_size,
+                config.read_buffer_size()
+            );
+        }
+
+        let auth_secret = config.auth.secret();
+        if auth_secret.is_none() {
+            warn!("No shared secret configured, any client that can connect may inject input");
+        }
 
-        Ok(Self { config, simulator })
+        Self {
+            config,
+            simulator,
+            scope,
+            auth_secret,
+            metrics: Arc::new(ServerMetrics::default()),
+        }
     }
 
-    /// Start the TCP server to listen for input events
+    /// Start the server to listen for input events, over UDP as well as TCP
+    /// when the udp transport is configured
     pub async fn start(&self) -> Result<()> {
-        let bind_address = format!("{}:{}", self.config.network.host, self.config.network.port);
-        info!("Starting input server on {}", bind_address);
+        let network = &self.config.network;
+        let addresses = resolve_listen_addresses(&network.host, network.port).await?;
+        let allowlist = ClientAllowlist::parse(&self.config.allowed_clients)?;
+        if !allowlist.is_unrestricted() {
+            info!(
+                "Only accepting clients from {:?}",
+                self.config.allowed_clients
+            );
+        }
+        info!(
+            "Starting input server on {}",
+            join_host_port(&network.host, network.port)
+        );
 
-        let listener = TcpListener::bind(&bind_address).await?;
-        info!("Server listening on {}", bind_address);
+        if self.config.metrics.enabled {
+            let metrics_address = self.config.metrics.bind_address.clone();
+            let metrics = Arc::clone(&self.metrics);
+            tokio::spawn(async move {
+                if let Err(e) = serve_metrics(&metrics_address, metrics).await {
+                    error!("Metrics endpoint stopped: {}", e);
+                }
+            });
+        }
 
-        loop {
-            match listener.accept().await {
+        let serve = async {
+            match self.config.network.transport {
+                TransportKind::Tcp => self.serve_tcp(&addresses, &allowlist).await,
+                TransportKind::Udp => {
+                    if self.config.network.tls || self.auth_secret.is_some() {
+                        bail!(
+                            "TLS and authentication are not supported over UDP, use the tcp transport"
+                        );
+                    }
+
+                    tokio::try_join!(
+                        self.serve_tcp(&addresses, &allowlist),
+                        self.serve_udp(&addresses, &allowlist)
+                    )?;
+                    Ok(())
+                }
+            }
+        };
+
+        tokio::select! {
+            result = serve => result,
+            _ = crate::daemon::shutdown_signal() => {
+                info!("Received shutdown signal, stopping server");
+
+                // Don't leave anything held down on the machine after exiting
+                if let Err(e) = self.simulator.lock().await.release_all() {
+                    error!("Failed to release held input: {}", e);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Accept TCP clients on every address, each handled on its own task
+    async fn serve_tcp(&self, addresses: &[SocketAddr], allowlist: &ClientAllowlist) -> Result<()> {
+        let acceptor = if self.config.network.tls {
+            Some(tls_acceptor(&self.config.tls)?)
+        } else {
+            None
+        };
+
+        let (accepted_sender, mut accepted) = mpsc::channel(16);
+        for address in addresses {
+            let listener = bind_with_retry(*address, &self.config.bind_retry, bind_tcp).await?;
+            info!(
+                "Server listening on {}{}",
+                address,
+                if acceptor.is_some() { " (TLS)" } else { "" }
+            );
+
+            let accepted_sender = accepted_sender.clone();
+            tokio::spawn(async move {
+                loop {
+                    if accepted_sender.send(listener.accept().await).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(accepted_sender);
+
+        while let Some(result) = accepted.recv().await {
+            match result {
                 Ok((stream, addr)) => {
+                    if !allowlist.allows(addr.ip()) {
+                        warn!("Rejected connection from {}, not an allowed client", addr);
+                        drop(stream);
+                        continue;
+                    }
+
                     info!("New client connected from {}", addr);
                     let simulator = Arc::clone(&self.simulator);
+                    let session = self.new_session();
+
+                    let acceptor = acceptor.clone();
+                    let auth_secret = self.auth_secret.clone();
+                    let metrics = Arc::clone(&self.metrics);
 
                     // Spawn a task to handle each client connection
                     tokio::spawn(async move {
-                        if let Err(e) = Self::handle_client(stream, simulator).await {
+                        let result = match acceptor {
+                            Some(acceptor) => match acceptor.accept(stream).await {
+                                Ok(stream) => {
+                                    Self::handle_client(
+                                        stream,
+                                        addr,
+                                        auth_secret,
+                                        simulator,
+                                        session,
+                                    )
+                                    .await
+                                }
+                                Err(e) => Err(anyhow!("TLS handshake failed: {}", e)),
+                            },
+                            None => {
+                                Self::handle_client(stream, addr, auth_secret, simulator, session)
+                                    .await
+                            }
+                        };
+                        if let Err(e) = result {
                             error!("Error handling client {}: {}", addr, e);
+                            metrics.record_error();
                         }
                         info!("Client {} disconnected", addr);
                     });
@@ -54,17 +243,279 @@ impl InputServer {
                 }
             }
         }
+
+        Ok(())
+    }
+
+    /// Receive one packet per datagram on every address, keeping a session per
+    /// peer address
+    async fn serve_udp(&self, addresses: &[SocketAddr], allowlist: &ClientAllowlist) -> Result<()> {
+        let (received_sender, mut received) = mpsc::channel(256);
+        for address in addresses {
+            let socket =
+                Arc::new(bind_with_retry(*address, &self.config.bind_retry, bind_udp).await?);
+            info!("Server listening on {} (UDP)", address);
+
+            let received_sender = received_sender.clone();
+            tokio::spawn(async move {
+                let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+                loop {
+                    let (len, peer) = match socket.recv_from(&mut buffer).await {
+                        Ok(received) => received,
+                        Err(e) => {
+                            error!("Failed to receive datagram: {}", e);
+                            continue;
+                        }
+                    };
+
+                    let datagram = buffer[..len].to_vec();
+                    if received_sender
+                        .send((datagram, peer, Arc::clone(&socket)))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(received_sender);
+
+        let mut sessions: HashMap<SocketAddr, ClientSession> = HashMap::new();
+        // Peers already told off, so each rejection is only logged once
+        let mut rejected: HashSet<SocketAddr> = HashSet::new();
+
+        let wire_format = self.config.network.wire_format;
+        loop {
+            let motion_due = sessions
+                .values()
+                .filter_map(|session| session.next_due())
+                .min();
+            let expiry_due = sessions
+                .values()
+                .filter_map(|session| session.idle_deadline())
+                .min();
+            let (mut datagram, peer, socket) = tokio::select! {
+                received = received.recv() => match received {
+                    Some(received) => received,
+                    None => break,
+                },
+                _ = tokio::time::sleep_until(motion_due.unwrap_or_else(Instant::now)), if motion_due.is_some() => {
+                    for session in sessions.values_mut() {
+                        Self::release_motion(&self.simulator, session).await;
+                    }
+                    continue;
+                }
+                // Datagram clients that vanish never say so, their sessions are dropped once idle
+                _ = tokio::time::sleep_until(expiry_due.unwrap_or_else(Instant::now)), if expiry_due.is_some() => {
+                    let now = Instant::now();
+                    let expired: Vec<SocketAddr> = sessions
+                        .iter()
+                        .filter(|(_, session)| session.idle_deadline().is_some_and(|due| due <= now))
+                        .map(|(peer, _)| *peer)
+                        .collect();
+                    for peer in expired {
+                        if let Some(mut session) = sessions.remove(&peer) {
+                            warn!(
+                                "UDP client {} sent nothing for {:?}, dropping its session",
+                                peer, session.idle_timeout
+                            );
+                            Self::release_pressed(&self.simulator, &mut session).await;
+                            session.log_latency();
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            if !allowlist.allows(peer.ip()) {
+                if rejected.insert(peer) {
+                    warn!("Ignoring datagrams from {}, not an allowed client", peer);
+                }
+                continue;
+            }
+
+            let packet =
+                match Self::try_deserialize_packet(&mut datagram, wire_format, &self.metrics) {
+                    Ok(Some(packet)) => packet,
+                    Ok(None) => {
+                        warn!("Dropping truncated datagram from {}", peer);
+                        continue;
+                    }
+                    Err(e) if e.is::<FrameTooLarge>() => {
+                        error!("Dropping oversized datagram from {}: {}", peer, e);
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("Dropping malformed datagram from {}: {}", peer, e);
+                        continue;
+                    }
+                };
+
+            let session = sessions.entry(peer).or_insert_with(|| {
+                info!("New UDP client {}", peer);
+                self.new_session()
+            });
+            session.last_activity = Instant::now();
+            session.track_sequence(packet.seq);
+
+            // Failing to answer one peer must not take the listener down for all of them
+            if let Message::Ping { token } = packet.message {
+                let pong = Self::pong(token);
+                if let Err(e) = Self::send_datagram(&socket, peer, &pong, wire_format).await {
+                    warn!("Failed to answer link check from {}: {}", peer, e);
+                }
+                continue;
+            }
+
+            if let Message::Disconnect { reason } = &packet.message {
+                info!("UDP client {} disconnected: {}", peer, reason);
+                if let Some(mut session) = sessions.remove(&peer) {
+                    Self::release_pressed(&self.simulator, &mut session).await;
+                    session.log_latency();
+                }
+                continue;
+            }
+
+            let handshake = matches!(packet.message, Message::Handshake(_));
+            if let Err(e) = Self::process_packet(packet, &self.simulator, session).await {
+                error!("Error handling datagram from {}: {}", peer, e);
+                self.metrics.record_error();
+            } else if handshake {
+                let reply = Self::handshake_reply(&self.simulator, session).await;
+                if let Err(e) = Self::send_datagram(&socket, peer, &reply, wire_format).await {
+                    warn!("Failed to answer handshake from {}: {}", peer, e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encode `packet` and send it to `peer` in one datagram
+    async fn send_datagram(
+        socket: &UdpSocket,
+        peer: SocketAddr,
+        packet: &Packet,
+        wire_format: WireFormat,
+    ) -> Result<()> {
+        let frame = encode_frame(packet, wire_format)?;
+        socket.send_to(&frame, peer).await?;
+        Ok(())
+    }
+
+    /// Serve a single client over an already established stream
+    pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+        peer: SocketAddr,
+    ) -> Result<()> {
+        Self::handle_client(
+            stream,
+            peer,
+            self.auth_secret.clone(),
+            Arc::clone(&self.simulator),
+            self.new_session(),
+        )
+        .await
+    }
+
+    fn new_session(&self) -> ClientSession {
+        ClientSession::new(
+            &self.config,
+            Arc::clone(&self.scope),
+            self.metrics.open_connection(),
+        )
     }
 
     /// Handle a single client connection
-    async fn handle_client(
-        mut stream: TcpStream,
-        simulator: Arc<Mutex<InputSimulator>>,
+    async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
+        mut stream: S,
+        peer: SocketAddr,
+        auth_secret: Option<String>,
+        simulator: Arc<Mutex<dyn InputSink>>,
+        mut session: ClientSession,
     ) -> Result<()> {
-        let mut buffer = vec![0u8; 4096];
+        let mut buffer = vec![0u8; session.read_buffer_size];
         let mut packet_buffer = Vec::new();
+        let idle_timeout = session.idle_timeout;
+        let mut idle_deadline = Instant::now() + idle_timeout;
+        let mut clipboard_due = Instant::now();
 
-        loop {
+        // Nothing from the client is processed until it has authenticated
+        if let Some(secret) = &auth_secret {
+            if let Err(e) =
+                Self::authenticate(&mut stream, &mut packet_buffer, secret, session.wire_format)
+                    .await
+            {
+                warn!("Rejected client {}: {}", peer, e);
+                return Ok(());
+            }
+            info!("Client {} authenticated", peer);
+        }
+
+        'session: loop {
+            // Deserialize every complete packet in the buffer, including any
+            // that arrived together with the authentication response
+            loop {
+                match Self::try_deserialize_packet(
+                    &mut packet_buffer,
+                    session.wire_format,
+                    session.connection.metrics(),
+                ) {
+                    Ok(Some(packet)) => {
+                        session.track_sequence(packet.seq);
+                        match packet.message {
+                            Message::Ping { token } => {
+                                let pong = Self::pong(token);
+                                stream
+                                    .write_all(&encode_frame(&pong, session.wire_format)?)
+                                    .await?;
+                                stream.flush().await?;
+                            }
+                            Message::Disconnect { reason } => {
+                                info!("Client {} disconnected: {}", peer, reason);
+                                break 'session;
+                            }
+                            Message::Handshake(_) => {
+                                Self::process_packet(packet, &simulator, &mut session).await?;
+                                let reply = Self::handshake_reply(&simulator, &session).await;
+                                stream
+                                    .write_all(&encode_frame(&reply, session.wire_format)?)
+                                    .await?;
+                                stream.flush().await?;
+                            }
+                            _ => Self::process_packet(packet, &simulator, &mut session).await?,
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) if e.is::<FrameTooLarge>() => {
+                        // The rest of the stream can't be framed anymore
+                        error!("Closing connection to {}: {}", peer, e);
+                        session.connection.metrics().record_error();
+                        break 'session;
+                    }
+                    Err(e) if e.is::<WireFormatMismatch>() => {
+                        error!("Closing connection to {}: {}", peer, e);
+                        session.connection.metrics().record_error();
+
+                        // Tell the client why, in the format it understands
+                        if let Some(mismatch) = e.downcast_ref::<WireFormatMismatch>() {
+                            let reason =
+                                format!("server uses the {} wire format", mismatch.configured);
+                            let disconnect = Packet::new(Message::Disconnect { reason });
+                            stream
+                                .write_all(&encode_frame(&disconnect, mismatch.received)?)
+                                .await?;
+                            stream.flush().await?;
+                        }
+                        break 'session;
+                    }
+                    Err(e) => warn!("Dropping malformed packet: {}", e),
+                }
+            }
+
+            let motion_due = session.next_due();
             tokio::select! {
                 // Read data from client
                 result = stream.read(&mut buffer) => {
@@ -74,12 +525,8 @@ impl InputServer {
                             break;
                         }
                         Ok(n) => {
+                            idle_deadline = Instant::now() + idle_timeout;
                             packet_buffer.extend_from_slice(&buffer[..n]);
-
-                            // Try to deserialize complete packets
-                            while let Some(packet) = Self::try_deserialize_packet(&mut packet_buffer)? {
-                                Self::process_packet(packet, &simulator).await?;
-                            }
                         }
                         Err(e) => {
                             error!("Error reading from client: {}", e);
@@ -88,6 +535,33 @@ impl InputServer {
                     }
                 }
 
+                _ = tokio::time::sleep_until(motion_due.unwrap_or(idle_deadline)), if motion_due.is_some() => {
+                    Self::release_motion(&simulator, &mut session).await;
+                }
+
+                // Send clipboard changes made on this side to the client
+                _ = tokio::time::sleep_until(clipboard_due), if session.watch_clipboard => {
+                    clipboard_due = Instant::now() + session.clipboard_poll_interval;
+                    let packets = Self::poll_clipboard(&simulator, &mut session).await;
+                    for packet in &packets {
+                        stream
+                            .write_all(&encode_frame(packet, session.wire_format)?)
+                            .await?;
+                    }
+                    if !packets.is_empty() {
+                        stream.flush().await?;
+                    }
+                }
+
+                // A vanished client never closes the connection, so stop waiting on it
+                _ = tokio::time::sleep_until(idle_deadline), if !idle_timeout.is_zero() => {
+                    warn!(
+                        "Client {} sent nothing for {:?}, closing the connection",
+                        peer, idle_timeout
+                    );
+                    break;
+                }
+
                 // Handle graceful shutdown
                 _ = tokio::signal::ctrl_c() => {
                     info!("Received shutdown signal");
@@ -96,48 +570,153 @@ impl InputServer {
             }
         }
 
+        Self::release_pressed(&simulator, &mut session).await;
+        session.log_latency();
+
         Ok(())
     }
 
-    /// Try to deserialize a complete packet from the buffer
-    fn try_deserialize_packet(buffer: &mut Vec<u8>) -> Result<Option<Packet>> {
-        if buffer.is_empty() {
-            return Ok(None);
-        }
+    /// Challenge the client to prove it knows the shared secret
+    async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        packet_buffer: &mut Vec<u8>,
+        secret: &str,
+        wire_format: WireFormat,
+    ) -> Result<()> {
+        let nonce = generate_nonce()?;
+        let challenge = Packet::new(Message::AuthChallenge(AuthChallenge {
+            nonce: nonce.clone(),
+        }));
+        stream
+            .write_all(&encode_frame(&challenge, wire_format)?)
+            .await?;
+        stream.flush().await?;
 
-        // Try to deserialize the packet
-        match bincode::serde::decode_from_slice(buffer, bincode::config::standard()) {
-            Ok((packet, _)) => {
-                // If successful, clear the buffer and return the packet
-                buffer.clear();
-                Ok(Some(packet))
-            }
-            Err(e) => {
-                // If deserialization fails, it might be incomplete data
-                // For now, we'll just log and clear the buffer
-                // In a production system, you'd want more sophisticated packet framing
-                debug!("Failed to deserialize packet: {}", e);
-                buffer.clear();
-                Ok(None)
+        let response =
+            tokio::time::timeout(AUTH_TIMEOUT, read_frame(stream, packet_buffer, wire_format))
+                .await
+                .map_err(|_| anyhow!("timed out waiting for authentication"))??;
+
+        match response.map(|packet| packet.message) {
+            Some(Message::AuthResponse(response)) => {
+                if !verify_nonce(secret, &nonce, &response.mac) {
+                    bail!("invalid authentication response");
+                }
+                Ok(())
             }
+            Some(_) => bail!("sent a packet before authenticating"),
+            None => bail!("disconnected during authentication"),
         }
     }
 
+    /// Try to deserialize a complete packet from the buffer.
+    ///
+    /// Frames corrupted on the way fail their checksum and are counted in
+    /// `metrics` rather than decoded into input.
+    fn try_deserialize_packet(
+        buffer: &mut Vec<u8>,
+        wire_format: WireFormat,
+        metrics: &ServerMetrics,
+    ) -> Result<Option<Packet>> {
+        decode_frame(buffer, wire_format).map_err(|e| {
+            if !e.is::<ChecksumMismatch>() {
+                return e;
+            }
+            let count = metrics.record_corrupt_packet();
+            e.context(format!("corrupt packet, {} dropped so far", count))
+        })
+    }
+
     /// Process a received packet
-    async fn process_packet(packet: Packet, simulator: &Arc<Mutex<InputSimulator>>) -> Result<()> {
+    #[instrument(level = "debug", skip_all, fields(packet_id = %packet.id, kind = packet.message.kind()))]
+    async fn process_packet(
+        packet: Packet,
+        simulator: &Arc<Mutex<dyn InputSink>>,
+        session: &mut ClientSession,
+    ) -> Result<()> {
         debug!("Processing packet: {}", packet.id);
+        session.connection.metrics().record_packet();
+
+        if matches!(
+            packet.message,
+            Message::InputEvent(_) | Message::InputEventTyped(_) | Message::Batch(_)
+        ) && let Some(histogram) = session.latency.record(packet.timestamp)
+        {
+            info!("Input latency: {}", histogram);
+        }
 
         match packet.message {
             Message::InputEvent(event) => {
-                let mut sim = simulator.lock().await;
-                if let Err(e) = sim.simulate_input(&event) {
-                    error!("Failed to simulate input event: {}", e);
+                if let Err(app) = session.scope.check_focus() {
+                    debug!("Dropping raw input event while {} has focus", app);
+                    return Ok(());
+                }
+                if let Err(rejected) = validate::check_raw(&event) {
+                    session.reject(rejected);
+                    return Ok(());
+                }
+
+                let is_release = event.event_type == raw_event_types::EV_KEY && event.value == 0;
+                if !is_release && !session.rate_limiter.allow() {
+                    return Ok(());
+                }
+
+                let mut sim = match raw_key_event(&event) {
+                    Some(key_event) => {
+                        // Raw presses are released on disconnect like typed ones
+                        session.track_pressed(&key_event);
+                        Self::lock_paced(simulator, &key_event).await
+                    }
+                    None => simulator.lock().await,
+                };
+                match sim.simulate_input(&event) {
+                    Ok(()) => session.connection.metrics().record_event(),
+                    Err(e) => {
+                        error!("Failed to simulate input event: {}", e);
+                        session.connection.metrics().record_error();
+                    }
                 }
             }
             Message::InputEventTyped(event) => {
+                Self::simulate_typed(event, packet.timestamp, simulator, session).await;
+            }
+            Message::Batch(events) => {
+                for event in events {
+                    Self::simulate_typed(event, packet.timestamp, simulator, session).await;
+                }
+            }
+            Message::Handshake(handshake) => session.handle_handshake(handshake),
+            Message::ClipboardChunk(chunk) => {
+                if let Some(payload) = session.handle_clipboard_chunk(chunk) {
+                    Self::apply_clipboard(&payload, simulator, session).await;
+                }
+            }
+            Message::AuthChallenge(_) | Message::AuthResponse(_) => {
+                warn!("Ignoring unexpected authentication message");
+            }
+            Message::Ping { .. } | Message::Pong { .. } => {
+                debug!("Ignoring unexpected link check message");
+            }
+            Message::Disconnect { .. } => {
+                debug!("Ignoring unexpected disconnect message");
+            }
+            // Only keeps the connection from timing out
+            Message::Heartbeat => {}
+            // Only this client's input is released, other sessions share the simulator
+            Message::ResetInputState => {
+                debug!("Client reset its input state, releasing what it holds");
+                Self::release_pressed(simulator, session).await;
+            }
+            Message::LockState(state) => {
+                if let Err(app) = session.scope.check_focus() {
+                    debug!("Not syncing lock state while {} has focus", app);
+                    return Ok(());
+                }
+
                 let mut sim = simulator.lock().await;
-                if let Err(e) = sim.simulate_typed_input(&event) {
-                    error!("Failed to simulate typed input event: {}", e);
+                if let Err(e) = sim.sync_lock_state(&state) {
+                    error!("Failed to sync lock state: {}", e);
+                    session.connection.metrics().record_error();
                 }
             }
         }
@@ -145,26 +724,272 @@ impl InputServer {
         Ok(())
     }
 
-    /// Send a ping response to test connectivity
-    pub async fn ping(&self, host: Option<String>) -> Result<()> {
-        let target_host = host.unwrap_or(self.config.network.host.clone());
-        let target_port = self.config.network.port;
-        let address = format!("{}:{}", target_host, target_port);
+    /// Put clipboard content received from the client on the clipboard
+    async fn apply_clipboard(
+        payload: &ClipboardPayload,
+        simulator: &Arc<Mutex<dyn InputSink>>,
+        session: &mut ClientSession,
+    ) {
+        let Some(text) = payload.as_text() else {
+            warn!(
+                "Ignoring {:?} clipboard content, only text can be set",
+                payload.format
+            );
+            return;
+        };
+
+        let mut sim = simulator.lock().await;
+        match sim.set_clipboard_text(text) {
+            Ok(()) => session.clipboard_applied(text),
+            Err(e) => {
+                error!("Failed to set clipboard: {}", e);
+                session.connection.metrics().record_error();
+            }
+        }
+    }
+
+    /// Read the clipboard, returning its content in chunks once a change has settled
+    async fn poll_clipboard(
+        simulator: &Arc<Mutex<dyn InputSink>>,
+        session: &mut ClientSession,
+    ) -> Vec<Packet> {
+        let read = simulator.lock().await.clipboard_text();
+        match read {
+            Ok(Some(text)) => session
+                .outgoing_clipboard(text)
+                .into_iter()
+                .map(|chunk| Packet::new(Message::ClipboardChunk(chunk)))
+                .collect(),
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                warn!(
+                    "Not syncing the clipboard to the client, it can't be read: {}",
+                    e
+                );
+                session.watch_clipboard = false;
+                Vec::new()
+            }
+        }
+    }
+
+    /// Simulate a typed event captured at `timestamp` if the relay scope lets
+    /// it through, buffering pointer motion if configured to
+    async fn simulate_typed(
+        event: InputEventType,
+        timestamp: u64,
+        simulator: &Arc<Mutex<dyn InputSink>>,
+        session: &mut ClientSession,
+    ) {
+        if let Err(rejected) = validate::check_typed(&event) {
+            session.reject(rejected);
+            return;
+        }
+        if !is_release(&event) && !session.rate_limiter.allow() {
+            return;
+        }
+
+        let events = session.scope_input(event);
+        if !events.is_empty() {
+            let mut sim = simulator.lock().await;
+            for event in events {
+                if session.motion.is_enabled() {
+                    if let InputEventType::MouseMove { x, y } = event {
+                        session.motion.push((x, y), timestamp, Instant::now());
+                        continue;
+                    }
+                    // Motion before a click or key has to land before it
+                    let moves = session.motion.drain();
+                    Self::simulate_moves(&mut *sim, session, moves);
+                }
+
+                if session.coalescer.is_enabled() {
+                    if EventCoalescer::coalesces(&event) {
+                        if let Some(due) = session.coalescer.push(event, Instant::now()) {
+                            Self::simulate_coalesced(&mut *sim, session, due);
+                        }
+                        continue;
+                    }
+                    // Same for summed motion, which is let out early rather
+                    // than holding the key back until its window closes
+                    if let Some(pending) = session.coalescer.drain() {
+                        Self::simulate_coalesced(&mut *sim, session, pending);
+                    }
+                }
+
+                if !sim.pacing_delay(&event).is_zero() {
+                    drop(sim);
+                    sim = Self::lock_paced(simulator, &event).await;
+                }
+
+                session.track_pressed(&event);
+                match sim.simulate_typed_input(&event) {
+                    Ok(()) => session.connection.metrics().record_event(),
+                    Err(e) => {
+                        error!("Failed to simulate typed input event: {}", e);
+                        session.connection.metrics().record_error();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Lock the simulator once `event` may be simulated, waiting out its key
+    /// pacing without holding the lock so other clients aren't held up
+    async fn lock_paced<'a>(
+        simulator: &'a Arc<Mutex<dyn InputSink>>,
+        event: &InputEventType,
+    ) -> MutexGuard<'a, dyn InputSink> {
+        loop {
+            let sim = simulator.lock().await;
+            let wait = sim.pacing_delay(event);
+            if wait.is_zero() {
+                return sim;
+            }
+            drop(sim);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Play out the buffered pointer motion and summed events that are due
+    async fn release_motion(simulator: &Arc<Mutex<dyn InputSink>>, session: &mut ClientSession) {
+        let now = Instant::now();
+        let moves = session.motion.take_due(now);
+        if !moves.is_empty() {
+            let mut sim = simulator.lock().await;
+            Self::simulate_moves(&mut *sim, session, moves);
+        }
+        if let Some(event) = session.coalescer.take_due(now) {
+            let mut sim = simulator.lock().await;
+            Self::simulate_coalesced(&mut *sim, session, event);
+        }
+    }
+
+    fn simulate_moves(sim: &mut dyn InputSink, session: &ClientSession, moves: Vec<(i32, i32)>) {
+        for (x, y) in moves {
+            match sim.simulate_typed_input(&InputEventType::MouseMove { x, y }) {
+                Ok(()) => session.connection.metrics().record_event(),
+                Err(e) => {
+                    error!("Failed to simulate buffered pointer motion: {}", e);
+                    session.connection.metrics().record_error();
+                }
+            }
+        }
+    }
+
+    fn simulate_coalesced(sim: &mut dyn InputSink, session: &ClientSession, event: InputEventType) {
+        match sim.simulate_typed_input(&event) {
+            Ok(()) => session.connection.metrics().record_event(),
+            Err(e) => {
+                error!("Failed to simulate summed pointer input: {}", e);
+                session.connection.metrics().record_error();
+            }
+        }
+    }
+
+    /// Release every key and button the client left pressed, so nothing
+    /// stays stuck down once it is gone
+    async fn release_pressed(simulator: &Arc<Mutex<dyn InputSink>>, session: &mut ClientSession) {
+        let moves = session.motion.drain();
+        if !moves.is_empty() {
+            let mut sim = simulator.lock().await;
+            Self::simulate_moves(&mut *sim, session, moves);
+        }
+        if let Some(event) = session.coalescer.drain() {
+            let mut sim = simulator.lock().await;
+            Self::simulate_coalesced(&mut *sim, session, event);
+        }
+
+        let releases = session.take_pressed();
+        if releases.is_empty() {
+            return;
+        }
+
+        debug!("Releasing {} inputs left pressed", releases.len());
+        for event in releases {
+            let mut sim = Self::lock_paced(simulator, &event).await;
+            if let Err(e) = sim.simulate_typed_input(&event) {
+                error!("Failed to release input: {}", e);
+            }
+        }
+    }
+
+    /// Answer to a client's handshake, announcing the server's own capabilities
+    /// and the screen absolute coordinates are scaled to
+    async fn handshake_reply(
+        simulator: &Arc<Mutex<dyn InputSink>>,
+        session: &ClientSession,
+    ) -> Packet {
+        let screen = simulator.lock().await.screen_size();
+        let screen = screen.unwrap_or_else(|e| {
+            let fallback = session.fallback_screen_size;
+            warn!(
+                "Could not query the screen size, reporting {}x{} to the client: {}",
+                fallback.width, fallback.height, e
+            );
+            fallback
+        });
+        debug!(
+            "Reporting screen {}x{} to the client",
+            screen.width, screen.height
+        );
+
+        Packet::new(Message::Handshake(Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            clipboard_formats: session.clipboard_config.formats.clone(),
+            max_clipboard_size: session.clipboard_config.max_payload_size,
+            screen: Some(screen.into()),
+        }))
+    }
+
+    /// Answer to a link check ping
+    fn pong(token: u64) -> Packet {
+        Packet::new(Message::Pong {
+            token,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        })
+    }
+
+    /// Ping a server and wait for its pong to test connectivity
+    pub async fn ping(config: &ServerConfig, host: Option<String>) -> Result<()> {
+        let target_host = host.unwrap_or(config.network.host.clone());
+        let address = join_host_port(&target_host, config.network.port);
 
+        let wire_format = config.network.wire_format;
         info!("Attempting to connect to {}", address);
 
         match TcpStream::connect(&address).await {
             Ok(mut stream) => {
                 info!("Successfully connected to {}", address);
 
-                // Send a simple ping packet
-                let ping_packet = Packet::input_event("PING".to_string(), 0, 0);
-                let serialized =
-                    bincode::serde::encode_to_vec(&ping_packet, bincode::config::standard())?;
-                stream.write_all(&serialized).await?;
+                let token = rand::random::<u64>();
+                let ping_packet = Packet::new(Message::Ping { token });
+                stream
+                    .write_all(&encode_frame(&ping_packet, wire_format)?)
+                    .await?;
 
-                info!("Ping sent successfully");
-                Ok(())
+                let mut buffer = Vec::new();
+                // Anything other than a well formed pong means this isn't an Asteria server
+                let reply = tokio::time::timeout(
+                    PING_TIMEOUT,
+                    read_frame(&mut stream, &mut buffer, wire_format),
+                )
+                .await
+                .map_err(|_| anyhow!("Timed out waiting for a pong from {}", address))?
+                .map_err(|e| anyhow!("{} sent an invalid reply: {}", address, e))?;
+
+                match reply.map(|packet| packet.message) {
+                    Some(Message::Pong {
+                        token: answered,
+                        version,
+                    }) if answered == token => {
+                        info!("Ping answered by {} (asteria-server {})", address, version);
+                        Ok(())
+                    }
+                    Some(Message::AuthChallenge(_)) => {
+                        bail!("{} requires authentication before answering pings", address)
+                    }
+                    _ => bail!("{} did not answer the ping", address),
+                }
             }
             Err(e) => {
                 error!("Failed to connect to {}: {}", address, e);
@@ -174,8 +999,825 @@ impl InputServer {
     }
 }
 
-impl Default for InputServer {
-    fn default() -> Self {
-        Self::new().expect("Failed to create input server")
+/// Whether an event lets go of something, which is never rate limited so
+/// nothing stays stuck down
+fn is_release(event: &InputEventType) -> bool {
+    matches!(
+        event,
+        InputEventType::KeyRelease { .. }
+            | InputEventType::MouseButton { pressed: false, .. }
+            | InputEventType::GamepadButton { pressed: false, .. }
+    )
+}
+
+/// Whether `release` lets go of what `event` presses or repeats
+fn is_released_by(event: &InputEventType, release: &InputEventType) -> bool {
+    match (event, release) {
+        (
+            InputEventType::KeyPress { key_code } | InputEventType::KeyRepeat { key_code },
+            InputEventType::KeyRelease { key_code: released },
+        ) => key_code == released,
+        (
+            InputEventType::MouseButton {
+                button,
+                pressed: true,
+            },
+            InputEventType::MouseButton {
+                button: released,
+                pressed: false,
+            },
+        ) => button == released,
+        (
+            InputEventType::GamepadButton {
+                button,
+                pressed: true,
+            },
+            InputEventType::GamepadButton {
+                button: released,
+                pressed: false,
+            },
+        ) => button == released,
+        _ => false,
+    }
+}
+
+/// The key event a raw `EV_KEY` event stands for, used to pace it like a typed one
+fn raw_key_event(event: &InputEvent) -> Option<InputEventType> {
+    if event.event_type != raw_event_types::EV_KEY {
+        return None;
+    }
+
+    let key_code = event.code;
+    match event.value {
+        0 => Some(InputEventType::KeyRelease { key_code }),
+        1 => Some(InputEventType::KeyPress { key_code }),
+        2 => Some(InputEventType::KeyRepeat { key_code }),
+        _ => None,
+    }
+}
+
+/// Run `create` until it succeeds, sleeping with a doubling delay between attempts
+async fn retry_with_backoff<T>(
+    attempts: u32,
+    initial_delay: Duration,
+    mut create: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let attempts = attempts.max(1);
+    let mut delay = initial_delay;
+
+    for attempt in 1..=attempts {
+        match create() {
+            Ok(value) => {
+                if attempt > 1 {
+                    info!("Input simulator ready after {} attempts", attempt);
+                }
+                return Ok(value);
+            }
+            Err(e) if attempt < attempts => {
+                warn!(
+                    "Failed to create input simulator (attempt {}/{}): {}, retrying in {:?}",
+                    attempt, attempts, e, delay
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => {
+                error!(
+                    "Failed to create input simulator (attempt {}/{}): {}",
+                    attempt, attempts, e
+                );
+                return Err(e);
+            }
+        }
+    }
+
+    unreachable!("at least one attempt is always made")
+}
+
+/// Per-connection state negotiated with a client
+struct ClientSession {
+    clipboard_config: ClipboardConfig,
+    clipboard_formats: Vec<ClipboardFormat>,
+    clipboard: ClipboardAssembler,
+    /// Holds back clipboard changes on this side until they settle
+    clipboard_debouncer: ClipboardDebouncer,
+    /// Whether the clipboard is checked for changes to send, once text was agreed on over TCP
+    watch_clipboard: bool,
+    /// Whether the clipboard content found when watching started has been recorded
+    clipboard_seeded: bool,
+    /// How often the clipboard is checked for changes
+    clipboard_poll_interval: Duration,
+    /// Id of the next clipboard transfer sent to the client
+    next_transfer_id: u32,
+    scope: Arc<RelayScope>,
+    blocked_action: BlockedInputAction,
+    /// Events held back while an app outside the relay scope has focus
+    held: VecDeque<InputEventType>,
+    /// App currently blocked by the relay scope, used to log transitions once
+    blocked_app: Option<String>,
+    /// Sequence number of the last packet received on this connection
+    last_seq: Option<u64>,
+    /// Keys currently held down by this client
+    pressed_keys: HashSet<u16>,
+    /// Mouse buttons currently held down by this client
+    pressed_buttons: HashSet<u8>,
+    /// Gamepad buttons currently held down by this client
+    pressed_gamepad_buttons: HashSet<u16>,
+    /// Delay between the client capturing input and the server receiving it
+    latency: LatencyStats,
+    /// How long the connection may go without any packet, zero to wait forever
+    idle_timeout: Duration,
+    /// When the last packet arrived, for expiring datagram sessions
+    last_activity: Instant,
+    /// Counts the session as an active connection and gives access to the server's metrics
+    connection: ConnectionGuard,
+    /// Encoding the client's packets are expected in
+    wire_format: WireFormat,
+    /// Caps how many of the client's events are simulated per second
+    rate_limiter: RateLimiter,
+    /// Pointer motion waiting to be played out
+    motion: MotionBuffer,
+    /// Pointer motion or scrolling being summed into one event
+    coalescer: EventCoalescer,
+    /// Bytes read from the connection at once
+    read_buffer_size: usize,
+    /// Screen reported to the client when the real one can't be queried
+    fallback_screen_size: ScreenSize,
+}
+
+impl ClientSession {
+    fn new(config: &ServerConfig, scope: Arc<RelayScope>, connection: ConnectionGuard) -> Self {
+        Self {
+            clipboard_config: config.clipboard.clone(),
+            clipboard_formats: Vec::new(),
+            clipboard: ClipboardAssembler::new(config.clipboard.max_payload_size),
+            clipboard_debouncer: ClipboardDebouncer::new(Duration::from_millis(
+                config.clipboard.sync_debounce_ms,
+            )),
+            watch_clipboard: false,
+            clipboard_seeded: false,
+            clipboard_poll_interval: Duration::from_millis(config.clipboard.sync_poll_interval_ms),
+            next_transfer_id: 0,
+            scope,
+            blocked_action: config.scope.blocked_action,
+            held: VecDeque::new(),
+            blocked_app: None,
+            last_seq: None,
+            pressed_keys: HashSet::new(),
+            pressed_buttons: HashSet::new(),
+            pressed_gamepad_buttons: HashSet::new(),
+            latency: LatencyStats::new(Duration::from_secs(
+                config.simulation.latency_log_interval_secs,
+            )),
+            idle_timeout: Duration::from_secs(config.heartbeat.timeout_secs),
+            last_activity: Instant::now(),
+            connection,
+            wire_format: config.network.wire_format,
+            rate_limiter: RateLimiter::new(&config.rate_limit),
+            motion: MotionBuffer::new(Duration::from_millis(config.simulation.motion_buffer_ms)),
+            coalescer: EventCoalescer::new(Duration::from_millis(
+                config.simulation.coalesce_window_ms,
+            )),
+            read_buffer_size: config.read_buffer_size(),
+            fallback_screen_size: config.simulation.fallback_screen_size,
+        }
+    }
+
+    /// When the session expires unless another packet arrives, `None` if it never does
+    fn idle_deadline(&self) -> Option<Instant> {
+        (!self.idle_timeout.is_zero()).then(|| self.last_activity + self.idle_timeout)
+    }
+
+    /// When the next buffered move or summed event is due, if any is waiting
+    fn next_due(&self) -> Option<Instant> {
+        [self.motion.next_release(), self.coalescer.next_flush()]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Drop an event the server won't simulate, with a warning that keeps count
+    fn reject(&self, rejected: RejectedInput) {
+        let count = self.connection.metrics().record_rejected_event();
+        warn!(
+            "Dropping input with an {}, {} rejected so far",
+            rejected, count
+        );
+    }
+
+    /// Log the latency measured over the whole connection
+    fn log_latency(&self) {
+        let histogram = self.latency.histogram();
+        if !histogram.is_empty() {
+            info!("Input latency over the session: {}", histogram);
+        }
+    }
+
+    /// Keep track of which keys and buttons a simulated event leaves held down
+    fn track_pressed(&mut self, event: &InputEventType) {
+        match *event {
+            InputEventType::KeyPress { key_code } => {
+                self.pressed_keys.insert(key_code);
+            }
+            InputEventType::KeyRelease { key_code } => {
+                self.pressed_keys.remove(&key_code);
+            }
+            InputEventType::MouseButton { button, pressed } => {
+                if pressed {
+                    self.pressed_buttons.insert(button);
+                } else {
+                    self.pressed_buttons.remove(&button);
+                }
+            }
+            InputEventType::GamepadButton { button, pressed } => {
+                if pressed {
+                    self.pressed_gamepad_buttons.insert(button);
+                } else {
+                    self.pressed_gamepad_buttons.remove(&button);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Release events for everything still held down, forgetting it
+    fn take_pressed(&mut self) -> Vec<InputEventType> {
+        let keys = self
+            .pressed_keys
+            .drain()
+            .map(|key_code| InputEventType::KeyRelease { key_code });
+        let buttons = self
+            .pressed_buttons
+            .drain()
+            .map(|button| InputEventType::MouseButton {
+                button,
+                pressed: false,
+            });
+        let gamepad_buttons =
+            self.pressed_gamepad_buttons
+                .drain()
+                .map(|button| InputEventType::GamepadButton {
+                    button,
+                    pressed: false,
+                });
+        keys.chain(buttons).chain(gamepad_buttons).collect()
+    }
+
+    /// Record a packet's sequence number, warning about gaps and regressions
+    fn track_sequence(&mut self, seq: u64) {
+        if let Some(last) = self.last_seq {
+            if seq <= last {
+                warn!("Packet sequence went backwards from {} to {}", last, seq);
+            } else if seq > last + 1 {
+                warn!(
+                    "Packet sequence skipped from {} to {}, {} packets missing",
+                    last,
+                    seq,
+                    seq - last - 1
+                );
+            }
+        }
+
+        self.last_seq = Some(seq);
+    }
+
+    /// Apply the relay scope, returning the events that may be simulated now
+    fn scope_input(&mut self, event: InputEventType) -> Vec<InputEventType> {
+        match self.scope.check_focus() {
+            Ok(()) => {
+                if let Some(app) = self.blocked_app.take() {
+                    info!("{} lost focus, resuming input", app);
+                }
+
+                let mut events: Vec<InputEventType> = self.held.drain(..).collect();
+                events.push(event);
+                events
+            }
+            Err(_) if is_release(&event) => {
+                // A press held back is dropped along with its release, or it would be
+                // replayed with nothing letting go of it. Other releases always pass,
+                // so input pressed in an allowed app never gets stuck.
+                let press_held = self.held.iter().any(|held| {
+                    !matches!(held, InputEventType::KeyRepeat { .. })
+                        && is_released_by(held, &event)
+                });
+                self.held.retain(|held| !is_released_by(held, &event));
+                if press_held { Vec::new() } else { vec![event] }
+            }
+            Err(app) => {
+                if self.blocked_app.as_deref() != Some(app.as_str()) {
+                    warn!("Blocked input for {}, it is outside the relay scope", app);
+                    self.blocked_app = Some(app);
+                }
+
+                if self.blocked_action == BlockedInputAction::Hold {
+                    if self.held.len() >= MAX_HELD_EVENTS {
+                        self.held.pop_front();
+                    }
+                    self.held.push_back(event);
+                }
+
+                Vec::new()
+            }
+        }
+    }
+
+    /// Agree on clipboard formats and the smaller of both size limits
+    fn handle_handshake(&mut self, handshake: Handshake) {
+        if handshake.protocol_version != PROTOCOL_VERSION {
+            warn!(
+                "Client speaks protocol version {}, server speaks {}",
+                handshake.protocol_version, PROTOCOL_VERSION
+            );
+        }
+
+        self.clipboard_formats =
+            negotiate_formats(&self.clipboard_config.formats, &handshake.clipboard_formats);
+        self.clipboard.set_max_size(
+            self.clipboard_config
+                .max_payload_size
+                .min(handshake.max_clipboard_size),
+        );
+
+        // Only text can be read from the clipboard
+        self.watch_clipboard = self.clipboard_formats.contains(&ClipboardFormat::Text)
+            && !self.clipboard_poll_interval.is_zero();
+
+        info!(
+            "Handshake complete, clipboard formats: {:?}",
+            self.clipboard_formats
+        );
+    }
+
+    /// Clipboard text read on this side, in chunks for the client once it has settled
+    fn outgoing_clipboard(&mut self, text: String) -> Vec<ClipboardChunk> {
+        // Whatever was on the clipboard before the client connected stays here
+        if !self.clipboard_seeded {
+            self.clipboard_applied(&text);
+            return Vec::new();
+        }
+
+        let Some(text) = self
+            .clipboard_debouncer
+            .observe(text, std::time::Instant::now())
+        else {
+            return Vec::new();
+        };
+
+        match chunk_payload(
+            self.next_transfer_id,
+            &ClipboardPayload::text(text),
+            self.clipboard.max_size(),
+        ) {
+            Ok(chunks) => {
+                self.next_transfer_id = self.next_transfer_id.wrapping_add(1);
+                debug!("Sending clipboard update in {} chunks", chunks.len());
+                chunks
+            }
+            Err(e) => {
+                warn!("Not syncing clipboard content: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Record clipboard text as synced, so it isn't sent back to the client
+    fn clipboard_applied(&mut self, text: &str) {
+        self.clipboard_seeded = true;
+        self.clipboard_debouncer.mark_seen(text.to_string());
+    }
+
+    /// Add a chunk of a clipboard transfer, returning the payload once it's complete
+    fn handle_clipboard_chunk(&mut self, chunk: ClipboardChunk) -> Option<ClipboardPayload> {
+        if !self.clipboard_formats.contains(&chunk.format) {
+            warn!(
+                "Ignoring clipboard chunk in unnegotiated format {:?}",
+                chunk.format
+            );
+            return None;
+        }
+
+        match self.clipboard.push(chunk) {
+            Ok(Some(payload)) => {
+                debug!(
+                    "Received {:?} clipboard payload ({} bytes)",
+                    payload.format,
+                    payload.data.len()
+                );
+                Some(payload)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Dropping clipboard transfer: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_simulator::tests::{Call, CallLog, RecordingInput};
+    use crate::scope::{FocusProvider, FocusedApp};
+    use asteria_core::auth::sign_nonce;
+    use asteria_core::protocol::{AuthResponse, LockState};
+    use enigo::{Button, Coordinate, Direction, Key};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Focus on an app that can't be identified, which a relay scope never allows
+    struct UnknownFocus;
+
+    impl FocusProvider for UnknownFocus {
+        fn focused_app(&self) -> Option<FocusedApp> {
+            None
+        }
+    }
+
+    /// Focus that a test moves between an allowed and a blocked app
+    struct SwitchedFocus(Arc<AtomicBool>);
+
+    impl FocusProvider for SwitchedFocus {
+        fn focused_app(&self) -> Option<FocusedApp> {
+            let executable = if self.0.load(Ordering::Relaxed) {
+                "notepad.exe"
+            } else {
+                "game.exe"
+            };
+            Some(FocusedApp {
+                executable: executable.to_string(),
+                window_class: String::new(),
+            })
+        }
+    }
+
+    fn session(config: &ServerConfig) -> ClientSession {
+        scoped_session(config, Box::new(SystemFocusProvider))
+    }
+
+    fn scoped_session(config: &ServerConfig, provider: Box<dyn FocusProvider>) -> ClientSession {
+        let scope = Arc::new(RelayScope::new(&config.scope, provider));
+        ClientSession::new(
+            config,
+            scope,
+            Arc::new(ServerMetrics::default()).open_connection(),
+        )
+    }
+
+    /// Simulator over a recording backend and a handle to what it records
+    fn recording_simulator(config: &ServerConfig) -> (Arc<Mutex<dyn InputSink>>, CallLog) {
+        let (backend, calls) = RecordingInput::new();
+        let simulator = InputSimulator::with_backend(backend, config.simulation.clone());
+        (Arc::new(Mutex::new(simulator)), calls)
+    }
+
+    async fn dispatch(
+        message: Message,
+        simulator: &Arc<Mutex<dyn InputSink>>,
+        session: &mut ClientSession,
+    ) {
+        InputServer::process_packet(Packet::new(message), simulator, session)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn typed_events_reach_the_simulator() {
+        let config = ServerConfig::default();
+        let (simulator, calls) = recording_simulator(&config);
+        let mut session = session(&config);
+
+        dispatch(
+            Message::InputEventTyped(InputEventType::KeyPress { key_code: 30 }),
+            &simulator,
+            &mut session,
+        )
+        .await;
+        dispatch(
+            Message::Batch(vec![
+                InputEventType::MouseMove { x: 3, y: -2 },
+                InputEventType::MouseButton {
+                    button: 0,
+                    pressed: true,
+                },
+            ]),
+            &simulator,
+            &mut session,
+        )
+        .await;
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                Call::Key(Key::Unicode('a'), Direction::Press),
+                Call::Move(3, -2, Coordinate::Rel),
+                Call::Button(Button::Left, Direction::Press),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn raw_events_reach_the_simulator_unless_rejected() {
+        let config = ServerConfig::default();
+        let (simulator, calls) = recording_simulator(&config);
+        let mut session = session(&config);
+
+        let raw = |code| {
+            Message::InputEvent(InputEvent {
+                event_type: raw_event_types::EV_KEY.to_string(),
+                code,
+                value: 1,
+            })
+        };
+        // Past the last key code Linux defines
+        dispatch(raw(0x300), &simulator, &mut session).await;
+        dispatch(raw(30), &simulator, &mut session).await;
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [Call::Key(Key::Unicode('a'), Direction::Press)]
+        );
+    }
+
+    #[tokio::test]
+    async fn reset_releases_what_the_client_holds() {
+        let config = ServerConfig::default();
+        let (simulator, calls) = recording_simulator(&config);
+        let mut session = session(&config);
+
+        dispatch(
+            Message::InputEventTyped(InputEventType::KeyPress { key_code: 30 }),
+            &simulator,
+            &mut session,
+        )
+        .await;
+        dispatch(Message::ResetInputState, &simulator, &mut session).await;
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                Call::Key(Key::Unicode('a'), Direction::Press),
+                Call::Key(Key::Unicode('a'), Direction::Release),
+            ]
+        );
+    }
+
+    /// Answer the server's challenge on `stream` the way a client with `secret` would
+    async fn answer_challenge(stream: &mut tokio::io::DuplexStream, secret: &str) {
+        let format = WireFormat::default();
+        let mut buffer = Vec::new();
+        let challenge = read_frame(stream, &mut buffer, format).await.unwrap();
+        let Some(Packet {
+            message: Message::AuthChallenge(challenge),
+            ..
+        }) = challenge
+        else {
+            panic!("expected an authentication challenge");
+        };
+
+        let response = Packet::new(Message::AuthResponse(AuthResponse {
+            mac: sign_nonce(secret, &challenge.nonce),
+        }));
+        stream
+            .write_all(&encode_frame(&response, format).unwrap())
+            .await
+            .unwrap();
+    }
+
+    async fn authenticate_client(client_secret: &'static str) -> Result<()> {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let client = tokio::spawn(async move {
+            answer_challenge(&mut client, client_secret).await;
+            client
+        });
+
+        let mut buffer = Vec::new();
+        let result =
+            InputServer::authenticate(&mut server, &mut buffer, "hunter2", WireFormat::default())
+                .await;
+        client.await.unwrap();
+        result
+    }
+
+    #[tokio::test]
+    async fn client_with_the_shared_secret_is_authenticated() {
+        authenticate_client("hunter2").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_with_another_secret_is_rejected() {
+        let error = authenticate_client("hunter3").await.unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("invalid authentication response")
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnect_releases_a_raw_press() {
+        let config = ServerConfig::default();
+        let (simulator, calls) = recording_simulator(&config);
+        let mut session = session(&config);
+
+        let raw = |code, value| {
+            Message::InputEvent(InputEvent {
+                event_type: raw_event_types::EV_KEY.to_string(),
+                code,
+                value,
+            })
+        };
+        dispatch(raw(30, 1), &simulator, &mut session).await;
+        dispatch(raw(48, 1), &simulator, &mut session).await;
+        dispatch(raw(48, 0), &simulator, &mut session).await;
+        // What the connection handler does once the client is gone
+        InputServer::release_pressed(&simulator, &mut session).await;
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                Call::Key(Key::Unicode('a'), Direction::Press),
+                Call::Key(Key::Unicode('b'), Direction::Press),
+                Call::Key(Key::Unicode('b'), Direction::Release),
+                Call::Key(Key::Unicode('a'), Direction::Release),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn blocking_scope_drops_input() {
+        let mut config = ServerConfig::default();
+        config.scope.allowed_apps = vec!["notepad.exe".to_string()];
+        let (simulator, calls) = recording_simulator(&config);
+        let mut session = scoped_session(&config, Box::new(UnknownFocus));
+
+        dispatch(
+            Message::InputEventTyped(InputEventType::KeyPress { key_code: 30 }),
+            &simulator,
+            &mut session,
+        )
+        .await;
+        dispatch(
+            Message::LockState(LockState {
+                caps: true,
+                num: false,
+                scroll: false,
+            }),
+            &simulator,
+            &mut session,
+        )
+        .await;
+
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn session_expires_after_the_heartbeat_timeout() {
+        let mut config = ServerConfig::default();
+        config.heartbeat.timeout_secs = 15;
+        let session = session(&config);
+
+        assert_eq!(
+            session.idle_deadline(),
+            Some(session.last_activity + Duration::from_secs(15))
+        );
+    }
+
+    #[test]
+    fn held_press_is_dropped_with_its_release() {
+        let mut config = ServerConfig::default();
+        config.scope.allowed_apps = vec!["notepad.exe".to_string()];
+        config.scope.blocked_action = BlockedInputAction::Hold;
+        let allowed = Arc::new(AtomicBool::new(false));
+        let mut session = scoped_session(&config, Box::new(SwitchedFocus(Arc::clone(&allowed))));
+
+        let press = |key_code| InputEventType::KeyPress { key_code };
+        let release = |key_code| InputEventType::KeyRelease { key_code };
+        assert!(session.scope_input(press(30)).is_empty());
+        assert!(
+            session
+                .scope_input(InputEventType::KeyRepeat { key_code: 30 })
+                .is_empty()
+        );
+        assert!(session.scope_input(release(30)).is_empty());
+        assert!(session.scope_input(press(48)).is_empty());
+
+        // Only the press that is still held down is replayed
+        allowed.store(true, Ordering::Relaxed);
+        let replayed = session.scope_input(release(48));
+        assert!(matches!(
+            replayed[..],
+            [
+                InputEventType::KeyPress { key_code: 48 },
+                InputEventType::KeyRelease { key_code: 48 },
+            ]
+        ));
+    }
+
+    #[test]
+    fn release_of_an_allowed_press_passes_while_holding() {
+        let mut config = ServerConfig::default();
+        config.scope.allowed_apps = vec!["notepad.exe".to_string()];
+        config.scope.blocked_action = BlockedInputAction::Hold;
+        let allowed = Arc::new(AtomicBool::new(true));
+        let mut session = scoped_session(&config, Box::new(SwitchedFocus(Arc::clone(&allowed))));
+
+        assert_eq!(
+            session
+                .scope_input(InputEventType::KeyPress { key_code: 30 })
+                .len(),
+            1
+        );
+        allowed.store(false, Ordering::Relaxed);
+        assert!(
+            session
+                .scope_input(InputEventType::KeyRepeat { key_code: 30 })
+                .is_empty()
+        );
+        assert_eq!(
+            session
+                .scope_input(InputEventType::KeyRelease { key_code: 30 })
+                .len(),
+            1
+        );
+        assert!(session.held.is_empty());
+    }
+
+    #[test]
+    fn releases_pass_a_blocking_relay_scope() {
+        let mut config = ServerConfig::default();
+        config.scope.allowed_apps = vec!["notepad.exe".to_string()];
+        let mut session = scoped_session(&config, Box::new(UnknownFocus));
+
+        let releases = [
+            InputEventType::KeyRelease { key_code: 30 },
+            InputEventType::MouseButton {
+                button: 1,
+                pressed: false,
+            },
+            InputEventType::GamepadButton {
+                button: 304,
+                pressed: false,
+            },
+        ];
+        for release in releases {
+            assert_eq!(session.scope_input(release).len(), 1);
+        }
+
+        let press = InputEventType::GamepadButton {
+            button: 304,
+            pressed: true,
+        };
+        assert!(session.scope_input(press).is_empty());
+    }
+
+    #[test]
+    fn session_without_timeout_never_expires() {
+        let mut config = ServerConfig::default();
+        config.heartbeat.timeout_secs = 0;
+        assert_eq!(session(&config).idle_deadline(), None);
+    }
+
+    #[tokio::test]
+    async fn retry_succeeds_after_a_transient_failure() {
+        let mut calls = 0;
+        let value = retry_with_backoff(3, Duration::from_millis(1), || {
+            calls += 1;
+            if calls == 1 {
+                bail!("input not ready");
+            }
+            Ok(calls)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(value, 2);
+        assert_eq!(calls, 2);
+    }
+
+    #[tokio::test]
+    async fn retry_gives_up_after_the_last_attempt() {
+        let mut calls = 0;
+        let result: Result<()> = retry_with_backoff(3, Duration::from_millis(1), || {
+            calls += 1;
+            bail!("input not ready")
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn retry_makes_at_least_one_attempt() {
+        let mut calls = 0;
+        let result = retry_with_backoff(0, Duration::ZERO, || {
+            calls += 1;
+            Ok(())
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(calls, 1);
     }
 }
@@ -0,0 +1,154 @@
+use asteria_core::protocol::{InputEvent, InputEventType, raw_event_types};
+use std::fmt;
+
+use crate::gamepad;
+use crate::input_simulator::{linux_button_to_enigo, linux_key, protocol_button_to_enigo};
+
+/// Input carrying a code the server has no mapping for, dropped rather
+/// than handed to the simulator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectedInput {
+    KeyCode(u16),
+    MouseButton(u8),
+    GamepadButton(u16),
+    GamepadAxis(u8),
+}
+
+impl fmt::Display for RejectedInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KeyCode(code) => write!(f, "unknown key code {}", code),
+            Self::MouseButton(button) => write!(f, "unknown mouse button {}", button),
+            Self::GamepadButton(button) => write!(f, "unknown gamepad button {:#x}", button),
+            Self::GamepadAxis(axis) => write!(f, "unknown gamepad axis {}", axis),
+        }
+    }
+}
+
+impl std::error::Error for RejectedInput {}
+
+/// Check that every code in a typed event is one the simulator maps
+pub fn check_typed(event: &InputEventType) -> Result<(), RejectedInput> {
+    match *event {
+        InputEventType::KeyPress { key_code }
+        | InputEventType::KeyRelease { key_code }
+        | InputEventType::KeyRepeat { key_code }
+            if linux_key(key_code).is_none() =>
+        {
+            Err(RejectedInput::KeyCode(key_code))
+        }
+        InputEventType::MouseButton { button, .. }
+            if protocol_button_to_enigo(button).is_none() =>
+        {
+            Err(RejectedInput::MouseButton(button))
+        }
+        InputEventType::GamepadButton { button, .. } if !gamepad::is_known_button(button) => {
+            Err(RejectedInput::GamepadButton(button))
+        }
+        InputEventType::GamepadAxis { axis, .. } if !gamepad::is_known_axis(axis) => {
+            Err(RejectedInput::GamepadAxis(axis))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that a raw key event is for a key or button the simulator maps.
+/// Other raw events are left to the unknown event policy.
+pub fn check_raw(event: &InputEvent) -> Result<(), RejectedInput> {
+    if event.event_type == raw_event_types::EV_KEY
+        && linux_key(event.code).is_none()
+        && linux_button_to_enigo(event.code).is_none()
+    {
+        return Err(RejectedInput::KeyCode(event.code));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asteria_core::protocol::{gamepad_axes, gamepad_buttons, mouse_buttons};
+
+    /// Key code no keyboard sends, past the last one Linux defines
+    const UNKNOWN_KEY: u16 = 0x300;
+
+    fn raw(event_type: &str, code: u16) -> InputEvent {
+        InputEvent {
+            event_type: event_type.to_string(),
+            code,
+            value: 1,
+        }
+    }
+
+    #[test]
+    fn mapped_codes_pass() {
+        let events = [
+            InputEventType::KeyPress { key_code: 30 },
+            InputEventType::KeyRelease { key_code: 1 },
+            InputEventType::MouseButton {
+                button: mouse_buttons::FORWARD,
+                pressed: true,
+            },
+            InputEventType::GamepadButton {
+                button: gamepad_buttons::SOUTH,
+                pressed: true,
+            },
+            InputEventType::GamepadAxis {
+                axis: gamepad_axes::RIGHT_TRIGGER,
+                value: 0,
+            },
+            InputEventType::MouseMove { x: 5, y: -5 },
+        ];
+        for event in events {
+            assert_eq!(check_typed(&event), Ok(()), "{:?}", event);
+        }
+    }
+
+    #[test]
+    fn unmapped_codes_are_rejected() {
+        assert_eq!(
+            check_typed(&InputEventType::KeyRepeat {
+                key_code: UNKNOWN_KEY
+            }),
+            Err(RejectedInput::KeyCode(UNKNOWN_KEY))
+        );
+        assert_eq!(
+            check_typed(&InputEventType::MouseButton {
+                button: 200,
+                pressed: false
+            }),
+            Err(RejectedInput::MouseButton(200))
+        );
+        assert_eq!(
+            check_typed(&InputEventType::GamepadButton {
+                button: 0x100,
+                pressed: true
+            }),
+            Err(RejectedInput::GamepadButton(0x100))
+        );
+        assert_eq!(
+            check_typed(&InputEventType::GamepadAxis { axis: 99, value: 0 }),
+            Err(RejectedInput::GamepadAxis(99))
+        );
+    }
+
+    #[test]
+    fn raw_key_events_accept_keys_and_buttons() {
+        assert_eq!(check_raw(&raw(raw_event_types::EV_KEY, 30)), Ok(()));
+        // BTN_LEFT arrives as EV_KEY too
+        assert_eq!(check_raw(&raw(raw_event_types::EV_KEY, 0x110)), Ok(()));
+        assert_eq!(
+            check_raw(&raw(raw_event_types::EV_KEY, UNKNOWN_KEY)),
+            Err(RejectedInput::KeyCode(UNKNOWN_KEY))
+        );
+    }
+
+    #[test]
+    fn other_raw_events_are_left_to_the_unknown_event_policy() {
+        assert_eq!(
+            check_raw(&raw(raw_event_types::EV_REL, UNKNOWN_KEY)),
+            Ok(())
+        );
+        assert_eq!(check_raw(&raw("EV_MSC", UNKNOWN_KEY)), Ok(()));
+    }
+}